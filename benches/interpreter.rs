@@ -0,0 +1,150 @@
+//! Interpreter benchmarks, run with `cargo bench` or `cargo bench -- NAME` for the ones matching NAME
+//!
+//! The program benchmarks report their throughput in instructions per second.
+//! Every VM reads from an empty input and writes to a sink, none of them
+//! touches the terminal.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use lc3_vm::keyboard::{ChannelInput, DEFAULT_POLL_INTERVAL};
+use lc3_vm::prelude::*;
+
+/// Counts R1 down from 20000 with ADD and BR
+const COUNT_LOOP: &str = "        .ORIG x3000
+        AND R0, R0, #0
+        LD R1, COUNT
+LOOP    ADD R0, R0, #1
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+COUNT   .FILL #20000
+        .END
+";
+
+/// Adds 1 to each word of a 256-word array 40 times with LDR and STR
+const MEMORY_LOOP: &str = "        .ORIG x3000
+        LD R3, PASSES
+PASS    LEA R1, ARRAY
+        LD R2, SIZE
+WORD    LDR R0, R1, #0
+        ADD R0, R0, #1
+        STR R0, R1, #0
+        ADD R1, R1, #1
+        ADD R2, R2, #-1
+        BRp WORD
+        ADD R3, R3, #-1
+        BRp PASS
+        HALT
+PASSES  .FILL #40
+SIZE    .FILL #256
+ARRAY   .BLKW #256
+        .END
+";
+
+/// Prints a 64-character line with PUTS 500 times
+const PUTS_LOOP: &str = "        .ORIG x3000
+        LD R1, LINES
+LINE    LEA R0, TEXT
+        PUTS
+        ADD R1, R1, #-1
+        BRp LINE
+        HALT
+LINES   .FILL #500
+TEXT    .STRINGZ \"The quick brown fox jumps over the lazy dog, again and again.\\n\"
+        .END
+";
+
+/// Reads KBSR 20000 times with LDI, no key ever arriving
+const POLL_LOOP: &str = "        .ORIG x3000
+        LD R2, POLLS
+POLL    LDI R1, KBSR
+        ADD R2, R2, #-1
+        BRp POLL
+        HALT
+POLLS   .FILL #20000
+KBSR    .FILL xFE00
+        .END
+";
+
+/// A VM with `source` loaded, reading nothing and printing into a sink
+fn vm_with(source: &str) -> Result<VM, VMError> {
+    let mut vm = VMBuilder::new()
+        .input(Box::new(std::io::empty()))
+        .output(Box::new(std::io::sink()))
+        .halt_banner(false)
+        .build()?;
+    vm.load_asm(source)?;
+    Ok(vm)
+}
+
+/// Runs `source` to the end, returning how many instructions it executed
+fn run(source: &str) -> Result<u64, VMError> {
+    let mut vm = vm_with(source)?;
+    vm.run()?;
+    Ok(vm.instructions_executed())
+}
+
+/// Runs the KBSR polling loop, the keyboard polling at most every `interval` instructions
+fn poll(interval: u64) -> Result<u64, VMError> {
+    // A channel never sending anything: polling finds no key but the input doesn't end
+    let (_sender, receiver) = std::sync::mpsc::channel();
+    let mut vm = VMBuilder::new()
+        .input_source(Box::new(ChannelInput::new(receiver)))
+        .output(Box::new(std::io::sink()))
+        .halt_banner(false)
+        .poll_interval(interval)
+        .build()?;
+    vm.load_asm(POLL_LOOP)?;
+    vm.run()?;
+    Ok(vm.instructions_executed())
+}
+
+/// An object file of 60K words at x0400 alternating two instructions
+fn large_image() -> Vec<u8> {
+    let mut image = vec![0x04, 0x00];
+    for i in 0..60_000u32 {
+        let word: u16 = if i % 2 == 0 { 0x1021 } else { 0x0FFE };
+        image.extend(word.to_be_bytes());
+    }
+    image
+}
+
+/// Benchmarks `iteration`, which returns the instructions it executed, per instruction
+fn bench_program(c: &mut Criterion, name: &str, iteration: impl Fn() -> Result<u64, VMError>) {
+    let instructions = match iteration() {
+        Ok(instructions) => instructions,
+        Err(e) => {
+            eprintln!("{}: failed: {}", name, e);
+            return;
+        }
+    };
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(instructions));
+    group.bench_function("run", |b| b.iter(&iteration));
+    group.finish();
+}
+
+fn programs(c: &mut Criterion) {
+    bench_program(c, "count_loop", || run(COUNT_LOOP));
+    bench_program(c, "memory_loop", || run(MEMORY_LOOP));
+    bench_program(c, "puts_loop", || run(PUTS_LOOP));
+    bench_program(c, "kbsr_poll_loop", || poll(DEFAULT_POLL_INTERVAL));
+    bench_program(c, "kbsr_poll_every", || poll(0));
+}
+
+fn setup(c: &mut Criterion) {
+    c.bench_function("vm_new", |b| b.iter(VM::new));
+
+    let image = large_image();
+    c.bench_function("load_60k_words", |b| {
+        b.iter(|| {
+            let mut vm = VM::new();
+            vm.load_bytes(black_box(&image), Endianness::Big)
+                .map(|_| vm)
+        })
+    });
+}
+
+criterion_group!(benches, programs, setup);
+criterion_main!(benches);
@@ -0,0 +1,8 @@
+; Reads two characters and prints them back
+        .ORIG x3000
+        GETC
+        OUT
+        GETC
+        OUT
+        HALT
+        .END
@@ -0,0 +1,5 @@
+; Reads one character into R0 and halts
+        .ORIG x3000
+        GETC
+        HALT
+        .END
@@ -0,0 +1,42 @@
+//! Prints the register trace of a program, as the trace snapshots in `tests/golden/` record it
+//!
+//! ```bash
+//! cargo run --example golden_trace -- tests/golden/traps.obj
+//! ```
+//!
+//! The keyboard reads `NAME.input` next to `NAME.obj` if there is one. Its
+//! output is compared with what `tests/golden/reference.py` prints for the
+//! same program.
+
+use std::path::Path;
+
+use lc3_vm::keyboard::ScriptedInput;
+use lc3_vm::loader::Endianness;
+use lc3_vm::trace::step_trace;
+use lc3_vm::{VMError, VM};
+
+fn trace(program: &str) -> Result<String, VMError> {
+    let mut vm = VM::new();
+    vm.capture_output();
+    vm.set_halt_banner(false);
+    let input = std::fs::read(Path::new(program).with_extension("input")).unwrap_or_default();
+    vm.set_input_source(Box::new(ScriptedInput::new(input)));
+    vm.load_program(program, Endianness::Big)?;
+
+    step_trace(&mut vm)
+}
+
+fn main() {
+    let Some(program) = std::env::args().nth(1) else {
+        eprintln!("Usage: golden_trace PROGRAM.obj");
+        std::process::exit(2);
+    };
+
+    match trace(&program) {
+        Ok(trace) => print!("{}", trace),
+        Err(e) => {
+            eprintln!("{}: {}", program, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,6 @@
+//! `liblc3_vm_ffi`, the C interface of [`lc3_vm::ffi`] as a shared library
+//!
+//! `cargo build -p lc3_vm_ffi --release` builds it, with the functions
+//! declared in `include/lc3_vm.h`.
+
+pub use lc3_vm::ffi::*;
@@ -0,0 +1,46 @@
+//! Runs arbitrary instruction streams, run with `cargo fuzz run execute`
+//!
+//! The first byte sets how many of the following bytes are keyboard input,
+//! the rest are up to 256 big-endian words loaded at x3000. The program gets
+//! NULs once its input runs out, prints into a buffer and stops after 10,000
+//! instructions. It may fail with any `VMError`, but never panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lc3_vm::keyboard::ScriptedInput;
+use lc3_vm::prelude::*;
+
+const MAX_WORDS: usize = 256;
+const MAX_INSTRUCTIONS: u64 = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&input_len, rest)) = data.split_first() else {
+        return;
+    };
+    let (input, program) = rest.split_at(usize::from(input_len).min(rest.len()));
+    let words: Vec<u16> = program
+        .chunks_exact(2)
+        .take(MAX_WORDS)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    let image: Vec<u8> = [0x30, 0x00]
+        .into_iter()
+        .chain(words.iter().flat_map(|word| word.to_be_bytes()))
+        .collect();
+
+    for predecode in [false, true] {
+        let mut vm = VMBuilder::new()
+            .input_source(Box::new(ScriptedInput::new(input.to_vec())))
+            .capture_output()
+            .halt_banner(false)
+            .eof_policy(EofPolicy::Zero)
+            .predecode(predecode)
+            .build()
+            .unwrap();
+        vm.load_bytes(&image, Endianness::Big).unwrap();
+
+        let _ = vm.run_with_limit(MAX_INSTRUCTIONS);
+        assert!(vm.instructions_executed() <= MAX_INSTRUCTIONS);
+    }
+});
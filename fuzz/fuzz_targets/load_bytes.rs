@@ -0,0 +1,41 @@
+//! Feeds arbitrary bytes to `VM::load_bytes`, run with `cargo fuzz run load_bytes`
+//!
+//! Every input must load or fail with a `VMError`, never panic. A loaded
+//! image must describe exactly the words after the origin and leave them
+//! in memory, within 0x0000..=0xFFFF unless wrapping is allowed.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lc3_vm::memory::MEMORY_MAX;
+use lc3_vm::prelude::*;
+
+fn check(data: &[u8], endianness: Endianness, allow_wrap: bool) {
+    let mut vm = VM::new();
+    vm.set_allow_wrap(allow_wrap);
+    let Ok(program) = vm.load_bytes(data, endianness) else {
+        return;
+    };
+
+    let mut words = data
+        .chunks_exact(2)
+        .map(|pair| endianness.word([pair[0], pair[1]]));
+    assert!(data.len() % 2 == 0);
+    assert_eq!(words.next(), Some(program.origin));
+    let words: Vec<u16> = words.collect();
+    assert_eq!(usize::from(program.len), words.len());
+    assert_eq!(program.end, program.origin.wrapping_add(program.len));
+    assert!(allow_wrap || usize::from(program.origin) + words.len() <= MEMORY_MAX);
+
+    let addresses = (0..program.len).map(|i| program.origin.wrapping_add(i));
+    for (address, word) in addresses.zip(words) {
+        assert_eq!(vm.memory().read_ram(address), word);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    for endianness in [Endianness::Big, Endianness::Little] {
+        check(data, endianness, false);
+        check(data, endianness, true);
+    }
+});
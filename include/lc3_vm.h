@@ -0,0 +1,118 @@
+#ifndef LC3_VM_H
+#define LC3_VM_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdint.h>
+#include <stddef.h>
+
+// The call succeeded, or the instruction ran and the program goes on
+#define LC3_OK 0
+
+// The program halted, stepping or running it again fails with [`LC3_ERR_HALTED`]
+#define LC3_HALTED 1
+
+// [`lc3_vm_run`] executed its instruction budget and the program goes on
+#define LC3_LIMIT_REACHED 2
+
+// A pointer argument was NULL
+#define LC3_ERR_NULL -1
+
+// The image isn't a valid `.obj` file or doesn't fit in memory
+#define LC3_ERR_LOAD -2
+
+// The register index is neither 0 to 7 nor [`LC3_REG_PC`]
+#define LC3_ERR_REGISTER -3
+
+// The program already halted
+#define LC3_ERR_HALTED -4
+
+// An instruction failed, e.g. a reserved opcode or a failed input or output
+#define LC3_ERR_EXECUTION -5
+
+// Register index of the program counter for [`lc3_vm_get_register`]
+#define LC3_REG_PC 8
+
+// A VM owned by C code, opaque on that side
+typedef struct Lc3Vm Lc3Vm;
+
+// Receives the bytes a program prints, `len` of them at `bytes`, with the user data it was registered with
+typedef void (*Lc3OutputCallback)(const uint8_t *bytes, size_t len, void *userdata);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates a VM printing to stdout, without the banner at HALT
+//
+// Release it with [`lc3_vm_free`].
+Lc3Vm *lc3_vm_new(void);
+
+// Releases a VM created by [`lc3_vm_new`], doing nothing for NULL
+//
+// # Safety
+// `vm` must be NULL or a pointer returned by [`lc3_vm_new`] that wasn't freed yet.
+void lc3_vm_free(Lc3Vm *vm);
+
+// Loads a big-endian `.obj` image of `len` bytes at `bytes`
+//
+// Execution starts at the origin of the first image loaded.
+//
+// # Safety
+// `vm` must come from [`lc3_vm_new`], and `bytes` must point to `len` readable bytes.
+int32_t lc3_vm_load_bytes(Lc3Vm *vm, const uint8_t *bytes, size_t len);
+
+// Executes one instruction
+//
+// # Returns
+// [`LC3_OK`], [`LC3_HALTED`] if this instruction halted the program, or an error code
+//
+// # Safety
+// `vm` must come from [`lc3_vm_new`].
+int32_t lc3_vm_step(Lc3Vm *vm);
+
+// Runs until the program halts or `max_instructions` instructions ran, 0 for no limit
+//
+// # Returns
+// [`LC3_HALTED`], [`LC3_LIMIT_REACHED`] or an error code
+//
+// # Safety
+// `vm` must come from [`lc3_vm_new`].
+int32_t lc3_vm_run(Lc3Vm *vm, uint64_t max_instructions);
+
+// Stores R0 to R7, or the PC for [`LC3_REG_PC`], in `value`
+//
+// # Safety
+// `vm` must come from [`lc3_vm_new`] and `value` must be writable.
+int32_t lc3_vm_get_register(const Lc3Vm *vm, uint32_t index, uint16_t *value);
+
+// Stores the word at `address` in `value`
+//
+// Device registers read as the RAM behind them, so reading memory never
+// consumes a key or has any other side effect.
+//
+// # Safety
+// `vm` must come from [`lc3_vm_new`] and `value` must be writable.
+int32_t lc3_vm_read_mem(const Lc3Vm *vm, uint16_t address, uint16_t *value);
+
+// Sends the program's output to `callback` with `userdata`, or back to stdout for NULL
+//
+// The output is buffered and passed on at each newline, before waiting for a
+// key and when a step or run ends.
+//
+// # Safety
+// `vm` must come from [`lc3_vm_new`]. `callback` is called with `userdata`
+// until the output is replaced or the VM freed, so `userdata` has to stay valid
+// that long.
+int32_t lc3_vm_set_output_callback(Lc3Vm *vm, Lc3OutputCallback callback, void *userdata);
+
+// Describes the last error returned on this thread, NULL if there was none
+//
+// The string stays valid until the next failing call on the same thread.
+const char *lc3_vm_last_error_message(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LC3_VM_H */
@@ -0,0 +1,10 @@
+#!/bin/sh
+# Builds the lc3vm module into a throwaway virtualenv and runs its tests
+set -eu
+cd "$(dirname "$0")"
+
+python3 -m venv .venv
+. .venv/bin/activate
+pip install --quiet "maturin>=1.5,<2" pytest
+maturin develop --quiet
+pytest tests
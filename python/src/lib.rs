@@ -0,0 +1,197 @@
+//! Python bindings, built into the `lc3vm` module with `maturin develop`
+//!
+//! ```python
+//! import lc3vm
+//!
+//! vm = lc3vm.VM()
+//! vm.load("prog.obj")
+//! vm.set_input(b"42\n")
+//! vm.run(limit=1_000_000)
+//! assert vm.r(2) == 8 and vm.output() == b"done\n"
+//! ```
+//!
+//! Output is always captured, [`PyVm::output`] returns what was printed since
+//! the last call. Errors raise `lc3vm.LC3Error`, whose `pc` attribute is the
+//! address of the failing instruction.
+
+use lc3_vm::disasm;
+use lc3_vm::keyboard::ScriptedInput;
+use lc3_vm::prelude::*;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyIndexError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+create_exception!(lc3vm, LC3Error, PyException, "An LC-3 program failed, `pc` is where");
+
+/// Converts a `VMError` to `LC3Error`, with the PC of the failing instruction
+fn lc3_error(vm: &VM, error: VMError) -> PyErr {
+    let (pc, message) = match &error {
+        VMError::AtInstruction { pc, instr, source } => (
+            *pc,
+            format!(
+                "at {} ({}): {:?}",
+                vm.describe_address(*pc),
+                disasm::disassemble(*instr, *pc),
+                source
+            ),
+        ),
+        other => (vm.pc(), format!("{other:?}")),
+    };
+
+    Python::with_gil(|py| {
+        let err = LC3Error::new_err(message);
+        match err.value_bound(py).setattr("pc", pc) {
+            Ok(()) => err,
+            Err(setattr_failed) => setattr_failed,
+        }
+    })
+}
+
+fn register_index(r: usize) -> PyResult<usize> {
+    match r {
+        0..=7 => Ok(r),
+        _ => Err(PyIndexError::new_err(format!("R{r} doesn't exist, R0-R7 do"))),
+    }
+}
+
+/// An LC-3 VM with its output captured and its input given up front
+#[pyclass(name = "VM", module = "lc3vm", unsendable)]
+pub struct PyVm {
+    vm: VM,
+}
+
+#[pymethods]
+impl PyVm {
+    /// Creates a VM with no input, whose output is captured
+    #[new]
+    fn new() -> Self {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_halt_banner(false);
+        vm.set_input_source(Box::new(ScriptedInput::new(Vec::new())));
+
+        Self { vm }
+    }
+
+    /// Loads a big-endian `.obj` file, returning its origin
+    fn load(&mut self, path: &str) -> PyResult<u16> {
+        let loaded = self.vm.load_program(path, Endianness::Big);
+        let programs = loaded.map_err(|e| lc3_error(&self.vm, e))?;
+
+        Ok(programs.first().map(|program| program.origin).unwrap_or_default())
+    }
+
+    /// Loads a big-endian `.obj` image from `bytes`, returning its origin
+    fn load_bytes(&mut self, image: &[u8]) -> PyResult<u16> {
+        let loaded = self.vm.load_bytes(image, Endianness::Big);
+        let program = loaded.map_err(|e| lc3_error(&self.vm, e))?;
+
+        Ok(program.origin)
+    }
+
+    /// Runs until the program halts, or `limit` instructions ran if given
+    ///
+    /// Returns True if the program halted, False if the limit stopped it.
+    #[pyo3(signature = (limit=None))]
+    fn run(&mut self, limit: Option<u64>) -> PyResult<bool> {
+        let outcome = match limit {
+            Some(limit) => self.vm.run_with_limit(limit),
+            None => self.vm.run(),
+        };
+
+        match outcome.map_err(|e| lc3_error(&self.vm, e))? {
+            RunOutcome::Halted(_) => Ok(true),
+            RunOutcome::Paused(_) => Ok(false),
+        }
+    }
+
+    /// Executes one instruction, returning True if it halted the program
+    fn step(&mut self) -> PyResult<bool> {
+        let outcome = self.vm.step().map_err(|e| lc3_error(&self.vm, e))?;
+
+        Ok(matches!(
+            outcome,
+            StepOutcome::Executed {
+                halted: Some(_),
+                ..
+            }
+        ))
+    }
+
+    /// Goes back to the power-on state, forgetting the loaded programs
+    fn reset(&mut self) {
+        self.vm.reset();
+    }
+
+    /// Whether the program halted
+    #[getter]
+    fn halted(&self) -> bool {
+        matches!(self.vm.state(), VMState::Halted(_))
+    }
+
+    /// Value of R0 to R7
+    fn r(&self, r: usize) -> PyResult<u16> {
+        let r = register_index(r)?;
+
+        self.vm.read_register(r).map_err(|e| lc3_error(&self.vm, e))
+    }
+
+    /// Sets R0 to R7
+    fn set_r(&mut self, r: usize, value: u16) -> PyResult<()> {
+        let r = register_index(r)?;
+        self.vm.write_register(r, value);
+
+        Ok(())
+    }
+
+    /// The program counter
+    #[getter]
+    fn pc(&self) -> u16 {
+        self.vm.pc()
+    }
+
+    #[setter]
+    fn set_pc(&mut self, pc: u16) {
+        self.vm.set_pc(pc);
+    }
+
+    /// The word at `address`, device registers reading as the RAM behind them
+    fn mem(&self, address: u16) -> u16 {
+        self.vm.memory().read_ram(address)
+    }
+
+    /// Writes the word at `address`
+    fn set_mem(&mut self, address: u16, value: u16) -> PyResult<()> {
+        self.vm
+            .write_memory(address, value)
+            .map_err(|e| lc3_error(&self.vm, e))
+    }
+
+    /// Number of instructions executed so far
+    #[getter]
+    fn instructions(&self) -> u64 {
+        self.vm.instructions_executed()
+    }
+
+    /// Feeds `data` to the keyboard, replacing any input not read yet
+    ///
+    /// A program asking for more input than that fails with `LC3Error`.
+    fn set_input(&mut self, data: &[u8]) {
+        self.vm
+            .set_input_source(Box::new(ScriptedInput::new(data.to_vec())));
+    }
+
+    /// Everything printed since the last call
+    fn output<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.vm.take_output())
+    }
+}
+
+#[pymodule]
+fn lc3vm(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyVm>()?;
+    m.add("LC3Error", m.py().get_type_bound::<LC3Error>())?;
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+"""Tests of the lc3vm module, run by run-tests.sh once maturin built it"""
+
+from pathlib import Path
+
+import pytest
+
+import lc3vm
+
+EXAMPLES = Path(__file__).resolve().parents[2] / "examples"
+
+# Echoes each key uppercased until a 'q', see tests/examples.rs
+UPCASE = bytes.fromhex("3000 2208 2408 f020 1601 0403 1002 f021 0ffa f025 ff8f ffe0")
+
+
+def test_simple_add_registers():
+    vm = lc3vm.VM()
+    assert vm.load(str(EXAMPLES / "simple_add.obj")) == 0x3000
+
+    assert vm.run(limit=1_000_000)
+    assert vm.halted
+    assert (vm.r(0), vm.r(1), vm.r(2)) == (5, 3, 8)
+    assert vm.instructions == 6
+
+
+def test_hello_world_output_is_bytes():
+    vm = lc3vm.VM()
+    vm.load_bytes((EXAMPLES / "hello-world.obj").read_bytes())
+
+    vm.run()
+
+    assert vm.output() == b"Hello World!"
+    assert vm.output() == b""
+
+
+def test_input_injection():
+    vm = lc3vm.VM()
+    vm.load_bytes(UPCASE)
+    vm.set_input(b"abcq")
+
+    assert vm.run(limit=10_000)
+    assert vm.output() == b"ABC"
+    assert vm.r(0) == ord("q")
+
+
+def test_step_limit_and_reset():
+    vm = lc3vm.VM()
+    vm.load_bytes(UPCASE)
+    vm.set_input(b"x")
+
+    assert not vm.run(limit=2)
+    assert vm.pc == 0x3002
+    assert not vm.step()
+    assert vm.r(0) == ord("x")
+
+    vm.reset()
+    assert vm.mem(0x3000) == 0
+    vm.set_mem(0x3000, 0xF025)
+    vm.set_r(3, 7)
+    assert vm.step()
+    assert vm.r(3) == 7
+
+
+def test_errors_carry_the_pc():
+    vm = lc3vm.VM()
+    # ADD R0, R0, #1 then a reserved opcode
+    vm.load_bytes(bytes.fromhex("3000 1021 d000"))
+
+    with pytest.raises(lc3vm.LC3Error) as error:
+        vm.run()
+    assert error.value.pc == 0x3001
+
+    vm.reset()
+    vm.load_bytes(UPCASE)
+    with pytest.raises(lc3vm.LC3Error, match="input"):
+        vm.run(limit=10_000)
+
+    with pytest.raises(IndexError):
+        vm.r(8)
@@ -0,0 +1,559 @@
+//! Two-pass LC-3 assembler
+//!
+//! Accepts the lc3as dialect: one `.ORIG`/`.END` block with labels, the
+//! `.FILL`, `.BLKW` and `.STRINGZ` directives, the real opcodes and the trap
+//! aliases. The first pass assigns an address to every label, the second
+//! encodes instructions and range-checks their offsets.
+//!
+//! Numbers are written as `#10` or `10` (decimal) and `x1F` (hex).
+
+use crate::errors::VMError;
+use crate::memory::MEMORY_MAX;
+use crate::symbols::SymbolTable;
+
+/// Output of the assembler: a single memory image and its labels
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assembled {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: SymbolTable,
+}
+
+impl Assembled {
+    /// Encodes the image in the lc3as `.obj` format: big-endian origin then big-endian words
+    pub fn to_obj(&self) -> Vec<u8> {
+        std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    }
+}
+
+/// A source line split into its label, mnemonic and operands
+struct Line<'a> {
+    number: usize,
+    label: Option<&'a str>,
+    mnemonic: Option<String>,
+    operands: Vec<&'a str>,
+}
+
+/// Assembles LC-3 source text into a memory image
+///
+/// # Errors
+/// Returns `VMError::AssemblyError` with the 1-based line number of the first problem
+pub fn assemble(source: &str) -> Result<Assembled, VMError> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(index, text)| tokenize(index.saturating_add(1), text))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // First pass: find the origin and give every label its address
+    let mut origin: Option<u16> = None;
+    let mut address: usize = 0;
+    let mut symbols = SymbolTable::new();
+    let mut labels: Vec<(&str, u16)> = Vec::new();
+
+    for line in &lines {
+        if origin.is_none() {
+            match line.mnemonic.as_deref() {
+                Some(".ORIG") => {
+                    let value = parse_number(operand(line, 0)?, line.number)?;
+                    let start = to_word(value, line.number)?;
+                    origin = Some(start);
+                    address = usize::from(start);
+                }
+                Some(_) => return Err(error(line.number, "expected .ORIG before any code")),
+                None if line.label.is_some() => {
+                    return Err(error(line.number, "label before .ORIG"))
+                }
+                None => (),
+            }
+            continue;
+        }
+
+        if let Some(label) = line.label {
+            if lookup(&labels, label).is_some() {
+                return Err(error(line.number, format!("duplicate label {}", label)));
+            }
+            let label_address = u16::try_from(address)
+                .map_err(|_| error(line.number, "label past the end of memory"))?;
+            labels.push((label, label_address));
+            symbols.insert(label, label_address);
+        }
+
+        if line.mnemonic.as_deref() == Some(".END") {
+            break;
+        }
+
+        address = address
+            .checked_add(size_of(line)?)
+            .filter(|end| *end <= MEMORY_MAX)
+            .ok_or_else(|| error(line.number, "program runs past the end of memory"))?;
+    }
+
+    let origin = origin.ok_or_else(|| error(lines.len(), "missing .ORIG"))?;
+
+    // Second pass: encode every line now that all labels are known
+    let mut words: Vec<u16> = Vec::new();
+    let mut pc = origin;
+
+    for line in lines
+        .iter()
+        .skip_while(|line| line.mnemonic.as_deref() != Some(".ORIG"))
+        .skip(1)
+    {
+        let Some(mnemonic) = line.mnemonic.as_deref() else {
+            continue;
+        };
+        if mnemonic == ".END" {
+            break;
+        }
+
+        for word in encode(line, mnemonic, pc, &labels)? {
+            words.push(word);
+            pc = pc.wrapping_add(1);
+        }
+    }
+
+    Ok(Assembled {
+        origin,
+        words,
+        symbols,
+    })
+}
+
+fn error(line: usize, message: impl Into<String>) -> VMError {
+    VMError::AssemblyError {
+        line,
+        message: message.into(),
+    }
+}
+
+const OPCODES: [&str; 23] = [
+    "ADD", "AND", "NOT", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI",
+    "STR", "TRAP", "RTI", "GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT", "BR",
+];
+
+fn is_mnemonic(token: &str) -> bool {
+    let upper = token.to_ascii_uppercase();
+
+    upper.starts_with('.')
+        || OPCODES.contains(&upper.as_str())
+        || upper.strip_prefix("BR").is_some_and(is_branch_flags)
+}
+
+/// Condition suffixes accepted after BR, always written in n, z, p order
+fn is_branch_flags(flags: &str) -> bool {
+    ["N", "Z", "P", "NZ", "NP", "ZP", "NZP"].contains(&flags)
+}
+
+/// Splits a source line into label, mnemonic and operands, dropping comments
+fn tokenize(number: usize, text: &str) -> Result<Line<'_>, VMError> {
+    let mut tokens: Vec<&str> = Vec::new();
+    let mut rest = text;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() || rest.starts_with(';') {
+            break;
+        }
+
+        if rest.starts_with('"') {
+            // A string literal runs to the next unescaped quote
+            let mut escaped = false;
+            let end = rest
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| {
+                    let closes = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    closes
+                })
+                .map(|(index, _)| index.saturating_add(1))
+                .ok_or_else(|| error(number, "unterminated string"))?;
+            let (token, remainder) = rest.split_at(end);
+            tokens.push(token);
+            rest = remainder;
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == ',' || c == ';')
+                .unwrap_or(rest.len());
+            let (token, remainder) = rest.split_at(end);
+            tokens.push(token);
+            rest = remainder;
+        }
+    }
+
+    let mut tokens = tokens.into_iter();
+    let mut label = None;
+    let mut mnemonic = None;
+
+    if let Some(first) = tokens.next() {
+        if is_mnemonic(first) {
+            mnemonic = Some(first.to_ascii_uppercase());
+        } else {
+            label = Some(first.trim_end_matches(':'));
+            mnemonic = tokens.next().map(str::to_ascii_uppercase);
+        }
+    }
+
+    Ok(Line {
+        number,
+        label,
+        mnemonic,
+        operands: tokens.collect(),
+    })
+}
+
+fn operand<'a>(line: &Line<'a>, index: usize) -> Result<&'a str, VMError> {
+    line.operands
+        .get(index)
+        .copied()
+        .ok_or_else(|| error(line.number, "missing operand"))
+}
+
+/// Number of words a line occupies in memory
+fn size_of(line: &Line) -> Result<usize, VMError> {
+    Ok(match line.mnemonic.as_deref() {
+        None => 0,
+        Some(".BLKW") => {
+            let count = parse_number(operand(line, 0)?, line.number)?;
+            usize::try_from(count).map_err(|_| error(line.number, "negative .BLKW size"))?
+        }
+        Some(".STRINGZ") => unescape(operand(line, 0)?, line.number)?
+            .len()
+            .saturating_add(1),
+        Some(_) => 1,
+    })
+}
+
+fn encode(
+    line: &Line,
+    mnemonic: &str,
+    pc: u16,
+    labels: &[(&str, u16)],
+) -> Result<Vec<u16>, VMError> {
+    let n = line.number;
+    let reg = |index: usize| -> Result<u16, VMError> { parse_register(operand(line, index)?, n) };
+    // PC-relative offset to a label or literal, checked to fit in `bits`
+    let offset = |index: usize, bits: u32| -> Result<u16, VMError> {
+        let token = operand(line, index)?;
+        let value = match lookup(labels, token) {
+            Some(target) => i32::from(target).wrapping_sub(i32::from(pc.wrapping_add(1))),
+            None => parse_number(token, n)?,
+        };
+        signed_field(value, bits, n)
+    };
+    let immediate = |index: usize, bits: u32| -> Result<u16, VMError> {
+        signed_field(parse_number(operand(line, index)?, n)?, bits, n)
+    };
+
+    let word = match mnemonic {
+        "ADD" | "AND" => {
+            let opcode: u16 = if mnemonic == "ADD" { 0x1 } else { 0x5 };
+            let base = opcode << 12 | reg(0)? << 9 | reg(1)? << 6;
+            match parse_register(operand(line, 2)?, n) {
+                Ok(sr2) => base | sr2,
+                Err(_) => base | 1 << 5 | immediate(2, 5)?,
+            }
+        }
+        "NOT" => 0x9 << 12 | reg(0)? << 9 | reg(1)? << 6 | 0x3F,
+        "JMP" => 0xC << 12 | reg(0)? << 6,
+        "RET" => 0xC << 12 | 7 << 6,
+        "JSR" => 0x4 << 12 | 1 << 11 | offset(0, 11)?,
+        "JSRR" => 0x4 << 12 | reg(0)? << 6,
+        "LD" => 0x2 << 12 | reg(0)? << 9 | offset(1, 9)?,
+        "LDI" => 0xA << 12 | reg(0)? << 9 | offset(1, 9)?,
+        "LDR" => 0x6 << 12 | reg(0)? << 9 | reg(1)? << 6 | immediate(2, 6)?,
+        "LEA" => 0xE << 12 | reg(0)? << 9 | offset(1, 9)?,
+        "ST" => 0x3 << 12 | reg(0)? << 9 | offset(1, 9)?,
+        "STI" => 0xB << 12 | reg(0)? << 9 | offset(1, 9)?,
+        "STR" => 0x7 << 12 | reg(0)? << 9 | reg(1)? << 6 | immediate(2, 6)?,
+        "RTI" => 0x8 << 12,
+        "TRAP" => {
+            let vector = parse_number(operand(line, 0)?, n)?;
+            let vector = u8::try_from(vector).map_err(|_| error(n, "trap vector out of range"))?;
+            0xF << 12 | u16::from(vector)
+        }
+        "GETC" => 0xF020,
+        "OUT" => 0xF021,
+        "PUTS" => 0xF022,
+        "IN" => 0xF023,
+        "PUTSP" => 0xF024,
+        "HALT" => 0xF025,
+        ".FILL" => {
+            let token = operand(line, 0)?;
+            match lookup(labels, token) {
+                Some(address) => address,
+                None => to_word(parse_number(token, n)?, n)?,
+            }
+        }
+        ".BLKW" => return Ok(vec![0; size_of(line)?]),
+        ".STRINGZ" => {
+            let text = unescape(operand(line, 0)?, n)?;
+            return Ok(text
+                .into_iter()
+                .map(u16::from)
+                .chain(std::iter::once(0))
+                .collect());
+        }
+        ".ORIG" => return Err(error(n, "only one .ORIG block is supported")),
+        _ => match mnemonic
+            .strip_prefix("BR")
+            .filter(|flags| flags.is_empty() || is_branch_flags(flags))
+        {
+            Some(flags) => {
+                // A bare BR branches unconditionally, like BRnzp
+                let flags = if flags.is_empty() { "NZP" } else { flags };
+                let mut word = 0;
+                for (flag, bit) in [('N', 11), ('Z', 10), ('P', 9)] {
+                    if flags.contains(flag) {
+                        word |= 1 << bit;
+                    }
+                }
+                word | offset(0, 9)?
+            }
+            None => return Err(error(n, format!("unknown instruction {}", mnemonic))),
+        },
+    };
+
+    Ok(vec![word])
+}
+
+fn lookup(labels: &[(&str, u16)], token: &str) -> Option<u16> {
+    labels
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(token))
+        .map(|&(_, address)| address)
+}
+
+fn parse_register(token: &str, line: usize) -> Result<u16, VMError> {
+    let digit = token
+        .strip_prefix(['R', 'r'])
+        .and_then(|digit| digit.parse::<u16>().ok())
+        .filter(|digit| *digit < 8);
+
+    digit.ok_or_else(|| error(line, format!("expected a register, found {}", token)))
+}
+
+fn parse_number(token: &str, line: usize) -> Result<i32, VMError> {
+    let invalid = || error(line, format!("invalid number {}", token));
+
+    let (digits, radix) = if let Some(hex) = token.strip_prefix(['x', 'X']) {
+        (hex, 16)
+    } else {
+        (token.strip_prefix('#').unwrap_or(token), 10)
+    };
+
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits),
+    };
+
+    let value = i32::from_str_radix(digits, radix).map_err(|_| invalid())?;
+
+    Ok(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+/// Checks that `value` fits in a `bits`-wide two's complement field and masks it
+fn signed_field(value: i32, bits: u32, line: usize) -> Result<u16, VMError> {
+    let limit = 1i32 << bits.saturating_sub(1);
+
+    if value < limit.wrapping_neg() || value >= limit {
+        return Err(error(
+            line,
+            format!("value {} does not fit in {} bits", value, bits),
+        ));
+    }
+
+    let mask = (1i32 << bits).wrapping_sub(1);
+    u16::try_from(value & mask).map_err(|_| error(line, "value out of range"))
+}
+
+/// Converts a .FILL or .ORIG value to a word, accepting both signed and unsigned spellings
+fn to_word(value: i32, line: usize) -> Result<u16, VMError> {
+    if let Ok(word) = u16::try_from(value) {
+        return Ok(word);
+    }
+
+    i16::try_from(value)
+        .map(|signed| u16::from_ne_bytes(signed.to_ne_bytes()))
+        .map_err(|_| error(line, format!("value {} does not fit in 16 bits", value)))
+}
+
+/// Strips the quotes from a .STRINGZ literal and resolves escape sequences
+fn unescape(token: &str, line: usize) -> Result<Vec<u8>, VMError> {
+    let text = token
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .ok_or_else(|| error(line, ".STRINGZ expects a quoted string"))?;
+
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(other) => other,
+                None => return Err(error(line, "dangling escape in string")),
+            }
+        } else {
+            c
+        };
+
+        let byte = u8::try_from(c).map_err(|_| error(line, "non-ASCII character in string"))?;
+        bytes.push(byte);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIMPLE_ADD: &str = "\
+        .ORIG x3000
+        AND R0, R0, #0      ; clear R0
+        ADD R0, R0, #5
+        AND R1, R1, #0
+        ADD R1, R1, #3
+        ADD R2, R0, R1      ; R2 = R0 + R1
+        HALT
+        .END
+";
+
+    const HELLO_WORLD: &str = "\
+        .ORIG x3000
+        LEA R0, HELLO
+        PUTS
+        HALT
+HELLO   .STRINGZ \"Hello World!\"
+        .END
+";
+
+    #[test]
+    fn test_assemble_simple_add_matches_lc3as() -> Result<(), VMError> {
+        let assembled = assemble(SIMPLE_ADD)?;
+
+        assert_eq!(
+            assembled.to_obj(),
+            include_bytes!("../examples/simple_add.obj").to_vec()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_assemble_hello_world_matches_lc3as() -> Result<(), VMError> {
+        let assembled = assemble(HELLO_WORLD)?;
+
+        assert_eq!(
+            assembled.to_obj(),
+            include_bytes!("../examples/hello-world.obj").to_vec()
+        );
+        assert_eq!(assembled.symbols.lookup(0x3003), Some(("HELLO", 0x3003)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_assemble_all_opcodes() -> Result<(), VMError> {
+        let source = "\
+        .ORIG x3000
+START   ADD R1, R2, R3
+        ADD R1, R2, #-1
+        AND R4, R5, #15
+        NOT R6, R7
+LOOP    BRnp LOOP
+        BR START
+        JMP R3
+        RET
+        JSR SUB
+        JSRR R4
+        LD R0, DATA
+        LDI R1, DATA
+        LDR R2, R6, #-32
+        LEA R3, START
+        ST R4, DATA
+        STI R5, DATA
+        STR R6, R5, #31
+        TRAP x23
+        GETC
+        OUT
+        PUTSP
+        RTI
+SUB     RET
+DATA    .FILL xBEEF
+        .FILL #-1
+        .FILL START
+        .BLKW 2
+        .END
+";
+        let assembled = assemble(source)?;
+
+        assert_eq!(
+            assembled.words,
+            vec![
+                0x1283, 0x12BF, 0x596F, 0x9DFF, 0x0BFF, 0x0FFA, 0xC0C0, 0xC1C0, 0x480D, 0x4100,
+                0x200C, 0xA20B, 0x65A0, 0xE7F2, 0x3808, 0xBA07, 0x7D5F, 0xF023, 0xF020, 0xF021,
+                0xF024, 0x8000, 0xC1C0, 0xBEEF, 0xFFFF, 0x3000, 0x0000, 0x0000,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_assemble_offset_out_of_range() {
+        let source = "\
+        .ORIG x3000
+        BRz FAR
+        .BLKW 300
+FAR     HALT
+        .END
+";
+
+        assert!(matches!(
+            assemble(source),
+            Err(VMError::AssemblyError { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_assemble_immediate_out_of_range() {
+        let source = ".ORIG x3000\nADD R0, R0, #16\n.END\n";
+
+        assert!(matches!(
+            assemble(source),
+            Err(VMError::AssemblyError { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_assemble_undefined_label() {
+        let source = ".ORIG x3000\nLD R0, NOWHERE\n.END\n";
+
+        assert!(matches!(
+            assemble(source),
+            Err(VMError::AssemblyError { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_assemble_missing_orig() {
+        assert!(matches!(
+            assemble("ADD R0, R0, #1\n"),
+            Err(VMError::AssemblyError { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+//! `lc3-vm batch`: runs a corpus of programs and tabulates how each one ended
+
+use std::path::{Path, PathBuf};
+
+use lc3_vm::builder::VMBuilder;
+use lc3_vm::expect::{self, TrailingNewlines};
+use lc3_vm::keyboard::EofPolicy;
+use lc3_vm::{PauseReason, RunOutcome, VMError};
+
+use crate::cli;
+
+/// How one program of the batch ended
+enum Outcome {
+    Halted,
+    /// Stopped by `--limit`
+    Limit,
+    /// Stopped by `--timeout`
+    Timeout,
+    /// Paused for another reason, such as a breakpoint compiled into the program
+    Paused(String),
+    Error(String),
+    /// Halted with an output other than its `.expect` file
+    Mismatch(String),
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Halted => "halted",
+            Outcome::Limit => "limit",
+            Outcome::Timeout => "timeout",
+            Outcome::Paused(_) => "paused",
+            Outcome::Error(_) => "error",
+            Outcome::Mismatch(_) => "mismatch",
+        }
+    }
+
+    fn detail(&self) -> &str {
+        match self {
+            Outcome::Paused(detail) | Outcome::Error(detail) | Outcome::Mismatch(detail) => detail,
+            _ => "",
+        }
+    }
+
+    fn failed(&self) -> bool {
+        matches!(self, Outcome::Error(_) | Outcome::Mismatch(_))
+    }
+}
+
+/// Adds the `.obj` files under `dir` to `found`, descending into subdirectories
+fn find_programs(dir: &Path, found: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_programs(&path, found)?;
+        } else if path.extension().is_some_and(|extension| extension == "obj") {
+            found.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the program at `path` without keyboard input, returning how it ended and how many instructions ran
+fn run_program(path: &Path, batch: &cli::Batch) -> (Outcome, u64) {
+    let filename = path.to_string_lossy().into_owned();
+    let mut builder = VMBuilder::new()
+        .program(&filename)
+        .symbols(crate::find_symbols(None, std::slice::from_ref(&filename)))
+        .input(Box::new(std::io::empty()))
+        .eof_policy(EofPolicy::Zero)
+        .capture_output()
+        .halt_banner(false);
+    if let Some(limit) = batch.limit {
+        builder = builder.limit(limit);
+    }
+    if let Some(timeout) = batch.timeout {
+        builder = builder.time_limit(timeout);
+    }
+    let mut vm = match builder.build() {
+        Ok(vm) => vm,
+        Err(VMError::InProgram { source, .. }) => {
+            return (Outcome::Error(format!("failed to load: {}", source)), 0)
+        }
+        Err(e) => return (Outcome::Error(format!("failed to load: {}", e)), 0),
+    };
+
+    let outcome = match vm.run_program() {
+        Ok(RunOutcome::Halted(_)) => Outcome::Halted,
+        Ok(RunOutcome::Paused(PauseReason::LimitReached { .. })) => Outcome::Limit,
+        Ok(RunOutcome::Paused(PauseReason::TimeLimitReached { .. })) => Outcome::Timeout,
+        Ok(RunOutcome::Paused(reason)) => Outcome::Paused(format!("{:?}", reason)),
+        Err(e) => Outcome::Error(vm.describe_error(&e)),
+    };
+    let instructions = vm.instructions_executed();
+
+    // Only a program that halted printed everything it was going to
+    let expected = path.with_extension("expect");
+    if !matches!(outcome, Outcome::Halted) || !expected.exists() {
+        return (outcome, instructions);
+    }
+    let expected_name = expected.to_string_lossy().into_owned();
+    let outcome = match std::fs::read(&expected) {
+        Ok(text) => match expect::check(
+            &String::from_utf8_lossy(&text),
+            &String::from_utf8_lossy(&vm.take_output()),
+            TrailingNewlines::Ignore,
+            &expected_name,
+            "output",
+        ) {
+            Some(diff) => {
+                eprint!("{}", diff);
+                Outcome::Mismatch(format!("output differs from {}", expected_name))
+            }
+            None => Outcome::Halted,
+        },
+        Err(e) => Outcome::Error(format!("failed to read {}: {}", expected_name, e)),
+    };
+
+    (outcome, instructions)
+}
+
+/// Runs every program under the directories of `batch` and prints a table of the outcomes,
+/// returning the exit status
+pub fn run(batch: cli::Batch) -> i32 {
+    let mut programs = Vec::new();
+    for dir in &batch.dirs {
+        if let Err(e) = find_programs(Path::new(dir), &mut programs) {
+            eprintln!("Failed to read directory {:?}: {}", dir, e);
+            return 1;
+        }
+    }
+    programs.sort();
+    if programs.is_empty() {
+        eprintln!("No .obj files under {}", batch.dirs.join(", "));
+        return 1;
+    }
+
+    let rows: Vec<(String, Outcome, u64)> = programs
+        .iter()
+        .map(|path| {
+            let (outcome, instructions) = run_program(path, &batch);
+            (path.to_string_lossy().into_owned(), outcome, instructions)
+        })
+        .collect();
+
+    let width = rows
+        .iter()
+        .map(|(path, _, _)| path.len())
+        .chain(std::iter::once("PROGRAM".len()))
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{:<width$}  {:<8}  {:>12}",
+        "PROGRAM", "OUTCOME", "INSTRUCTIONS"
+    );
+    for (path, outcome, instructions) in &rows {
+        let line = format!(
+            "{:<width$}  {:<8}  {:>12}  {}",
+            path,
+            outcome.name(),
+            instructions,
+            outcome.detail()
+        );
+        println!("{}", line.trim_end());
+    }
+
+    let count = |wanted: fn(&Outcome) -> bool| {
+        rows.iter()
+            .filter(|(_, outcome, _)| wanted(outcome))
+            .count()
+    };
+    let failed = count(Outcome::failed);
+    println!(
+        "{} programs: {} halted, {} stopped by a limit, {} failed",
+        rows.len(),
+        count(|outcome| matches!(outcome, Outcome::Halted)),
+        count(|outcome| matches!(
+            outcome,
+            Outcome::Limit | Outcome::Timeout | Outcome::Paused(_)
+        )),
+        failed
+    );
+
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
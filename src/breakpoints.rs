@@ -0,0 +1,549 @@
+//! Address breakpoints checked by the VM before each instruction
+//!
+//! Conditions are written with a tiny grammar shared with the debugger's
+//! watches: `R0`-`R7`, register aliases such as `sp`, `pc`, `flags` and
+//! `mem[x4000]` name values of the machine, `#5`, `5` and `x10` are
+//! constants, and `R2 == 0` or `flags == z` compare them.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::memory::Memory;
+use crate::registers::{RegisterAliases, RegisterFlags, Registers};
+
+/// Handle returned by [`crate::VM::add_breakpoint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BreakpointId(u32);
+
+/// A value read from the machine when a condition is evaluated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    /// One of R0-R7
+    Register(usize),
+    /// The program counter, i.e. the breakpoint's address
+    Pc,
+    /// A memory cell, read without triggering memory-mapped devices
+    Memory(u16),
+    /// The condition flags, as the N, Z and P bits of the PSR: 4, 2 or 1
+    Flags,
+    /// A constant
+    Constant(u16),
+}
+
+/// How two values are compared, as unsigned 16-bit numbers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Condition deciding whether a breakpoint stops the VM, e.g. `R2 == 0`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    /// Compares two values
+    Compare {
+        left: Value,
+        comparison: Comparison,
+        right: Value,
+    },
+    /// True while the condition flags equal the given flag
+    Flag(RegisterFlags),
+}
+
+impl Value {
+    /// Reads the value from the machine, `None` for an invalid register
+    pub(crate) fn read(self, registers: &Registers, memory: &Memory) -> Option<u16> {
+        match self {
+            Value::Register(r) => registers.get(r).ok(),
+            Value::Pc => Some(registers.pc),
+            Value::Memory(address) => Some(memory.read_ram(address)),
+            Value::Flags => Some(flag_bits(registers.condition)),
+            Value::Constant(value) => Some(value),
+        }
+    }
+
+    /// Formats `value` as read from this value: `N`, `Z` or `P` for the flags, hex otherwise
+    pub fn format(self, value: u16) -> String {
+        match (self, flag(value)) {
+            (Value::Flags, Some(RegisterFlags::Neg)) => "N".to_string(),
+            (Value::Flags, Some(RegisterFlags::Zro)) => "Z".to_string(),
+            (Value::Flags, Some(RegisterFlags::Pos)) => "P".to_string(),
+            _ => format!("x{:04X}", value),
+        }
+    }
+}
+
+fn flag_bits(flag: RegisterFlags) -> u16 {
+    match flag {
+        RegisterFlags::Neg => 4,
+        RegisterFlags::Zro => 2,
+        RegisterFlags::Pos => 1,
+    }
+}
+
+fn flag(bits: u16) -> Option<RegisterFlags> {
+    match bits {
+        4 => Some(RegisterFlags::Neg),
+        2 => Some(RegisterFlags::Zro),
+        1 => Some(RegisterFlags::Pos),
+        _ => None,
+    }
+}
+
+/// Parses a hex number such as `x4000`, `0x4000` or `4000`
+fn hex(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('x'))
+        .unwrap_or(text);
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+impl Value {
+    /// Parses a value like [`Value::from_str`], naming registers by `aliases` as well
+    ///
+    /// # Errors
+    /// Returns a message naming what was expected if `text` is no value
+    pub fn parse_with(text: &str, aliases: &RegisterAliases) -> Result<Self, String> {
+        let text = text.trim();
+        let lower = text.to_ascii_lowercase();
+        let invalid = || {
+            format!(
+                "Expected R0-R7, a register alias such as sp, pc, flags, mem[ADDR] or a number such as #5 or x10, got {:?}",
+                text
+            )
+        };
+
+        if let Some(address) = lower
+            .strip_prefix("mem[")
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            return hex(address.trim()).map(Value::Memory).ok_or_else(invalid);
+        }
+        match lower.as_str() {
+            "pc" => return Ok(Value::Pc),
+            "flags" => return Ok(Value::Flags),
+            _ => (),
+        }
+        if let Some(number) = lower.strip_prefix('r') {
+            if let Ok(r) = number.parse::<usize>() {
+                return if r < 8 {
+                    Ok(Value::Register(r))
+                } else {
+                    Err(invalid())
+                };
+            }
+        }
+        if let Some(r) = aliases.register(&lower) {
+            return Ok(Value::Register(r));
+        }
+        if lower.starts_with('r') {
+            return Err(invalid());
+        }
+        if lower.starts_with('x') || lower.starts_with("0x") {
+            return hex(&lower).map(Value::Constant).ok_or_else(invalid);
+        }
+        // Decimal constants wrap like the assembler's, so #-1 is xFFFF
+        let decimal = lower.strip_prefix('#').unwrap_or(&lower);
+        let value = decimal.parse::<i32>().map_err(|_| invalid())?;
+        u16::try_from(value)
+            .or_else(|_| i16::try_from(value).map(|value| u16::from_be_bytes(value.to_be_bytes())))
+            .map(Value::Constant)
+            .map_err(|_| invalid())
+    }
+
+    /// Names the value for display, with the alias of a register that has one: `R6 (SP)`
+    pub fn name(self, aliases: &RegisterAliases) -> String {
+        match self {
+            Value::Register(r) => match aliases.get(r) {
+                Some(alias) => format!("R{} ({})", r, alias),
+                None => self.to_string(),
+            },
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = String;
+
+    /// Parses a value, naming registers by the default [`RegisterAliases`] as well
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Value::parse_with(text, &RegisterAliases::default())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Register(r) => write!(f, "R{}", r),
+            Value::Pc => write!(f, "pc"),
+            Value::Memory(address) => write!(f, "mem[x{:04X}]", address),
+            Value::Flags => write!(f, "flags"),
+            Value::Constant(value) => write!(f, "x{:04X}", value),
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "==" => Ok(Comparison::Eq),
+            "!=" => Ok(Comparison::Ne),
+            "<" => Ok(Comparison::Lt),
+            "<=" => Ok(Comparison::Le),
+            ">" => Ok(Comparison::Gt),
+            ">=" => Ok(Comparison::Ge),
+            other => Err(format!("Unknown comparison {:?}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Compare {
+                left,
+                comparison,
+                right,
+            } => write!(f, "{} {} {}", left, comparison, right),
+            Condition::Flag(RegisterFlags::Neg) => write!(f, "flags == n"),
+            Condition::Flag(RegisterFlags::Zro) => write!(f, "flags == z"),
+            Condition::Flag(RegisterFlags::Pos) => write!(f, "flags == p"),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    /// Parses a condition, naming registers by the default [`RegisterAliases`] as well
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Condition::parse_with(text, &RegisterAliases::default())
+    }
+}
+
+impl Condition {
+    /// Parses `LEFT OP RIGHT` such as `R2 == 0` or `mem[x4000] != #0`, and `flags == n`, `z` or `p`
+    ///
+    /// Registers are named R0-R7 or by `aliases`, such as `sp == xFE00`.
+    ///
+    /// # Errors
+    /// Returns a message naming what was expected if `text` is no condition
+    pub fn parse_with(text: &str, aliases: &RegisterAliases) -> Result<Self, String> {
+        // Two-character operators first, so `<=` isn't read as `<`
+        let (position, operator) = ["==", "!=", "<=", ">=", "<", ">"]
+            .iter()
+            .filter_map(|operator| text.find(operator).map(|position| (position, *operator)))
+            .min_by_key(|(position, _)| *position)
+            .ok_or_else(|| {
+                format!(
+                    "Expected a comparison such as R2 == 0 or flags == z, got {:?}",
+                    text
+                )
+            })?;
+        let (left, rest) = text.split_at(position);
+        let right = rest.strip_prefix(operator).unwrap_or(rest).trim();
+        let left = Value::parse_with(left, aliases)?;
+        let comparison: Comparison = operator.parse()?;
+
+        let flag = match right.to_ascii_lowercase().as_str() {
+            "n" => Some(RegisterFlags::Neg),
+            "z" => Some(RegisterFlags::Zro),
+            "p" => Some(RegisterFlags::Pos),
+            _ => None,
+        };
+        match (left, comparison, flag) {
+            (Value::Flags, Comparison::Eq, Some(flag)) => Ok(Condition::Flag(flag)),
+            (Value::Flags, _, Some(_)) => Err("Flags can only be compared with ==".to_string()),
+            _ => Ok(Condition::Compare {
+                left,
+                comparison,
+                right: Value::parse_with(right, aliases)?,
+            }),
+        }
+    }
+
+    /// Evaluates the condition against the machine state
+    ///
+    /// A condition reading an invalid register is false.
+    pub fn eval(&self, registers: &Registers, memory: &Memory) -> bool {
+        match *self {
+            Condition::Compare {
+                left,
+                comparison,
+                right,
+            } => match (left.read(registers, memory), right.read(registers, memory)) {
+                (Some(left), Some(right)) => match comparison {
+                    Comparison::Eq => left == right,
+                    Comparison::Ne => left != right,
+                    Comparison::Lt => left < right,
+                    Comparison::Le => left <= right,
+                    Comparison::Gt => left > right,
+                    Comparison::Ge => left >= right,
+                },
+                _ => false,
+            },
+            Condition::Flag(flag) => registers.condition == flag,
+        }
+    }
+}
+
+/// A breakpoint on the instruction at `address`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub id: BreakpointId,
+    pub address: u16,
+    /// Disabled breakpoints are kept but never stop the VM
+    pub enabled: bool,
+    /// Only stop when this holds, `None` stops every time
+    pub condition: Option<Condition>,
+    /// Times the address was reached while the condition was false
+    pub skipped: u64,
+}
+
+/// The breakpoints of a VM, in the order they were added
+#[derive(Debug, Default, Clone)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    next_id: u32,
+}
+
+impl Breakpoints {
+    /// Adds an enabled breakpoint at `address`, stopping only when `condition` holds if given
+    pub fn add(&mut self, address: u16, condition: Option<Condition>) -> BreakpointId {
+        let id = BreakpointId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.list.push(Breakpoint {
+            id,
+            address,
+            enabled: true,
+            condition,
+            skipped: 0,
+        });
+
+        id
+    }
+
+    /// Removes a breakpoint, returning false if `id` is unknown
+    pub fn remove(&mut self, id: BreakpointId) -> bool {
+        let len = self.list.len();
+        self.list.retain(|breakpoint| breakpoint.id != id);
+
+        self.list.len() != len
+    }
+
+    /// Enables or disables a breakpoint, returning false if `id` is unknown
+    pub fn set_enabled(&mut self, id: BreakpointId, enabled: bool) -> bool {
+        match self.list.iter_mut().find(|breakpoint| breakpoint.id == id) {
+            Some(breakpoint) => {
+                breakpoint.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the breakpoints in the order they were added
+    pub fn list(&self) -> &[Breakpoint] {
+        &self.list
+    }
+
+    /// Returns true if an enabled breakpoint at `address` stops the VM
+    ///
+    /// Breakpoints whose condition is false count the hit as skipped.
+    pub fn hit(&mut self, address: u16, registers: &Registers, memory: &Memory) -> bool {
+        let mut hit = false;
+
+        for breakpoint in self
+            .list
+            .iter_mut()
+            .filter(|breakpoint| breakpoint.enabled && breakpoint.address == address)
+        {
+            match breakpoint.condition {
+                Some(condition) if !condition.eval(registers, memory) => {
+                    breakpoint.skipped = breakpoint.skipped.saturating_add(1);
+                }
+                _ => hit = true,
+            }
+        }
+
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_remove_breakpoints() {
+        let (registers, memory) = (Registers::default(), Memory::new());
+        let mut breakpoints = Breakpoints::default();
+        let first = breakpoints.add(0x3000, None);
+        let second = breakpoints.add(0x3005, None);
+
+        assert_ne!(first, second);
+        assert!(breakpoints.hit(0x3000, &registers, &memory));
+        assert!(breakpoints.hit(0x3005, &registers, &memory));
+        assert!(!breakpoints.hit(0x3001, &registers, &memory));
+
+        assert!(breakpoints.remove(first));
+        assert!(!breakpoints.remove(first));
+        assert!(!breakpoints.hit(0x3000, &registers, &memory));
+        assert_eq!(breakpoints.list().len(), 1);
+    }
+
+    #[test]
+    fn test_disabled_breakpoint_is_not_hit() {
+        let (registers, memory) = (Registers::default(), Memory::new());
+        let mut breakpoints = Breakpoints::default();
+        let id = breakpoints.add(0x3000, None);
+
+        assert!(breakpoints.set_enabled(id, false));
+        assert!(!breakpoints.hit(0x3000, &registers, &memory));
+        assert_eq!(breakpoints.list().first().map(|b| b.enabled), Some(false));
+
+        assert!(breakpoints.set_enabled(id, true));
+        assert!(breakpoints.hit(0x3000, &registers, &memory));
+    }
+
+    #[test]
+    fn test_condition_eval() -> Result<(), crate::errors::VMError> {
+        let mut registers = Registers::default();
+        let mut memory = Memory::new();
+        registers.set(2, 7);
+        memory.write_ram(0x4000, 1);
+
+        let compare = |left, comparison, right| Condition::Compare {
+            left,
+            comparison,
+            right,
+        };
+
+        assert!(
+            compare(Value::Register(2), Comparison::Eq, Value::Constant(7))
+                .eval(&registers, &memory)
+        );
+        assert!(
+            compare(Value::Memory(0x4000), Comparison::Ne, Value::Constant(0))
+                .eval(&registers, &memory)
+        );
+        assert!(
+            compare(Value::Pc, Comparison::Ge, Value::Constant(0x3000)).eval(&registers, &memory)
+        );
+        assert!(
+            !compare(Value::Register(2), Comparison::Lt, Value::Register(0))
+                .eval(&registers, &memory)
+        );
+        assert!(
+            !compare(Value::Register(8), Comparison::Eq, Value::Register(8))
+                .eval(&registers, &memory)
+        );
+        assert!(Condition::Flag(RegisterFlags::Zro).eval(&registers, &memory));
+        assert!(!Condition::Flag(RegisterFlags::Neg).eval(&registers, &memory));
+        assert!(compare(Value::Flags, Comparison::Eq, Value::Constant(2)).eval(&registers, &memory));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_values() {
+        assert_eq!("R3".parse(), Ok(Value::Register(3)));
+        assert_eq!("r7".parse(), Ok(Value::Register(7)));
+        assert_eq!("PC".parse(), Ok(Value::Pc));
+        assert_eq!("flags".parse(), Ok(Value::Flags));
+        assert_eq!("mem[x4000]".parse(), Ok(Value::Memory(0x4000)));
+        assert_eq!("#-1".parse(), Ok(Value::Constant(0xFFFF)));
+        assert_eq!("10".parse(), Ok(Value::Constant(10)));
+        assert_eq!("x10".parse(), Ok(Value::Constant(0x10)));
+        assert!("R8".parse::<Value>().is_err());
+        assert!("mem[x]".parse::<Value>().is_err());
+
+        assert_eq!(Value::Memory(0x4000).to_string(), "mem[x4000]");
+        assert_eq!(Value::Flags.format(4), "N");
+        assert_eq!(Value::Register(3).format(4), "x0004");
+    }
+
+    #[test]
+    fn test_parse_conditions() {
+        assert_eq!(
+            "R2 == 0".parse(),
+            Ok(Condition::Compare {
+                left: Value::Register(2),
+                comparison: Comparison::Eq,
+                right: Value::Constant(0),
+            })
+        );
+        assert_eq!(
+            "mem[x4000]<=R1".parse(),
+            Ok(Condition::Compare {
+                left: Value::Memory(0x4000),
+                comparison: Comparison::Le,
+                right: Value::Register(1),
+            })
+        );
+        assert_eq!(
+            "flags == z".parse(),
+            Ok(Condition::Flag(RegisterFlags::Zro))
+        );
+        assert!("flags < n".parse::<Condition>().is_err());
+        assert_eq!(
+            "r2>=#-1".parse::<Condition>().map(|c| c.to_string()),
+            Ok("R2 >= xFFFF".to_string())
+        );
+        assert!("R2".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn test_parse_register_aliases() -> Result<(), crate::errors::VMError> {
+        for name in ["sp", "SP", "R6", "r6"] {
+            assert_eq!(name.parse(), Ok(Value::Register(6)), "{}", name);
+        }
+        assert_eq!("ra".parse(), Ok(Value::Register(7)));
+        assert!("fp".parse::<Value>().is_err());
+        assert!("rx".parse::<Value>().is_err());
+
+        let mut aliases = RegisterAliases::none();
+        aliases.set(5, "FP")?;
+        assert_eq!(Value::parse_with("fp", &aliases), Ok(Value::Register(5)));
+        assert!(Value::parse_with("sp", &aliases).is_err());
+        assert_eq!(
+            Condition::parse_with("fp < r6", &aliases),
+            Ok(Condition::Compare {
+                left: Value::Register(5),
+                comparison: Comparison::Lt,
+                right: Value::Register(6),
+            })
+        );
+
+        assert_eq!(Value::Register(5).name(&aliases), "R5 (FP)");
+        assert_eq!(Value::Register(6).name(&aliases), "R6");
+        assert_eq!(
+            "sp == xFE00".parse::<Condition>().map(|c| c.to_string()),
+            Ok("R6 == xFE00".to_string())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,540 @@
+//! Step-by-step configuration of a [`VM`]
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use crate::console::{self, Console};
+use crate::errors::VMError;
+use crate::keyboard::{EofPolicy, InputSource, ReaderInput};
+use crate::loader::{Endianness, Format};
+use crate::output::Output;
+use crate::registers::RegisterAliases;
+use crate::session::Session;
+use crate::stack::StackBounds;
+use crate::symbols::SymbolTable;
+use crate::{Sanitize, VM};
+
+/// Collects the options of a VM and creates it with [`VMBuilder::build`]
+///
+/// ```no_run
+/// use lc3_vm::prelude::*;
+///
+/// let mut vm = VMBuilder::new()
+///     .program("examples/hello-world.obj")
+///     .input(Box::new(std::io::empty()))
+///     .limit(1_000_000)
+///     .build()?;
+/// vm.run_program()?;
+/// # Ok::<(), VMError>(())
+/// ```
+#[derive(Default)]
+pub struct VMBuilder {
+    programs: Vec<String>,
+    format: Option<Format>,
+    endianness: Endianness,
+    input: Option<Box<dyn InputSource>>,
+    console: Option<Box<dyn Console>>,
+    output: Option<Output>,
+    reset_vector: Option<u16>,
+    limit: Option<u64>,
+    time_limit: Option<Duration>,
+    breakpoints: Vec<u16>,
+    seed: Option<u64>,
+    strict_load: bool,
+    allow_wrap: bool,
+    symbols: SymbolTable,
+    profiling: bool,
+    coverage: bool,
+    predecode: bool,
+    call_tracking: bool,
+    undo_depth: usize,
+    stack_bounds: Option<StackBounds>,
+    register_aliases: Option<RegisterAliases>,
+    deterministic: Option<Vec<u8>>,
+    eof_policy: EofPolicy,
+    input_timeout: Option<Duration>,
+    poll_interval: Option<u64>,
+    timeout_sentinel: Option<u8>,
+    max_string_length: Option<usize>,
+    extension_traps: bool,
+    halt_banner: Option<bool>,
+    quiet: bool,
+    sanitize: Option<Sanitize>,
+    in_prompt: Option<Option<String>>,
+    echo_getc: bool,
+    echo_kbdr: bool,
+    loop_detection: Option<u64>,
+    record: bool,
+    replay: Option<Session>,
+}
+
+impl VMBuilder {
+    /// Starts from the defaults of [`VM::new`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a program file to load, `-` reading it from stdin
+    ///
+    /// Programs are loaded in the order they were added.
+    pub fn program(mut self, path: &str) -> Self {
+        self.programs.push(path.to_string());
+        self
+    }
+
+    /// Loads every program as `format` instead of detecting it per file
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Sets the byte order of object files
+    pub fn endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    /// Reads keyboard input from `input` instead of stdin
+    pub fn input(mut self, input: Box<dyn Read>) -> Self {
+        self.input = Some(Box::new(ReaderInput::new(input)));
+        self
+    }
+
+    /// Reads keyboard input from an [`InputSource`] that can be polled without blocking
+    pub fn input_source(mut self, input: Box<dyn InputSource>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Reads keys from `console` in raw mode, see [`VM::set_console`]
+    ///
+    /// Without a console or any other input, a VM whose stdin is a terminal reads
+    /// it as a console.
+    pub fn console(mut self, console: Box<dyn Console>) -> Self {
+        self.console = Some(console);
+        self
+    }
+
+    /// Runs deterministically with `script` as the only keyboard input, see [`VM::set_deterministic`]
+    ///
+    /// Takes precedence over [`VMBuilder::input`] and [`VMBuilder::input_source`].
+    pub fn deterministic(mut self, script: Vec<u8>) -> Self {
+        self.deterministic = Some(script);
+        self
+    }
+
+    /// See [`VM::set_eof_policy`]
+    pub fn eof_policy(mut self, policy: EofPolicy) -> Self {
+        self.eof_policy = policy;
+        self
+    }
+
+    /// See [`VM::set_poll_interval`]
+    pub fn poll_interval(mut self, interval: u64) -> Self {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    /// See [`VM::set_input_timeout`]
+    pub fn input_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.input_timeout = timeout;
+        self
+    }
+
+    /// See [`VM::set_timeout_sentinel`]
+    pub fn timeout_sentinel(mut self, sentinel: Option<u8>) -> Self {
+        self.timeout_sentinel = sentinel;
+        self
+    }
+
+    /// See [`VM::set_max_string_length`]
+    pub fn max_string_length(mut self, max: usize) -> Self {
+        self.max_string_length = Some(max);
+        self
+    }
+
+    /// See [`VM::set_extension_traps`]
+    pub fn extension_traps(mut self, enabled: bool) -> Self {
+        self.extension_traps = enabled;
+        self
+    }
+
+    /// See [`VM::set_in_prompt`]
+    pub fn in_prompt(mut self, prompt: Option<String>) -> Self {
+        self.in_prompt = Some(prompt);
+        self
+    }
+
+    /// See [`VM::set_getc_echo`]
+    pub fn getc_echo(mut self, enabled: bool) -> Self {
+        self.echo_getc = enabled;
+        self
+    }
+
+    /// See [`VM::set_kbdr_echo`]
+    pub fn kbdr_echo(mut self, enabled: bool) -> Self {
+        self.echo_kbdr = enabled;
+        self
+    }
+
+    /// See [`VM::set_sanitize`]
+    pub fn sanitize(mut self, sanitize: Sanitize) -> Self {
+        self.sanitize = Some(sanitize);
+        self
+    }
+
+    /// See [`VM::set_halt_banner`]
+    pub fn halt_banner(mut self, enabled: bool) -> Self {
+        self.halt_banner = Some(enabled);
+        self
+    }
+
+    /// See [`VM::set_quiet`]
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Writes the program's output to `output` instead of stdout
+    pub fn output(mut self, output: Box<dyn Write>) -> Self {
+        self.output = Some(Output::stream(output));
+        self
+    }
+
+    /// Collects the program's output in memory, see [`VM::take_output`]
+    pub fn capture_output(mut self) -> Self {
+        self.output = Some(Output::Captured(Vec::new()));
+        self
+    }
+
+    /// Starts execution at `pc` instead of the origin of the first program, see [`VM::set_reset_vector`]
+    pub fn reset_vector(mut self, pc: u16) -> Self {
+        self.reset_vector = Some(pc);
+        self
+    }
+
+    /// Stops [`VM::run_program`] after `max_instructions`
+    pub fn limit(mut self, max_instructions: u64) -> Self {
+        self.limit = Some(max_instructions);
+        self
+    }
+
+    /// See [`VM::set_time_limit`]
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+
+    /// Pauses before the instruction at `address`, can be given several times
+    pub fn breakpoint(mut self, address: u16) -> Self {
+        self.breakpoints.push(address);
+        self
+    }
+
+    /// Starts with registers scrambled from `seed`, see [`VM::scramble_registers`]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// See [`VM::set_strict_load`]
+    pub fn strict_load(mut self, strict: bool) -> Self {
+        self.strict_load = strict;
+        self
+    }
+
+    /// See [`VM::set_allow_wrap`]
+    pub fn allow_wrap(mut self, allow: bool) -> Self {
+        self.allow_wrap = allow;
+        self
+    }
+
+    /// Adds labels used in diagnostics, see [`VM::add_symbols`]
+    pub fn symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols.extend(symbols);
+        self
+    }
+
+    /// See [`VM::set_profiling`]
+    pub fn profiling(mut self, enabled: bool) -> Self {
+        self.profiling = enabled;
+        self
+    }
+
+    /// See [`VM::set_coverage`]
+    pub fn coverage(mut self, enabled: bool) -> Self {
+        self.coverage = enabled;
+        self
+    }
+
+    /// See [`VM::set_predecode`]
+    pub fn predecode(mut self, enabled: bool) -> Self {
+        self.predecode = enabled;
+        self
+    }
+
+    /// See [`VM::set_call_tracking`]
+    pub fn call_tracking(mut self, enabled: bool) -> Self {
+        self.call_tracking = enabled;
+        self
+    }
+
+    /// See [`VM::set_undo_depth`]
+    pub fn undo_depth(mut self, depth: usize) -> Self {
+        self.undo_depth = depth;
+        self
+    }
+
+    /// See [`VM::set_stack_bounds`]
+    pub fn stack_bounds(mut self, bounds: Option<StackBounds>) -> Self {
+        self.stack_bounds = bounds;
+        self
+    }
+
+    /// Names the registers by `aliases` instead of SP and RA, see [`VM::set_register_aliases`]
+    pub fn register_aliases(mut self, aliases: RegisterAliases) -> Self {
+        self.register_aliases = Some(aliases);
+        self
+    }
+
+    /// See [`VM::set_loop_detection`]
+    pub fn loop_detection(mut self, threshold: Option<u64>) -> Self {
+        self.loop_detection = threshold;
+        self
+    }
+
+    /// See [`VM::start_recording`]
+    pub fn record(mut self, enabled: bool) -> Self {
+        self.record = enabled;
+        self
+    }
+
+    /// See [`VM::replay`]
+    pub fn replay(mut self, session: Session) -> Self {
+        self.replay = Some(session);
+        self
+    }
+
+    /// Creates the VM and loads the programs
+    ///
+    /// # Errors
+    /// Returns `VMError::InProgram` with the path and the error of the first
+    /// program that fails to load, or `VMError::ConsoleFailed` if the console
+    /// can't be switched to raw mode
+    pub fn build(self) -> Result<VM, VMError> {
+        let mut vm = VM::new();
+        vm.set_strict_load(self.strict_load);
+        vm.set_allow_wrap(self.allow_wrap);
+        vm.set_instruction_limit(self.limit);
+        vm.set_time_limit(self.time_limit);
+        vm.set_profiling(self.profiling);
+        vm.set_coverage(self.coverage);
+        vm.set_predecode(self.predecode);
+        vm.set_call_tracking(self.call_tracking);
+        vm.set_undo_depth(self.undo_depth);
+        vm.set_stack_bounds(self.stack_bounds);
+        if let Some(aliases) = self.register_aliases {
+            vm.set_register_aliases(aliases);
+        }
+        vm.set_loop_detection(self.loop_detection);
+        vm.set_eof_policy(self.eof_policy);
+        vm.set_input_timeout(self.input_timeout);
+        if let Some(interval) = self.poll_interval {
+            vm.set_poll_interval(interval);
+        }
+        vm.set_timeout_sentinel(self.timeout_sentinel);
+        vm.set_extension_traps(self.extension_traps);
+        vm.set_getc_echo(self.echo_getc);
+        vm.set_kbdr_echo(self.echo_kbdr);
+        if let Some(max) = self.max_string_length {
+            vm.set_max_string_length(max);
+        }
+
+        // Only a run reading the keyboard live gets the terminal
+        let console = match (&self.input, &self.deterministic, &self.replay) {
+            (None, None, None) => self.console.or_else(console::stdin_console),
+            _ => None,
+        };
+        if self.record {
+            vm.start_recording();
+        }
+        if let Some(session) = self.replay {
+            vm.replay(session);
+        }
+        if let Some(input) = self.input {
+            vm.set_input_source(input);
+        }
+        if let Some(script) = self.deterministic {
+            vm.set_deterministic(Some(script));
+        }
+        if let Some(output) = self.output {
+            vm.set_output_sink(output);
+        }
+        if let Some(prompt) = self.in_prompt {
+            vm.set_in_prompt(prompt);
+        }
+        if let Some(enabled) = self.halt_banner {
+            vm.set_halt_banner(enabled);
+        }
+        vm.set_quiet(self.quiet);
+        if let Some(sanitize) = self.sanitize {
+            vm.set_sanitize(sanitize);
+        }
+        if let Some(seed) = self.seed {
+            vm.scramble_registers(seed);
+        }
+        if let Some(pc) = self.reset_vector {
+            vm.set_reset_vector(pc);
+        }
+
+        for path in &self.programs {
+            let result = match self.format {
+                Some(format) => vm.load_program_as(path, format, self.endianness),
+                None => vm.load_program(path, self.endianness),
+            };
+
+            result.map_err(|source| VMError::InProgram {
+                path: path.clone(),
+                source: Box::new(source),
+            })?;
+        }
+        vm.add_symbols(self.symbols);
+        for address in self.breakpoints {
+            vm.add_breakpoint(address);
+        }
+
+        // Last, so a failed load leaves the terminal alone
+        if let Some(console) = console {
+            vm.set_console(console)?;
+        }
+
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HaltReason, PauseReason, RunOutcome};
+
+    #[test]
+    fn test_defaults_match_new() -> Result<(), VMError> {
+        let vm = VMBuilder::new().build()?;
+
+        assert_eq!(vm.pc(), VM::new().pc());
+        assert!(vm.loaded_segments().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_program_input_and_limit() -> Result<(), VMError> {
+        let mut vm = VMBuilder::new()
+            .program("examples/simple_add.obj")
+            .input(Box::new(&b""[..]))
+            .limit(2)
+            .build()?;
+
+        assert_eq!(
+            vm.run_program()?,
+            RunOutcome::Paused(PauseReason::LimitReached { executed: 2 })
+        );
+        assert_eq!(vm.pc(), 0x3002);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_vector_and_limit() -> Result<(), VMError> {
+        let mut vm = VMBuilder::new()
+            .program("examples/simple_add.obj")
+            .reset_vector(0x3005)
+            .limit(10)
+            .build()?;
+
+        assert_eq!(vm.pc(), 0x3005);
+        assert_eq!(vm.run_program()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(2)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_breakpoints_and_seed() -> Result<(), VMError> {
+        let mut vm = VMBuilder::new()
+            .program("examples/simple_add.obj")
+            .breakpoint(0x3001)
+            .breakpoint(0x3003)
+            .seed(42)
+            .build()?;
+
+        assert_ne!(vm.read_register(3)?, 0);
+        assert_eq!(
+            vm.run_program()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3001))
+        );
+        assert_eq!(
+            vm.run_program()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3003))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_reaches_program() -> Result<(), VMError> {
+        let mut vm = VMBuilder::new()
+            .program("examples/getc.asm")
+            .input(Box::new(&b"x"[..]))
+            .build()?;
+
+        assert_eq!(vm.run_program()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(0)?, u16::from(b'x'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_and_captured_output() -> Result<(), VMError> {
+        let mut vm = VMBuilder::new()
+            .program("examples/getc.asm")
+            .input(Box::new(&b"y"[..]))
+            .capture_output()
+            .limit(10)
+            .build()?;
+
+        assert_eq!(vm.run_program()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(0)?, u16::from(b'y'));
+        assert!(vm.take_output().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_stream() -> Result<(), VMError> {
+        let mut vm = VMBuilder::new()
+            .program("examples/simple_add.obj")
+            .output(Box::new(std::io::sink()))
+            .build()?;
+
+        assert_eq!(vm.run_program()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert!(vm.take_output().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_error_names_program() {
+        let result = VMBuilder::new()
+            .program("examples/simple_add.obj")
+            .program("does/not/exist.obj")
+            .build();
+
+        assert!(matches!(
+            result,
+            Err(VMError::InProgram { path, source })
+                if path == "does/not/exist.obj"
+                    && matches!(*source, VMError::OpenFileFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,108 @@
+//! Shadow call stack kept from JSR, JSRR and RET for backtraces
+
+use std::fmt;
+
+/// One active subroutine call, see [`VM::backtrace`](crate::VM::backtrace)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Address of the subroutine that was called
+    pub target: u16,
+    /// Address the subroutine returns to, right after the JSR or JSRR
+    pub return_address: u16,
+    /// The target with its label, e.g. `PRINT (x3010)`
+    pub function: String,
+    /// The address of the call with its label, e.g. `MAIN+2 (x3002)`
+    pub caller: String,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} called from {}", self.function, self.caller)
+    }
+}
+
+/// Calls that haven't returned yet, innermost last
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CallStack {
+    /// `(target, return_address)` of every open call
+    frames: Vec<(u16, u16)>,
+    /// Set once a RET didn't match the innermost call
+    unreliable: bool,
+}
+
+impl CallStack {
+    /// Records a JSR or JSRR from `return_address - 1` to `target`
+    pub(crate) fn call(&mut self, target: u16, return_address: u16) {
+        self.frames.push((target, return_address));
+    }
+
+    /// Records a RET, i.e. JMP R7, that continued at `address`
+    ///
+    /// A program that changed R7 by hand may return somewhere other than the
+    /// innermost call. Frames up to a matching call are dropped, and without
+    /// a match the stack is kept as it is; either way it is no longer reliable.
+    pub(crate) fn ret(&mut self, address: u16) {
+        match self
+            .frames
+            .iter()
+            .rposition(|&(_, return_address)| return_address == address)
+        {
+            Some(index) => {
+                if index.saturating_add(1) != self.frames.len() {
+                    self.unreliable = true;
+                }
+                self.frames.truncate(index);
+            }
+            None => self.unreliable = true,
+        }
+    }
+
+    /// Returns the open calls, innermost first
+    pub(crate) fn frames(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.frames.iter().rev().copied()
+    }
+
+    /// Returns false once the program returned somewhere the stack didn't expect
+    pub(crate) fn is_reliable(&self) -> bool {
+        !self.unreliable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_and_return() {
+        let mut stack = CallStack::default();
+        stack.call(0x3010, 0x3001);
+        stack.call(0x3020, 0x3011);
+
+        assert_eq!(
+            stack.frames().collect::<Vec<_>>(),
+            vec![(0x3020, 0x3011), (0x3010, 0x3001)]
+        );
+
+        stack.ret(0x3011);
+        assert_eq!(stack.frames().collect::<Vec<_>>(), vec![(0x3010, 0x3001)]);
+        assert!(stack.is_reliable());
+    }
+
+    #[test]
+    fn test_unexpected_return_marks_unreliable() {
+        let mut stack = CallStack::default();
+        stack.call(0x3010, 0x3001);
+        stack.call(0x3020, 0x3011);
+
+        // Returns straight to the outer caller
+        stack.ret(0x3001);
+        assert!(stack.frames().next().is_none());
+        assert!(!stack.is_reliable());
+
+        let mut stack = CallStack::default();
+        stack.call(0x3010, 0x3001);
+        stack.ret(0x4000);
+        assert_eq!(stack.frames().count(), 1);
+        assert!(!stack.is_reliable());
+    }
+}
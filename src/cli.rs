@@ -0,0 +1,1157 @@
+//! The subcommands of the binary and their options, parsed from the command line
+
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use lc3_vm::builder::VMBuilder;
+use lc3_vm::debugger::DEFAULT_UNDO_DEPTH;
+use lc3_vm::expect::TrailingNewlines;
+use lc3_vm::keyboard::EofPolicy;
+use lc3_vm::loader::{Endianness, Format};
+use lc3_vm::loops::DEFAULT_LOOP_THRESHOLD;
+use lc3_vm::stack::StackBounds;
+use lc3_vm::Sanitize;
+
+pub const USAGE: &str = "Usage: ./lc3-vm [run] [options] path/to/program.obj [more.obj ...]";
+
+pub const HELP: &str = "\
+Usage: ./lc3-vm [run] [options] path/to/program.obj [more.obj ...]
+       ./lc3-vm <command> [options] ...
+
+Commands:
+  run       Run programs, the default when the first argument is a path
+  debug     Step through programs in a debugger
+  asm       Assemble a source file into an object file
+  disasm    Print an address-annotated listing of programs
+  inspect   Print what program files contain without running them
+  batch     Run every object file under directories and tabulate how each ended
+  help      Print this help, or the help of a command with `help <command>`
+
+Run `./lc3-vm <command> --help` for the options of a command.
+";
+
+pub const RUN_HELP: &str = "\
+Usage: ./lc3-vm [run] [options] path/to/program.obj [more.obj ...]
+
+Runs the programs. A path of `-` reads the program image from stdin.
+
+Loading:
+  --format obj|ihex|hex|asm   Format of the program files, guessed from the extension otherwise
+  --le                        Program words are little-endian
+  --strict-load               Refuse programs that overlap
+  --allow-wrap                Load programs that wrap past the end of memory
+  --symbols FILE              Labels for diagnostics, prog.sym next to each program otherwise
+  --entry ADDR                Start at ADDR instead of the origin of the first program
+  --seed N                    Start with registers scrambled from N, as on real hardware
+  --load-state FILE           Start from a JSON state file written by --save-state, programs are optional
+
+Running:
+  --limit N                   Stop after N instructions
+  --timeout DUR               Stop after DUR, such as 500ms, 10s or 2m
+  --break ADDR                Stop before the instruction at ADDR, can be given several times
+  --detect-loops              Stop a program spinning in a loop it can never leave
+  --stack TOP:BOTTOM          Fail when R6 leaves the stack, such as 0xFDFF:0xFA00
+  --ext-traps                 Enable the extension traps
+  --predecode                 Decode the loaded programs once up front, faster on compute-heavy code
+  --exit-r0                   Exit with the value of R0 when the program halts
+
+Input:
+  --deterministic FILE        Read the keyboard input from FILE
+  --stdin-file FILE           Feed FILE to GETC and KBDR in place of the keyboard
+  --eof error|zero|halt|hang  What reading past the end of the input does
+  --input-timeout MS          Give up waiting for a key after MS milliseconds
+  --timeout-char CODE         Character read when a key doesn't come in time
+  --poll-interval N           Instructions between two KBSR polls finding no key, 500 by default, 0 for every read
+  --record FILE               Save the keys typed during the run
+  --replay FILE               Type the keys of a recorded session
+  --no-prompt                 Don't print the prompt of the IN trap
+  --echo-getc                 Print the characters GETC reads
+  --echo-kbdr                 Print the characters read from KBDR
+
+Output:
+  --quiet                     Keep stdout for the program's output: no halt banner, prompts and diffs on stderr
+  --sanitize off|dot|hex      Replace unprintable characters in the output
+  --trace                     Log every instruction to stderr
+  --trace-traps               Log every trap to stderr
+  --trace-traps-file FILE     Log every trap to FILE
+  --dump-regs                 Print the registers after the run
+  --profile                   Print instruction counts and the hottest addresses
+  --stats                     Print a line with the instruction count, speed, memory accesses and traps
+  --coverage FILE             Write which instructions were executed to FILE
+  --dump-obj START END FILE   Save memory from START through END as an object file after the run
+  --json                      Print a JSON report of the run to stdout, the program's output going to stderr
+  --json-out FILE             Write the JSON report to FILE instead
+  --capture-output            Embed the program's output in the JSON report, base64 encoded
+  --save-state FILE           Write the registers and memory to a JSON state file when the run ends
+
+Checking:
+  --expect-output FILE        Compare the output with FILE, printing a diff and exiting 1 if they differ
+  --trailing-newlines MODE    strict to tell apart outputs ending in different newlines, ignore by default
+
+  -h, --help                  Print this help
+";
+
+pub const DEBUG_HELP: &str = "\
+Usage: ./lc3-vm debug [--tui | --script FILE] [options] path/to/program.obj [more.obj ...]
+
+Reads debugger commands from the prompt, one per line:
+
+  break ADDR [if COND]        Stop before the instruction at ADDR, only while COND such as R2 == 0 holds
+  watch [--break] VALUE       Print R0-R7, pc, flags or mem[ADDR] after each step and stop,
+                              stopping the program when it changes with --break
+  run                         Start the program over from where it was loaded
+  continue                    Run on from where the program stopped
+  step [N]                    Execute N instructions, 1 by default
+  back [N]                    Undo the last N instructions, 1 by default
+  regs                        Print the registers, with SP after R6 and RA after R7
+  mem ADDR [N]                Print N words from ADDR, 8 by default
+  list [ADDR]                 Disassemble 5 instructions before ADDR and 10 after, the PC by default
+  bt                          Print the subroutine calls that haven't returned, innermost first
+  set REG VALUE               Write R0-R7, sp, ra or pc, such as set r0 xBEEF
+  poke [--force] ADDR VALUE   Write one word of memory
+  fill [--force] START END VALUE
+                              Write VALUE from START through END
+  save START END FILE         Write memory from START through END to an object file
+  quit                        Stop debugging
+
+In the full-screen debugger s steps, c continues, b toggles a breakpoint on
+the selected line and q quits.
+
+  --tui                       Use the full-screen debugger
+  --script FILE               Run the commands in FILE, echoing each one, and exit 1 at the first that fails
+  --undo-depth N              Instructions back can undo, 10000 by default, 0 for none
+  --format obj|ihex|hex|asm   Format of the program files, guessed from the extension otherwise
+  --le                        Program words are little-endian
+  --symbols FILE              Labels, prog.sym next to each program otherwise
+  -h, --help                  Print this help
+";
+
+pub const ASM_HELP: &str = "\
+Usage: ./lc3-vm asm path/to/program.asm [-o path/to/program.obj]
+
+Assembles a source file into an lc3as object file, next to the source unless -o is given.
+
+  -o FILE                     Where to write the object file
+  -h, --help                  Print this help
+";
+
+pub const DISASM_HELP: &str = "\
+Usage: ./lc3-vm disasm [options] path/to/program.obj [more.obj ...]
+
+Prints an address-annotated listing of the programs without running them.
+
+  --format obj|ihex|hex|asm   Format of the program files, guessed from the extension otherwise
+  --le                        Program words are little-endian
+  --symbols FILE              Labels, prog.sym next to each program otherwise
+  -h, --help                  Print this help
+";
+
+pub const INSPECT_HELP: &str = "\
+Usage: ./lc3-vm inspect [options] path/to/program.obj [more.obj ...]
+
+Prints the format, size, segments and symbols of each program file without running it.
+
+  --format obj|ihex|hex|asm   Format of the program files, guessed from the extension otherwise
+  --le                        Program words are little-endian
+  --symbols FILE              Labels, prog.sym next to each program otherwise
+  -h, --help                  Print this help
+";
+
+pub const BATCH_HELP: &str = "\
+Usage: ./lc3-vm batch [options] path/to/dir [more dirs ...]
+
+Runs every .obj file under the directories without keyboard input, reading
+past the end of the input as zero, and prints how each program ended. A
+prog.expect file next to prog.obj holds the output the program should print.
+Exits 1 if a program failed or printed something else.
+
+  --limit N                   Stop each program after N instructions, such as 1M
+  --timeout DUR               Stop each program after DUR, such as 500ms, 2s or 1m
+  -h, --help                  Print this help
+";
+
+/// What the command line asks for
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Box<Options>),
+    Debug {
+        load: Load,
+        tui: bool,
+        script: Option<String>,
+        /// Instructions `back` can undo, 0 for none
+        undo_depth: usize,
+    },
+    Asm {
+        input: String,
+        output: Option<String>,
+    },
+    Disasm(Load),
+    Inspect(Load),
+    Batch(Batch),
+    /// Print this help text
+    Help(&'static str),
+}
+
+/// Which program files to load and how, shared by the commands that read programs
+#[derive(Debug, Default, PartialEq)]
+pub struct Load {
+    pub format: Option<Format>,
+    pub endianness: Endianness,
+    pub symbols_file: Option<String>,
+    pub filenames: Vec<String>,
+}
+
+/// Options of `batch`
+#[derive(Debug, Default, PartialEq)]
+pub struct Batch {
+    pub dirs: Vec<String>,
+    pub limit: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+impl Load {
+    /// Takes `arg` if it's one of the loading flags, returning whether it was
+    fn flag(&mut self, arg: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, String> {
+        match arg {
+            "--le" => self.endianness = Endianness::Little,
+            "--format" => {
+                self.format = Some(parsed(
+                    args,
+                    "--format expects one of: obj, ihex, hex, asm",
+                    Format::from_name,
+                )?)
+            }
+            "--symbols" => {
+                self.symbols_file = Some(value(args, "--symbols expects a path to a .sym file")?)
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// A builder loading the programs
+    pub fn builder(&self) -> VMBuilder {
+        let mut builder = VMBuilder::new().endianness(self.endianness);
+        for filename in &self.filenames {
+            builder = builder.program(filename);
+        }
+        if let Some(format) = self.format {
+            builder = builder.format(format);
+        }
+
+        builder
+    }
+}
+
+/// Everything that can be set on the command line of a run
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub strict_load: bool,
+    pub allow_wrap: bool,
+    pub load: Load,
+    pub entry: Option<u16>,
+    pub seed: Option<u64>,
+    pub limit: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub breakpoints: Vec<u16>,
+    pub detect_loops: bool,
+    pub stack: Option<StackBounds>,
+    pub ext_traps: bool,
+    pub predecode: bool,
+    pub exit_r0: bool,
+    pub deterministic: Option<String>,
+    pub stdin_file: Option<String>,
+    pub eof_policy: EofPolicy,
+    pub input_timeout: Option<Duration>,
+    pub poll_interval: Option<u64>,
+    pub timeout_char: Option<u8>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub no_prompt: bool,
+    pub echo_getc: bool,
+    pub echo_kbdr: bool,
+    pub quiet: bool,
+    pub sanitize: Option<Sanitize>,
+    pub trace: bool,
+    pub trace_traps: bool,
+    pub trace_traps_file: Option<String>,
+    pub dump_regs: bool,
+    pub stats: bool,
+    pub profile: bool,
+    pub coverage: Option<String>,
+    pub dump: Option<(RangeInclusive<u16>, String)>,
+    pub json: bool,
+    pub json_out: Option<String>,
+    pub capture_output: bool,
+    pub load_state: Option<String>,
+    pub save_state: Option<String>,
+    pub expect_output: Option<String>,
+    pub trailing_newlines: TrailingNewlines,
+}
+
+impl Options {
+    /// A builder with every option that doesn't involve opening files or the terminal
+    pub fn builder(&self) -> VMBuilder {
+        let mut builder = self
+            .load
+            .builder()
+            .strict_load(self.strict_load)
+            .allow_wrap(self.allow_wrap)
+            .profiling(self.profile)
+            .predecode(self.predecode)
+            .loop_detection(self.detect_loops.then_some(DEFAULT_LOOP_THRESHOLD))
+            .record(self.record.is_some())
+            .coverage(self.coverage.is_some())
+            .call_tracking(true)
+            .stack_bounds(self.stack)
+            .eof_policy(self.eof_policy)
+            .input_timeout(self.input_timeout)
+            .timeout_sentinel(self.timeout_char)
+            .extension_traps(self.ext_traps)
+            .getc_echo(self.echo_getc)
+            .kbdr_echo(self.echo_kbdr);
+
+        if let Some(interval) = self.poll_interval {
+            builder = builder.poll_interval(interval);
+        }
+        if let Some(sanitize) = self.sanitize {
+            builder = builder.sanitize(sanitize);
+        }
+        if let Some(entry) = self.entry {
+            builder = builder.reset_vector(entry);
+        }
+        if let Some(seed) = self.seed {
+            builder = builder.seed(seed);
+        }
+        if let Some(limit) = self.limit {
+            builder = builder.limit(limit);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.time_limit(timeout);
+        }
+        for &address in &self.breakpoints {
+            builder = builder.breakpoint(address);
+        }
+        if self.quiet {
+            builder = builder.quiet(true);
+        }
+        if self.no_prompt {
+            builder = builder.in_prompt(None);
+        }
+
+        builder
+    }
+}
+
+/// Parses a hex address such as `0x3000`, `x3000` or `3000`
+pub fn parse_address(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('x'))
+        .unwrap_or(text);
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Parses a count such as `1000`, `10k`, `1M` or `2G`
+pub fn parse_count(text: &str) -> Option<u64> {
+    let (digits, scale) = match text.char_indices().last() {
+        Some((at, 'k')) => (text.get(..at)?, 1_000),
+        Some((at, 'M')) => (text.get(..at)?, 1_000_000),
+        Some((at, 'G')) => (text.get(..at)?, 1_000_000_000),
+        _ => (text, 1),
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+/// Parses a duration such as `500ms`, `10s` or `2m`, a bare number being seconds
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let (digits, unit): (&str, fn(u64) -> Option<Duration>) =
+        if let Some(ms) = text.strip_suffix("ms") {
+            (ms, |n| Some(Duration::from_millis(n)))
+        } else if let Some(minutes) = text.strip_suffix('m') {
+            (minutes, |n| n.checked_mul(60).map(Duration::from_secs))
+        } else {
+            (text.strip_suffix('s').unwrap_or(text), |n| {
+                Some(Duration::from_secs(n))
+            })
+        };
+
+    digits.parse::<u64>().ok().and_then(unit)
+}
+
+/// The value following a flag, or the error `expected` if there is none
+fn value(args: &mut impl Iterator<Item = String>, expected: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| expected.to_string())
+}
+
+/// The value following a flag, converted with `parse`
+fn parsed<T>(
+    args: &mut impl Iterator<Item = String>,
+    expected: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<T, String> {
+    let text = value(args, expected)?;
+    parse(&text).ok_or_else(|| format!("{}, got {:?}", expected, text))
+}
+
+/// Parses the arguments without the program name
+///
+/// The first argument picks the command, a path or a flag running the programs.
+///
+/// # Errors
+/// Returns a message naming the flag that is unknown, lacks its value or got a malformed one
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    let command = args.peek().cloned();
+
+    match command.as_deref() {
+        Some("run") => parse_run(args.skip(1)),
+        Some("debug") => parse_debug(args.skip(1)),
+        Some("asm") => parse_asm(args.skip(1)),
+        Some("disasm") => parse_programs(args.skip(1), DISASM_HELP, Command::Disasm),
+        Some("inspect") => parse_programs(args.skip(1), INSPECT_HELP, Command::Inspect),
+        Some("batch") => parse_batch(args.skip(1)),
+        Some("help") => Ok(Command::Help(match args.nth(1).as_deref() {
+            Some("run") => RUN_HELP,
+            Some("debug") => DEBUG_HELP,
+            Some("asm") => ASM_HELP,
+            Some("disasm") => DISASM_HELP,
+            Some("inspect") => INSPECT_HELP,
+            Some("batch") => BATCH_HELP,
+            Some(other) => return Err(format!("Unknown command {}", other)),
+            None => HELP,
+        })),
+        Some("-h" | "--help") => Ok(Command::Help(HELP)),
+        _ => parse_run(args),
+    }
+}
+
+/// Parses a command that only reads programs, taking no flags besides the loading flags
+fn parse_programs(
+    args: impl IntoIterator<Item = String>,
+    help: &'static str,
+    command: fn(Load) -> Command,
+) -> Result<Command, String> {
+    let mut load = Load::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if load.flag(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(help)),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ => load.filenames.push(arg),
+        }
+    }
+
+    if load.filenames.is_empty() {
+        return Err("No program given".to_string());
+    }
+
+    Ok(command(load))
+}
+
+/// Parses the arguments of `debug`
+fn parse_debug(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut load = Load::default();
+    let mut tui = false;
+    let mut script: Option<String> = None;
+    let mut undo_depth = DEFAULT_UNDO_DEPTH;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if load.flag(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(DEBUG_HELP)),
+            "--undo-depth" => {
+                undo_depth = parsed(
+                    &mut args,
+                    "--undo-depth expects a number of instructions such as 1000, 0 for none",
+                    |text| parse_count(text).and_then(|count| usize::try_from(count).ok()),
+                )?
+            }
+            "--tui" => tui = true,
+            "--script" => {
+                script = Some(value(
+                    &mut args,
+                    "--script expects a file with debugger commands",
+                )?)
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ => load.filenames.push(arg),
+        }
+    }
+
+    if load.filenames.is_empty() {
+        return Err("No program given".to_string());
+    }
+    if tui && script.is_some() {
+        return Err("--script runs without the full-screen debugger, drop --tui".to_string());
+    }
+
+    Ok(Command::Debug {
+        load,
+        tui,
+        script,
+        undo_depth,
+    })
+}
+
+/// Parses the arguments of `batch`
+fn parse_batch(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut batch = Batch::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(BATCH_HELP)),
+            "--limit" => {
+                batch.limit = Some(parsed(
+                    &mut args,
+                    "--limit expects a number of instructions such as 5000 or 1M",
+                    parse_count,
+                )?)
+            }
+            "--timeout" => {
+                batch.timeout = Some(parsed(
+                    &mut args,
+                    "--timeout expects a duration such as 500ms, 10s or 2m",
+                    parse_duration,
+                )?)
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ => batch.dirs.push(arg),
+        }
+    }
+
+    if batch.dirs.is_empty() {
+        return Err("No directory given".to_string());
+    }
+
+    Ok(Command::Batch(batch))
+}
+
+/// Parses the arguments of `asm`
+fn parse_asm(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut input: Option<String> = None;
+    let mut output: Option<String> = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(ASM_HELP)),
+            "-o" => {
+                output = Some(value(
+                    &mut args,
+                    "-o expects a path to write the object file to",
+                )?)
+            }
+            flag if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+            _ if input.is_some() => return Err("asm takes a single source file".to_string()),
+            _ => input = Some(arg),
+        }
+    }
+
+    match input {
+        Some(input) => Ok(Command::Asm { input, output }),
+        None => Err("No source file to assemble".to_string()),
+    }
+}
+
+/// Parses the arguments of `run`
+fn parse_run(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let args = &mut args;
+        if options.load.flag(&arg, args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(RUN_HELP)),
+            "--strict-load" => options.strict_load = true,
+            "--allow-wrap" => options.allow_wrap = true,
+            "--entry" => {
+                options.entry = Some(parsed(
+                    args,
+                    "--entry expects a hex address such as 0x3000",
+                    parse_address,
+                )?)
+            }
+            "--seed" => {
+                options.seed = Some(parsed(args, "--seed expects a number", |n| {
+                    n.parse::<u64>().ok()
+                })?)
+            }
+            "--limit" => {
+                options.limit = Some(parsed(
+                    args,
+                    "--limit expects a number of instructions such as 5000 or 1M",
+                    parse_count,
+                )?)
+            }
+            "--timeout" => {
+                options.timeout = Some(parsed(
+                    args,
+                    "--timeout expects a duration such as 500ms, 10s or 2m",
+                    parse_duration,
+                )?)
+            }
+            "--break" => options.breakpoints.push(parsed(
+                args,
+                "--break expects a hex address such as 0x3000",
+                parse_address,
+            )?),
+            "--detect-loops" => options.detect_loops = true,
+            "--stack" => {
+                options.stack = Some(parsed(
+                    args,
+                    "--stack expects the top and bottom of the stack such as 0xFDFF:0xFA00",
+                    |bounds| bounds.parse::<StackBounds>().ok(),
+                )?)
+            }
+            "--ext-traps" => options.ext_traps = true,
+            "--predecode" => options.predecode = true,
+            "--exit-r0" => options.exit_r0 = true,
+            "--deterministic" => {
+                options.deterministic = Some(value(
+                    args,
+                    "--deterministic expects a file with the keyboard input",
+                )?)
+            }
+            "--stdin-file" => {
+                options.stdin_file = Some(value(
+                    args,
+                    "--stdin-file expects a file with the keyboard input",
+                )?)
+            }
+            "--eof" => {
+                options.eof_policy = parsed(
+                    args,
+                    "--eof expects one of error, zero, halt or hang",
+                    |policy| match policy {
+                        "error" => Some(EofPolicy::Error),
+                        "zero" => Some(EofPolicy::Zero),
+                        "halt" => Some(EofPolicy::Halt),
+                        "hang" => Some(EofPolicy::Hang),
+                        _ => None,
+                    },
+                )?
+            }
+            "--input-timeout" => {
+                options.input_timeout = Some(parsed(
+                    args,
+                    "--input-timeout expects a number of milliseconds",
+                    |ms| ms.parse::<u64>().ok().map(Duration::from_millis),
+                )?)
+            }
+            "--poll-interval" => {
+                options.poll_interval = Some(parsed(
+                    args,
+                    "--poll-interval expects a number of instructions such as 500",
+                    parse_count,
+                )?)
+            }
+            "--timeout-char" => {
+                options.timeout_char = Some(parsed(
+                    args,
+                    "--timeout-char expects a character code below 0x100 such as 0xFF",
+                    |c| parse_address(c).and_then(|c| u8::try_from(c).ok()),
+                )?)
+            }
+            "--record" => {
+                options.record = Some(value(
+                    args,
+                    "--record expects a path to write the session to",
+                )?)
+            }
+            "--replay" => {
+                options.replay = Some(value(
+                    args,
+                    "--replay expects a path to a recorded session",
+                )?)
+            }
+            "--no-prompt" => options.no_prompt = true,
+            "--echo-getc" => options.echo_getc = true,
+            "--echo-kbdr" => options.echo_kbdr = true,
+            "--quiet" => options.quiet = true,
+            "--sanitize" => {
+                options.sanitize = Some(parsed(
+                    args,
+                    "--sanitize expects one of off, dot or hex",
+                    |mode| match mode {
+                        "off" => Some(Sanitize::Off),
+                        "dot" => Some(Sanitize::Dot),
+                        "hex" => Some(Sanitize::Hex),
+                        _ => None,
+                    },
+                )?)
+            }
+            "--trace" => options.trace = true,
+            "--trace-traps" => options.trace_traps = true,
+            "--trace-traps-file" => {
+                options.trace_traps_file = Some(value(
+                    args,
+                    "--trace-traps-file expects a path to write the trap log to",
+                )?)
+            }
+            "--dump-regs" => options.dump_regs = true,
+            "--profile" => options.profile = true,
+            "--stats" => options.stats = true,
+            "--coverage" => {
+                options.coverage = Some(value(
+                    args,
+                    "--coverage expects a path to write the report to",
+                )?)
+            }
+            "--dump-obj" => {
+                const EXPECTED: &str =
+                    "--dump-obj expects a start address, an end address and an output path";
+                let start = parsed(args, EXPECTED, parse_address)?;
+                let end = parsed(args, EXPECTED, parse_address)?;
+                let path = value(args, EXPECTED)?;
+                if start > end {
+                    return Err(format!(
+                        "--dump-obj got a start address x{:04X} after the end address x{:04X}",
+                        start, end
+                    ));
+                }
+                options.dump = Some((start..=end, path));
+            }
+            "--json" | "--json-out" if !cfg!(feature = "serde") => {
+                return Err(format!("{} needs lc3-vm built with the serde feature", arg))
+            }
+            "--json" => options.json = true,
+            "--json-out" => {
+                options.json = true;
+                options.json_out = Some(value(
+                    args,
+                    "--json-out expects a path to write the report to",
+                )?)
+            }
+            "--capture-output" => options.capture_output = true,
+            "--load-state" | "--save-state" if !cfg!(feature = "serde") => {
+                return Err(format!("{} needs lc3-vm built with the serde feature", arg))
+            }
+            "--load-state" => {
+                options.load_state = Some(value(
+                    args,
+                    "--load-state expects a state file to start from",
+                )?)
+            }
+            "--save-state" => {
+                options.save_state = Some(value(
+                    args,
+                    "--save-state expects a path to write the state file to",
+                )?)
+            }
+            "--expect-output" => {
+                options.expect_output = Some(value(
+                    args,
+                    "--expect-output expects a file with the output the program should print",
+                )?)
+            }
+            "--trailing-newlines" => {
+                options.trailing_newlines = parsed(
+                    args,
+                    "--trailing-newlines expects strict or ignore",
+                    |mode| match mode {
+                        "strict" => Some(TrailingNewlines::Strict),
+                        "ignore" => Some(TrailingNewlines::Ignore),
+                        _ => None,
+                    },
+                )?
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ => options.load.filenames.push(arg),
+        }
+    }
+
+    if options.load.filenames.is_empty() && options.load_state.is_none() {
+        return Err("No program to run".to_string());
+    }
+    let keyboards = [&options.deterministic, &options.stdin_file, &options.replay];
+    if keyboards.iter().filter(|file| file.is_some()).count() > 1 {
+        return Err(
+            "Only one of --deterministic, --stdin-file and --replay can provide the keyboard input"
+                .to_string(),
+        );
+    }
+    if options.timeout_char.is_some() && options.input_timeout.is_none() {
+        return Err("--timeout-char needs --input-timeout".to_string());
+    }
+    if options.capture_output && !options.json {
+        return Err("--capture-output needs --json".to_string());
+    }
+
+    Ok(Command::Run(Box::new(options)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str]) -> Result<Options, String> {
+        match parse(args.iter().map(|arg| arg.to_string()))? {
+            Command::Run(options) => Ok(*options),
+            other => Err(format!("{:?}", other)),
+        }
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("0x4000"), Some(0x4000));
+        assert_eq!(parse_address("x3000"), Some(0x3000));
+        assert_eq!(parse_address("FE00"), Some(0xFE00));
+        assert_eq!(parse_address("0x10000"), None);
+        assert_eq!(parse_address("start"), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("10s"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("3"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_duration("1h"), None);
+        assert_eq!(parse_duration("ms"), None);
+    }
+
+    #[test]
+    fn test_program_only() -> Result<(), String> {
+        let options = run(&["prog.obj"])?;
+
+        assert_eq!(
+            options,
+            Options {
+                load: Load {
+                    filenames: vec!["prog.obj".to_string()],
+                    ..Load::default()
+                },
+                ..Options::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flags() -> Result<(), String> {
+        let options = run(&[
+            "--trace",
+            "--trace-traps",
+            "--limit",
+            "100",
+            "--break",
+            "x3002",
+            "--break",
+            "0x3010",
+            "--entry",
+            "3001",
+            "--seed",
+            "7",
+            "--quiet",
+            "--timeout",
+            "250ms",
+            "--dump-regs",
+            "--stats",
+            "a.obj",
+            "b.obj",
+        ])?;
+
+        assert!(options.trace && options.trace_traps && options.quiet && options.dump_regs);
+        assert!(options.stats);
+        assert_eq!(options.limit, Some(100));
+        assert_eq!(options.breakpoints, [0x3002, 0x3010]);
+        assert_eq!(options.entry, Some(0x3001));
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.timeout, Some(Duration::from_millis(250)));
+        assert_eq!(options.load.filenames, ["a.obj", "b.obj"]);
+
+        Ok(())
+    }
+
+    fn command(args: &[&str]) -> Result<Command, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_help() {
+        assert_eq!(
+            command(&["prog.obj", "--help"]),
+            Ok(Command::Help(RUN_HELP))
+        );
+        assert_eq!(command(&["-h"]), Ok(Command::Help(HELP)));
+        assert_eq!(command(&["help"]), Ok(Command::Help(HELP)));
+        assert_eq!(command(&["help", "disasm"]), Ok(Command::Help(DISASM_HELP)));
+        assert_eq!(command(&["asm", "--help"]), Ok(Command::Help(ASM_HELP)));
+        assert_eq!(command(&["inspect", "-h"]), Ok(Command::Help(INSPECT_HELP)));
+        assert_eq!(
+            command(&["help", "frobnicate"]),
+            Err("Unknown command frobnicate".to_string())
+        );
+    }
+
+    #[test]
+    fn test_run_is_the_default_command() -> Result<(), String> {
+        assert_eq!(
+            command(&["run", "--limit", "5", "prog.obj"])?,
+            command(&["--limit", "5", "prog.obj"])?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_program_commands() {
+        let load = Load {
+            endianness: Endianness::Little,
+            symbols_file: Some("prog.sym".to_string()),
+            filenames: vec!["prog.obj".to_string()],
+            ..Load::default()
+        };
+
+        assert_eq!(
+            command(&["disasm", "--le", "--symbols", "prog.sym", "prog.obj"]),
+            Ok(Command::Disasm(load))
+        );
+        assert!(matches!(
+            command(&["debug", "--tui", "prog.obj"]),
+            Ok(Command::Debug { load: Load { filenames, .. }, tui: true, script: None, undo_depth: DEFAULT_UNDO_DEPTH })
+                if filenames == ["prog.obj"]
+        ));
+        assert!(matches!(
+            command(&["debug", "--undo-depth", "0", "prog.obj"]),
+            Ok(Command::Debug { undo_depth: 0, .. })
+        ));
+        assert_eq!(
+            command(&["debug", "--undo-depth", "-1", "prog.obj"]),
+            Err(
+                "--undo-depth expects a number of instructions such as 1000, 0 for none, got \"-1\""
+                    .to_string()
+            )
+        );
+        assert!(matches!(
+            command(&["debug", "--script", "cmds.txt", "prog.obj"]),
+            Ok(Command::Debug { tui: false, script: Some(script), .. }) if script == "cmds.txt"
+        ));
+        assert_eq!(
+            command(&["debug", "--tui", "--script", "cmds.txt", "prog.obj"]),
+            Err("--script runs without the full-screen debugger, drop --tui".to_string())
+        );
+        assert!(matches!(
+            command(&["inspect", "--format", "hex", "prog.hex"]),
+            Ok(Command::Inspect(Load {
+                format: Some(Format::Hex),
+                ..
+            }))
+        ));
+        assert_eq!(
+            command(&["disasm", "--limit", "5", "prog.obj"]),
+            Err("Unknown option --limit".to_string())
+        );
+        assert_eq!(command(&["inspect"]), Err("No program given".to_string()));
+    }
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("1500"), Some(1500));
+        assert_eq!(parse_count("10k"), Some(10_000));
+        assert_eq!(parse_count("1M"), Some(1_000_000));
+        assert_eq!(parse_count("2G"), Some(2_000_000_000));
+        assert_eq!(parse_count("M"), None);
+        assert_eq!(parse_count("1m"), None);
+        assert_eq!(parse_count("99999999999G"), None);
+    }
+
+    #[test]
+    fn test_batch_command() {
+        assert_eq!(
+            command(&["batch", "tests/objs", "--limit", "1M", "--timeout", "2s"]),
+            Ok(Command::Batch(Batch {
+                dirs: vec!["tests/objs".to_string()],
+                limit: Some(1_000_000),
+                timeout: Some(Duration::from_secs(2)),
+            }))
+        );
+        assert_eq!(
+            command(&["batch", "--limit", "1M"]),
+            Err("No directory given".to_string())
+        );
+    }
+
+    #[test]
+    fn test_asm_command() {
+        assert_eq!(
+            command(&["asm", "prog.asm", "-o", "out.obj"]),
+            Ok(Command::Asm {
+                input: "prog.asm".to_string(),
+                output: Some("out.obj".to_string()),
+            })
+        );
+        assert_eq!(
+            command(&["asm", "a.asm", "b.asm"]),
+            Err("asm takes a single source file".to_string())
+        );
+        assert_eq!(
+            command(&["asm", "prog.asm", "-o"]),
+            Err("-o expects a path to write the object file to".to_string())
+        );
+        assert_eq!(
+            command(&["asm"]),
+            Err("No source file to assemble".to_string())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            run(&["--entry", "0xZZ", "prog.obj"]),
+            Err("--entry expects a hex address such as 0x3000, got \"0xZZ\"".to_string())
+        );
+        assert_eq!(
+            run(&["prog.obj", "--break"]),
+            Err("--break expects a hex address such as 0x3000".to_string())
+        );
+        assert_eq!(
+            run(&["--limit", "-5", "prog.obj"]),
+            Err(
+                "--limit expects a number of instructions such as 5000 or 1M, got \"-5\""
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            run(&["--tracee", "prog.obj"]),
+            Err("Unknown option --tracee".to_string())
+        );
+        assert_eq!(run(&["--quiet"]), Err("No program to run".to_string()));
+        assert_eq!(
+            run(&["--dump-obj", "x3010", "x3000", "out.obj", "prog.obj"]),
+            Err("--dump-obj got a start address x3010 after the end address x3000".to_string())
+        );
+    }
+
+    #[test]
+    fn test_conflicting_flags() {
+        assert_eq!(
+            run(&[
+                "--deterministic",
+                "keys.txt",
+                "--replay",
+                "run.log",
+                "prog.obj"
+            ]),
+            Err(
+                "Only one of --deterministic, --stdin-file and --replay can provide the keyboard input"
+                    .to_string()
+            )
+        );
+        assert!(run(&["--stdin-file", "in.txt", "--replay", "run.log", "prog.obj"]).is_err());
+        assert_eq!(
+            run(&["--timeout-char", "0xFF", "prog.obj"]),
+            Err("--timeout-char needs --input-timeout".to_string())
+        );
+        assert_eq!(
+            run(&["--capture-output", "prog.obj"]),
+            Err("--capture-output needs --json".to_string())
+        );
+        assert!(run(&[
+            "--input-timeout",
+            "10",
+            "--timeout-char",
+            "0xFF",
+            "prog.obj"
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn test_expected_output_flags() -> Result<(), String> {
+        let options = run(&[
+            "--stdin-file",
+            "in.txt",
+            "--expect-output",
+            "out.txt",
+            "--trailing-newlines",
+            "strict",
+            "prog.obj",
+        ])?;
+
+        assert_eq!(options.stdin_file.as_deref(), Some("in.txt"));
+        assert_eq!(options.expect_output.as_deref(), Some("out.txt"));
+        assert_eq!(options.trailing_newlines, TrailingNewlines::Strict);
+        assert_eq!(
+            run(&["--trailing-newlines", "some", "prog.obj"]),
+            Err("--trailing-newlines expects strict or ignore, got \"some\"".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_flags() -> Result<(), String> {
+        let options = run(&["--json-out", "report.json", "--capture-output", "prog.obj"])?;
+
+        assert!(options.json && options.capture_output);
+        assert_eq!(options.json_out.as_deref(), Some("report.json"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_flags() -> Result<(), String> {
+        // The state file stands in for the programs
+        let options = run(&["--load-state", "in.json", "--save-state", "out.json"])?;
+
+        assert_eq!(options.load_state.as_deref(), Some("in.json"));
+        assert_eq!(options.save_state.as_deref(), Some("out.json"));
+        assert!(options.load.filenames.is_empty());
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "serde"))]
+    #[test]
+    fn test_json_needs_serde() {
+        assert_eq!(
+            run(&["--json", "prog.obj"]),
+            Err("--json needs lc3-vm built with the serde feature".to_string())
+        );
+    }
+
+    #[test]
+    fn test_options_map_onto_builder() -> Result<(), Box<dyn std::error::Error>> {
+        let options = run(&[
+            "--break",
+            "0x3003",
+            "--entry",
+            "0x3001",
+            "--limit",
+            "50",
+            "examples/simple_add.obj",
+        ])?;
+        let mut vm = options
+            .builder()
+            .input(Box::new(std::io::empty()))
+            .build()
+            .map_err(|e| format!("{:?}", e))?;
+
+        assert_eq!(vm.pc(), 0x3001);
+        assert_eq!(
+            vm.run_program().map_err(|e| format!("{:?}", e))?,
+            lc3_vm::RunOutcome::Paused(lc3_vm::PauseReason::Breakpoint(0x3003))
+        );
+
+        Ok(())
+    }
+}
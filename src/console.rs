@@ -0,0 +1,527 @@
+//! The terminal a program's user types on
+//!
+//! [`Console`] hides the platform: [`UnixConsole`] drives a terminal through
+//! termios on Unix, [`CrosstermConsole`] through crossterm with the `crossterm`
+//! feature, which is what Windows uses, and [`StdConsole`] reads stdin as it is
+//! anywhere else.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::keyboard::InputSource;
+
+/// A terminal that can deliver keys as they are pressed
+pub trait Console {
+    /// Delivers every key as it's pressed, without echoing it
+    fn enable_raw_mode(&mut self) -> io::Result<()>;
+
+    /// Restores the settings from before [`Console::enable_raw_mode`]
+    fn disable_raw_mode(&mut self) -> io::Result<()>;
+
+    /// Returns the next key if one was pressed, without waiting
+    fn poll_key(&mut self) -> io::Result<Option<u8>>;
+
+    /// Waits for the next key
+    fn read_key(&mut self) -> io::Result<u8>;
+
+    /// Waits at most `timeout` for the next key, `None` if none was pressed
+    ///
+    /// Consoles that can't wait for a limited time wait for the next key.
+    fn read_key_timeout(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+        let _ = timeout;
+        self.read_key().map(Some)
+    }
+
+    /// Shows `bytes` on the terminal
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()>;
+}
+
+impl<C: Console + ?Sized> Console for Box<C> {
+    fn enable_raw_mode(&mut self) -> io::Result<()> {
+        (**self).enable_raw_mode()
+    }
+
+    fn disable_raw_mode(&mut self) -> io::Result<()> {
+        (**self).disable_raw_mode()
+    }
+
+    fn poll_key(&mut self) -> io::Result<Option<u8>> {
+        (**self).poll_key()
+    }
+
+    fn read_key(&mut self) -> io::Result<u8> {
+        (**self).read_key()
+    }
+
+    fn read_key_timeout(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+        (**self).read_key_timeout(timeout)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        (**self).write(bytes)
+    }
+}
+
+/// Input source reading the keys of a [`Console`]
+///
+/// Created with [`ConsoleInput::raw`] it keeps the console in raw mode until dropped.
+pub struct ConsoleInput<C: Console> {
+    console: C,
+    raw: bool,
+}
+
+impl<C: Console> ConsoleInput<C> {
+    /// Reads the keys of `console` in whatever mode it's in
+    pub fn new(console: C) -> Self {
+        Self {
+            console,
+            raw: false,
+        }
+    }
+
+    /// Switches `console` to raw mode and reads its keys, restoring the console when dropped
+    ///
+    /// # Errors
+    /// Fails if raw mode can't be enabled, leaving the console as it was
+    pub fn raw(mut console: C) -> io::Result<Self> {
+        console.enable_raw_mode()?;
+
+        Ok(Self { console, raw: true })
+    }
+}
+
+impl<C: Console> InputSource for ConsoleInput<C> {
+    fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+        self.console.poll_key()
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        self.console.read_key()
+    }
+
+    fn read_byte_timeout(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+        self.console.read_key_timeout(timeout)
+    }
+}
+
+impl<C: Console> Drop for ConsoleInput<C> {
+    fn drop(&mut self) {
+        if self.raw {
+            // Nothing left to do if the terminal went away
+            let _ = self.console.disable_raw_mode();
+        }
+    }
+}
+
+/// Console reading stdin and writing stdout with no control over the terminal
+///
+/// Raw mode does nothing and polling waits for the next key like reading,
+/// so it works on any platform and with piped input.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdConsole;
+
+impl StdConsole {
+    /// Reads stdin and writes stdout
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Console for StdConsole {
+    fn enable_raw_mode(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn disable_raw_mode(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn poll_key(&mut self) -> io::Result<Option<u8>> {
+        self.read_key().map(Some)
+    }
+
+    fn read_key(&mut self) -> io::Result<u8> {
+        let mut buffer = [0; 1];
+        io::stdin().read_exact(&mut buffer)?;
+
+        Ok(u8::from_be_bytes(buffer))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(bytes)?;
+        stdout.flush()
+    }
+}
+
+/// Returns the console on stdin if stdin is a terminal that supports raw mode
+pub fn stdin_console() -> Option<Box<dyn Console>> {
+    #[cfg(unix)]
+    if io::IsTerminal::is_terminal(&io::stdin()) {
+        return Some(Box::new(UnixConsole::new(libc::STDIN_FILENO)));
+    }
+    #[cfg(all(not(unix), feature = "crossterm"))]
+    if io::IsTerminal::is_terminal(&io::stdin()) {
+        return Some(Box::new(CrosstermConsole::new()));
+    }
+
+    None
+}
+
+#[cfg(feature = "crossterm")]
+pub use self::crossterm::CrosstermConsole;
+
+#[cfg(feature = "crossterm")]
+mod crossterm {
+    use super::Console;
+    use std::io::{self, Write};
+    use std::time::{Duration, Instant};
+
+    use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+    use crossterm::terminal;
+
+    /// Console on the terminal of the process through crossterm, written to through stdout
+    ///
+    /// Keys are read as key events and turned into the bytes a Unix terminal
+    /// sends for them, keys with no ASCII byte are skipped.
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct CrosstermConsole;
+
+    impl CrosstermConsole {
+        /// Reads the keys of the terminal of the process
+        pub fn new() -> Self {
+            Self
+        }
+
+        /// Waits at most `timeout` for a key event, the byte of the key if it has one
+        fn next_key(timeout: Duration) -> io::Result<Option<u8>> {
+            let deadline = Instant::now().checked_add(timeout);
+            loop {
+                let left = deadline.map_or(timeout, |deadline| {
+                    deadline.saturating_duration_since(Instant::now())
+                });
+                if !event::poll(left)? {
+                    return Ok(None);
+                }
+                if let Event::Key(key) = event::read()? {
+                    if let Some(byte) = key_byte(key) {
+                        return Ok(Some(byte));
+                    }
+                }
+            }
+        }
+    }
+
+    /// The byte a terminal sends for `key`, `None` for releases and keys without one
+    pub(super) fn key_byte(key: KeyEvent) -> Option<u8> {
+        if key.kind == KeyEventKind::Release {
+            return None;
+        }
+        let byte = match key.code {
+            KeyCode::Char(c) => u8::try_from(c).ok().filter(u8::is_ascii)?,
+            KeyCode::Enter => b'\n',
+            KeyCode::Tab => b'\t',
+            KeyCode::Backspace => 0x7F,
+            KeyCode::Esc => 0x1B,
+            _ => return None,
+        };
+        match key.modifiers.contains(KeyModifiers::CONTROL) && byte.is_ascii_alphabetic() {
+            // Ctrl-A is x01 up to Ctrl-Z at x1A
+            true => Some(byte & 0x1F),
+            false => Some(byte),
+        }
+    }
+
+    impl Console for CrosstermConsole {
+        fn enable_raw_mode(&mut self) -> io::Result<()> {
+            terminal::enable_raw_mode()
+        }
+
+        fn disable_raw_mode(&mut self) -> io::Result<()> {
+            terminal::disable_raw_mode()
+        }
+
+        fn poll_key(&mut self) -> io::Result<Option<u8>> {
+            Self::next_key(Duration::ZERO)
+        }
+
+        fn read_key(&mut self) -> io::Result<u8> {
+            loop {
+                if let Event::Key(key) = event::read()? {
+                    if let Some(byte) = key_byte(key) {
+                        return Ok(byte);
+                    }
+                }
+            }
+        }
+
+        fn read_key_timeout(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+            Self::next_key(timeout)
+        }
+
+        fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+            let mut stdout = io::stdout();
+            stdout.write_all(bytes)?;
+            stdout.flush()
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use unix::UnixConsole;
+
+#[cfg(unix)]
+mod unix {
+    use super::Console;
+    use std::io::{self, Write};
+    use std::os::fd::RawFd;
+    use std::time::Duration;
+
+    use termios::{tcsetattr, Termios, ECHO, ICANON, TCSAFLUSH};
+
+    /// Console on a Unix terminal, such as stdin or `/dev/tty`, written to through stdout
+    ///
+    /// Polling never waits for a key. The terminal's settings are saved when the
+    /// console is created, so any copy of it can restore them.
+    #[derive(Debug, Clone, Copy)]
+    pub struct UnixConsole {
+        fd: RawFd,
+        /// Settings when the console was created, `None` if `fd` isn't a terminal
+        original: Option<Termios>,
+    }
+
+    impl UnixConsole {
+        /// Reads the keys of the terminal open on `fd`
+        pub fn new(fd: RawFd) -> Self {
+            Self {
+                fd,
+                original: Termios::from_fd(fd).ok(),
+            }
+        }
+
+        /// Waits at most `timeout_ms` for a key, -1 waiting as long as it takes
+        fn wait(&self, timeout_ms: libc::c_int) -> io::Result<bool> {
+            let mut poll = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            loop {
+                // SAFETY: `poll` points to exactly one pollfd
+                match unsafe { libc::poll(&mut poll, 1, timeout_ms) } {
+                    -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+                    -1 => return Err(io::Error::last_os_error()),
+                    ready => return Ok(ready > 0),
+                }
+            }
+        }
+    }
+
+    impl Console for UnixConsole {
+        fn enable_raw_mode(&mut self) -> io::Result<()> {
+            let mut raw = self.original.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::Unsupported, "the input is not a terminal")
+            })?;
+            raw.c_lflag &= !(ICANON | ECHO);
+
+            tcsetattr(self.fd, TCSAFLUSH, &raw)
+        }
+
+        fn disable_raw_mode(&mut self) -> io::Result<()> {
+            match &self.original {
+                Some(original) => tcsetattr(self.fd, TCSAFLUSH, original),
+                None => Ok(()),
+            }
+        }
+
+        fn poll_key(&mut self) -> io::Result<Option<u8>> {
+            if self.wait(0)? {
+                self.read_key().map(Some)
+            } else {
+                Ok(None)
+            }
+        }
+
+        fn read_key(&mut self) -> io::Result<u8> {
+            let mut byte: u8 = 0;
+            loop {
+                // SAFETY: reads at most one byte into `byte`
+                let read = unsafe { libc::read(self.fd, std::ptr::addr_of_mut!(byte).cast(), 1) };
+                match read {
+                    1 => return Ok(byte),
+                    0 => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "the terminal closed",
+                        ))
+                    }
+                    _ => {
+                        let error = io::Error::last_os_error();
+                        if error.kind() != io::ErrorKind::Interrupted {
+                            return Err(error);
+                        }
+                    }
+                }
+            }
+        }
+
+        fn read_key_timeout(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+            let timeout_ms = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+            if self.wait(timeout_ms)? {
+                self.read_key().map(Some)
+            } else {
+                Ok(None)
+            }
+        }
+
+        fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+            let mut stdout = io::stdout();
+            stdout.write_all(bytes)?;
+            stdout.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::Keyboard;
+    use crate::VMError;
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    /// Console with keys typed in advance, recording what was done to it
+    #[derive(Default)]
+    struct MockConsole {
+        keys: VecDeque<u8>,
+        raw: Rc<Cell<bool>>,
+        written: Vec<u8>,
+    }
+
+    impl Console for MockConsole {
+        fn enable_raw_mode(&mut self) -> io::Result<()> {
+            self.raw.set(true);
+            Ok(())
+        }
+
+        fn disable_raw_mode(&mut self) -> io::Result<()> {
+            self.raw.set(false);
+            Ok(())
+        }
+
+        fn poll_key(&mut self) -> io::Result<Option<u8>> {
+            Ok(self.keys.pop_front())
+        }
+
+        fn read_key(&mut self) -> io::Result<u8> {
+            self.keys
+                .pop_front()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+        }
+
+        fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.written.extend_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_console() -> io::Result<()> {
+        let mut console = MockConsole::default();
+        console.enable_raw_mode()?;
+        assert!(console.raw.get());
+        console.write(b"ok")?;
+        console.disable_raw_mode()?;
+
+        assert!(!console.raw.get());
+        assert_eq!(console.written, b"ok");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_console_input_restores_console() -> io::Result<()> {
+        let raw = Rc::new(Cell::new(false));
+        let input = ConsoleInput::raw(MockConsole {
+            raw: Rc::clone(&raw),
+            ..MockConsole::default()
+        })?;
+        assert!(raw.get());
+
+        drop(input);
+        assert!(!raw.get());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyboard_reads_console() -> Result<(), VMError> {
+        let console = MockConsole {
+            keys: VecDeque::from(b"ab".to_vec()),
+            ..MockConsole::default()
+        };
+        let mut keyboard = Keyboard::with_source(Box::new(ConsoleInput::new(console)));
+
+        keyboard.poll()?;
+        assert_eq!(keyboard.take_data(), u16::from(b'a'));
+        assert!(matches!(keyboard.read_byte(), Ok(b'b')));
+        keyboard.poll()?;
+        assert_eq!(keyboard.status(), 0);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "crossterm")]
+    #[test]
+    fn test_crossterm_keys_are_terminal_bytes() {
+        use ::crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        let bytes: Vec<Option<u8>> = [
+            key(KeyCode::Char('a'), KeyModifiers::NONE),
+            key(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            key(KeyCode::Enter, KeyModifiers::NONE),
+            key(KeyCode::Backspace, KeyModifiers::NONE),
+            key(KeyCode::Char('é'), KeyModifiers::NONE),
+            key(KeyCode::Left, KeyModifiers::NONE),
+        ]
+        .into_iter()
+        .map(self::crossterm::key_byte)
+        .collect();
+        assert_eq!(
+            bytes,
+            [Some(b'a'), Some(0x03), Some(b'\n'), Some(0x7F), None, None]
+        );
+
+        let mut release = key(KeyCode::Char('a'), KeyModifiers::NONE);
+        release.kind = KeyEventKind::Release;
+        assert_eq!(self::crossterm::key_byte(release), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_console_polls_without_waiting() -> io::Result<()> {
+        let mut fds = [0; 2];
+        // SAFETY: pipe writes two descriptors into `fds`
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let [read_end, write_end] = fds;
+        let mut console = UnixConsole::new(read_end);
+
+        assert!(console.poll_key()?.is_none());
+        // SAFETY: writes one byte from a live buffer, then closes the descriptor
+        unsafe {
+            libc::write(write_end, b"k".as_ptr().cast(), 1);
+            libc::close(write_end);
+        }
+        assert_eq!(console.poll_key()?, Some(b'k'));
+        assert!(console.read_key().is_err());
+
+        // SAFETY: the descriptor is no longer used
+        unsafe { libc::close(read_end) };
+
+        Ok(())
+    }
+}
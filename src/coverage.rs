@@ -0,0 +1,183 @@
+//! Which loaded instructions were executed
+
+use std::fmt;
+
+use crate::loader::LoadedProgram;
+use crate::memory::MEMORY_MAX;
+use crate::symbols::SymbolTable;
+
+const WORDS: usize = MEMORY_MAX / 64;
+
+/// One bit per address, set once the address was fetched
+#[derive(Debug, Clone)]
+pub(crate) struct Coverage {
+    bits: Box<[u64; WORDS]>,
+}
+
+impl Coverage {
+    pub(crate) fn new() -> Self {
+        Self {
+            bits: Box::new([0; WORDS]),
+        }
+    }
+
+    /// Marks `address` as executed
+    pub(crate) fn mark(&mut self, address: u16) {
+        if let Some(word) = self.bits.get_mut(usize::from(address >> 6)) {
+            *word |= 1 << (address & 63);
+        }
+    }
+
+    /// Returns true if `address` was executed
+    pub(crate) fn contains(&self, address: u16) -> bool {
+        self.bits
+            .get(usize::from(address >> 6))
+            .is_some_and(|word| word & (1 << (address & 63)) != 0)
+    }
+
+    /// Lists every address of the loaded programs with whether it was executed
+    pub(crate) fn report(
+        &self,
+        programs: &[LoadedProgram],
+        symbols: &SymbolTable,
+    ) -> CoverageReport {
+        let mut report = CoverageReport::default();
+
+        for program in programs {
+            let mut executed: u16 = 0;
+            for address in (0..program.len).map(|offset| program.origin.wrapping_add(offset)) {
+                let hit = self.contains(address);
+                if hit {
+                    executed = executed.saturating_add(1);
+                }
+                report.lines.push(CoverageLine {
+                    address,
+                    executed: hit,
+                    label: symbols.label_at(address).map(str::to_string),
+                });
+            }
+
+            report.segments.push(SegmentCoverage {
+                origin: program.origin,
+                len: program.len,
+                executed,
+            });
+        }
+
+        report
+    }
+}
+
+/// One loaded address in a [`CoverageReport`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageLine {
+    pub address: u16,
+    /// Whether the word was ever fetched as an instruction
+    pub executed: bool,
+    /// Label defined at the address, if symbols are loaded
+    pub label: Option<String>,
+}
+
+/// Summary of one loaded program in a [`CoverageReport`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentCoverage {
+    pub origin: u16,
+    pub len: u16,
+    /// Number of addresses of the program that were executed
+    pub executed: u16,
+}
+
+impl SegmentCoverage {
+    /// Returns the share of executed addresses, from 0 to 100
+    pub fn percent(&self) -> f64 {
+        if self.len == 0 {
+            return 100.0;
+        }
+
+        f64::from(self.executed) * 100.0 / f64::from(self.len)
+    }
+}
+
+/// Executed and never executed addresses of every loaded program
+///
+/// Data words such as `.FILL` and `.STRINGZ` are listed too and show up as
+/// never executed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CoverageReport {
+    pub lines: Vec<CoverageLine>,
+    pub segments: Vec<SegmentCoverage>,
+}
+
+impl CoverageReport {
+    /// Returns the addresses that were never executed
+    pub fn uncovered(&self) -> impl Iterator<Item = u16> + '_ {
+        self.lines
+            .iter()
+            .filter(|line| !line.executed)
+            .map(|line| line.address)
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    /// Prints `+` or `-` for every address, then the share covered per program
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            let marker = if line.executed { '+' } else { '-' };
+            match &line.label {
+                Some(label) => writeln!(f, "{} x{:04X} {}", marker, line.address, label)?,
+                None => writeln!(f, "{} x{:04X}", marker, line.address)?,
+            }
+        }
+
+        for segment in &self.segments {
+            writeln!(
+                f,
+                "x{:04X}: {}/{} addresses executed ({:.1}%)",
+                segment.origin,
+                segment.executed,
+                segment.len,
+                segment.percent()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_lines_and_summary() {
+        let mut coverage = Coverage::new();
+        coverage.mark(0x3000);
+        coverage.mark(0x3002);
+        coverage.mark(0x4000);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("SKIPPED", 0x3001);
+
+        let report = coverage.report(&[LoadedProgram::new(0x3000, 4)], &symbols);
+
+        assert_eq!(report.uncovered().collect::<Vec<_>>(), vec![0x3001, 0x3003]);
+        assert_eq!(
+            report.to_string(),
+            "+ x3000
+- x3001 SKIPPED
++ x3002
+- x3003
+x3000: 2/4 addresses executed (50.0%)
+"
+        );
+    }
+
+    #[test]
+    fn test_mark_last_address() {
+        let mut coverage = Coverage::new();
+        coverage.mark(0xFFFF);
+
+        assert!(coverage.contains(0xFFFF));
+        assert!(!coverage.contains(0xFFFE));
+    }
+}
@@ -0,0 +1,1244 @@
+//! The line debugger's commands, independent of where the lines come from
+//!
+//! [`DebugCommand`] parses one line such as `break x3010` or `mem x4000 16`
+//! and [`LineDebugger`] executes it against a VM, writing what it has to say
+//! to any `Write`. The prompt of `lc3-vm debug` and `--script` files both feed
+//! lines through [`LineDebugger::execute_line`].
+//!
+//! Conditions of `break ADDR if COND` and the values of `watch` use the
+//! grammar of [`Condition`] and [`Value`], where registers can also be named
+//! by the VM's [`RegisterAliases`], such as `sp` for R6.
+
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::breakpoints::{Condition, Value};
+use crate::disasm;
+use crate::registers::RegisterAliases;
+use crate::snapshot::Snapshot;
+use crate::{HaltReason, PauseReason, RunOutcome, VMError, VMState, VM};
+
+/// Instructions run per `run` or `continue`, so a program that never stops can't hang a script
+pub const RUN_CHUNK: u64 = 1_000_000;
+
+/// Words per line of `mem`
+const MEMORY_LINE_WORDS: usize = 8;
+
+/// Instructions `list` shows before the address it's given
+const LIST_BEFORE: u16 = 5;
+
+/// Instructions `list` shows after the address it's given
+const LIST_AFTER: u16 = 10;
+
+/// First address of user space, `poke` and `fill` need `--force` below it
+const USER_SPACE: u16 = 0x3000;
+
+/// First device register, `poke` and `fill` need `--force` from here on
+const DEVICE_REGISTERS: u16 = 0xFE00;
+
+/// Instructions `back` can undo unless `lc3-vm debug --undo-depth` says otherwise
+pub const DEFAULT_UNDO_DEPTH: usize = 10_000;
+
+/// Prompt of the interactive debugger, also echoed before each command of a script
+pub const PROMPT: &str = "(lc3) ";
+
+/// One command of the line debugger
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugCommand {
+    /// `break ADDR [if COND]`: stop before the instruction at ADDR, only while COND holds if given
+    Break(u16, Option<Condition>),
+    /// `watch [--break] VALUE`: print VALUE after every command that runs the program,
+    /// stopping the program when it changes with `--break`
+    Watch { value: Value, stop: bool },
+    /// `run`: start the program over from where it was loaded
+    Run,
+    /// `continue`: run on from where the program stopped
+    Continue,
+    /// `step [N]`: execute N instructions, 1 by default
+    Step(u64),
+    /// `back [N]`: undo the last N instructions, 1 by default
+    Back(u64),
+    /// `regs`: print the registers
+    Regs,
+    /// `mem ADDR [N]`: print N words from ADDR, 8 by default
+    Mem(u16, u16),
+    /// `set REG VALUE`: write R0-R7 or the PC
+    Set(Value, u16),
+    /// `poke [--force] ADDR VALUE`: write one word of memory
+    Poke {
+        address: u16,
+        value: u16,
+        force: bool,
+    },
+    /// `fill [--force] START END VALUE`: write VALUE from START through END
+    Fill {
+        start: u16,
+        end: u16,
+        value: u16,
+        force: bool,
+    },
+    /// `save START END PATH`: write memory from START through END to an object file
+    Save { start: u16, end: u16, path: String },
+    /// `list [ADDR]`: disassemble around ADDR, the PC by default
+    List(Option<u16>),
+    /// `bt`: print the subroutine calls that haven't returned, innermost first
+    Backtrace,
+    /// `quit`: stop debugging
+    Quit,
+}
+
+/// Parses an address such as `x3010`, `0x3010` or `3010`
+fn address(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('x'))
+        .unwrap_or(text);
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Parses a constant such as `0xBEEF`, `x10`, `#-1` or `5` for `what`
+fn constant(text: &str, what: &str) -> Result<u16, String> {
+    match text.parse() {
+        Ok(Value::Constant(value)) => Ok(value),
+        _ => Err(format!(
+            "{} expects a value such as xBEEF or #5, got {:?}",
+            what, text
+        )),
+    }
+}
+
+/// Whether writing `address` needs `--force`: system space and the device registers
+fn protected(address: u16) -> bool {
+    !(USER_SPACE..DEVICE_REGISTERS).contains(&address)
+}
+
+impl FromStr for DebugCommand {
+    type Err = String;
+
+    /// Parses a command, naming registers by the default [`RegisterAliases`] as well
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        DebugCommand::parse_with(line, &RegisterAliases::default())
+    }
+}
+
+impl DebugCommand {
+    /// Parses one line of the debugger, naming registers by `aliases` as well as R0-R7
+    ///
+    /// # Errors
+    /// Returns a message saying what the command expects
+    pub fn parse_with(line: &str, aliases: &RegisterAliases) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let arguments: Vec<&str> = words.collect();
+
+        let command = match (name, arguments.as_slice()) {
+            ("break" | "b", [at, condition @ ..])
+                if condition.is_empty() || condition.first() == Some(&"if") =>
+            {
+                let at =
+                    address(at).ok_or_else(|| format!("break expects an address, got {:?}", at))?;
+                let condition = match condition.get(1..) {
+                    Some(words) => Some(Condition::parse_with(&words.join(" "), aliases)?),
+                    None => None,
+                };
+                DebugCommand::Break(at, condition)
+            }
+            ("break" | "b", _) => {
+                return Err(
+                    "break expects an address such as x3010, then if and a condition".to_string(),
+                )
+            }
+            ("watch" | "w", [flag, rest @ ..]) if !rest.is_empty() || *flag != "--break" => {
+                let (stop, words) = match *flag {
+                    "--break" => (true, rest),
+                    _ => (false, arguments.as_slice()),
+                };
+                let value = Value::parse_with(&words.join(" "), aliases)?;
+                if let Value::Constant(_) = value {
+                    return Err("watch expects a register, pc, flags or mem[ADDR]".to_string());
+                }
+                DebugCommand::Watch { value, stop }
+            }
+            ("watch" | "w", _) => {
+                return Err("watch expects a register, pc, flags or mem[ADDR]".to_string())
+            }
+            ("run" | "r", []) => DebugCommand::Run,
+            ("continue" | "c", []) => DebugCommand::Continue,
+            ("step" | "s", []) => DebugCommand::Step(1),
+            ("step" | "s", [count]) => {
+                DebugCommand::Step(count.parse().map_err(|_| {
+                    format!("step expects a number of instructions, got {:?}", count)
+                })?)
+            }
+            ("back", []) => DebugCommand::Back(1),
+            ("back", [count]) => {
+                DebugCommand::Back(count.parse().map_err(|_| {
+                    format!("back expects a number of instructions, got {:?}", count)
+                })?)
+            }
+            ("regs", []) => DebugCommand::Regs,
+            ("mem", [start, rest @ ..]) if rest.len() <= 1 => {
+                let start = address(start)
+                    .ok_or_else(|| format!("mem expects an address, got {:?}", start))?;
+                let count = match rest.first() {
+                    Some(count) => count
+                        .parse()
+                        .map_err(|_| format!("mem expects a number of words, got {:?}", count))?,
+                    None => 8,
+                };
+                DebugCommand::Mem(start, count)
+            }
+            ("mem", _) => {
+                return Err(
+                    "mem expects an address and a number of words such as x4000 16".to_string(),
+                )
+            }
+            ("set", [target, value]) => match Value::parse_with(target, aliases) {
+                Ok(target @ (Value::Register(_) | Value::Pc)) => {
+                    DebugCommand::Set(target, constant(value, "set")?)
+                }
+                _ => {
+                    return Err(format!(
+                        "set expects R0-R7, a register alias or pc, got {:?}",
+                        target
+                    ))
+                }
+            },
+            ("set", _) => {
+                return Err("set expects a register and a value such as r0 xBEEF".to_string())
+            }
+            ("poke", words) => {
+                let (force, words) = match words {
+                    ["--force", rest @ ..] => (true, rest),
+                    _ => (false, words),
+                };
+                let [at, value] = words else {
+                    return Err(
+                        "poke expects an address and a value such as x4000 x1234".to_string()
+                    );
+                };
+                DebugCommand::Poke {
+                    address: address(at)
+                        .ok_or_else(|| format!("poke expects an address, got {:?}", at))?,
+                    value: constant(value, "poke")?,
+                    force,
+                }
+            }
+            ("fill", words) => {
+                let (force, words) = match words {
+                    ["--force", rest @ ..] => (true, rest),
+                    _ => (false, words),
+                };
+                let [start, end, value] = words else {
+                    return Err(
+                        "fill expects a start, an end and a value such as x4000 x4010 0"
+                            .to_string(),
+                    );
+                };
+                let start = address(start)
+                    .ok_or_else(|| format!("fill expects a start address, got {:?}", start))?;
+                let end = address(end)
+                    .ok_or_else(|| format!("fill expects an end address, got {:?}", end))?;
+                if start > end {
+                    return Err(format!(
+                        "fill got a start address x{:04X} after the end address x{:04X}",
+                        start, end
+                    ));
+                }
+                DebugCommand::Fill {
+                    start,
+                    end,
+                    value: constant(value, "fill")?,
+                    force,
+                }
+            }
+            ("save", [start, end, path]) => {
+                let start = address(start)
+                    .ok_or_else(|| format!("save expects a start address, got {:?}", start))?;
+                let end = address(end)
+                    .ok_or_else(|| format!("save expects an end address, got {:?}", end))?;
+                if start > end {
+                    return Err(format!(
+                        "save got a start address x{:04X} after the end address x{:04X}",
+                        start, end
+                    ));
+                }
+                DebugCommand::Save {
+                    start,
+                    end,
+                    path: path.to_string(),
+                }
+            }
+            ("save", _) => {
+                return Err(
+                    "save expects a start, an end and a path such as x3000 x30FF out.obj"
+                        .to_string(),
+                )
+            }
+            ("list" | "l", []) => DebugCommand::List(None),
+            ("list" | "l", [at]) => DebugCommand::List(Some(
+                address(at).ok_or_else(|| format!("list expects an address, got {:?}", at))?,
+            )),
+            ("list" | "l", _) => return Err("list got too many arguments".to_string()),
+            ("bt", []) => DebugCommand::Backtrace,
+            ("quit" | "q", []) => DebugCommand::Quit,
+            ("run" | "r" | "continue" | "c" | "step" | "s" | "regs" | "bt" | "quit" | "q", _)
+            | ("back", _) => return Err(format!("{} got too many arguments", name)),
+            _ => return Err(format!("Unknown command {}", name)),
+        };
+
+        Ok(command)
+    }
+}
+
+/// A value printed after the commands that run the program
+struct Watch {
+    value: Value,
+    /// What it was when last printed
+    last: Option<u16>,
+    /// Stop the program when it changes
+    stop: bool,
+}
+
+/// Why `run` or `continue` stopped
+enum Stop {
+    Outcome(RunOutcome),
+    /// A `watch --break` value changed
+    Watch(Value),
+}
+
+/// State of the line debugger: the machine as loaded, for `run` to start over, and the watches
+pub struct LineDebugger {
+    loaded: Snapshot,
+    watches: Vec<Watch>,
+}
+
+impl LineDebugger {
+    /// Starts debugging `vm` as it is now, collecting the program's output to print it after each command
+    pub fn new(vm: &mut VM) -> Self {
+        vm.capture_output();
+
+        Self {
+            loaded: vm.snapshot(),
+            watches: Vec::new(),
+        }
+    }
+
+    /// Parses and executes one line, returning whether to go on
+    ///
+    /// Blank lines and lines starting with `#` do nothing. Parse errors and
+    /// commands that fail are returned as messages.
+    pub fn execute_line(
+        &mut self,
+        vm: &mut VM,
+        line: &str,
+        out: &mut dyn Write,
+    ) -> Result<bool, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(true);
+        }
+
+        let command = DebugCommand::parse_with(line, vm.register_aliases())?;
+        self.execute(vm, command, out)
+    }
+
+    /// Runs every line of `script`, echoing each command after the prompt before its output
+    ///
+    /// Stops at `quit` or at the first command that fails, whose error names its line.
+    pub fn run_script(
+        &mut self,
+        vm: &mut VM,
+        script: &str,
+        out: &mut dyn Write,
+    ) -> Result<(), String> {
+        for (number, line) in script.lines().enumerate() {
+            let command = line.trim();
+            if command.is_empty() || command.starts_with('#') {
+                continue;
+            }
+            writeln!(out, "{}{}", PROMPT, command).map_err(|e| e.to_string())?;
+            let go_on = self
+                .execute_line(vm, command, out)
+                .map_err(|message| format!("line {}: {}", number.saturating_add(1), message))?;
+            if !go_on {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Executes `command`, returning whether to go on
+    pub fn execute(
+        &mut self,
+        vm: &mut VM,
+        command: DebugCommand,
+        out: &mut dyn Write,
+    ) -> Result<bool, String> {
+        match command {
+            DebugCommand::Break(at, None) => {
+                vm.add_breakpoint(at);
+                writeln!(out, "Breakpoint at {}", vm.describe_address(at))
+            }
+            DebugCommand::Break(at, Some(condition)) => {
+                vm.add_conditional_breakpoint(at, condition);
+                writeln!(
+                    out,
+                    "Breakpoint at {} if {}",
+                    vm.describe_address(at),
+                    condition
+                )
+            }
+            DebugCommand::Watch { value, stop } => {
+                let last = vm.value(value);
+                self.watches.push(Watch { value, last, stop });
+                let when = if stop {
+                    ", stopping when it changes"
+                } else {
+                    ""
+                };
+                writeln!(out, "Watching {} = {}{}", value, show(value, last), when)
+            }
+            DebugCommand::Run => {
+                vm.restore(&self.loaded);
+                let stop = self.run_on(vm);
+                self.report(vm, stop, out)?;
+                self.print_watches(vm, out)
+            }
+            DebugCommand::Continue => {
+                if let VMState::Halted(_) = vm.state() {
+                    return Err("The program has halted, run starts it over".to_string());
+                }
+                let stop = self.run_on(vm);
+                self.report(vm, stop, out)?;
+                self.print_watches(vm, out)
+            }
+            DebugCommand::Step(count) => {
+                let before = self.stop_values(vm);
+                let mut changed = None;
+                for _ in 0..count {
+                    let stepped = vm.step().map_err(|e| describe_error(vm, &e));
+                    let flushed = vm.flush_output().map_err(|e| describe_error(vm, &e));
+                    print_output(vm, out)?;
+                    stepped?;
+                    flushed?;
+                    changed = first_change(vm, &before);
+                    if !matches!(vm.state(), VMState::Running) || changed.is_some() {
+                        break;
+                    }
+                }
+                if let Some(value) = changed {
+                    writeln!(out, "{} changed", value).map_err(|e| e.to_string())?;
+                }
+                match vm.state() {
+                    VMState::Halted(reason) => writeln!(out, "{}", describe_halt(reason)),
+                    VMState::Paused(PauseReason::Breakpoint(pc)) => {
+                        writeln!(out, "Breakpoint at {}", vm.describe_address(*pc))
+                    }
+                    _ => writeln!(out, "{}", next_instruction(vm)),
+                }
+                .and_then(|()| self.print_watches(vm, out))
+            }
+            DebugCommand::Back(count) => {
+                let mut undone: u64 = 0;
+                while undone < count && vm.step_back() {
+                    undone = undone.saturating_add(1);
+                }
+                if undone == 0 {
+                    return Err("Nothing to step back over: the undo journal is off or empty, \
+                         or the last instruction did I/O"
+                        .to_string());
+                }
+                if undone < count {
+                    writeln!(out, "Stepped back {} instructions, no further", undone)
+                        .map_err(|e| e.to_string())?;
+                }
+                writeln!(out, "{}", next_instruction(vm)).and_then(|()| self.print_watches(vm, out))
+            }
+            DebugCommand::Regs => write!(
+                out,
+                "{}",
+                vm.registers_snapshot().with_aliases(vm.register_aliases())
+            ),
+            DebugCommand::Mem(start, count) => {
+                let addresses: Vec<u16> = (0..count)
+                    .map(|offset| start.wrapping_add(offset))
+                    .collect();
+                addresses.chunks(MEMORY_LINE_WORDS).try_for_each(|line| {
+                    let words: Vec<String> = line
+                        .iter()
+                        .map(|&address| format!("{:04X}", vm.memory().read_ram(address)))
+                        .collect();
+                    let first = line.first().copied().unwrap_or(start);
+                    writeln!(out, "x{:04X}: {}", first, words.join(" "))
+                })
+            }
+            DebugCommand::Set(target, value) => {
+                let old = vm.value(target);
+                match target {
+                    Value::Pc => vm.set_pc_only(value),
+                    Value::Register(r) => vm.write_register(r, value),
+                    _ => return Err(format!("set can't write {}", target)),
+                }
+                writeln!(
+                    out,
+                    "{}: {} -> {}",
+                    target.name(vm.register_aliases()),
+                    show(target, old),
+                    show(target, Some(value))
+                )
+            }
+            DebugCommand::Poke {
+                address,
+                value,
+                force,
+            } => {
+                check_writable(address, address, force)?;
+                let cell = Value::Memory(address);
+                let old = vm.value(cell);
+                vm.write_memory(address, value)
+                    .map_err(|e| describe_error(vm, &e))?;
+                writeln!(
+                    out,
+                    "{}: {} -> {}",
+                    cell,
+                    show(cell, old),
+                    show(cell, Some(value))
+                )
+            }
+            DebugCommand::Fill {
+                start,
+                end,
+                value,
+                force,
+            } => {
+                check_writable(start, end, force)?;
+                for address in start..=end {
+                    vm.write_memory(address, value)
+                        .map_err(|e| describe_error(vm, &e))?;
+                }
+                writeln!(
+                    out,
+                    "Filled x{:04X}-x{:04X} ({} words) with x{:04X}",
+                    start,
+                    end,
+                    u32::from(end.wrapping_sub(start)).saturating_add(1),
+                    value
+                )
+            }
+            DebugCommand::Save { start, end, path } => {
+                vm.save_program(&path, start..=end)
+                    .map_err(|e| e.to_string())?;
+                writeln!(
+                    out,
+                    "Saved x{:04X}-x{:04X} ({} words) to {}",
+                    start,
+                    end,
+                    u32::from(end.wrapping_sub(start)).saturating_add(1),
+                    path
+                )
+            }
+            DebugCommand::List(at) => write!(out, "{}", listing(vm, at.unwrap_or(vm.pc()))),
+            DebugCommand::Backtrace => {
+                if !vm.backtrace_is_reliable() {
+                    writeln!(
+                        out,
+                        "The program returned somewhere other than the innermost call, this may be wrong"
+                    )
+                    .map_err(|e| e.to_string())?;
+                }
+                let backtrace = vm.backtrace();
+                if backtrace.is_empty() {
+                    writeln!(out, "No subroutine calls")
+                } else {
+                    backtrace
+                        .iter()
+                        .enumerate()
+                        .try_for_each(|(depth, frame)| writeln!(out, "#{} {}", depth, frame))
+                }
+            }
+            DebugCommand::Quit => return Ok(false),
+        }
+        .map_err(|e| e.to_string())?;
+
+        Ok(true)
+    }
+
+    /// Values of the watches that stop the program, as they are now
+    fn stop_values(&self, vm: &VM) -> Vec<(Value, Option<u16>)> {
+        self.watches
+            .iter()
+            .filter(|watch| watch.stop)
+            .map(|watch| (watch.value, vm.value(watch.value)))
+            .collect()
+    }
+
+    /// Runs until the program stops, one step at a time while a watch stops it when it changes
+    fn run_on(&self, vm: &mut VM) -> Result<Stop, VMError> {
+        let before = self.stop_values(vm);
+        if before.is_empty() {
+            return vm.run_with_limit(RUN_CHUNK).map(Stop::Outcome);
+        }
+
+        let result = step_until_change(vm, &before);
+        let flushed = vm.flush_output();
+        let stop = result?;
+        flushed?;
+
+        Ok(stop)
+    }
+
+    /// Prints what the program printed and why a run stopped
+    fn report(
+        &mut self,
+        vm: &mut VM,
+        stop: Result<Stop, VMError>,
+        out: &mut dyn Write,
+    ) -> Result<(), String> {
+        print_output(vm, out)?;
+        let message = match stop {
+            Ok(Stop::Outcome(RunOutcome::Halted(reason))) => describe_halt(&reason),
+            Ok(Stop::Outcome(RunOutcome::Paused(PauseReason::Breakpoint(pc)))) => {
+                format!("Breakpoint at {}", vm.describe_address(pc))
+            }
+            Ok(Stop::Outcome(RunOutcome::Paused(PauseReason::LimitReached { executed }))) => {
+                format!(
+                    "Still running after {} instructions, stopped at {}",
+                    executed,
+                    vm.describe_address(vm.pc())
+                )
+            }
+            Ok(Stop::Outcome(RunOutcome::Paused(reason))) => {
+                format!("Paused at {}: {:?}", vm.describe_address(vm.pc()), reason)
+            }
+            Ok(Stop::Watch(value)) => format!(
+                "{} changed, stopped at {}",
+                value.name(vm.register_aliases()),
+                vm.describe_address(vm.pc())
+            ),
+            Err(e) => return Err(describe_error(vm, &e)),
+        };
+
+        writeln!(out, "{}", message).map_err(|e| e.to_string())
+    }
+
+    /// Prints every watch, with the value it had when last printed if it changed since
+    fn print_watches(&mut self, vm: &VM, out: &mut dyn Write) -> std::io::Result<()> {
+        for watch in &mut self.watches {
+            let now = vm.value(watch.value);
+            let name = watch.value.name(vm.register_aliases());
+            if now == watch.last {
+                writeln!(out, "{}: {}", name, show(watch.value, now))?;
+            } else {
+                writeln!(
+                    out,
+                    "{}: {} -> {}",
+                    name,
+                    show(watch.value, watch.last),
+                    show(watch.value, now)
+                )?;
+                watch.last = now;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Steps until the program stops or one of the `before` values changes, at most [`RUN_CHUNK`] times
+fn step_until_change(vm: &mut VM, before: &[(Value, Option<u16>)]) -> Result<Stop, VMError> {
+    for _ in 0..RUN_CHUNK {
+        vm.step()?;
+        match vm.state() {
+            VMState::Halted(reason) => {
+                return Ok(Stop::Outcome(RunOutcome::Halted(reason.clone())))
+            }
+            VMState::Paused(reason) => {
+                return Ok(Stop::Outcome(RunOutcome::Paused(reason.clone())))
+            }
+            VMState::Running => (),
+        }
+        if let Some(value) = first_change(vm, before) {
+            return Ok(Stop::Watch(value));
+        }
+    }
+
+    Ok(Stop::Outcome(RunOutcome::Paused(
+        PauseReason::LimitReached {
+            executed: RUN_CHUNK,
+        },
+    )))
+}
+
+/// The first of the `before` values that has a different value now
+fn first_change(vm: &VM, before: &[(Value, Option<u16>)]) -> Option<Value> {
+    before
+        .iter()
+        .find(|(value, was)| vm.value(*value) != *was)
+        .map(|(value, _)| *value)
+}
+
+/// Disassembles the instructions from 5 before `center` to 10 after it
+///
+/// The PC is marked with `=>` and enabled breakpoints with `*`, labels get a
+/// line of their own and words outside every loaded program are shown as
+/// `.FILL` data.
+pub fn listing(vm: &VM, center: u16) -> String {
+    let start = center.wrapping_sub(LIST_BEFORE);
+    let mut listing = String::new();
+
+    for offset in 0..=LIST_BEFORE.saturating_add(LIST_AFTER) {
+        let address = start.wrapping_add(offset);
+        let word = vm.memory().read_ram(address);
+        let breakpoint = vm
+            .breakpoints()
+            .iter()
+            .any(|breakpoint| breakpoint.address == address && breakpoint.enabled);
+        let marker = match (address == vm.pc(), breakpoint) {
+            (true, true) => "*=>",
+            (true, false) => " =>",
+            (false, true) => "*  ",
+            (false, false) => "   ",
+        };
+
+        if let Some(label) = vm.symbols().label_at(address) {
+            listing.push_str(&format!("{}:\n", label));
+        }
+        let text = if vm.is_loaded(address) {
+            disasm::disassemble(word, address)
+        } else {
+            format!(".FILL x{:04X}  ; not loaded", word)
+        };
+        listing.push_str(&format!(
+            "{} x{:04X}: {:04X}  {}\n",
+            marker, address, word, text
+        ));
+    }
+
+    listing
+}
+
+/// Refuses to write system space or the device registers from `start` through `end` unless `force`d
+fn check_writable(start: u16, end: u16, force: bool) -> Result<(), String> {
+    match (start..=end).find(|&address| protected(address)) {
+        Some(address) if !force => Err(format!(
+            "x{:04X} is outside user space x{:04X}-x{:04X}, add --force to write it anyway",
+            address,
+            USER_SPACE,
+            DEVICE_REGISTERS.wrapping_sub(1)
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// A watched value as it's printed, `?` if it can't be read
+fn show(value: Value, read: Option<u16>) -> String {
+    read.map_or_else(|| "?".to_string(), |read| value.format(read))
+}
+
+/// Writes the output the program printed since the last command, ending it with a newline
+fn print_output(vm: &mut VM, out: &mut dyn Write) -> Result<(), String> {
+    let output = vm.take_output();
+    if output.is_empty() {
+        return Ok(());
+    }
+    out.write_all(&output).map_err(|e| e.to_string())?;
+    if !output.ends_with(b"\n") {
+        writeln!(out).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// The instruction at the PC, such as `LOOP+1 (x3005): ADD R1, R1, #-1`
+fn next_instruction(vm: &VM) -> String {
+    let word = vm.memory().read_ram(vm.pc());
+    format!(
+        "{}: {}",
+        vm.describe_address(vm.pc()),
+        disasm::disassemble(word, vm.pc())
+    )
+}
+
+fn describe_halt(reason: &HaltReason) -> String {
+    match reason {
+        HaltReason::TrapHalt => "Program halted".to_string(),
+        other => format!("Program halted: {}", other),
+    }
+}
+
+fn describe_error(vm: &VM, error: &VMError) -> String {
+    match error {
+        VMError::Halted(_) => "The program has halted, run starts it over".to_string(),
+        // The PC is already past a faulting instruction, the error says where it was
+        VMError::AtInstruction { .. } | VMError::StackViolation { .. } => {
+            format!("Error {}", vm.describe_error(error))
+        }
+        other => format!("Error: {}", vm.describe_error(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNT_TO_TWO: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #1
+        ADD R0, R0, #1
+        HALT
+        .END
+";
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!("break x3010".parse(), Ok(DebugCommand::Break(0x3010, None)));
+        assert_eq!(
+            "break x3010 if R2 == 0".parse(),
+            Ok(DebugCommand::Break(0x3010, "R2 == 0".parse().ok()))
+        );
+        assert_eq!(
+            "watch mem[x4000]".parse(),
+            Ok(DebugCommand::Watch {
+                value: Value::Memory(0x4000),
+                stop: false
+            })
+        );
+        assert_eq!(
+            "watch --break flags".parse(),
+            Ok(DebugCommand::Watch {
+                value: Value::Flags,
+                stop: true
+            })
+        );
+        assert_eq!(
+            "watch #5".parse::<DebugCommand>(),
+            Err("watch expects a register, pc, flags or mem[ADDR]".to_string())
+        );
+        assert_eq!("step".parse(), Ok(DebugCommand::Step(1)));
+        assert_eq!("s 5".parse(), Ok(DebugCommand::Step(5)));
+        assert_eq!("back".parse(), Ok(DebugCommand::Back(1)));
+        assert_eq!("back 3".parse(), Ok(DebugCommand::Back(3)));
+        assert_eq!("mem 0x4000 16".parse(), Ok(DebugCommand::Mem(0x4000, 16)));
+        assert_eq!("mem x4000".parse(), Ok(DebugCommand::Mem(0x4000, 8)));
+        assert_eq!("continue".parse(), Ok(DebugCommand::Continue));
+        assert_eq!(
+            "save x3000 x30FF out.obj".parse(),
+            Ok(DebugCommand::Save {
+                start: 0x3000,
+                end: 0x30FF,
+                path: "out.obj".to_string()
+            })
+        );
+        assert_eq!(
+            "save x3000 out.obj".parse::<DebugCommand>(),
+            Err("save expects a start, an end and a path such as x3000 x30FF out.obj".to_string())
+        );
+        assert_eq!(
+            "jump x3000".parse::<DebugCommand>(),
+            Err("Unknown command jump".to_string())
+        );
+        assert_eq!(
+            "step five".parse::<DebugCommand>(),
+            Err("step expects a number of instructions, got \"five\"".to_string())
+        );
+        assert_eq!(
+            "regs R0".parse::<DebugCommand>(),
+            Err("regs got too many arguments".to_string())
+        );
+    }
+
+    #[test]
+    fn test_script_stops_at_breakpoints_and_restarts() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_TWO)?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+
+        let script = "# comments and blank lines are skipped\n\nbreak x3002\nrun\nstep\nrun\ncontinue\nquit\nregs\n";
+        assert_eq!(debugger.run_script(&mut vm, script, &mut out), Ok(()));
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "(lc3) break x3002\nBreakpoint at x3002\n\
+             (lc3) run\nBreakpoint at x3002\n\
+             (lc3) step\nx3003: HALT\n\
+             (lc3) run\nBreakpoint at x3002\n\
+             (lc3) continue\nProgram halted\n\
+             (lc3) quit\n"
+        );
+        assert_eq!(vm.read_register(0)?, 2);
+
+        Ok(())
+    }
+
+    const COUNT_DOWN: &str = "
+        .ORIG x3000
+        AND R3, R3, #0
+        ADD R3, R3, #3
+LOOP    ADD R3, R3, #-1
+        BRp LOOP
+        HALT
+        .END
+";
+
+    /// The transcript of `script` run against `source`
+    fn transcript(source: &str, script: &str) -> Result<String, VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(source)?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+        let result = debugger.run_script(&mut vm, script, &mut out);
+        assert_eq!(result, Ok(()));
+
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    #[test]
+    fn test_watch_reports_changes_after_each_step() -> Result<(), VMError> {
+        let out = transcript(
+            COUNT_DOWN,
+            "watch R3
+watch flags
+step 2
+step 2
+step 2
+step
+",
+        )?;
+
+        assert_eq!(
+            out,
+            "(lc3) watch R3\nWatching R3 = x0000\n\
+             (lc3) watch flags\nWatching flags = Z\n\
+             (lc3) step 2\nLOOP (x3002): ADD R3, R3, #-1\nR3: x0000 -> x0003\nflags: Z -> P\n\
+             (lc3) step 2\nLOOP (x3002): ADD R3, R3, #-1\nR3: x0003 -> x0002\nflags: P\n\
+             (lc3) step 2\nLOOP (x3002): ADD R3, R3, #-1\nR3: x0002 -> x0001\nflags: P\n\
+             (lc3) step\nLOOP+1 (x3003): BRp x3002\nR3: x0001 -> x0000\nflags: P -> Z\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_back_undoes_steps() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_undo_depth(DEFAULT_UNDO_DEPTH);
+        vm.load_asm(COUNT_DOWN)?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+
+        let script = "watch R3\nstep 3\nback\nback 5\n";
+        assert_eq!(debugger.run_script(&mut vm, script, &mut out), Ok(()));
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "(lc3) watch R3\nWatching R3 = x0000\n\
+             (lc3) step 3\nLOOP+1 (x3003): BRp x3002\nR3: x0000 -> x0002\n\
+             (lc3) back\nLOOP (x3002): ADD R3, R3, #-1\nR3: x0002 -> x0003\n\
+             (lc3) back 5\nStepped back 2 instructions, no further\n\
+             x3000: AND R3, R3, #0\nR3: x0003 -> x0000\n"
+        );
+
+        assert_eq!(
+            debugger.run_script(&mut vm, "back\n", &mut Vec::new()),
+            Err(
+                "line 1: Nothing to step back over: the undo journal is off or empty, \
+                 or the last instruction did I/O"
+                    .to_string()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_watch_break_stops_when_the_value_changes() -> Result<(), VMError> {
+        let out = transcript(
+            COUNT_DOWN,
+            "watch --break R3\nrun\ncontinue\ncontinue\nbreak x3004 if R3 == 0\ncontinue\ncontinue\n",
+        )?;
+
+        assert_eq!(
+            out,
+            "(lc3) watch --break R3\nWatching R3 = x0000, stopping when it changes\n\
+             (lc3) run\nR3 changed, stopped at LOOP (x3002)\nR3: x0000 -> x0003\n\
+             (lc3) continue\nR3 changed, stopped at LOOP+1 (x3003)\nR3: x0003 -> x0002\n\
+             (lc3) continue\nR3 changed, stopped at LOOP+1 (x3003)\nR3: x0002 -> x0001\n\
+             (lc3) break x3004 if R3 == 0\nBreakpoint at LOOP+2 (x3004) if R3 == x0000\n\
+             (lc3) continue\nR3 changed, stopped at LOOP+1 (x3003)\nR3: x0001 -> x0000\n\
+             (lc3) continue\nBreakpoint at LOOP+2 (x3004)\nR3: x0000\n"
+        );
+
+        Ok(())
+    }
+
+    const BRANCH: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+        BRz ZERO
+        ADD R0, R0, #1
+        HALT
+        ADD R0, R0, #3
+ZERO    ADD R0, R0, #2
+        HALT
+        .END
+";
+
+    #[test]
+    fn test_backtrace() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_call_tracking(true);
+        vm.load_asm(".ORIG x3000\nMAIN JSR SUB\nHALT\nSUB ADD R0, R0, #1\nRET\n.END\n")?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+
+        // Returning into SUB itself matches no call
+        let script = "bt\nbreak x3003\nrun\nbt\nset ra x3002\ncontinue\nbt\n";
+        assert_eq!(debugger.run_script(&mut vm, script, &mut out), Ok(()));
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "(lc3) bt\nNo subroutine calls\n\
+             (lc3) break x3003\nBreakpoint at SUB+1 (x3003)\n\
+             (lc3) run\nBreakpoint at SUB+1 (x3003)\n\
+             (lc3) bt\n#0 SUB (x3002) called from MAIN (x3000)\n\
+             (lc3) set ra x3002\nR7 (RA): x3001 -> x3002\n\
+             (lc3) continue\nBreakpoint at SUB+1 (x3003)\n\
+             (lc3) bt\n\
+             The program returned somewhere other than the innermost call, this may be wrong\n\
+             #0 SUB (x3002) called from MAIN (x3000)\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_poke_patches_a_branch_target() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(BRANCH)?;
+        vm.run()?;
+        assert_eq!(vm.read_register(0)?, 2);
+
+        // BRz +2 lands on the ADD #3 before ZERO instead
+        let out = transcript(
+            BRANCH,
+            "break x3001\nrun\npoke x3001 x0402\ncontinue\nregs\n",
+        )?;
+        assert!(
+            out.contains("(lc3) poke x3001 x0402\nmem[x3001]: x0403 -> x0402\n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("\nR0: x0005      5\n"), "{}", out);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_and_fill() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(BRANCH)?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+
+        let script = "set r0 0xBEEF\nset pc ZERO\nset pc x3005\n";
+        assert_eq!(
+            debugger.run_script(&mut vm, script, &mut out),
+            Err("line 2: set expects a value such as xBEEF or #5, got \"ZERO\"".to_string())
+        );
+        let script = "set pc x3005\nfill x4000 x4003 #-1\nstep\n";
+        assert_eq!(debugger.run_script(&mut vm, script, &mut out), Ok(()));
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "(lc3) set r0 0xBEEF\nR0: x0000 -> xBEEF\n\
+             (lc3) set pc ZERO\n\
+             (lc3) set pc x3005\npc: x3000 -> x3005\n\
+             (lc3) fill x4000 x4003 #-1\nFilled x4000-x4003 (4 words) with xFFFF\n\
+             (lc3) step\nZERO+1 (x3006): HALT\n"
+        );
+        assert_eq!(vm.read_register(0)?, 0xBEF1);
+        // Only the PC moved, a reset still starts at the program
+        assert_eq!(vm.reset_vector(), 0x3000);
+        assert_eq!(vm.memory().read_ram(0x4003), 0xFFFF);
+        assert_eq!(vm.memory().read_ram(0x4004), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_writes_an_object_file() -> Result<(), VMError> {
+        let path = std::env::temp_dir().join(format!("lc3-debug-save-{}.obj", std::process::id()));
+        let path = path.to_string_lossy();
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_TWO)?;
+        vm.write_memory(0xFFFF, 0xBEEF)?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+
+        // The end is included, so the last word of memory can be saved
+        let script = format!("save x3000 x3001 {}\nsave xFFFF xFFFF {}\n", path, path);
+        let result = debugger.run_script(&mut vm, &script, &mut out);
+        let saved = std::fs::read(path.as_ref());
+        let _ = std::fs::remove_file(path.as_ref());
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            format!(
+                "(lc3) save x3000 x3001 {path}\nSaved x3000-x3001 (2 words) to {path}\n\
+                 (lc3) save xFFFF xFFFF {path}\nSaved xFFFF-xFFFF (1 words) to {path}\n"
+            )
+        );
+        assert_eq!(saved.ok(), Some(vec![0xFF, 0xFF, 0xBE, 0xEF]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_aliases() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(BRANCH)?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+
+        let script = "set sp xFE00\nset R6 xFDFF\nbreak x3001 if sp == xFDFF\nregs\n";
+        assert_eq!(debugger.run_script(&mut vm, script, &mut out), Ok(()));
+        let printed = String::from_utf8_lossy(&out);
+        assert!(
+            printed.contains("(lc3) set sp xFE00\nR6 (SP): x0000 -> xFE00\n"),
+            "{}",
+            printed
+        );
+        assert!(printed.contains("\nR6: xFDFF   -513 (SP)\nR7: x0000      0 (RA)\n"));
+        assert_eq!(
+            vm.breakpoints().first().and_then(|b| b.condition),
+            "R6 == xFDFF".parse().ok()
+        );
+
+        let mut aliases = RegisterAliases::none();
+        aliases.set(5, "fp")?;
+        vm.set_register_aliases(aliases);
+        out.clear();
+        assert_eq!(
+            debugger.run_script(&mut vm, "set sp 0\n", &mut out),
+            Err("line 1: set expects R0-R7, a register alias or pc, got \"sp\"".to_string())
+        );
+        assert_eq!(
+            debugger.run_script(&mut vm, "set fp #7\n", &mut out),
+            Ok(())
+        );
+        assert!(String::from_utf8_lossy(&out).ends_with("R5 (FP): x0000 -> x0007\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_writes_outside_user_space_need_force() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(BRANCH)?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+
+        assert_eq!(
+            debugger.execute_line(&mut vm, "poke x0025 x1234", &mut out),
+            Err(
+                "x0025 is outside user space x3000-xFDFF, add --force to write it anyway"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            debugger.execute_line(&mut vm, "fill xFDFF xFE00 0", &mut out),
+            Err(
+                "xFE00 is outside user space x3000-xFDFF, add --force to write it anyway"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            debugger.execute_line(&mut vm, "poke --force x0025 x1234", &mut out),
+            Ok(true)
+        );
+        assert_eq!(vm.memory().read_ram(0x0025), 0x1234);
+        assert_eq!(
+            "fill x4010 x4000 0".parse::<DebugCommand>(),
+            Err("fill got a start address x4010 after the end address x4000".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_listing_around_the_pc() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_DOWN)?;
+        vm.add_breakpoint(0x3003);
+        vm.step()?;
+
+        assert_eq!(
+            listing(&vm, vm.pc()),
+            "    x2FFC: 0000  .FILL x0000  ; not loaded
+    x2FFD: 0000  .FILL x0000  ; not loaded
+    x2FFE: 0000  .FILL x0000  ; not loaded
+    x2FFF: 0000  .FILL x0000  ; not loaded
+    x3000: 56E0  AND R3, R3, #0
+ => x3001: 16E3  ADD R3, R3, #3
+LOOP:
+    x3002: 16FF  ADD R3, R3, #-1
+*   x3003: 03FE  BRp x3002
+    x3004: F025  HALT
+    x3005: 0000  .FILL x0000  ; not loaded
+    x3006: 0000  .FILL x0000  ; not loaded
+    x3007: 0000  .FILL x0000  ; not loaded
+    x3008: 0000  .FILL x0000  ; not loaded
+    x3009: 0000  .FILL x0000  ; not loaded
+    x300A: 0000  .FILL x0000  ; not loaded
+    x300B: 0000  .FILL x0000  ; not loaded
+"
+        );
+
+        let mut out = Vec::new();
+        let mut debugger = LineDebugger::new(&mut vm);
+        assert_eq!(
+            debugger.execute_line(&mut vm, "list x3008", &mut out),
+            Ok(true)
+        );
+        let text = String::from_utf8_lossy(&out);
+        assert!(text.starts_with("*   x3003: 03FE  BRp x3002\n"), "{}", text);
+        assert_eq!(text.lines().count(), 16);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_script_stops_at_the_first_failing_command() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_TWO)?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+
+        assert_eq!(
+            debugger.run_script(&mut vm, "run\ncontinue\nregs\n", &mut out),
+            Err("line 2: The program has halted, run starts it over".to_string())
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "(lc3) run\nProgram halted\n(lc3) continue\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors_name_the_faulting_instruction() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(".ORIG x3000\nADD R0, R0, #1\n.FILL xD000\nHALT\n.END\n")?;
+        let mut debugger = LineDebugger::new(&mut vm);
+        let mut out = Vec::new();
+
+        assert_eq!(
+            debugger.run_script(&mut vm, "run\n", &mut out),
+            Err("line 1: Error at x3001 (.FILL xD000): unimplemented opcode Res".to_string())
+        );
+
+        Ok(())
+    }
+}
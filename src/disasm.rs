@@ -0,0 +1,161 @@
+//! Disassembler turning memory words back into lc3as assembly text
+
+use std::ops::Range;
+
+use crate::instruction::Instruction;
+use crate::loader::LoadedProgram;
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+
+/// Disassembles a single word fetched from `address`
+///
+/// PC-relative targets are printed as absolute addresses, e.g. `BRnp x3010`.
+/// Words that aren't a sensible instruction are rendered as `.FILL x1234`.
+pub fn disassemble(word: u16, address: u16) -> String {
+    match Instruction::decode(word) {
+        Ok(instruction) => instruction.to_asm(address),
+        Err(_) => format!(".FILL x{:04X}", word),
+    }
+}
+
+/// Disassembles a range of memory into listing lines such as `x3000: 5020  AND R0, R0, #0`
+pub fn disassemble_range(memory: &Memory, range: Range<u16>) -> Vec<String> {
+    range
+        .map(|address| {
+            let word = memory.read_ram(address);
+            format!(
+                "x{:04X}: {:04X}  {}",
+                address,
+                word,
+                disassemble(word, address)
+            )
+        })
+        .collect()
+}
+
+/// Disassembles every loaded segment into an annotated listing
+///
+/// Each segment starts with its `.ORIG` line. Labels from `symbols` get a line
+/// of their own and replace the absolute address of PC-relative targets.
+/// Words that aren't instructions are marked as data with `.FILL`.
+pub fn listing(memory: &Memory, segments: &[LoadedProgram], symbols: &SymbolTable) -> Vec<String> {
+    let name = |target: u16| match symbols.label_at(target) {
+        Some(label) => label.to_string(),
+        None => format!("x{:04X}", target),
+    };
+    let mut lines = Vec::new();
+
+    for segment in segments {
+        lines.push(format!(".ORIG x{:04X}", segment.origin));
+
+        for address in (0..segment.len).map(|i| segment.origin.wrapping_add(i)) {
+            if let Some(label) = symbols.label_at(address) {
+                lines.push(format!("{}:", label));
+            }
+
+            let word = memory.read_ram(address);
+            let text = match Instruction::decode(word) {
+                Ok(instruction) => instruction.to_asm_with(address, name),
+                Err(_) => format!(".FILL x{:04X}", word),
+            };
+            lines.push(format!("x{:04X}: {:04X}  {}", address, word, text));
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_every_form() {
+        let cases: [(u16, &str); 28] = [
+            (0x1283, "ADD R1, R2, R3"),
+            (0x12BF, "ADD R1, R2, #-1"),
+            (0x596F, "AND R4, R5, #15"),
+            (0x5042, "AND R0, R1, R2"),
+            (0x9DFF, "NOT R6, R7"),
+            (0x0BFF, "BRnp x3000"),
+            (0x0E05, "BRnzp x3006"),
+            (0x0401, "BRz x3002"),
+            (0x0810, "BRn x3011"),
+            (0xC0C0, "JMP R3"),
+            (0xC1C0, "RET"),
+            (0x4FFF, "JSR x3000"),
+            (0x4100, "JSRR R4"),
+            (0x200C, "LD R0, x300D"),
+            (0xA3FE, "LDI R1, x2FFF"),
+            (0x65A0, "LDR R2, R6, #-32"),
+            (0xE7F2, "LEA R3, x2FF3"),
+            (0x3808, "ST R4, x3009"),
+            (0xBA07, "STI R5, x3008"),
+            (0x7D5F, "STR R6, R5, #31"),
+            (0x8000, "RTI"),
+            (0xF020, "GETC"),
+            (0xF021, "OUT"),
+            (0xF022, "PUTS"),
+            (0xF023, "IN"),
+            (0xF024, "PUTSP"),
+            (0xF025, "HALT"),
+            (0xF030, "TRAP x30"),
+        ];
+
+        for (word, expected) in cases {
+            assert_eq!(disassemble(word, 0x3000), expected, "word x{:04X}", word);
+        }
+    }
+
+    #[test]
+    fn test_disassemble_data_words() {
+        assert_eq!(disassemble(0x0000, 0x3000), ".FILL x0000");
+        assert_eq!(disassemble(0xD123, 0x3000), ".FILL xD123");
+        assert_eq!(disassemble(0x0048, 0x3000), ".FILL x0048");
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        let mut memory = Memory::new();
+        memory.write_ram(0x3000, 0x5020);
+        memory.write_ram(0x3001, 0x1025);
+        memory.write_ram(0x3002, 0xF025);
+
+        assert_eq!(
+            disassemble_range(&memory, 0x3000..0x3003),
+            vec![
+                "x3000: 5020  AND R0, R0, #0",
+                "x3001: 1025  ADD R0, R0, #5",
+                "x3002: F025  HALT",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listing_with_labels_and_segments() {
+        let mut memory = Memory::new();
+        memory.write_ram(0x3000, 0x1021);
+        memory.write_ram(0x3001, 0x0FFE);
+        memory.write_ram(0x3002, 0x0041);
+        memory.write_ram(0x4000, 0xF025);
+
+        let segments = [LoadedProgram::new(0x3000, 3), LoadedProgram::new(0x4000, 1)];
+        let mut symbols = SymbolTable::new();
+        symbols.insert("LOOP", 0x3000);
+        symbols.insert("CHAR", 0x3002);
+
+        assert_eq!(
+            listing(&memory, &segments, &symbols),
+            vec![
+                ".ORIG x3000",
+                "LOOP:",
+                "x3000: 1021  ADD R0, R0, #1",
+                "x3001: 0FFE  BRnzp LOOP",
+                "CHAR:",
+                "x3002: 0041  .FILL x0041",
+                ".ORIG x4000",
+                "x4000: F025  HALT",
+            ]
+        );
+    }
+}
@@ -0,0 +1,478 @@
+//! Builds instruction words from their fields, the inverse of [`Instruction::decode`](crate::instruction::Instruction::decode)
+//!
+//! Meant for tests and tools that need a few instructions without going
+//! through the assembler. Every field is checked to fit before it is packed.
+//!
+//! ```
+//! use lc3_vm::encode::{encode_add_imm, encode_br};
+//!
+//! assert_eq!(encode_add_imm(0, 1, -1)?, 0x107F);
+//! assert_eq!(encode_br(false, false, true, -3)?, 0x03FD);
+//! # Ok::<(), lc3_vm::VMError>(())
+//! ```
+
+use crate::errors::VMError;
+
+/// Checks that `r` names one of R0-R7
+fn register(r: usize) -> Result<u16, VMError> {
+    u16::try_from(r)
+        .ok()
+        .filter(|&r| r < 8)
+        .ok_or(VMError::InvalidRegister)
+}
+
+/// Checks that `value` fits in a `bits`-wide two's complement field and masks it
+fn signed(field: &'static str, value: i16, bits: u32) -> Result<u16, VMError> {
+    let limit = 1i32 << bits.saturating_sub(1);
+    let value = i32::from(value);
+    if value < limit.wrapping_neg() || value >= limit {
+        return Err(VMError::FieldOutOfRange { field, value });
+    }
+
+    let mask = (1i32 << bits).wrapping_sub(1);
+    u16::try_from(value & mask).map_err(|_| VMError::FieldOutOfRange { field, value })
+}
+
+/// `ADD dr, sr1, sr2`
+///
+/// # Errors
+/// Returns `VMError::InvalidRegister` if a register isn't R0-R7
+pub fn encode_add_reg(dr: usize, sr1: usize, sr2: usize) -> Result<u16, VMError> {
+    Ok(0x1000 | register(dr)? << 9 | register(sr1)? << 6 | register(sr2)?)
+}
+
+/// `ADD dr, sr1, #imm5`
+///
+/// # Errors
+/// * `VMError::InvalidRegister` - If a register isn't R0-R7
+/// * `VMError::FieldOutOfRange` - If `imm5` isn't within -16..=15
+pub fn encode_add_imm(dr: usize, sr1: usize, imm5: i16) -> Result<u16, VMError> {
+    Ok(0x1020 | register(dr)? << 9 | register(sr1)? << 6 | signed("imm5", imm5, 5)?)
+}
+
+/// `AND dr, sr1, sr2`
+///
+/// # Errors
+/// Returns `VMError::InvalidRegister` if a register isn't R0-R7
+pub fn encode_and_reg(dr: usize, sr1: usize, sr2: usize) -> Result<u16, VMError> {
+    Ok(0x5000 | register(dr)? << 9 | register(sr1)? << 6 | register(sr2)?)
+}
+
+/// `AND dr, sr1, #imm5`
+///
+/// # Errors
+/// * `VMError::InvalidRegister` - If a register isn't R0-R7
+/// * `VMError::FieldOutOfRange` - If `imm5` isn't within -16..=15
+pub fn encode_and_imm(dr: usize, sr1: usize, imm5: i16) -> Result<u16, VMError> {
+    Ok(0x5020 | register(dr)? << 9 | register(sr1)? << 6 | signed("imm5", imm5, 5)?)
+}
+
+/// `NOT dr, sr`
+///
+/// # Errors
+/// Returns `VMError::InvalidRegister` if a register isn't R0-R7
+pub fn encode_not(dr: usize, sr: usize) -> Result<u16, VMError> {
+    Ok(0x903F | register(dr)? << 9 | register(sr)? << 6)
+}
+
+/// `BRnzp offset9`, with the condition bits that are set
+///
+/// A BR with no condition bit never branches, it is encoded all the same.
+///
+/// # Errors
+/// Returns `VMError::FieldOutOfRange` if `offset9` isn't within -256..=255
+pub fn encode_br(n: bool, z: bool, p: bool, offset9: i16) -> Result<u16, VMError> {
+    let flags = u16::from(n) << 11 | u16::from(z) << 10 | u16::from(p) << 9;
+    Ok(flags | signed("offset9", offset9, 9)?)
+}
+
+/// `JMP base`
+///
+/// # Errors
+/// Returns `VMError::InvalidRegister` if `base` isn't R0-R7
+pub fn encode_jmp(base: usize) -> Result<u16, VMError> {
+    Ok(0xC000 | register(base)? << 6)
+}
+
+/// `RET`, which is `JMP R7`
+pub fn encode_ret() -> u16 {
+    0xC1C0
+}
+
+/// `JSR offset11`
+///
+/// # Errors
+/// Returns `VMError::FieldOutOfRange` if `offset11` isn't within -1024..=1023
+pub fn encode_jsr(offset11: i16) -> Result<u16, VMError> {
+    Ok(0x4800 | signed("offset11", offset11, 11)?)
+}
+
+/// `JSRR base`
+///
+/// # Errors
+/// Returns `VMError::InvalidRegister` if `base` isn't R0-R7
+pub fn encode_jsrr(base: usize) -> Result<u16, VMError> {
+    Ok(0x4000 | register(base)? << 6)
+}
+
+/// The instructions with a register in [11:9] and a 9-bit PC offset
+fn pc_relative(opcode: u16, r: usize, offset9: i16) -> Result<u16, VMError> {
+    Ok(opcode << 12 | register(r)? << 9 | signed("offset9", offset9, 9)?)
+}
+
+/// `LD dr, offset9`
+///
+/// # Errors
+/// * `VMError::InvalidRegister` - If `dr` isn't R0-R7
+/// * `VMError::FieldOutOfRange` - If `offset9` isn't within -256..=255
+pub fn encode_ld(dr: usize, offset9: i16) -> Result<u16, VMError> {
+    pc_relative(0x2, dr, offset9)
+}
+
+/// `LDI dr, offset9`
+///
+/// # Errors
+/// * `VMError::InvalidRegister` - If `dr` isn't R0-R7
+/// * `VMError::FieldOutOfRange` - If `offset9` isn't within -256..=255
+pub fn encode_ldi(dr: usize, offset9: i16) -> Result<u16, VMError> {
+    pc_relative(0xA, dr, offset9)
+}
+
+/// `LEA dr, offset9`
+///
+/// # Errors
+/// * `VMError::InvalidRegister` - If `dr` isn't R0-R7
+/// * `VMError::FieldOutOfRange` - If `offset9` isn't within -256..=255
+pub fn encode_lea(dr: usize, offset9: i16) -> Result<u16, VMError> {
+    pc_relative(0xE, dr, offset9)
+}
+
+/// `ST sr, offset9`
+///
+/// # Errors
+/// * `VMError::InvalidRegister` - If `sr` isn't R0-R7
+/// * `VMError::FieldOutOfRange` - If `offset9` isn't within -256..=255
+pub fn encode_st(sr: usize, offset9: i16) -> Result<u16, VMError> {
+    pc_relative(0x3, sr, offset9)
+}
+
+/// `STI sr, offset9`
+///
+/// # Errors
+/// * `VMError::InvalidRegister` - If `sr` isn't R0-R7
+/// * `VMError::FieldOutOfRange` - If `offset9` isn't within -256..=255
+pub fn encode_sti(sr: usize, offset9: i16) -> Result<u16, VMError> {
+    pc_relative(0xB, sr, offset9)
+}
+
+/// `LDR dr, base, offset6`
+///
+/// # Errors
+/// * `VMError::InvalidRegister` - If a register isn't R0-R7
+/// * `VMError::FieldOutOfRange` - If `offset6` isn't within -32..=31
+pub fn encode_ldr(dr: usize, base: usize, offset6: i16) -> Result<u16, VMError> {
+    Ok(0x6000 | register(dr)? << 9 | register(base)? << 6 | signed("offset6", offset6, 6)?)
+}
+
+/// `STR sr, base, offset6`
+///
+/// # Errors
+/// * `VMError::InvalidRegister` - If a register isn't R0-R7
+/// * `VMError::FieldOutOfRange` - If `offset6` isn't within -32..=31
+pub fn encode_str(sr: usize, base: usize, offset6: i16) -> Result<u16, VMError> {
+    Ok(0x7000 | register(sr)? << 9 | register(base)? << 6 | signed("offset6", offset6, 6)?)
+}
+
+/// `RTI`
+pub fn encode_rti() -> u16 {
+    0x8000
+}
+
+/// `TRAP vector`
+pub fn encode_trap(vector: u8) -> u16 {
+    0xF000 | u16::from(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::{Instruction, Operand};
+
+    /// Random cases per property, a splitmix64 stream so failures reproduce
+    const CASES: usize = 4096;
+
+    struct Cases(u64);
+
+    impl Cases {
+        fn next(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        }
+
+        fn word(&mut self) -> u16 {
+            let [.., high, low] = self.next().to_be_bytes();
+            u16::from_be_bytes([high, low])
+        }
+
+        fn register(&mut self) -> usize {
+            usize::from(self.word() & 0x7)
+        }
+
+        fn flag(&mut self) -> bool {
+            self.word() & 1 == 1
+        }
+
+        /// A value that fits in a `bits`-wide signed field
+        fn signed(&mut self, bits: i32) -> i16 {
+            let field = self.word() & (1u16 << bits).wrapping_sub(1);
+            i16::from_ne_bytes(crate::opdcodes::sign_extend(field, bits).to_ne_bytes())
+        }
+    }
+
+    #[test]
+    fn test_encoders_decode_to_their_fields() -> Result<(), VMError> {
+        let decode = |word: u16| Instruction::decode(word).ok();
+
+        for r in 0..8 {
+            let (a, b) = (r, r ^ 7);
+            for imm5 in -16..=15 {
+                assert_eq!(
+                    decode(encode_add_imm(a, b, imm5)?),
+                    Some(Instruction::Add {
+                        dr: a,
+                        sr1: b,
+                        operand: Operand::Immediate(imm5)
+                    })
+                );
+                assert_eq!(
+                    decode(encode_and_imm(b, a, imm5)?),
+                    Some(Instruction::And {
+                        dr: b,
+                        sr1: a,
+                        operand: Operand::Immediate(imm5)
+                    })
+                );
+            }
+            assert_eq!(
+                decode(encode_add_reg(a, b, a)?),
+                Some(Instruction::Add {
+                    dr: a,
+                    sr1: b,
+                    operand: Operand::Register(a)
+                })
+            );
+            assert_eq!(
+                decode(encode_and_reg(a, a, b)?),
+                Some(Instruction::And {
+                    dr: a,
+                    sr1: a,
+                    operand: Operand::Register(b)
+                })
+            );
+            assert_eq!(
+                decode(encode_not(a, b)?),
+                Some(Instruction::Not { dr: a, sr: b })
+            );
+            assert_eq!(decode(encode_jmp(a)?), Some(Instruction::Jmp { base: a }));
+            assert_eq!(decode(encode_jsrr(a)?), Some(Instruction::Jsrr { base: a }));
+
+            for offset in [-32, -1, 0, 1, 31] {
+                assert_eq!(
+                    decode(encode_ldr(a, b, offset)?),
+                    Some(Instruction::Ldr {
+                        dr: a,
+                        base: b,
+                        offset
+                    })
+                );
+                assert_eq!(
+                    decode(encode_str(a, b, offset)?),
+                    Some(Instruction::Str {
+                        sr: a,
+                        base: b,
+                        offset
+                    })
+                );
+            }
+
+            for offset in [-256, -1, 0, 1, 255] {
+                assert_eq!(
+                    decode(encode_ld(a, offset)?),
+                    Some(Instruction::Ld { dr: a, offset })
+                );
+                assert_eq!(
+                    decode(encode_ldi(a, offset)?),
+                    Some(Instruction::Ldi { dr: a, offset })
+                );
+                assert_eq!(
+                    decode(encode_lea(a, offset)?),
+                    Some(Instruction::Lea { dr: a, offset })
+                );
+                assert_eq!(
+                    decode(encode_st(a, offset)?),
+                    Some(Instruction::St { sr: a, offset })
+                );
+                assert_eq!(
+                    decode(encode_sti(a, offset)?),
+                    Some(Instruction::Sti { sr: a, offset })
+                );
+            }
+        }
+
+        for flags in 1..8u8 {
+            let (n, z, p) = (flags & 4 != 0, flags & 2 != 0, flags & 1 != 0);
+            for offset in [-256, -3, 0, 2, 255] {
+                assert_eq!(
+                    decode(encode_br(n, z, p, offset)?),
+                    Some(Instruction::Br { n, z, p, offset })
+                );
+            }
+        }
+        for offset in [-1024, -1, 0, 5, 1023] {
+            assert_eq!(
+                decode(encode_jsr(offset)?),
+                Some(Instruction::Jsr { offset })
+            );
+        }
+        for vector in [0x00, 0x20, 0x25, 0xFF] {
+            assert_eq!(
+                decode(encode_trap(vector)),
+                Some(Instruction::Trap { vector })
+            );
+        }
+        assert_eq!(decode(encode_ret()), Some(Instruction::Jmp { base: 7 }));
+        assert_eq!(decode(encode_rti()), Some(Instruction::Rti));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_fields_round_trip() -> Result<(), VMError> {
+        let mut cases = Cases(0x5EED);
+        let decode = |word: u16| Instruction::decode(word).ok();
+
+        for _ in 0..CASES {
+            let (dr, sr1, sr2) = (cases.register(), cases.register(), cases.register());
+            let (imm5, offset6) = (cases.signed(5), cases.signed(6));
+            let (offset9, offset11) = (cases.signed(9), cases.signed(11));
+
+            assert_eq!(
+                decode(encode_add_reg(dr, sr1, sr2)?),
+                Some(Instruction::Add {
+                    dr,
+                    sr1,
+                    operand: Operand::Register(sr2)
+                })
+            );
+            assert_eq!(
+                decode(encode_and_imm(dr, sr1, imm5)?),
+                Some(Instruction::And {
+                    dr,
+                    sr1,
+                    operand: Operand::Immediate(imm5)
+                })
+            );
+            assert_eq!(
+                decode(encode_ldr(dr, sr1, offset6)?),
+                Some(Instruction::Ldr {
+                    dr,
+                    base: sr1,
+                    offset: offset6
+                })
+            );
+            assert_eq!(
+                decode(encode_str(dr, sr1, offset6)?),
+                Some(Instruction::Str {
+                    sr: dr,
+                    base: sr1,
+                    offset: offset6
+                })
+            );
+            assert_eq!(
+                decode(encode_lea(dr, offset9)?),
+                Some(Instruction::Lea {
+                    dr,
+                    offset: offset9
+                })
+            );
+            assert_eq!(
+                decode(encode_sti(dr, offset9)?),
+                Some(Instruction::Sti {
+                    sr: dr,
+                    offset: offset9
+                })
+            );
+            assert_eq!(
+                decode(encode_jsr(offset11)?),
+                Some(Instruction::Jsr { offset: offset11 })
+            );
+
+            let (n, z, p) = (cases.flag(), cases.flag(), cases.flag());
+            let branch = decode(encode_br(n, z, p, offset9)?);
+            if n || z || p {
+                assert_eq!(
+                    branch,
+                    Some(Instruction::Br {
+                        n,
+                        z,
+                        p,
+                        offset: offset9
+                    })
+                );
+            } else {
+                assert_eq!(branch, None);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_any_value_is_encoded_or_rejected() {
+        let mut cases = Cases(0xF1E1D);
+
+        for _ in 0..CASES {
+            let value = i16::from_ne_bytes(cases.word().to_ne_bytes());
+            let r = usize::from(cases.word() & 0xF);
+
+            let fits = (-16..=15).contains(&value) && r < 8;
+            assert_eq!(encode_add_imm(r, 0, value).is_ok(), fits);
+            let fits = (-256..=255).contains(&value) && r < 8;
+            assert_eq!(encode_ld(r, value).is_ok(), fits);
+            assert_eq!(encode_jsr(value).is_ok(), (-1024..=1023).contains(&value));
+        }
+    }
+
+    #[test]
+    fn test_fields_out_of_range() {
+        assert!(matches!(
+            encode_add_reg(8, 0, 0),
+            Err(VMError::InvalidRegister)
+        ));
+        assert!(matches!(
+            encode_add_imm(0, 0, 16),
+            Err(VMError::FieldOutOfRange {
+                field: "imm5",
+                value: 16
+            })
+        ));
+        assert!(matches!(
+            encode_ldr(0, 0, -33),
+            Err(VMError::FieldOutOfRange {
+                field: "offset6",
+                value: -33
+            })
+        ));
+        assert!(matches!(
+            encode_br(true, true, true, 256),
+            Err(VMError::FieldOutOfRange { .. })
+        ));
+        assert!(matches!(
+            encode_jsr(-1025),
+            Err(VMError::FieldOutOfRange { .. })
+        ));
+    }
+}
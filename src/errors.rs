@@ -1,18 +1,222 @@
-use crate::Opcode;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::instruction::Opcode;
+use crate::stack::StackBounds;
 
 #[derive(Debug)]
 pub enum VMError {
-    InvalidMemoryAccess(u16), // This includes the address that was attempted to be accessed
+    /// A device or protection fault at the address, plain RAM reads and writes never fail
+    InvalidMemoryAccess(u16),
     InvalidRegister,
     UnimplemedOpcode(Opcode),
     InvalidCharacter,
     TrapError(TrapError),
     LoadFailed,
     OpenFileFailed(String),
+    SaveFailed(String),
+    InvalidSnapshot(String),
+    InvalidSession(&'static str),
+    /// The VM was stepped after it halted
+    Halted(HaltReason),
+    OverlappingLoad {
+        origin: u16,
+        existing: u16,
+    },
+    ProgramTooLarge {
+        origin: u16,
+        words: usize,
+    },
+    MalformedObject {
+        len: usize,
+        reason: &'static str,
+    },
+    InvalidIntelHex {
+        line: usize,
+        reason: &'static str,
+    },
+    InvalidHexListing {
+        line: usize,
+        token: String,
+    },
+    AssemblyError {
+        line: usize,
+        message: String,
+    },
+    /// An instruction field given to [`encode`](crate::encode) doesn't fit its bits
+    FieldOutOfRange {
+        field: &'static str,
+        value: i32,
+    },
+    /// The keyboard input ended, see [`EofPolicy`](crate::keyboard::EofPolicy)
+    InputExhausted,
+    /// Built-in trap vectors x20-x25 can only be replaced with
+    /// [`VM::override_trap`](crate::VM::override_trap)
+    ReservedTrap(u8),
+    /// The console couldn't be switched to raw mode, see [`VM::set_console`](crate::VM::set_console)
+    ConsoleFailed(String),
+    /// A deterministic run read a device register that has no deterministic backing
+    NondeterministicDevice(u16),
+    /// The instruction at `pc` moved R6, or used it to load or store, outside the stack
+    ///
+    /// `sp` is the offending address: the new R6, or R6 plus the offset of an LDR or STR.
+    StackViolation {
+        sp: u16,
+        bounds: StackBounds,
+        pc: u16,
+    },
+    /// An instruction failed to execute, `source` says why
+    AtInstruction {
+        pc: u16,
+        instr: u16,
+        source: Box<VMError>,
+    },
+    /// A program given to [`VMBuilder`](crate::builder::VMBuilder) failed to load
+    InProgram {
+        path: String,
+        source: Box<VMError>,
+    },
+}
+
+impl VMError {
+    /// Returns the error without the instruction or program context added by
+    /// [`VM::step`](crate::VM::step) and [`VMBuilder::build`](crate::builder::VMBuilder::build)
+    pub fn root(&self) -> &VMError {
+        match self {
+            VMError::AtInstruction { source, .. } | VMError::InProgram { source, .. } => {
+                source.root()
+            }
+            other => other,
+        }
+    }
 }
 
+impl fmt::Display for VMError {
+    /// Describes the error for a user, with plain hex addresses
+    ///
+    /// [`VM::describe_error`](crate::VM::describe_error) labels the addresses
+    /// with symbols and disassembles the faulting instruction instead.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VMError::InvalidMemoryAccess(address) => {
+                write!(f, "invalid memory access x{:04X}", address)
+            }
+            VMError::InvalidRegister => write!(f, "invalid register"),
+            VMError::UnimplemedOpcode(opcode) => write!(f, "unimplemented opcode {:?}", opcode),
+            VMError::InvalidCharacter => write!(f, "invalid character"),
+            VMError::TrapError(error) => write!(f, "{}", error),
+            VMError::LoadFailed => write!(f, "failed to load the program"),
+            VMError::OpenFileFailed(path) => write!(f, "failed to open file {:?}", path),
+            VMError::SaveFailed(reason) => write!(f, "failed to save: {}", reason),
+            VMError::InvalidSnapshot(reason) => write!(f, "invalid snapshot: {}", reason),
+            VMError::InvalidSession(reason) => write!(f, "invalid session: {}", reason),
+            VMError::Halted(reason) => write!(f, "the machine has halted: {}", reason),
+            VMError::OverlappingLoad { origin, existing } => write!(
+                f,
+                "program at x{:04X} overlaps program loaded at x{:04X}",
+                origin, existing
+            ),
+            VMError::ProgramTooLarge { origin, words } => write!(
+                f,
+                "program of {} words at x{:04X} runs past the end of memory",
+                words, origin
+            ),
+            VMError::MalformedObject { len, reason } => {
+                write!(f, "malformed object file of {} bytes: {}", len, reason)
+            }
+            VMError::InvalidIntelHex { line, reason } => {
+                write!(f, "invalid Intel HEX on line {}: {}", line, reason)
+            }
+            VMError::InvalidHexListing { line, token } => {
+                write!(f, "invalid hex listing on line {}: {:?}", line, token)
+            }
+            VMError::AssemblyError { line, message } => write!(f, "line {}: {}", line, message),
+            VMError::FieldOutOfRange { field, value } => {
+                write!(f, "{} {} doesn't fit its field", field, value)
+            }
+            VMError::InputExhausted => write!(f, "the keyboard input ended"),
+            VMError::ReservedTrap(vector) => write!(
+                f,
+                "trap x{:02X} is built in, replace it with override_trap",
+                vector
+            ),
+            VMError::ConsoleFailed(reason) => write!(f, "console failed: {}", reason),
+            VMError::NondeterministicDevice(address) => write!(
+                f,
+                "device register x{:04X} can't be read in a deterministic run",
+                address
+            ),
+            VMError::StackViolation { sp, bounds, pc } => write!(
+                f,
+                "at x{:04X}: stack pointer x{:04X} outside the stack {}",
+                pc, sp, bounds
+            ),
+            VMError::AtInstruction { pc, instr, source } => {
+                write!(f, "at x{:04X} (x{:04X}): {}", pc, instr, source)
+            }
+            VMError::InProgram { path, source } => write!(f, "{}: {}", path, source),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VMError {}
+
 #[derive(Debug)]
 pub enum TrapError {
     IOError(String),
     InvalidTrapVector(u16),
+    /// PUTS or PUTSP found no terminating zero within the string length limit
+    UnterminatedString {
+        start: u16,
+    },
+}
+
+impl fmt::Display for TrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrapError::IOError(message) => write!(f, "IO error: {}", message),
+            TrapError::InvalidTrapVector(vector) => {
+                write!(f, "invalid trap vector 0x{:04X}", vector)
+            }
+            TrapError::UnterminatedString { start } => {
+                write!(f, "string at x{:04X} has no terminating zero", start)
+            }
+        }
+    }
+}
+
+/// Why the VM stopped executing
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HaltReason {
+    /// The program executed TRAP x25, or a custom trap handler halted
+    TrapHalt,
+    /// The program cleared bit 15 of the machine control register
+    McrCleared,
+    /// The loop detector saw the program spin without side effects, see [`VM::set_loop_detection`](crate::VM::set_loop_detection)
+    LikelyInfiniteLoop {
+        /// Addresses of the instructions in the loop, in ascending order
+        pcs: Vec<u16>,
+    },
+    /// The program asked for a key after the input ended, with [`EofPolicy::Halt`](crate::keyboard::EofPolicy::Halt)
+    InputExhausted,
+    /// The interrupt flag was set, see [`VM::set_interrupt_flag`](crate::VM::set_interrupt_flag)
+    Interrupted,
+}
+
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HaltReason::TrapHalt => write!(f, "the program executed HALT"),
+            HaltReason::McrCleared => write!(f, "the program cleared the MCR"),
+            HaltReason::LikelyInfiniteLoop { .. } => {
+                write!(f, "the program was stuck in a loop")
+            }
+            HaltReason::InputExhausted => write!(f, "the input ended"),
+            HaltReason::Interrupted => write!(f, "the run was interrupted"),
+        }
+    }
 }
@@ -0,0 +1,284 @@
+//! Checking what a program printed against what it should have printed
+//!
+//! Mismatches are reported as a unified diff of the lines, as `diff -u` would
+//! print them, so graders can tell at a glance where the output went wrong.
+
+use std::cmp::Ordering;
+
+/// Lines of context around each change in a diff
+const CONTEXT: usize = 3;
+
+/// Largest table of line pairs compared to find the shortest diff, bigger
+/// outputs get a diff replacing every line in between the common ends
+const MAX_TABLE: usize = 4_000_000;
+
+/// How newlines at the end of the output are compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrailingNewlines {
+    /// Any number of newlines at the end count as one
+    #[default]
+    Ignore,
+    /// The output has to end exactly like the expectation
+    Strict,
+}
+
+/// Compares `actual` with `expected`, the diff between them if they differ
+pub fn check(
+    expected: &str,
+    actual: &str,
+    newlines: TrailingNewlines,
+    expected_name: &str,
+    actual_name: &str,
+) -> Option<String> {
+    let (expected, actual) = match newlines {
+        TrailingNewlines::Ignore => (single_newline(expected), single_newline(actual)),
+        TrailingNewlines::Strict => (expected.to_string(), actual.to_string()),
+    };
+    if expected == actual {
+        return None;
+    }
+
+    Some(unified_diff(&expected, &actual, expected_name, actual_name))
+}
+
+/// `text` ending with exactly one newline, or empty
+fn single_newline(text: &str) -> String {
+    let trimmed = text.trim_end_matches(['\r', '\n']);
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", trimmed)
+    }
+}
+
+/// One line of a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+impl Edit<'_> {
+    /// Whether the line is in the old text
+    fn in_old(&self) -> bool {
+        !matches!(self, Edit::Added(_))
+    }
+
+    /// Whether the line is in the new text
+    fn in_new(&self) -> bool {
+        !matches!(self, Edit::Removed(_))
+    }
+}
+
+/// Length of the longest common subsequence of `old[i..]` and `new[j..]`
+fn common(table: &[Vec<u32>], i: usize, j: usize) -> u32 {
+    table
+        .get(i)
+        .and_then(|row| row.get(j))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// The edits turning `old` into `new` while keeping as many lines as possible
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (_, old_rest) = old.split_at(prefix);
+    let (_, new_rest) = new.split_at(prefix);
+    let suffix = old_rest
+        .iter()
+        .rev()
+        .zip(new_rest.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_middle, old_suffix) = old_rest.split_at(old_rest.len().saturating_sub(suffix));
+    let (new_middle, _) = new_rest.split_at(new_rest.len().saturating_sub(suffix));
+
+    let mut edits: Vec<Edit> = old
+        .iter()
+        .take(prefix)
+        .map(|line| Edit::Same(line))
+        .collect();
+    let (n, m) = (old_middle.len(), new_middle.len());
+    if n.saturating_mul(m) > MAX_TABLE {
+        edits.extend(old_middle.iter().map(|line| Edit::Removed(line)));
+        edits.extend(new_middle.iter().map(|line| Edit::Added(line)));
+    } else {
+        let mut table = vec![vec![0u32; m.saturating_add(1)]; n.saturating_add(1)];
+        for (i, a) in old_middle.iter().enumerate().rev() {
+            for (j, b) in new_middle.iter().enumerate().rev() {
+                let length = if a == b {
+                    common(&table, i.saturating_add(1), j.saturating_add(1)).saturating_add(1)
+                } else {
+                    common(&table, i.saturating_add(1), j).max(common(
+                        &table,
+                        i,
+                        j.saturating_add(1),
+                    ))
+                };
+                if let Some(cell) = table.get_mut(i).and_then(|row| row.get_mut(j)) {
+                    *cell = length;
+                }
+            }
+        }
+
+        let (mut old_lines, mut new_lines) =
+            (old_middle.iter().peekable(), new_middle.iter().peekable());
+        let (mut i, mut j) = (0usize, 0usize);
+        while let (Some(a), Some(b)) = (old_lines.peek(), new_lines.peek()) {
+            if a == b {
+                edits.push(Edit::Same(a));
+                old_lines.next();
+                new_lines.next();
+                i = i.saturating_add(1);
+                j = j.saturating_add(1);
+                continue;
+            }
+            match common(&table, i.saturating_add(1), j).cmp(&common(
+                &table,
+                i,
+                j.saturating_add(1),
+            )) {
+                Ordering::Less => {
+                    edits.push(Edit::Added(b));
+                    new_lines.next();
+                    j = j.saturating_add(1);
+                }
+                Ordering::Equal | Ordering::Greater => {
+                    edits.push(Edit::Removed(a));
+                    old_lines.next();
+                    i = i.saturating_add(1);
+                }
+            }
+        }
+        edits.extend(old_lines.map(|line| Edit::Removed(line)));
+        edits.extend(new_lines.map(|line| Edit::Added(line)));
+    }
+    edits.extend(old_suffix.iter().map(|line| Edit::Same(line)));
+
+    edits
+}
+
+/// Start and length of a hunk in the `@@` header, the start is the line before for empty hunks
+fn range(start: usize, len: usize) -> String {
+    let start = if len == 0 {
+        start
+    } else {
+        start.saturating_add(1)
+    };
+    format!("{},{}", start, len)
+}
+
+/// Line by line differences between `old` and `new` in the unified format
+///
+/// ```
+/// use lc3_vm::expect::unified_diff;
+///
+/// let diff = unified_diff("Hello, Ada!\n", "Hello, Bob!\n", "expected", "output");
+/// assert_eq!(diff, "--- expected\n+++ output\n@@ -1,1 +1,1 @@\n-Hello, Ada!\n+Hello, Bob!\n");
+/// ```
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits = edits(&old_lines, &new_lines);
+
+    // Ranges of edits shown together, each change with its context
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (position, _) in edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Same(_)))
+    {
+        let start = position.saturating_sub(CONTEXT);
+        let end = position
+            .saturating_add(CONTEXT)
+            .saturating_add(1)
+            .min(edits.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (start, end) in hunks {
+        let before = edits.iter().take(start);
+        let shown = edits.iter().skip(start).take(end.saturating_sub(start));
+        diff.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(
+                before.clone().filter(|edit| edit.in_old()).count(),
+                shown.clone().filter(|edit| edit.in_old()).count()
+            ),
+            range(
+                before.filter(|edit| edit.in_new()).count(),
+                shown.clone().filter(|edit| edit.in_new()).count()
+            )
+        ));
+        for edit in shown {
+            let (marker, line) = match edit {
+                Edit::Same(line) => (' ', line),
+                Edit::Removed(line) => ('-', line),
+                Edit::Added(line) => ('+', line),
+            };
+            diff.push(marker);
+            diff.push_str(line);
+            if !line.ends_with('\n') {
+                diff.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_output() {
+        assert_eq!(
+            check("a\nb\n", "a\nb\n", TrailingNewlines::Strict, "e", "o"),
+            None
+        );
+        assert_eq!(
+            check("a\nb", "a\nb\n\n", TrailingNewlines::Ignore, "e", "o"),
+            None
+        );
+        assert_eq!(check("\n", "", TrailingNewlines::Ignore, "e", "o"), None);
+    }
+
+    #[test]
+    fn test_strict_trailing_newlines() {
+        assert_eq!(
+            check("a\nb", "a\nb\n", TrailingNewlines::Strict, "e", "o").as_deref(),
+            Some("--- e\n+++ o\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n")
+        );
+    }
+
+    #[test]
+    fn test_diff_hunks_keep_three_lines_of_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n";
+
+        assert_eq!(
+            unified_diff(old, new, "e", "o"),
+            "--- e\n+++ o\n\
+             @@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n\
+             @@ -9,4 +9,3 @@\n 9\n 10\n 11\n-12\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_keeps_the_common_lines() {
+        assert_eq!(
+            unified_diff("a\nb\nc\n", "b\nc\nd\n", "e", "o"),
+            "--- e\n+++ o\n@@ -1,3 +1,3 @@\n-a\n b\n c\n+d\n"
+        );
+        assert_eq!(
+            unified_diff("", "a\n", "e", "o"),
+            "--- e\n+++ o\n@@ -0,0 +1,1 @@\n+a\n"
+        );
+    }
+}
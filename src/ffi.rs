@@ -0,0 +1,271 @@
+//! C interface for embedding the VM, behind the `ffi` feature
+//!
+//! `include/lc3_vm.h` declares these functions for C and C++. A VM is created
+//! with [`lc3_vm_new`] and released with [`lc3_vm_free`]; every other function
+//! takes it as its first argument. Functions return [`LC3_OK`], a positive
+//! status such as [`LC3_HALTED`], or a negative error code, in which case
+//! [`lc3_vm_last_error_message`] describes what went wrong.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CString};
+use std::io::{self, Write};
+
+use crate::loader::Endianness;
+use crate::{RunOutcome, VMError, VMState, VM};
+
+/// The call succeeded, or the instruction ran and the program goes on
+pub const LC3_OK: i32 = 0;
+/// The program halted, stepping or running it again fails with [`LC3_ERR_HALTED`]
+pub const LC3_HALTED: i32 = 1;
+/// [`lc3_vm_run`] executed its instruction budget and the program goes on
+pub const LC3_LIMIT_REACHED: i32 = 2;
+
+/// A pointer argument was NULL
+pub const LC3_ERR_NULL: i32 = -1;
+/// The image isn't a valid `.obj` file or doesn't fit in memory
+pub const LC3_ERR_LOAD: i32 = -2;
+/// The register index is neither 0 to 7 nor [`LC3_REG_PC`]
+pub const LC3_ERR_REGISTER: i32 = -3;
+/// The program already halted
+pub const LC3_ERR_HALTED: i32 = -4;
+/// An instruction failed, e.g. a reserved opcode or a failed input or output
+pub const LC3_ERR_EXECUTION: i32 = -5;
+
+/// Register index of the program counter for [`lc3_vm_get_register`]
+pub const LC3_REG_PC: u32 = 8;
+
+/// Receives the bytes a program prints, `len` of them at `bytes`, with the user data it was registered with
+pub type Lc3OutputCallback = extern "C" fn(bytes: *const u8, len: usize, userdata: *mut c_void);
+
+/// A VM owned by C code, opaque on that side
+pub struct Lc3Vm {
+    vm: VM,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Remembers `message` for [`lc3_vm_last_error_message`] and returns `code`
+fn fail(code: i32, message: &str) -> i32 {
+    // A message with a NUL in it is cut there
+    let message = message.split('\0').next().unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message).ok());
+
+    code
+}
+
+/// Maps a `VMError` to its error code and remembers its description
+fn fail_with(vm: &VM, error: &VMError) -> i32 {
+    let source = match error {
+        VMError::AtInstruction { source, .. } => source,
+        other => other,
+    };
+    let code = match source {
+        VMError::Halted(_) => LC3_ERR_HALTED,
+        VMError::LoadFailed
+        | VMError::OverlappingLoad { .. }
+        | VMError::ProgramTooLarge { .. }
+        | VMError::MalformedObject { .. } => LC3_ERR_LOAD,
+        VMError::InvalidRegister => LC3_ERR_REGISTER,
+        _ => LC3_ERR_EXECUTION,
+    };
+
+    fail(code, &vm.describe_error(error))
+}
+
+/// Passes the program's output to a C callback
+struct CallbackOutput {
+    callback: Lc3OutputCallback,
+    userdata: *mut c_void,
+}
+
+impl Write for CallbackOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.callback)(buf.as_ptr(), buf.len(), self.userdata);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates a VM printing to stdout, without the banner at HALT
+///
+/// Release it with [`lc3_vm_free`].
+#[no_mangle]
+pub extern "C" fn lc3_vm_new() -> *mut Lc3Vm {
+    let mut vm = VM::new();
+    vm.set_halt_banner(false);
+
+    Box::into_raw(Box::new(Lc3Vm { vm }))
+}
+
+/// Releases a VM created by [`lc3_vm_new`], doing nothing for NULL
+///
+/// # Safety
+/// `vm` must be NULL or a pointer returned by [`lc3_vm_new`] that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn lc3_vm_free(vm: *mut Lc3Vm) {
+    if !vm.is_null() {
+        // SAFETY: the caller passes a pointer from lc3_vm_new, which came from Box::into_raw
+        drop(unsafe { Box::from_raw(vm) });
+    }
+}
+
+/// Loads a big-endian `.obj` image of `len` bytes at `bytes`
+///
+/// Execution starts at the origin of the first image loaded.
+///
+/// # Safety
+/// `vm` must come from [`lc3_vm_new`], and `bytes` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lc3_vm_load_bytes(vm: *mut Lc3Vm, bytes: *const u8, len: usize) -> i32 {
+    // SAFETY: the caller passes a live VM or NULL
+    let Some(vm) = (unsafe { vm.as_mut() }) else {
+        return fail(LC3_ERR_NULL, "the VM is NULL");
+    };
+    if bytes.is_null() {
+        return fail(LC3_ERR_NULL, "the image is NULL");
+    }
+    // SAFETY: the caller guarantees `len` readable bytes at `bytes`
+    let image = unsafe { std::slice::from_raw_parts(bytes, len) };
+
+    match vm.vm.load_bytes(image, Endianness::Big) {
+        Ok(_) => LC3_OK,
+        Err(e) => fail_with(&vm.vm, &e),
+    }
+}
+
+/// Executes one instruction
+///
+/// # Returns
+/// [`LC3_OK`], [`LC3_HALTED`] if this instruction halted the program, or an error code
+///
+/// # Safety
+/// `vm` must come from [`lc3_vm_new`].
+#[no_mangle]
+pub unsafe extern "C" fn lc3_vm_step(vm: *mut Lc3Vm) -> i32 {
+    // SAFETY: the caller passes a live VM or NULL
+    let Some(vm) = (unsafe { vm.as_mut() }) else {
+        return fail(LC3_ERR_NULL, "the VM is NULL");
+    };
+
+    let stepped = vm.vm.step().and_then(|_| vm.vm.flush_output());
+    match (stepped, vm.vm.state()) {
+        (Err(e), _) => fail_with(&vm.vm, &e),
+        (Ok(()), VMState::Halted(_)) => LC3_HALTED,
+        (Ok(()), _) => LC3_OK,
+    }
+}
+
+/// Runs until the program halts or `max_instructions` instructions ran, 0 for no limit
+///
+/// # Returns
+/// [`LC3_HALTED`], [`LC3_LIMIT_REACHED`] or an error code
+///
+/// # Safety
+/// `vm` must come from [`lc3_vm_new`].
+#[no_mangle]
+pub unsafe extern "C" fn lc3_vm_run(vm: *mut Lc3Vm, max_instructions: u64) -> i32 {
+    // SAFETY: the caller passes a live VM or NULL
+    let Some(vm) = (unsafe { vm.as_mut() }) else {
+        return fail(LC3_ERR_NULL, "the VM is NULL");
+    };
+
+    let outcome = match max_instructions {
+        0 => vm.vm.run(),
+        max => vm.vm.run_with_limit(max),
+    };
+    match outcome {
+        Ok(RunOutcome::Halted(_)) => LC3_HALTED,
+        Ok(RunOutcome::Paused(_)) => LC3_LIMIT_REACHED,
+        Err(e) => fail_with(&vm.vm, &e),
+    }
+}
+
+/// Stores R0 to R7, or the PC for [`LC3_REG_PC`], in `value`
+///
+/// # Safety
+/// `vm` must come from [`lc3_vm_new`] and `value` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn lc3_vm_get_register(vm: *const Lc3Vm, index: u32, value: *mut u16) -> i32 {
+    // SAFETY: the caller passes a live VM and a writable value, or NULL
+    let (Some(vm), Some(value)) = (unsafe { vm.as_ref() }, unsafe { value.as_mut() }) else {
+        return fail(LC3_ERR_NULL, "the VM or the value is NULL");
+    };
+
+    let read = match index {
+        LC3_REG_PC => Ok(vm.vm.pc()),
+        r => usize::try_from(r)
+            .map_err(|_| VMError::InvalidRegister)
+            .and_then(|r| vm.vm.read_register(r)),
+    };
+    match read {
+        Ok(read) => {
+            *value = read;
+            LC3_OK
+        }
+        Err(e) => fail_with(&vm.vm, &e),
+    }
+}
+
+/// Stores the word at `address` in `value`
+///
+/// Device registers read as the RAM behind them, so reading memory never
+/// consumes a key or has any other side effect.
+///
+/// # Safety
+/// `vm` must come from [`lc3_vm_new`] and `value` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn lc3_vm_read_mem(vm: *const Lc3Vm, address: u16, value: *mut u16) -> i32 {
+    // SAFETY: the caller passes a live VM and a writable value, or NULL
+    let (Some(vm), Some(value)) = (unsafe { vm.as_ref() }, unsafe { value.as_mut() }) else {
+        return fail(LC3_ERR_NULL, "the VM or the value is NULL");
+    };
+
+    *value = vm.vm.memory().read_ram(address);
+    LC3_OK
+}
+
+/// Sends the program's output to `callback` with `userdata`, or back to stdout for NULL
+///
+/// The output is buffered and passed on at each newline, before waiting for a
+/// key and when a step or run ends.
+///
+/// # Safety
+/// `vm` must come from [`lc3_vm_new`]. `callback` is called with `userdata`
+/// until the output is replaced or the VM freed, so `userdata` has to stay valid
+/// that long.
+#[no_mangle]
+pub unsafe extern "C" fn lc3_vm_set_output_callback(
+    vm: *mut Lc3Vm,
+    callback: Option<Lc3OutputCallback>,
+    userdata: *mut c_void,
+) -> i32 {
+    // SAFETY: the caller passes a live VM or NULL
+    let Some(vm) = (unsafe { vm.as_mut() }) else {
+        return fail(LC3_ERR_NULL, "the VM is NULL");
+    };
+
+    match callback {
+        Some(callback) => vm
+            .vm
+            .set_output(Box::new(CallbackOutput { callback, userdata })),
+        None => vm.vm.set_output(Box::new(io::stdout())),
+    }
+    LC3_OK
+}
+
+/// Describes the last error returned on this thread, NULL if there was none
+///
+/// The string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn lc3_vm_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
@@ -0,0 +1,415 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opcode {
+    Br = 0, /* branch */
+    Add,    /* add  */
+    Ld,     /* load */
+    St,     /* store */
+    Jsr,    /* jump register */
+    And,    /* bitwise and */
+    Ldr,    /* load register */
+    Str,    /* store register */
+    Rti,    /* unused */
+    Not,    /* bitwise not */
+    Ldi,    /* load indirect */
+    Sti,    /* store indirect */
+    Jmp,    /* jump */
+    Res,    /* reserved (unused) */
+    Lea,    /* load effective address */
+    Trap,   /* execute trap */
+}
+
+impl From<u16> for Opcode {
+    fn from(op: u16) -> Self {
+        match op {
+            0 => Opcode::Br,
+            1 => Opcode::Add,
+            2 => Opcode::Ld,
+            3 => Opcode::St,
+            4 => Opcode::Jsr,
+            5 => Opcode::And,
+            6 => Opcode::Ldr,
+            7 => Opcode::Str,
+            8 => Opcode::Rti,
+            9 => Opcode::Not,
+            10 => Opcode::Ldi,
+            11 => Opcode::Sti,
+            12 => Opcode::Jmp,
+            13 => Opcode::Res,
+            14 => Opcode::Lea,
+            15 => Opcode::Trap,
+            _ => Opcode::Res, // Default to reserved opcode instead of panicking
+        }
+    }
+}
+
+impl Opcode {
+    /// The top four bits of an instruction with this opcode
+    #[allow(clippy::as_conversions)] // The discriminants are the opcode bits
+    pub const fn bits(self) -> u16 {
+        self as u16
+    }
+}
+
+/// Sign extends a number to 16 bits based on its most significant bit
+///
+/// Takes a number and the count of its significant bits, then extends
+/// the sign bit (MSB) across the remaining high bits of a 16-bit value
+///
+/// # Arguments
+/// * `number` - The number to sign extend
+/// * `bit_count` - The number of significant bits in the original number
+///
+/// # Returns
+/// The sign-extended 16-bit value
+#[inline]
+pub fn sign_extend(number: u16, bit_count: i32) -> u16 {
+    // A count outside 1..=16 leaves nothing to extend
+    let Some(bits) = u32::try_from(bit_count)
+        .ok()
+        .filter(|bits| (1..=16).contains(bits))
+    else {
+        return number;
+    };
+    if (number >> bits.wrapping_sub(1)) & 1 == 1 {
+        // Shifting by 16 would overflow, a 16-bit number has no bits above it
+        number | u16::MAX.checked_shl(bits).unwrap_or(0)
+    } else {
+        number
+    }
+}
+
+/// Second operand of ADD and AND
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(usize),
+    Immediate(i16),
+}
+
+/// A decoded LC-3 instruction with its operand fields extracted
+///
+/// Register fields are indices 0-7, offsets are already sign-extended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    Br {
+        n: bool,
+        z: bool,
+        p: bool,
+        offset: i16,
+    },
+    Add {
+        dr: usize,
+        sr1: usize,
+        operand: Operand,
+    },
+    Ld {
+        dr: usize,
+        offset: i16,
+    },
+    St {
+        sr: usize,
+        offset: i16,
+    },
+    Jsr {
+        offset: i16,
+    },
+    Jsrr {
+        base: usize,
+    },
+    And {
+        dr: usize,
+        sr1: usize,
+        operand: Operand,
+    },
+    Ldr {
+        dr: usize,
+        base: usize,
+        offset: i16,
+    },
+    Str {
+        sr: usize,
+        base: usize,
+        offset: i16,
+    },
+    Rti,
+    Not {
+        dr: usize,
+        sr: usize,
+    },
+    Ldi {
+        dr: usize,
+        offset: i16,
+    },
+    Sti {
+        sr: usize,
+        offset: i16,
+    },
+    Jmp {
+        base: usize,
+    },
+    Lea {
+        dr: usize,
+        offset: i16,
+    },
+    Trap {
+        vector: u8,
+    },
+}
+
+/// Reasons a word is not a well-formed instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodeError {
+    /// Opcode 1101 is reserved
+    ReservedOpcode,
+    /// Bits the ISA requires to be zero (or all ones for NOT) are not
+    InvalidReservedBits,
+    /// BR with none of n, z or p set never branches, in practice it is data
+    EmptyBranchCondition,
+}
+
+fn register(word: u16, shift: u16) -> usize {
+    usize::from((word >> shift) & 0x7)
+}
+
+fn offset(word: u16, bits: i32) -> i16 {
+    let mask = (1u16 << bits).wrapping_sub(1);
+    i16::from_ne_bytes(sign_extend(word & mask, bits).to_ne_bytes())
+}
+
+impl Instruction {
+    /// Decodes a 16-bit word, checking that unused fields hold their required values
+    ///
+    /// # Errors
+    /// Returns the `DecodeError` explaining why the word isn't a sensible instruction
+    pub fn decode(word: u16) -> Result<Self, DecodeError> {
+        Self::decode_with(word, true)
+    }
+
+    /// Decodes a 16-bit word the way the hardware runs it, ignoring the unused fields
+    ///
+    /// A `BR` with none of n, z or p set decodes to a branch that is never taken.
+    ///
+    /// # Errors
+    /// Returns `DecodeError::ReservedOpcode` for opcode 1101, any other word decodes
+    pub fn decode_lenient(word: u16) -> Result<Self, DecodeError> {
+        Self::decode_with(word, false)
+    }
+
+    /// Decodes `word`, rejecting unused fields that don't hold their required values if `strict`
+    fn decode_with(word: u16, strict: bool) -> Result<Self, DecodeError> {
+        let dr = register(word, 9);
+        let sr1 = register(word, 6);
+
+        let alu_operand = || {
+            if (word >> 5) & 0x1 == 1 {
+                Ok(Operand::Immediate(offset(word, 5)))
+            } else if !strict || (word >> 3) & 0x3 == 0 {
+                Ok(Operand::Register(register(word, 0)))
+            } else {
+                Err(DecodeError::InvalidReservedBits)
+            }
+        };
+
+        let instruction = match Opcode::from(word >> 12) {
+            Opcode::Br => {
+                let (n, z, p) = (
+                    (word >> 11) & 1 == 1,
+                    (word >> 10) & 1 == 1,
+                    (word >> 9) & 1 == 1,
+                );
+                if strict && !(n || z || p) {
+                    return Err(DecodeError::EmptyBranchCondition);
+                }
+                Instruction::Br {
+                    n,
+                    z,
+                    p,
+                    offset: offset(word, 9),
+                }
+            }
+            Opcode::Add => Instruction::Add {
+                dr,
+                sr1,
+                operand: alu_operand()?,
+            },
+            Opcode::And => Instruction::And {
+                dr,
+                sr1,
+                operand: alu_operand()?,
+            },
+            Opcode::Ld => Instruction::Ld {
+                dr,
+                offset: offset(word, 9),
+            },
+            Opcode::Ldi => Instruction::Ldi {
+                dr,
+                offset: offset(word, 9),
+            },
+            Opcode::Lea => Instruction::Lea {
+                dr,
+                offset: offset(word, 9),
+            },
+            Opcode::St => Instruction::St {
+                sr: dr,
+                offset: offset(word, 9),
+            },
+            Opcode::Sti => Instruction::Sti {
+                sr: dr,
+                offset: offset(word, 9),
+            },
+            Opcode::Ldr => Instruction::Ldr {
+                dr,
+                base: sr1,
+                offset: offset(word, 6),
+            },
+            Opcode::Str => Instruction::Str {
+                sr: dr,
+                base: sr1,
+                offset: offset(word, 6),
+            },
+            Opcode::Jsr if (word >> 11) & 1 == 1 => Instruction::Jsr {
+                offset: offset(word, 11),
+            },
+            Opcode::Jsr if !strict || word & 0x0E3F == 0 => Instruction::Jsrr { base: sr1 },
+            Opcode::Not if !strict || word & 0x3F == 0x3F => Instruction::Not { dr, sr: sr1 },
+            Opcode::Jmp if !strict || word & 0x0E3F == 0 => Instruction::Jmp { base: sr1 },
+            Opcode::Rti if !strict || word & 0x0FFF == 0 => Instruction::Rti,
+            Opcode::Trap if !strict || word & 0x0F00 == 0 => Instruction::Trap {
+                vector: u8::try_from(word & 0xFF).unwrap_or_default(),
+            },
+            Opcode::Res => return Err(DecodeError::ReservedOpcode),
+            _ => return Err(DecodeError::InvalidReservedBits),
+        };
+
+        Ok(instruction)
+    }
+
+    /// Renders the instruction as lc3as assembly
+    ///
+    /// PC-relative operands are resolved to absolute addresses using `address`,
+    /// the location the instruction was fetched from.
+    pub fn to_asm(self, address: u16) -> String {
+        self.to_asm_with(address, |target| format!("x{:04X}", target))
+    }
+
+    /// Renders the instruction as lc3as assembly, naming PC-relative targets with `name`
+    ///
+    /// Used by listings that print a label instead of the absolute address.
+    pub fn to_asm_with(self, address: u16, name: impl Fn(u16) -> String) -> String {
+        let target = |offset: i16| name(address.wrapping_add(1).wrapping_add_signed(offset));
+        let alu = |name: &str, dr: usize, sr1: usize, operand: Operand| match operand {
+            Operand::Register(sr2) => format!("{} R{}, R{}, R{}", name, dr, sr1, sr2),
+            Operand::Immediate(imm) => format!("{} R{}, R{}, #{}", name, dr, sr1, imm),
+        };
+
+        match self {
+            Instruction::Br { n, z, p, offset } => format!(
+                "BR{}{}{} {}",
+                if n { "n" } else { "" },
+                if z { "z" } else { "" },
+                if p { "p" } else { "" },
+                target(offset)
+            ),
+            Instruction::Add { dr, sr1, operand } => alu("ADD", dr, sr1, operand),
+            Instruction::And { dr, sr1, operand } => alu("AND", dr, sr1, operand),
+            Instruction::Not { dr, sr } => format!("NOT R{}, R{}", dr, sr),
+            Instruction::Ld { dr, offset } => format!("LD R{}, {}", dr, target(offset)),
+            Instruction::Ldi { dr, offset } => format!("LDI R{}, {}", dr, target(offset)),
+            Instruction::Lea { dr, offset } => format!("LEA R{}, {}", dr, target(offset)),
+            Instruction::St { sr, offset } => format!("ST R{}, {}", sr, target(offset)),
+            Instruction::Sti { sr, offset } => format!("STI R{}, {}", sr, target(offset)),
+            Instruction::Ldr { dr, base, offset } => {
+                format!("LDR R{}, R{}, #{}", dr, base, offset)
+            }
+            Instruction::Str { sr, base, offset } => {
+                format!("STR R{}, R{}, #{}", sr, base, offset)
+            }
+            Instruction::Jmp { base: 7 } => "RET".to_string(),
+            Instruction::Jmp { base } => format!("JMP R{}", base),
+            Instruction::Jsr { offset } => format!("JSR {}", target(offset)),
+            Instruction::Jsrr { base } => format!("JSRR R{}", base),
+            Instruction::Rti => "RTI".to_string(),
+            Instruction::Trap { vector } => match vector {
+                0x20 => "GETC".to_string(),
+                0x21 => "OUT".to_string(),
+                0x22 => "PUTS".to_string(),
+                0x23 => "IN".to_string(),
+                0x24 => "PUTSP".to_string(),
+                0x25 => "HALT".to_string(),
+                _ => format!("TRAP x{:02X}", vector),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_fields() {
+        assert_eq!(
+            Instruction::decode(0x1025),
+            Ok(Instruction::Add {
+                dr: 0,
+                sr1: 0,
+                operand: Operand::Immediate(5)
+            })
+        );
+        assert_eq!(
+            Instruction::decode(0x0BFE),
+            Ok(Instruction::Br {
+                n: true,
+                z: false,
+                p: true,
+                offset: -2
+            })
+        );
+        assert_eq!(
+            Instruction::decode(0x6F7F),
+            Ok(Instruction::Ldr {
+                dr: 7,
+                base: 5,
+                offset: -1
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_words() {
+        assert_eq!(
+            Instruction::decode(0xD000),
+            Err(DecodeError::ReservedOpcode)
+        );
+        assert_eq!(
+            Instruction::decode(0x0000),
+            Err(DecodeError::EmptyBranchCondition)
+        );
+        // ADD register mode with bits [4:3] set
+        assert_eq!(
+            Instruction::decode(0x1018),
+            Err(DecodeError::InvalidReservedBits)
+        );
+        // NOT without the trailing ones
+        assert_eq!(
+            Instruction::decode(0x903E),
+            Err(DecodeError::InvalidReservedBits)
+        );
+        assert_eq!(
+            Instruction::decode(0xF125),
+            Err(DecodeError::InvalidReservedBits)
+        );
+    }
+
+    #[test]
+    fn test_decode_every_word_without_panicking() {
+        for word in 0..=u16::MAX {
+            if let Ok(instruction) = Instruction::decode(word) {
+                assert!(!instruction.to_asm(0x3000).is_empty());
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Undo journal behind [`VM::step_back`](crate::VM::step_back)
+
+use std::collections::VecDeque;
+
+use crate::registers::RegisterFlags;
+
+/// What one executed instruction overwrote
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Undo {
+    /// Address of the instruction, the PC to return to
+    pub(crate) pc: u16,
+    pub(crate) condition: RegisterFlags,
+    /// The destination register and its old value
+    pub(crate) register: Option<(usize, u16)>,
+    /// The stored-to address and its old value
+    pub(crate) memory: Option<(u16, u16)>,
+}
+
+/// The last `depth` reversible instructions, newest last
+#[derive(Debug, Clone)]
+pub(crate) struct Journal {
+    entries: VecDeque<Undo>,
+    depth: usize,
+}
+
+impl Journal {
+    pub(crate) fn new(depth: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(depth),
+            depth,
+        }
+    }
+
+    /// Records an instruction, forgetting the oldest once the journal is full
+    pub(crate) fn push(&mut self, undo: Undo) {
+        if self.entries.len() == self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(undo);
+    }
+
+    /// Takes the most recent instruction to undo
+    pub(crate) fn pop(&mut self) -> Option<Undo> {
+        self.entries.pop_back()
+    }
+
+    /// Forgets everything, e.g. after an instruction whose I/O can't be undone
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn undo(pc: u16) -> Undo {
+        Undo {
+            pc,
+            condition: RegisterFlags::Zro,
+            register: None,
+            memory: None,
+        }
+    }
+
+    #[test]
+    fn test_journal_keeps_last_entries() {
+        let mut journal = Journal::new(2);
+        journal.push(undo(0x3000));
+        journal.push(undo(0x3001));
+        journal.push(undo(0x3002));
+
+        assert_eq!(journal.pop().map(|undo| undo.pc), Some(0x3002));
+        assert_eq!(journal.pop().map(|undo| undo.pc), Some(0x3001));
+        assert_eq!(journal.pop(), None);
+    }
+}
@@ -0,0 +1,539 @@
+use crate::errors::VMError;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+pub use crate::memory::{MR_KBDR, MR_KBSR};
+
+/// Default for [`Keyboard::set_poll_interval`]
+pub const DEFAULT_POLL_INTERVAL: u64 = 500;
+
+/// Where the keyboard gets its characters from
+///
+/// The KBSR/KBDR device polls, the GETC and IN traps wait.
+pub trait InputSource {
+    /// Returns the next character if one is available, `None` if no key is pressed
+    fn poll_byte(&mut self) -> io::Result<Option<u8>>;
+
+    /// Waits for the next character
+    fn read_byte(&mut self) -> io::Result<u8>;
+
+    /// Waits at most `timeout` for the next character, `None` if none arrived
+    ///
+    /// Sources that can't wait for a limited time wait for the next character.
+    fn read_byte_timeout(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+        let _ = timeout;
+        self.read_byte().map(Some)
+    }
+}
+
+/// Input source reading from any [`Read`], such as stdin, a file or a byte slice
+///
+/// Reading is blocking, so polling waits for the next byte as well. A NUL
+/// byte stands for "no key pressed" when polling.
+pub struct ReaderInput {
+    reader: Box<dyn Read>,
+}
+
+impl ReaderInput {
+    /// Wraps `reader`
+    pub fn new(reader: Box<dyn Read>) -> Self {
+        Self { reader }
+    }
+}
+
+impl InputSource for ReaderInput {
+    fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+        let c = self.read_byte()?;
+
+        Ok((c != 0).then_some(c))
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut buffer = [0; 1];
+        self.reader.read_exact(&mut buffer)?;
+
+        Ok(u8::from_be_bytes(buffer))
+    }
+}
+
+/// What happens when a program waits for or polls a key after its input ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofPolicy {
+    /// Fail with the input error
+    #[default]
+    Error,
+    /// Deliver a NUL character, so GETC leaves 0x0000 in R0
+    Zero,
+    /// Halt with `HaltReason::InputExhausted`
+    Halt,
+    /// Block forever, like a terminal nobody types into
+    Hang,
+}
+
+/// Input source receiving characters over a channel, e.g. from a thread reading stdin
+///
+/// Polling never blocks and waiting can time out. The input ends once every
+/// sender is gone.
+pub struct ChannelInput {
+    receiver: Receiver<u8>,
+}
+
+impl ChannelInput {
+    /// Receives the characters sent to `receiver`
+    pub fn new(receiver: Receiver<u8>) -> Self {
+        Self { receiver }
+    }
+
+    /// Reads `reader` on a background thread, so the VM can wait for it with a timeout
+    pub fn spawn(reader: impl Read + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for byte in io::BufReader::new(reader).bytes() {
+                let sent = byte.ok().map(|byte| sender.send(byte));
+                if !matches!(sent, Some(Ok(()))) {
+                    break;
+                }
+            }
+        });
+
+        Self::new(receiver)
+    }
+}
+
+fn input_ended() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "the input ended")
+}
+
+impl InputSource for ChannelInput {
+    fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+        match self.receiver.try_recv() {
+            Ok(byte) => Ok(Some(byte)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(input_ended()),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        self.receiver.recv().map_err(|_| input_ended())
+    }
+
+    fn read_byte_timeout(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(byte) => Ok(Some(byte)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(input_ended()),
+        }
+    }
+}
+
+/// Input source feeding a fixed script of bytes in order, never waiting for a live keyboard
+///
+/// Polling receives the next byte straight away and reports no key once the
+/// script is used up, while waiting for a byte past its end fails.
+pub struct ScriptedInput {
+    bytes: VecDeque<u8>,
+}
+
+impl ScriptedInput {
+    /// Feeds `bytes`, first to last
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes: bytes.into(),
+        }
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.bytes.pop_front())
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        self.bytes.pop_front().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the input script has no more input",
+            )
+        })
+    }
+}
+
+/// Input source fed key by key by its host, such as a browser page's key events
+///
+/// Clones share the same queue: the VM owns one as its input source while the
+/// host keeps another to push keys into. Polling reports no key while the queue
+/// is empty, and waiting fails with `io::ErrorKind::WouldBlock` instead of
+/// blocking, so a host that can't block checks [`VM::waits_for_key`](crate::VM::waits_for_key)
+/// before stepping.
+#[derive(Debug, Clone, Default)]
+pub struct KeyQueue {
+    keys: Rc<RefCell<VecDeque<u8>>>,
+}
+
+impl KeyQueue {
+    /// Creates an empty queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a key behind the ones not read yet
+    pub fn push(&self, key: u8) {
+        self.keys.borrow_mut().push_back(key);
+    }
+
+    /// Number of keys not read yet
+    pub fn len(&self) -> usize {
+        self.keys.borrow().len()
+    }
+
+    /// Whether every key has been read
+    pub fn is_empty(&self) -> bool {
+        self.keys.borrow().is_empty()
+    }
+}
+
+impl InputSource for KeyQueue {
+    fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.keys.borrow_mut().pop_front())
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        self.keys
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WouldBlock, "no key is queued"))
+    }
+}
+
+/// Memory-mapped keyboard device backing the KBSR/KBDR registers
+///
+/// The device owns its input source and keeps the last received character
+/// until the program reads it through KBDR or a GETC/IN trap.
+pub struct Keyboard {
+    input: Box<dyn InputSource>,
+    data: Option<u8>,
+    timeout: Option<Duration>,
+    poll_interval: u64,
+    /// Instruction count of the last [`Keyboard::poll_at`] that found no key
+    idle_since: Option<u64>,
+}
+
+impl Keyboard {
+    /// Creates a keyboard that reads its characters from the given reader
+    pub fn new(input: Box<dyn Read>) -> Self {
+        Self::with_source(Box::new(ReaderInput::new(input)))
+    }
+
+    /// Creates a keyboard that reads its characters from the given input source
+    pub fn with_source(input: Box<dyn InputSource>) -> Self {
+        Self {
+            input,
+            data: None,
+            timeout: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            idle_since: None,
+        }
+    }
+
+    /// Checks the input source for a character, unless one is already waiting in KBDR
+    ///
+    /// # Returns
+    /// The character received by this call, if any
+    ///
+    /// # Errors
+    /// Returns `VMError::InputExhausted` once the input source ended, or
+    /// `VMError::InvalidCharacter` if it can't be read
+    pub fn poll(&mut self) -> Result<Option<u8>, VMError> {
+        if self.data.is_some() {
+            return Ok(None);
+        }
+
+        let received = match self.timeout {
+            Some(timeout) => self.input.read_byte_timeout(timeout),
+            None => self.input.poll_byte(),
+        };
+        self.data = received.map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => VMError::InputExhausted,
+            _ => VMError::InvalidCharacter,
+        })?;
+
+        Ok(self.data)
+    }
+
+    /// Polls like [`Keyboard::poll`], remembering `now`, the instruction count, if no key was pressed
+    ///
+    /// # Errors
+    /// Fails like [`Keyboard::poll`]
+    pub fn poll_at(&mut self, now: u64) -> Result<Option<u8>, VMError> {
+        let received = self.poll();
+        self.idle_since = (received.is_ok() && self.data.is_none()).then_some(now);
+
+        received
+    }
+
+    /// Returns true while polling again at instruction count `now` would be too soon
+    ///
+    /// That is when no character is waiting and the last [`Keyboard::poll_at`] found
+    /// none fewer than the poll interval instructions ago. KBSR keeps reporting no key
+    /// pressed meanwhile, so a loop polling it doesn't reach the input source, and
+    /// the system call behind it, on every instruction.
+    pub fn throttled(&self, now: u64) -> bool {
+        self.data.is_none()
+            && self
+                .idle_since
+                .and_then(|since| now.checked_sub(since))
+                .is_some_and(|elapsed| elapsed < self.poll_interval)
+    }
+
+    /// Sets how many instructions pass between two polls that find no key, 0 polling every time
+    pub fn set_poll_interval(&mut self, interval: u64) {
+        self.poll_interval = interval;
+    }
+
+    /// Waits for a character, used by the GETC and IN traps
+    ///
+    /// A character already received through KBSR is returned first, so the
+    /// device and the traps never skip each other's input.
+    ///
+    /// # Errors
+    /// Fails with `io::ErrorKind::TimedOut` if a timeout is set and no character arrived in time
+    pub fn read_byte(&mut self) -> io::Result<u8> {
+        match (self.data.take(), self.timeout) {
+            (Some(c), _) => Ok(c),
+            (None, Some(timeout)) => self.input.read_byte_timeout(timeout)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::TimedOut, "no key was pressed in time")
+            }),
+            (None, None) => self.input.read_byte(),
+        }
+    }
+
+    /// Limits how long polling and reading wait for a character, `None` waiting as long as it takes
+    ///
+    /// Polling that times out reports no key pressed.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Replaces the input source with a reader, e.g. the controlling TTY when stdin carries the program
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.set_source(Box::new(ReaderInput::new(input)));
+    }
+
+    /// Replaces the input source
+    pub fn set_source(&mut self, input: Box<dyn InputSource>) {
+        self.input = input;
+        self.idle_since = None;
+    }
+
+    /// Returns the character waiting in KBDR, if any
+    pub fn pending(&self) -> Option<u8> {
+        self.data
+    }
+
+    /// Puts a character in KBDR as if it had just been received, or clears it
+    pub fn set_pending(&mut self, data: Option<u8>) {
+        self.data = data;
+    }
+
+    /// Drops a character that was received but not read yet, keeping the input source
+    pub fn reset(&mut self) {
+        self.data = None;
+        self.idle_since = None;
+    }
+
+    /// Returns the value of KBSR: bit 15 is set while a character is waiting
+    pub fn status(&self) -> u16 {
+        if self.data.is_some() {
+            1 << 15
+        } else {
+            0
+        }
+    }
+
+    /// Returns the value of KBDR and clears the ready bit
+    pub fn take_data(&mut self) -> u16 {
+        self.data.take().map(u16::from).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_keyboard_poll_and_take() -> Result<(), VMError> {
+        let mut keyboard = Keyboard::new(Box::new(Cursor::new(b"a".to_vec())));
+
+        assert_eq!(keyboard.status(), 0);
+
+        keyboard.poll()?;
+        assert_eq!(keyboard.status(), 1 << 15);
+        assert_eq!(keyboard.take_data(), u16::from(b'a'));
+
+        // Reading KBDR clears the ready bit
+        assert_eq!(keyboard.status(), 0);
+        assert_eq!(keyboard.take_data(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyboard_nul_is_not_ready() -> Result<(), VMError> {
+        let mut keyboard = Keyboard::new(Box::new(Cursor::new(vec![0])));
+
+        keyboard.poll()?;
+        assert_eq!(keyboard.status(), 0);
+        assert_eq!(keyboard.take_data(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyboard_reset_drops_pending_character() -> Result<(), VMError> {
+        let mut keyboard = Keyboard::new(Box::new(Cursor::new(b"ab".to_vec())));
+
+        keyboard.poll()?;
+        keyboard.reset();
+        assert_eq!(keyboard.status(), 0);
+
+        // The input source is kept
+        keyboard.poll()?;
+        assert_eq!(keyboard.take_data(), u16::from(b'b'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyboard_poll_keeps_unread_character() -> Result<(), VMError> {
+        let mut keyboard = Keyboard::new(Box::new(Cursor::new(b"ab".to_vec())));
+
+        keyboard.poll()?;
+        keyboard.poll()?;
+        assert_eq!(keyboard.take_data(), u16::from(b'a'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_poll_throttle() -> Result<(), VMError> {
+        // NUL means no key pressed
+        let mut keyboard = Keyboard::new(Box::new(Cursor::new(b"\0a".to_vec())));
+        keyboard.set_poll_interval(10);
+        assert!(!keyboard.throttled(0));
+
+        assert_eq!(keyboard.poll_at(100)?, None);
+        assert!(keyboard.throttled(109));
+        assert!(!keyboard.throttled(110));
+        // The instruction count went back, e.g. after a reset
+        assert!(!keyboard.throttled(50));
+
+        assert_eq!(keyboard.poll_at(110)?, Some(b'a'));
+        assert!(!keyboard.throttled(111));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_byte_takes_polled_character_first() -> Result<(), VMError> {
+        let mut keyboard = Keyboard::new(Box::new(Cursor::new(b"ab".to_vec())));
+
+        keyboard.poll()?;
+        assert!(matches!(keyboard.read_byte(), Ok(b'a')));
+        assert_eq!(keyboard.status(), 0);
+        assert!(matches!(keyboard.read_byte(), Ok(b'b')));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scripted_input_runs_out() -> Result<(), VMError> {
+        let mut keyboard = Keyboard::with_source(Box::new(ScriptedInput::new(b"a".to_vec())));
+
+        keyboard.poll()?;
+        assert_eq!(keyboard.take_data(), u16::from(b'a'));
+        keyboard.poll()?;
+        assert_eq!(keyboard.status(), 0);
+        assert!(keyboard.read_byte().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_queue_never_blocks() -> Result<(), VMError> {
+        let keys = KeyQueue::new();
+        let mut keyboard = Keyboard::with_source(Box::new(keys.clone()));
+
+        keyboard.poll()?;
+        assert_eq!(keyboard.status(), 0);
+        let error = keyboard.read_byte().err().map(|e| e.kind());
+        assert_eq!(error, Some(io::ErrorKind::WouldBlock));
+
+        keys.push(b'a');
+        keys.push(b'b');
+        assert_eq!(keys.len(), 2);
+        keyboard.poll()?;
+        assert_eq!(keyboard.take_data(), u16::from(b'a'));
+        assert_eq!(keyboard.read_byte().ok(), Some(b'b'));
+        assert!(keys.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_channel_input_times_out() -> Result<(), VMError> {
+        let (sender, receiver) = mpsc::channel();
+        let mut keyboard = Keyboard::with_source(Box::new(ChannelInput::new(receiver)));
+        keyboard.set_timeout(Some(Duration::from_millis(10)));
+
+        keyboard.poll()?;
+        assert_eq!(keyboard.status(), 0);
+        assert!(matches!(
+            keyboard.read_byte(),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut
+        ));
+
+        assert!(sender.send(b'a').is_ok());
+        assert!(matches!(keyboard.read_byte(), Ok(b'a')));
+
+        drop(sender);
+        assert!(matches!(keyboard.poll(), Err(VMError::InputExhausted)));
+
+        Ok(())
+    }
+
+    /// Source with no key pressed until `read_byte` is called
+    struct Idle;
+
+    impl InputSource for Idle {
+        fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+            Ok(None)
+        }
+
+        fn read_byte(&mut self) -> io::Result<u8> {
+            Ok(b'z')
+        }
+    }
+
+    #[test]
+    fn test_custom_source_polls_without_blocking() -> Result<(), VMError> {
+        let mut keyboard = Keyboard::with_source(Box::new(Idle));
+
+        keyboard.poll()?;
+        assert_eq!(keyboard.status(), 0);
+        assert!(matches!(keyboard.read_byte(), Ok(b'z')));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyboard_poll_exhausted_input() {
+        let mut keyboard = Keyboard::new(Box::new(Cursor::new(Vec::new())));
+
+        assert!(matches!(keyboard.poll(), Err(VMError::InputExhausted)));
+    }
+}
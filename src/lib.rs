@@ -0,0 +1,117 @@
+//! An LC-3 virtual machine
+//!
+//! [`VM`] loads lc3as object files (and a few other image formats, see [`loader`])
+//! and runs them. The [`asm`] and [`disasm`] modules convert between assembly
+//! source and memory words, [`encode`] builds single instruction words.
+//!
+//! ```no_run
+//! use lc3_vm::prelude::*;
+//!
+//! let mut vm = VM::new();
+//! vm.load_program("examples/hello-world.obj", Endianness::Big)?;
+//! vm.run()?;
+//! # Ok::<(), VMError>(())
+//! ```
+//!
+//! Without the default `std` feature only the interpreter core is built, for
+//! `no_std` targets with an allocator: see [`machine::Cpu`].
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod asm;
+#[cfg(feature = "std")]
+pub mod breakpoints;
+#[cfg(feature = "std")]
+pub mod builder;
+#[cfg(feature = "std")]
+pub mod callstack;
+#[cfg(feature = "std")]
+pub mod console;
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod disasm;
+pub mod encode;
+pub mod errors;
+#[cfg(feature = "std")]
+pub mod expect;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod instruction;
+#[cfg(feature = "std")]
+mod journal;
+#[cfg(feature = "std")]
+pub mod keyboard;
+#[cfg(feature = "std")]
+pub mod loader;
+#[cfg(feature = "std")]
+pub mod loops;
+pub mod machine;
+pub mod memory;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod opdcodes;
+#[cfg(feature = "std")]
+mod output;
+#[cfg(feature = "std")]
+mod predecode;
+#[cfg(feature = "std")]
+pub mod profile;
+pub mod registers;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod session;
+#[cfg(feature = "std")]
+pub mod snapshot;
+pub mod stack;
+#[cfg(feature = "serde")]
+pub mod state;
+#[cfg(feature = "std")]
+pub mod symbols;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod traps;
+#[cfg(feature = "std")]
+pub mod tui;
+#[cfg(feature = "std")]
+mod vm;
+
+pub use errors::{HaltReason, TrapError, VMError};
+pub use instruction::Opcode;
+pub use machine::DEFAULT_IN_PROMPT;
+pub use memory::MR_MCR;
+#[cfg(feature = "std")]
+pub use output::Sanitize;
+#[cfg(feature = "std")]
+pub use vm::{PauseReason, RunOutcome, StepOutcome, VMState, DEFAULT_MAX_STRING_LENGTH, VM};
+
+/// The types needed to load and run a program
+#[cfg(feature = "std")]
+pub mod prelude {
+    pub use crate::breakpoints::{Breakpoint, BreakpointId, Comparison, Condition, Value};
+    pub use crate::builder::VMBuilder;
+    pub use crate::callstack::Frame;
+    pub use crate::coverage::CoverageReport;
+    pub use crate::keyboard::{EofPolicy, InputSource};
+    pub use crate::loader::{Endianness, Format, LoadedProgram};
+    pub use crate::memory::Memory;
+    pub use crate::metrics::Metrics;
+    pub use crate::profile::{HotAddress, ProfileReport};
+    pub use crate::registers::{RegisterFlags, RegisterSnapshot};
+    pub use crate::session::Session;
+    pub use crate::snapshot::Snapshot;
+    pub use crate::stack::StackBounds;
+    pub use crate::symbols::SymbolTable;
+    pub use crate::traps::{TrapHandler, VmContext};
+    pub use crate::{
+        HaltReason, Opcode, PauseReason, RunOutcome, StepOutcome, TrapError, VMError, VMState, VM,
+    };
+}
@@ -0,0 +1,210 @@
+use std::{fs::File, io::Read, path::PathBuf};
+
+use crate::errors::VMError;
+
+pub mod hex;
+pub mod ihex;
+
+/// Summary of a program image that was written into memory
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadedProgram {
+    /// Address of the first word of the image
+    pub origin: u16,
+    /// Number of words written starting at `origin`
+    pub len: u16,
+    /// Address one past the last word, wrapping to 0x0000 for images ending at 0xFFFF
+    pub end: u16,
+    /// File the image was read from, `None` for stdin and in-memory buffers
+    pub source: Option<PathBuf>,
+}
+
+impl LoadedProgram {
+    /// Describes `len` words written starting at `origin`
+    pub fn new(origin: u16, len: u16) -> Self {
+        Self {
+            origin,
+            len,
+            end: origin.wrapping_add(len),
+            source: None,
+        }
+    }
+
+    /// Returns true if `address` is one of the words written by this image
+    pub fn contains(&self, address: u16) -> bool {
+        let offset = usize::from(address.wrapping_sub(self.origin));
+
+        offset < usize::from(self.len)
+    }
+
+    /// Returns true if both images write to at least one common address
+    ///
+    /// Images wrapping past xFFFF, see [`VM::set_allow_wrap`](crate::VM::set_allow_wrap),
+    /// continue at x0000. Two ranges on that circle share an address exactly
+    /// when one of them holds the first word of the other.
+    pub fn overlaps(&self, other: &LoadedProgram) -> bool {
+        self.contains(other.origin) && other.len > 0 || other.contains(self.origin) && self.len > 0
+    }
+}
+
+/// On-disk encoding of a program image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Raw lc3as object file: big-endian origin followed by big-endian words
+    Obj,
+    /// Intel HEX records
+    Ihex,
+    /// Plain-text listing with one hex word per line, the first being the origin
+    Hex,
+    /// LC-3 assembly source, assembled on load
+    Asm,
+}
+
+impl Format {
+    /// Guesses the format of a file
+    ///
+    /// Intel HEX files are recognized by their leading ':', hex listings and
+    /// assembly sources by their `.hex` and `.asm` extensions, anything else
+    /// is treated as an lc3as object file.
+    pub fn detect(file: &str, buffer: &[u8]) -> Self {
+        if buffer.first() == Some(&b':') {
+            Format::Ihex
+        } else if file.ends_with(".hex") {
+            Format::Hex
+        } else if file.ends_with(".asm") {
+            Format::Asm
+        } else {
+            Format::Obj
+        }
+    }
+
+    /// The name [`Format::from_name`] parses
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Obj => "obj",
+            Format::Ihex => "ihex",
+            Format::Hex => "hex",
+            Format::Asm => "asm",
+        }
+    }
+
+    /// Parses a `--format` argument
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "obj" => Some(Format::Obj),
+            "ihex" => Some(Format::Ihex),
+            "hex" => Some(Format::Hex),
+            "asm" => Some(Format::Asm),
+            _ => None,
+        }
+    }
+}
+
+/// Byte order of the words in an object file
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Endianness {
+    /// Most significant byte first, as written by lc3as
+    #[default]
+    Big,
+    /// Least significant byte first, as written by some third-party tools
+    Little,
+}
+
+impl Endianness {
+    /// Combines two bytes of the image into a word
+    pub fn word(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endianness::Big => u16::from_be_bytes(bytes),
+            Endianness::Little => u16::from_le_bytes(bytes),
+        }
+    }
+
+    /// Returns true if `origin` is outside user space while its byte swap is inside
+    ///
+    /// This is what an image with the other byte order usually looks like,
+    /// e.g. x0030 for a program meant to start at x3000.
+    pub fn looks_swapped(origin: u16) -> bool {
+        let user_space = 0x3000..0xFE00;
+
+        !user_space.contains(&origin) && user_space.contains(&origin.swap_bytes())
+    }
+}
+
+/// Reads the whole contents of `file`, where `-` stands for stdin
+///
+/// # Errors
+/// * `VMError::OpenFileFailed` - If file cannot be opened
+/// * `VMError::LoadFailed` - If file cannot be read
+pub fn read_source(file: &str) -> Result<Vec<u8>, VMError> {
+    if file == "-" {
+        return read_all(&mut std::io::stdin());
+    }
+
+    let mut file = File::open(file).map_err(|_| VMError::OpenFileFailed(file.to_string()))?;
+
+    read_all(&mut file)
+}
+
+/// Reads a whole program image from `reader`
+///
+/// # Errors
+/// Returns `VMError::LoadFailed` if the reader fails
+pub fn read_all<R: Read>(reader: &mut R) -> Result<Vec<u8>, VMError> {
+    let mut buffer: Vec<u8> = Vec::new();
+
+    reader
+        .read_to_end(&mut buffer)
+        .map_err(|_| VMError::LoadFailed)?;
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlaps() {
+        let program = LoadedProgram::new(0x3000, 0x10);
+
+        assert!(program.overlaps(&LoadedProgram::new(0x300F, 1)));
+        assert!(program.overlaps(&LoadedProgram::new(0x2FF0, 0x11)));
+        assert!(!program.overlaps(&LoadedProgram::new(0x3010, 4)));
+        assert!(!program.overlaps(&LoadedProgram::new(0x3000, 0)));
+
+        // An image wrapping past xFFFF continues at x0000
+        let wrapped = LoadedProgram::new(0xFFFE, 4);
+        assert!(wrapped.overlaps(&LoadedProgram::new(0x0001, 1)));
+        assert!(LoadedProgram::new(0x0000, 1).overlaps(&wrapped));
+        assert!(!wrapped.overlaps(&LoadedProgram::new(0x0002, 1)));
+    }
+
+    #[test]
+    fn test_endianness_word() {
+        assert_eq!(Endianness::Big.word([0x30, 0x00]), 0x3000);
+        assert_eq!(Endianness::Little.word([0x30, 0x00]), 0x0030);
+        assert_eq!(Endianness::default(), Endianness::Big);
+    }
+
+    #[test]
+    fn test_endianness_looks_swapped() {
+        assert!(Endianness::looks_swapped(0x0030));
+        assert!(Endianness::looks_swapped(0x00FD));
+        assert!(!Endianness::looks_swapped(0x3000));
+        assert!(!Endianness::looks_swapped(0x0000));
+        // x00FF swaps to xFF00, device space, no better
+        assert!(!Endianness::looks_swapped(0x00FF));
+    }
+
+    #[test]
+    fn test_format_detect() {
+        assert_eq!(Format::detect("prog.hex", b":00000001FF"), Format::Ihex);
+        assert_eq!(Format::detect("prog.hex", b"3000\nF025\n"), Format::Hex);
+        assert_eq!(Format::detect("prog.asm", b".ORIG x3000\n"), Format::Asm);
+        assert_eq!(
+            Format::detect("prog.obj", &[0x30, 0x00, 0xF0, 0x25]),
+            Format::Obj
+        );
+        assert_eq!(Format::detect("-", &[]), Format::Obj);
+    }
+}
@@ -0,0 +1,95 @@
+//! Plain-text hex listings
+//!
+//! One hex word per line, the first word is the origin and every following
+//! word is loaded at consecutive addresses. Blank lines and `;` comments are
+//! ignored, and a word may be written with an optional `x` or `0x` prefix.
+
+use crate::errors::VMError;
+
+/// Parses a hex listing into its origin and words
+///
+/// # Errors
+/// * `VMError::InvalidHexListing` - With the 1-based line number of the first unparsable token
+/// * `VMError::MalformedObject` - If the listing has no origin line
+pub fn parse(text: &str) -> Result<(u16, Vec<u16>), VMError> {
+    let mut words: Vec<u16> = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let content = line.split(';').next().unwrap_or_default();
+
+        for token in content.split_whitespace() {
+            let word = parse_word(token).ok_or_else(|| VMError::InvalidHexListing {
+                line: index.saturating_add(1),
+                token: token.to_string(),
+            })?;
+            words.push(word);
+        }
+    }
+
+    if words.is_empty() {
+        return Err(VMError::MalformedObject {
+            len: text.len(),
+            reason: "hex listing has no origin line",
+        });
+    }
+
+    let origin = words.remove(0);
+
+    Ok((origin, words))
+}
+
+fn parse_word(token: &str) -> Option<u16> {
+    let digits = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix('x'))
+        .unwrap_or(token);
+
+    if digits.is_empty() || digits.len() > 4 {
+        return None;
+    }
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listing() -> Result<(), VMError> {
+        let text = "; simple add\n3000\n\n5020 ; AND R0, R0, #0\nx1025\n0xF025\n";
+
+        let (origin, words) = parse(text)?;
+
+        assert_eq!(origin, 0x3000);
+        assert_eq!(words, vec![0x5020, 0x1025, 0xF025]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_token() {
+        let text = "3000\n5020\n10G5\n";
+
+        assert!(matches!(
+            parse(text),
+            Err(VMError::InvalidHexListing { line: 3, token }) if token == "10G5"
+        ));
+    }
+
+    #[test]
+    fn test_parse_word_too_wide() {
+        assert!(matches!(
+            parse("3000\n12345\n"),
+            Err(VMError::InvalidHexListing { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_empty_listing() {
+        assert!(matches!(
+            parse("; nothing here\n\n"),
+            Err(VMError::MalformedObject { .. })
+        ));
+    }
+}
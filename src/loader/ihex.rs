@@ -0,0 +1,178 @@
+//! Intel HEX object format
+//!
+//! Each record is a line of the form `:LLAAAATT<data>CC` where `LL` is the
+//! data length, `AAAA` the byte address, `TT` the record type and `CC` the
+//! two's complement checksum of all preceding bytes. Only data (00) and
+//! end-of-file (01) records are supported.
+//!
+//! Byte addresses are mapped onto LC-3 words as big-endian pairs: the byte
+//! at address `2n` is the high byte of word `n` and `2n + 1` its low byte.
+
+use std::collections::BTreeMap;
+
+use crate::errors::VMError;
+
+const RECORD_DATA: u8 = 0x00;
+const RECORD_EOF: u8 = 0x01;
+
+/// Parses an Intel HEX file into contiguous blocks of words
+///
+/// # Returns
+/// One `(origin, words)` pair per contiguous run of word addresses, in
+/// ascending address order
+///
+/// # Errors
+/// Returns `VMError::InvalidIntelHex` with the 1-based line number of the
+/// first malformed record
+pub fn parse(text: &str) -> Result<Vec<(u16, Vec<u16>)>, VMError> {
+    let mut bytes: BTreeMap<usize, u8> = BTreeMap::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index.saturating_add(1);
+        let error = |reason| VMError::InvalidIntelHex {
+            line: line_number,
+            reason,
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let record = line
+            .strip_prefix(':')
+            .ok_or_else(|| error("record does not start with ':'"))?;
+        let record = decode_hex(record).ok_or_else(|| error("invalid hex digits"))?;
+
+        let (header, rest) = record
+            .split_first_chunk::<4>()
+            .ok_or_else(|| error("record is too short"))?;
+        let [len, address_high, address_low, record_type] = *header;
+
+        if rest.len() != usize::from(len).saturating_add(1) {
+            return Err(error("record length does not match its byte count"));
+        }
+
+        let checksum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if checksum != 0 {
+            return Err(error("checksum mismatch"));
+        }
+
+        match record_type {
+            RECORD_DATA => {
+                let address = usize::from(u16::from_be_bytes([address_high, address_low]));
+                let data = rest.split_last().map_or(&[][..], |(_, data)| data);
+
+                for (offset, byte) in data.iter().enumerate() {
+                    bytes.insert(address.saturating_add(offset), *byte);
+                }
+            }
+            RECORD_EOF => break,
+            _ => return Err(error("unsupported record type")),
+        }
+    }
+
+    Ok(into_blocks(&bytes))
+}
+
+/// Assembles byte-addressed data into runs of big-endian words
+///
+/// A word with only one of its bytes present keeps zero in the other half.
+fn into_blocks(bytes: &BTreeMap<usize, u8>) -> Vec<(u16, Vec<u16>)> {
+    let mut words: BTreeMap<u16, u16> = BTreeMap::new();
+
+    for (&address, &byte) in bytes {
+        let Ok(word_address) = u16::try_from(address >> 1) else {
+            continue;
+        };
+        let word = words.entry(word_address).or_insert(0);
+
+        if address & 1 == 0 {
+            *word |= u16::from(byte) << 8;
+        } else {
+            *word |= u16::from(byte);
+        }
+    }
+
+    let mut blocks: Vec<(u16, Vec<u16>)> = Vec::new();
+    let mut next_address: Option<u16> = None;
+
+    for (address, word) in words {
+        match blocks.last_mut() {
+            Some((_, block)) if next_address == Some(address) => block.push(word),
+            _ => blocks.push((address, vec![word])),
+        }
+        next_address = address.checked_add(1);
+    }
+
+    blocks
+}
+
+fn decode_hex(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+
+    digits
+        .as_bytes()
+        .chunks_exact(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multiple_records() -> Result<(), VMError> {
+        // simple_add split over two data records at byte address 0x6000 (word x3000)
+        let text = ":0660000050201025526043\n:0660060012631401F025F5\n:00000001FF\n";
+
+        let blocks = parse(text)?;
+
+        assert_eq!(
+            blocks,
+            vec![(0x3000, vec![0x5020, 0x1025, 0x5260, 0x1263, 0x1401, 0xF025])]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_bad_checksum() {
+        let text = ":0660000050201025526044\n:00000001FF\n";
+
+        assert!(matches!(
+            parse(text),
+            Err(VMError::InvalidIntelHex { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_record_straddling_word_boundary() -> Result<(), VMError> {
+        // Three bytes starting at the odd byte address 0x6001: the first lands in
+        // the low half of x3000, the next two form x3001
+        let text = ":03600100AABBCC6B\n:00000001FF\n";
+
+        let blocks = parse(text)?;
+
+        assert_eq!(blocks, vec![(0x3000, vec![0x00AA, 0xBBCC])]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_disjoint_records() -> Result<(), VMError> {
+        let text = ":02600000ABCD26\n:02800000123438\n:00000001FF\n";
+
+        let blocks = parse(text)?;
+
+        assert_eq!(blocks, vec![(0x3000, vec![0xABCD]), (0x4000, vec![0x1234])]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+//! Detection of tight loops that can never exit
+
+use std::collections::VecDeque;
+
+use crate::registers::Registers;
+
+/// Number of quiet instructions after which a repeating loop is reported
+pub const DEFAULT_LOOP_THRESHOLD: u64 = 10_000;
+
+/// Longest loop, in instructions, the detector recognizes
+const MAX_LOOP_LEN: usize = 16;
+
+/// Watches the register state after every instruction for an exact repeat
+///
+/// An instruction without I/O, trap or store is "quiet": it only changes the
+/// registers. Once the registers (PC and flags included) repeat during a run of
+/// quiet instructions, the machine is back in a state it already was in with
+/// the same memory, so it will go around the same loop forever.
+#[derive(Debug, Clone)]
+pub(crate) struct LoopDetector {
+    threshold: u64,
+    quiet: u64,
+    recent: VecDeque<Registers>,
+}
+
+impl LoopDetector {
+    /// Creates a detector that reports loops after `threshold` quiet instructions
+    pub(crate) fn new(threshold: u64) -> Self {
+        Self {
+            threshold,
+            quiet: 0,
+            recent: VecDeque::with_capacity(MAX_LOOP_LEN),
+        }
+    }
+
+    /// Forgets the quiet run, after an instruction with side effects
+    pub(crate) fn reset(&mut self) {
+        self.quiet = 0;
+        self.recent.clear();
+    }
+
+    /// Records the registers after a quiet instruction
+    ///
+    /// # Returns
+    /// The sorted addresses of the loop once the state repeats and at least
+    /// `threshold` quiet instructions ran
+    pub(crate) fn observe(&mut self, registers: &Registers) -> Option<Vec<u16>> {
+        self.quiet = self.quiet.saturating_add(1);
+
+        if let Some(start) = self.recent.iter().position(|seen| seen == registers) {
+            if self.quiet >= self.threshold {
+                let mut pcs: Vec<u16> = self.recent.iter().skip(start).map(|r| r.pc).collect();
+                pcs.sort_unstable();
+                pcs.dedup();
+                return Some(pcs);
+            }
+        }
+
+        if self.recent.len() == MAX_LOOP_LEN {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(registers.clone());
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(pc: u16) -> Registers {
+        let mut registers = Registers::default();
+        registers.pc = pc;
+        registers
+    }
+
+    #[test]
+    fn test_repeat_reported_after_threshold() {
+        let mut detector = LoopDetector::new(5);
+
+        let mut found = None;
+        for pc in [0x3001, 0x3000].iter().cycle().take(10) {
+            found = detector.observe(&at(*pc));
+            if found.is_some() {
+                break;
+            }
+        }
+
+        assert_eq!(found, Some(vec![0x3000, 0x3001]));
+        assert_eq!(detector.quiet, 5);
+    }
+
+    #[test]
+    fn test_reset_restarts_count() {
+        let mut detector = LoopDetector::new(3);
+        assert_eq!(detector.observe(&at(0x3000)), None);
+        assert_eq!(detector.observe(&at(0x3000)), None);
+        detector.reset();
+        assert_eq!(detector.observe(&at(0x3000)), None);
+        assert_eq!(detector.observe(&at(0x3000)), None);
+        assert_eq!(detector.observe(&at(0x3000)), Some(vec![0x3000]));
+    }
+}
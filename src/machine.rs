@@ -0,0 +1,723 @@
+//! The interpreter core, which builds without std
+//!
+//! [`execute`] gives a decoded [`Instruction`] its meaning on any [`Machine`]:
+//! something with registers, a memory to read and write, and a way to run a
+//! TRAP. [`VM`](crate::VM) is one, with its devices, debugger and host traps.
+//! [`Cpu`] is the smallest one, for `no_std` targets with an allocator: plain
+//! memory, the keyboard registers, and the console traps routed to a [`HostIo`]
+//! the firmware provides.
+//!
+//! ```
+//! use lc3_vm::machine::{Cpu, HostIo};
+//! use lc3_vm::VMError;
+//!
+//! /// A console without a keyboard, printing to nowhere
+//! struct Silent;
+//!
+//! impl HostIo for Silent {
+//!     fn read_key(&mut self) -> Result<u8, VMError> {
+//!         Err(VMError::InputExhausted)
+//!     }
+//!     fn poll_key(&mut self) -> Result<Option<u8>, VMError> {
+//!         Ok(None)
+//!     }
+//!     fn put_char(&mut self, _c: u8) -> Result<(), VMError> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut cpu = Cpu::new(Box::new(Silent));
+//! cpu.load_image(&[0x30, 0x00, 0x14, 0xA3, 0xF0, 0x25])?; // ADD R2, R2, #3 / HALT
+//! cpu.run(100)?;
+//! assert_eq!(cpu.registers().get(2)?, 3);
+//! # Ok::<(), VMError>(())
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::errors::{HaltReason, TrapError, VMError};
+use crate::instruction::{Instruction, Opcode, Operand};
+use crate::memory::{Memory, MEMORY_MAX, MR_DDR, MR_DSR, MR_KBDR, MR_KBSR, MR_MCR};
+use crate::registers::{RegisterFlags, Registers};
+
+/// Default for [`VM::set_in_prompt`](crate::VM::set_in_prompt), and what [`Cpu`] prints for IN
+pub const DEFAULT_IN_PROMPT: &str = "Enter a character: ";
+
+/// What an instruction needs from the machine it runs on
+pub trait Machine {
+    /// R0-R7, the PC and the condition flags
+    fn registers_mut(&mut self) -> &mut Registers;
+
+    /// Reads the word at `address`, going through the devices mapped there
+    fn read_memory(&mut self, address: u16) -> Result<u16, VMError>;
+
+    /// Reads the pointer cell of LDI or STI at `address`
+    ///
+    /// The cell holds an address, not data for a device, so it's always read
+    /// from plain RAM: a pointer cell at KBSR or KBDR neither polls the keyboard
+    /// nor consumes a key. Only the access through the pointer, like the single
+    /// access of LD, LDR, ST and STR, goes through the devices.
+    fn read_pointer(&mut self, address: u16) -> u16;
+
+    /// Writes the word at `address`, going through the devices mapped there
+    fn write_memory(&mut self, address: u16, value: u16) -> Result<(), VMError>;
+
+    /// Runs the trap routine of `vector`, the PC already pointing past the TRAP
+    fn trap(&mut self, vector: u8) -> Result<(), VMError>;
+}
+
+/// The console a [`Cpu`] runs its traps and keyboard registers on
+pub trait HostIo {
+    /// Waits for a key, for GETC and IN
+    fn read_key(&mut self) -> Result<u8, VMError>;
+
+    /// Returns a key if one was pressed, without waiting, for KBSR
+    fn poll_key(&mut self) -> Result<Option<u8>, VMError>;
+
+    /// Prints a character, for OUT, PUTS, PUTSP and IN
+    fn put_char(&mut self, c: u8) -> Result<(), VMError>;
+
+    /// Prints what IN shows before it reads a key, [`DEFAULT_IN_PROMPT`] unless overridden
+    fn in_prompt(&mut self) -> Result<(), VMError> {
+        DEFAULT_IN_PROMPT.bytes().try_for_each(|c| self.put_char(c))
+    }
+
+    /// Whether GETC prints the key it read, as IN always does, false unless overridden
+    fn echo_getc(&self) -> bool {
+        false
+    }
+}
+
+/// Second operand of ADD and AND
+#[inline]
+fn operand<M: Machine>(machine: &mut M, operand: Operand) -> u16 {
+    match operand {
+        Operand::Register(r) => machine.registers_mut().read(r),
+        Operand::Immediate(imm5) => u16::from_ne_bytes(imm5.to_ne_bytes()),
+    }
+}
+
+/// Sets `dr` and the condition flags
+#[inline]
+fn set<M: Machine>(machine: &mut M, dr: usize, value: u16) {
+    let registers = machine.registers_mut();
+    registers.set(dr, value);
+    registers.update_flags(dr);
+}
+
+/// Executes a decoded instruction, the PC already pointing past it
+///
+/// # Errors
+/// RTI fails with `VMError::UnimplemedOpcode`, otherwise only the memory and
+/// traps of the machine fail
+#[inline]
+pub fn execute<M: Machine>(machine: &mut M, instruction: Instruction) -> Result<(), VMError> {
+    let pc = machine.registers_mut().pc;
+    match instruction {
+        Instruction::Br { n, z, p, offset } => {
+            let registers = machine.registers_mut();
+            let taken = match registers.condition {
+                RegisterFlags::Neg => n,
+                RegisterFlags::Zro => z,
+                RegisterFlags::Pos => p,
+            };
+            if taken {
+                registers.pc = pc.wrapping_add_signed(offset);
+            }
+        }
+        Instruction::Add {
+            dr,
+            sr1,
+            operand: second,
+        } => {
+            let value = machine
+                .registers_mut()
+                .read(sr1)
+                .wrapping_add(operand(machine, second));
+            set(machine, dr, value);
+        }
+        Instruction::And {
+            dr,
+            sr1,
+            operand: second,
+        } => {
+            let value = machine.registers_mut().read(sr1) & operand(machine, second);
+            set(machine, dr, value);
+        }
+        Instruction::Not { dr, sr } => {
+            let value = !machine.registers_mut().read(sr);
+            set(machine, dr, value);
+        }
+        Instruction::Ld { dr, offset } => {
+            let value = machine.read_memory(pc.wrapping_add_signed(offset))?;
+            set(machine, dr, value);
+        }
+        Instruction::Ldi { dr, offset } => {
+            let address = machine.read_pointer(pc.wrapping_add_signed(offset));
+            let value = machine.read_memory(address)?;
+            set(machine, dr, value);
+        }
+        Instruction::Ldr { dr, base, offset } => {
+            let address = machine
+                .registers_mut()
+                .read(base)
+                .wrapping_add_signed(offset);
+            let value = machine.read_memory(address)?;
+            set(machine, dr, value);
+        }
+        Instruction::Lea { dr, offset } => set(machine, dr, pc.wrapping_add_signed(offset)),
+        Instruction::St { sr, offset } => {
+            let value = machine.registers_mut().read(sr);
+            machine.write_memory(pc.wrapping_add_signed(offset), value)?;
+        }
+        Instruction::Sti { sr, offset } => {
+            let address = machine.read_pointer(pc.wrapping_add_signed(offset));
+            let value = machine.registers_mut().read(sr);
+            machine.write_memory(address, value)?;
+        }
+        Instruction::Str { sr, base, offset } => {
+            let registers = machine.registers_mut();
+            let address = registers.read(base).wrapping_add_signed(offset);
+            let value = registers.read(sr);
+            machine.write_memory(address, value)?;
+        }
+        Instruction::Jsr { offset } => {
+            let registers = machine.registers_mut();
+            registers.set(7, pc);
+            registers.pc = pc.wrapping_add_signed(offset);
+        }
+        Instruction::Jsrr { base } => {
+            let registers = machine.registers_mut();
+            registers.set(7, pc);
+            registers.pc = registers.read(base);
+        }
+        Instruction::Jmp { base } => {
+            let registers = machine.registers_mut();
+            registers.pc = registers.read(base);
+        }
+        Instruction::Rti => return Err(VMError::UnimplemedOpcode(Opcode::Rti)),
+        Instruction::Trap { vector } => machine.trap(vector)?,
+    }
+
+    Ok(())
+}
+
+/// Runs the built-in routine of trap x20-x25, the PC already pointing past the TRAP
+///
+/// Every machine shares these routines, reading keys from and printing to its
+/// [`HostIo`]. Strings of PUTS and PUTSP fail past `max_string_length`
+/// characters. HALT doesn't stop anything by itself, it returns true for the
+/// machine to stop.
+///
+/// # Errors
+/// Returns `TrapError::InvalidTrapVector` for any other vector, or the error
+/// of the console or of reading memory
+pub(crate) fn builtin_trap<M: Machine + HostIo>(
+    machine: &mut M,
+    vector: u8,
+    max_string_length: usize,
+) -> Result<bool, VMError> {
+    match vector {
+        0x20 => {
+            // GETC - Read a character into R0 without echoing it
+            let c = machine.read_key()?;
+            set(machine, 0, c.into());
+            if machine.echo_getc() {
+                machine.put_char(c)?;
+            }
+        }
+        0x21 => {
+            // OUT - Write the character in R0[7:0], the high 8 bits are ignored
+            let [_, c] = machine.registers_mut().get(0)?.to_be_bytes();
+            machine.put_char(c)?;
+        }
+        0x22 | 0x24 => {
+            // PUTS and PUTSP - Write the zero-terminated string at R0
+            let start = machine.registers_mut().get(0)?;
+            let text = read_string(machine, start, vector == 0x24, max_string_length)?;
+            text.into_iter().try_for_each(|c| machine.put_char(c))?;
+        }
+        0x23 => {
+            // IN - Prompt, then read a character into R0 and echo it
+            machine.in_prompt()?;
+            let c = machine.read_key()?;
+            machine.put_char(c)?;
+            machine.put_char(b'\n')?;
+            set(machine, 0, c.into());
+        }
+        // HALT
+        0x25 => return Ok(true),
+        _ => {
+            return Err(VMError::TrapError(TrapError::InvalidTrapVector(u16::from(
+                vector,
+            ))))
+        }
+    }
+
+    Ok(false)
+}
+
+/// Reads the zero-terminated string at `start` for PUTS, or for PUTSP when `packed`
+///
+/// PUTSP strings hold two characters per word, the low byte [7:0] first and then the
+/// high byte [15:8]. A high byte of x00 ends an odd-length string after its low byte,
+/// an even-length one ends with a word of x0000.
+///
+/// Nothing is printed for a string without a terminator, so the whole string is read
+/// before any of it is written.
+///
+/// # Errors
+/// Returns `TrapError::UnterminatedString` once the string is longer than
+/// `max_length` characters or wraps around the whole address space
+pub(crate) fn read_string<M: Machine>(
+    machine: &mut M,
+    start: u16,
+    packed: bool,
+    max_length: usize,
+) -> Result<Vec<u8>, VMError> {
+    let unterminated = VMError::TrapError(TrapError::UnterminatedString { start });
+    let mut text = Vec::new();
+    let mut address = start;
+
+    for _ in 0..MEMORY_MAX {
+        let value = machine.read_memory(address)?;
+        if value == 0 {
+            return Ok(text);
+        }
+
+        let [high, low] = value.to_be_bytes();
+        // A packed word with a zero high byte holds the last character
+        let last = packed && high == 0;
+        if !packed || low != 0 {
+            text.push(low);
+        }
+        if packed && !last {
+            text.push(high);
+        }
+
+        if text.len() > max_length {
+            log::warn!(
+                "string at x{:04X} has no terminator within {} characters",
+                start,
+                max_length
+            );
+            return Err(unterminated);
+        }
+        if last {
+            return Ok(text);
+        }
+
+        address = address.wrapping_add(1);
+    }
+
+    log::warn!(
+        "string at x{:04X} wraps around memory without a terminator",
+        start
+    );
+    Err(unterminated)
+}
+
+/// An LC-3 with nothing but memory, registers and a console
+///
+/// Device registers are KBSR and KBDR, polling [`HostIo::poll_key`], and the
+/// MCR, whose bit 15 stops the clock. The traps x20-x25 are the built-in
+/// routines of [`VM`](crate::VM), printing and reading through the [`HostIo`],
+/// except that HALT prints no banner. Other vectors fail.
+pub struct Cpu {
+    memory: Memory,
+    registers: Registers,
+    host: Box<dyn HostIo>,
+    /// A key KBSR received that KBDR wasn't read for yet
+    key: Option<u8>,
+    halted: Option<HaltReason>,
+}
+
+impl Cpu {
+    /// Creates a CPU with zeroed memory and the PC at x3000, using `host` as its console
+    pub fn new(host: Box<dyn HostIo>) -> Self {
+        Self {
+            memory: Memory::new(),
+            registers: Registers::default(),
+            host,
+            key: None,
+            halted: None,
+        }
+    }
+
+    /// Loads a big-endian lc3as `.obj` image and points the PC at its origin
+    ///
+    /// # Returns
+    /// The origin of the image
+    ///
+    /// # Errors
+    /// * `VMError::MalformedObject` - If the image is too short or has an odd length
+    /// * `VMError::ProgramTooLarge` - If the image would wrap past the end of memory
+    pub fn load_image(&mut self, image: &[u8]) -> Result<u16, VMError> {
+        let malformed = |reason| VMError::MalformedObject {
+            len: image.len(),
+            reason,
+        };
+        let Some((&[high, low], body)) = image.split_first_chunk() else {
+            return Err(malformed("file is shorter than the 2-byte origin"));
+        };
+        if !body.len().is_multiple_of(2) {
+            return Err(malformed(
+                "odd number of bytes, the last word is incomplete",
+            ));
+        }
+
+        let origin = u16::from_be_bytes([high, low]);
+        let words = body.len() / 2;
+        if usize::from(origin).saturating_add(words) > MEMORY_MAX {
+            return Err(VMError::ProgramTooLarge { origin, words });
+        }
+
+        let mut address = origin;
+        for pair in body.chunks_exact(2) {
+            if let [high, low] = *pair {
+                self.memory
+                    .write_ram(address, u16::from_be_bytes([high, low]));
+            }
+            address = address.wrapping_add(1);
+        }
+        self.registers.pc = origin;
+
+        Ok(origin)
+    }
+
+    /// Fetches, decodes and executes one instruction
+    ///
+    /// # Errors
+    /// * `VMError::Halted` - If the program already halted
+    /// * `VMError::AtInstruction` - If the instruction failed, with the reason
+    pub fn step(&mut self) -> Result<(), VMError> {
+        if let Some(reason) = &self.halted {
+            return Err(VMError::Halted(reason.clone()));
+        }
+
+        let pc = self.registers.pc;
+        let at_instruction = |instr, source| VMError::AtInstruction {
+            pc,
+            instr,
+            source: Box::new(source),
+        };
+        let word = self.read_memory(pc).map_err(|e| at_instruction(0, e))?;
+        self.registers.pc = pc.wrapping_add(1);
+
+        Instruction::decode_lenient(word)
+            .map_err(|_| VMError::UnimplemedOpcode(Opcode::Res))
+            .and_then(|instruction| execute(self, instruction))
+            .map_err(|e| at_instruction(word, e))
+    }
+
+    /// Runs until the program halts or `limit` instructions ran
+    ///
+    /// # Returns
+    /// How many instructions ran
+    ///
+    /// # Errors
+    /// The error of the instruction that failed, see [`Cpu::step`]
+    pub fn run(&mut self, limit: u64) -> Result<u64, VMError> {
+        let mut executed: u64 = 0;
+        while executed < limit && self.halted.is_none() {
+            self.step()?;
+            executed = executed.saturating_add(1);
+        }
+
+        Ok(executed)
+    }
+
+    /// Why the program halted, `None` while it runs
+    pub fn halted(&self) -> Option<&HaltReason> {
+        self.halted.as_ref()
+    }
+
+    /// R0-R7, the PC and the condition flags
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// The memory, device registers reading as the RAM behind them
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+}
+
+/// The console traps of a `Cpu` go to the firmware's [`HostIo`]
+impl HostIo for Cpu {
+    fn read_key(&mut self) -> Result<u8, VMError> {
+        self.host.read_key()
+    }
+
+    fn poll_key(&mut self) -> Result<Option<u8>, VMError> {
+        self.host.poll_key()
+    }
+
+    fn put_char(&mut self, c: u8) -> Result<(), VMError> {
+        self.host.put_char(c)
+    }
+
+    fn in_prompt(&mut self) -> Result<(), VMError> {
+        self.host.in_prompt()
+    }
+
+    fn echo_getc(&self) -> bool {
+        self.host.echo_getc()
+    }
+}
+
+impl Machine for Cpu {
+    #[inline]
+    fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    fn read_memory(&mut self, address: u16) -> Result<u16, VMError> {
+        match address {
+            MR_KBSR => {
+                if self.key.is_none() {
+                    self.key = self.host.poll_key()?;
+                }
+                Ok(if self.key.is_some() { 1 << 15 } else { 0 })
+            }
+            MR_KBDR => Ok(self.key.take().map(u16::from).unwrap_or_default()),
+            MR_DSR => Ok(1 << 15),
+            _ => Ok(self.memory.read_ram(address)),
+        }
+    }
+
+    #[inline]
+    fn read_pointer(&mut self, address: u16) -> u16 {
+        self.memory.read_ram(address)
+    }
+
+    fn write_memory(&mut self, address: u16, value: u16) -> Result<(), VMError> {
+        if address == MR_MCR && value & 0x8000 == 0 {
+            self.halted = Some(HaltReason::McrCleared);
+        }
+        if address == MR_DDR {
+            let [_, c] = value.to_be_bytes();
+            self.host.put_char(c)?;
+        }
+        self.memory.write_ram(address, value);
+
+        Ok(())
+    }
+
+    fn trap(&mut self, vector: u8) -> Result<(), VMError> {
+        let pc = self.registers.pc;
+        self.registers.set(7, pc);
+
+        if builtin_trap(self, vector, MEMORY_MAX)? {
+            self.halted = Some(HaltReason::TrapHalt);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Endianness;
+    use crate::VM;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    /// Keys given up front, and the output shared with the test
+    struct Console {
+        keys: VecDeque<u8>,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl HostIo for Console {
+        fn read_key(&mut self) -> Result<u8, VMError> {
+            self.keys.pop_front().ok_or(VMError::InputExhausted)
+        }
+
+        fn poll_key(&mut self) -> Result<Option<u8>, VMError> {
+            Ok(self.keys.pop_front())
+        }
+
+        fn put_char(&mut self, c: u8) -> Result<(), VMError> {
+            self.output.borrow_mut().push(c);
+            Ok(())
+        }
+    }
+
+    fn cpu(keys: &[u8]) -> (Cpu, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let console = Console {
+            keys: keys.iter().copied().collect(),
+            output: Rc::clone(&output),
+        };
+
+        (Cpu::new(Box::new(console)), output)
+    }
+
+    /// The same program on a `Cpu` and a `VM`, both fed `keys`
+    fn run_both(image: &[u8], keys: &[u8]) -> Result<(Cpu, Vec<u8>, VM), VMError> {
+        let (mut cpu, output) = cpu(keys);
+        cpu.load_image(image)?;
+        cpu.run(10_000)?;
+
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_halt_banner(false);
+        vm.set_input_source(Box::new(crate::keyboard::ScriptedInput::new(keys.to_vec())));
+        vm.load_bytes(image, Endianness::Big)?;
+        vm.run_with_limit(10_000)?;
+
+        let output = output.borrow().clone();
+        Ok((cpu, output, vm))
+    }
+
+    #[test]
+    fn test_cpu_runs_like_the_vm() -> Result<(), VMError> {
+        let program = crate::asm::assemble(
+            "        .ORIG x3000
+        LEA R0, HELLO
+        PUTS
+        GETC
+        OUT
+        IN
+        LEA R0, PACKED
+        PUTSP
+POLL    LDI R1, KBSR
+        BRzp POLL
+        LDI R2, KBDR
+        LD R3, COUNT
+        JSR DOUBLE
+        ADD R4, R3, #-7
+        NOT R5, R4
+        HALT
+DOUBLE  ADD R3, R3, R3
+        RET
+COUNT   .FILL #21
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+HELLO   .STRINGZ \"Hi \"
+PACKED  .FILL x6261
+        .FILL x0063
+        .END
+",
+        )?;
+        let (cpu, output, mut vm) = run_both(&program.to_obj(), b"xyz")?;
+
+        assert_eq!(cpu.halted(), Some(&HaltReason::TrapHalt));
+        assert_eq!(output, b"Hi xEnter a character: y\nabc");
+        assert_eq!(output, vm.take_output());
+        assert_eq!(cpu.registers(), &vm.registers);
+        assert_eq!(cpu.registers().get(2)?, u16::from(b'z'));
+        assert_eq!(cpu.registers().get(3)?, 42);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_indirect_pointers_are_plain_ram() -> Result<(), VMError> {
+        // LDI's pointer cell sits at KBSR and STI's points at DDR
+        let program = crate::asm::assemble(
+            "        .ORIG xFDF0
+        LDI R1, CELL
+        LD R0, BANG
+        STI R0, DISPLAY
+        GETC
+        HALT
+BANG    .FILL x0021
+VALUE   .FILL x1234
+DISPLAY .FILL xFE06
+        .BLKW 8
+CELL    .FILL xFDF6
+        .END
+",
+        )?;
+        let (cpu, output, mut vm) = run_both(&program.to_obj(), b"k")?;
+
+        assert_eq!(cpu.halted(), Some(&HaltReason::TrapHalt));
+        // Fetching the pointer at xFE00 left the key for GETC
+        assert_eq!(cpu.registers().get(1)?, 0x1234);
+        assert_eq!(cpu.registers().get(0)?, u16::from(b'k'));
+        assert_eq!(output, b"!");
+        assert_eq!(output, vm.take_output());
+        assert_eq!(cpu.registers(), &vm.registers);
+        // Only the write to DDR reached a device
+        assert_eq!(vm.metrics().device_accesses, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cpu_halts_when_the_mcr_is_cleared() -> Result<(), VMError> {
+        // AND R0, R0, #0 / STI R0, MCR / ADD R1, R1, #1 / xFFFE
+        let (mut cpu, _) = cpu(b"");
+        cpu.load_image(&[0x30, 0x00, 0x50, 0x20, 0xB0, 0x01, 0x12, 0x61, 0xFF, 0xFE])?;
+
+        assert_eq!(cpu.run(100)?, 2);
+        assert_eq!(cpu.halted(), Some(&HaltReason::McrCleared));
+        assert_eq!(cpu.registers().get(1)?, 0);
+        assert!(matches!(
+            cpu.step(),
+            Err(VMError::Halted(HaltReason::McrCleared))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cpu_errors() -> Result<(), VMError> {
+        let (mut cpu, _) = cpu(b"");
+        assert!(matches!(
+            cpu.load_image(&[0x30]),
+            Err(VMError::MalformedObject { len: 1, .. })
+        ));
+        assert!(matches!(
+            cpu.load_image(&[0xFF, 0xFF, 0, 0, 0, 0]),
+            Err(VMError::ProgramTooLarge {
+                origin: 0xFFFF,
+                words: 2
+            })
+        ));
+
+        // A reserved opcode, then TRAP x26 and GETC without input
+        for (word, root) in [
+            (0xD000, VMError::UnimplemedOpcode(Opcode::Res)),
+            (
+                0xF026,
+                VMError::TrapError(TrapError::InvalidTrapVector(0x26)),
+            ),
+            (0xF020, VMError::InputExhausted),
+        ] {
+            let [high, low] = u16::to_be_bytes(word);
+            cpu.load_image(&[0x30, 0x00, high, low])?;
+            let error = cpu.step().err();
+            assert!(
+                matches!(&error, Some(VMError::AtInstruction { pc: 0x3000, instr, source })
+                    if *instr == word && format!("{source:?}") == format!("{root:?}")),
+                "{:?}",
+                error
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cpu_decodes_unused_fields_like_the_vm() -> Result<(), VMError> {
+        // BR without conditions, ADD with bits 4-3 set, NOT with zero low bits,
+        // ADD R2, R2, #5, LEA R4, then JMP R4 with its unused bits set over a
+        // skipped ADD, and TRAP x25 with bits 11-8 set
+        let words: [u16; 9] = [
+            0x3000, 0x01FF, 0x121A, 0x9640, 0x14A5, 0xE802, 0xCF07, 0x1261, 0xFF25,
+        ];
+        let image: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let (cpu, _, vm) = run_both(&image, b"")?;
+
+        assert_eq!(cpu.halted(), Some(&HaltReason::TrapHalt));
+        assert_eq!(cpu.registers(), &vm.registers);
+
+        Ok(())
+    }
+}
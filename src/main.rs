@@ -1,325 +1,775 @@
-mod errors;
-mod memory;
-mod opdcodes;
-mod registers;
-
-use std::{fs::File, io::Read};
-
-use errors::{TrapError, VMError};
-use memory::Memory;
-use opdcodes::*;
-use registers::Registers;
-use termios::*;
-
-struct VM {
-    memory: Memory,
-    registers: Registers,
-    state: VMState,
-}
-
-#[derive(Debug, PartialEq)]
-enum VMState {
-    Running,
-    Halted,
-}
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, IntoRawFd};
+use std::time::{Duration, Instant};
+
+use lc3_vm::builder::VMBuilder;
+#[cfg(unix)]
+use lc3_vm::console::UnixConsole;
+use lc3_vm::debugger::{self, LineDebugger};
+use lc3_vm::keyboard::ChannelInput;
+use lc3_vm::loader::Format;
+use lc3_vm::session::Session;
+use lc3_vm::symbols::SymbolTable;
+use lc3_vm::{asm, disasm, expect, HaltReason, PauseReason, RunOutcome, TrapError, VMError, VM};
+
+mod batch;
+mod cli;
+#[cfg(unix)]
+mod screen;
+mod terminal;
+
+use terminal::EXIT_INTERRUPTED;
+
+/// Exit status when `--limit` stops a program that didn't halt, as used by timeout(1)
+const EXIT_LIMIT_REACHED: i32 = 124;
+
+/// Exit status when the program calls a trap vector nobody handles
+const EXIT_INVALID_TRAP: i32 = 2;
+
+/// Exit status when `--break` stops the program, as for a process stopped by SIGTRAP
+const EXIT_BREAKPOINT: i32 = 5;
+
+/// `lc3-vm asm prog.asm [-o prog.obj]`: assembles a source file into an lc3as object file
+fn assemble_file(input: String, output: Option<String>) {
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&input)
+            .with_extension("obj")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let source = match std::fs::read_to_string(&input) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Failed to read {:?}: {}", input, e);
+            std::process::exit(1);
+        }
+    };
 
-impl VM {
-    /// Creates a new VM instance with initialized memory and registers
-    pub fn new() -> Self {
-        Self {
-            memory: Memory::new(),
-            registers: Registers::new(),
-            state: VMState::Running,
+    match asm::assemble(&source) {
+        Ok(assembled) => {
+            if let Err(e) = std::fs::write(&output, assembled.to_obj()) {
+                eprintln!("Failed to write {:?}: {}", output, e);
+                std::process::exit(1);
+            }
+        }
+        Err(VMError::AssemblyError { line, message }) => {
+            eprintln!("{}:{}: {}", input, line, message);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to assemble {:?}: {}", input, e);
+            std::process::exit(1);
         }
     }
+}
 
-    /// Reads a 16-bit value from the specified memory address
-    ///
-    /// # Errors
-    /// Returns `VMError::InvalidMemoryAccess` if address is invalid
-    pub fn read_memory(&mut self, address: u16) -> Result<u16, VMError> {
-        self.memory.read(address)
-    }
+/// Creates the VM, exiting with a readable message if a program fails to load
+fn build_vm(builder: VMBuilder) -> VM {
+    let (filename, error) = match builder.build() {
+        Ok(vm) => return vm,
+        Err(VMError::InProgram { path, source }) => (path, *source),
+        Err(e) => {
+            eprintln!("Failed to create the VM: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    /// Writes a 16-bit value to the specified memory address
-    ///
-    /// # Errors
-    /// Returns `VMError::InvalidMemoryAccess` if address is invalid
-    pub fn write_memory(&mut self, address: u16, value: u16) -> Result<(), VMError> {
-        self.memory.write(address, value)
+    match error {
+        VMError::OverlappingLoad { origin, existing } => {
+            eprintln!(
+                "Error loading program {:?}: image at 0x{:04X} overlaps program loaded at 0x{:04X}",
+                filename, origin, existing
+            );
+        }
+        VMError::ProgramTooLarge { origin, words } => {
+            eprintln!(
+                "Error loading program {:?}: {} words at 0x{:04X} would wrap past the end of memory (use --allow-wrap to load anyway)",
+                filename, words, origin
+            );
+        }
+        VMError::MalformedObject { len, reason } => {
+            eprintln!(
+                "Error loading program {:?}: malformed object file ({} bytes): {}",
+                filename, len, reason
+            );
+        }
+        VMError::InvalidIntelHex { line, reason } => {
+            eprintln!(
+                "Error loading program {:?}: invalid Intel HEX record on line {}: {}",
+                filename, line, reason
+            );
+        }
+        VMError::InvalidHexListing { line, token } => {
+            eprintln!(
+                "Error loading program {:?}: invalid hex word {:?} on line {}",
+                filename, token, line
+            );
+        }
+        VMError::AssemblyError { line, message } => {
+            eprintln!("{}:{}: {}", filename, line, message);
+        }
+        e => {
+            eprintln!("Error loading program {:?}: {}", filename, e);
+        }
     }
+    std::process::exit(1);
+}
 
-    /// Reads the value of the specified register
-    ///
-    /// # Errors
-    /// Returns `VMError::InvalidRegister` if register number is invalid
-    pub fn read_register(&self, r: usize) -> Result<u16, VMError> {
-        self.registers.get(r)
+/// Reads the symbol table given with `--symbols`, or collects the `.sym` files next to each program
+fn find_symbols(symbols_file: Option<String>, filenames: &[String]) -> SymbolTable {
+    match symbols_file {
+        Some(path) => match SymbolTable::load(&path) {
+            Ok(symbols) => symbols,
+            Err(_) => {
+                eprintln!("Failed to read symbol table: {:?}", path);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let mut symbols = SymbolTable::new();
+            for filename in filenames.iter().filter(|name| name.as_str() != "-") {
+                let sym_path = std::path::Path::new(filename).with_extension("sym");
+                if let Some(Ok(table)) = sym_path.to_str().map(SymbolTable::load) {
+                    symbols.extend(table);
+                }
+            }
+            symbols
+        }
     }
+}
 
-    /// Writes a 16-bit value to the specified register
-    pub fn write_register(&mut self, r: usize, value: u16) {
-        self.registers.set(r, value);
+/// Prints an address-annotated listing of the given files without running them
+fn disassemble_files(load: cli::Load) {
+    let symbols = find_symbols(load.symbols_file.clone(), &load.filenames);
+    // Nothing runs, so the VM gets no keyboard and leaves the terminal alone
+    let vm = build_vm(
+        load.builder()
+            .symbols(symbols)
+            .input(Box::new(std::io::empty())),
+    );
+
+    for line in disasm::listing(vm.memory(), vm.loaded_segments(), vm.symbols()) {
+        println!("{}", line);
     }
+}
 
-    /// Updates the condition flags based on the value in the specified register
-    pub fn update_flags(&mut self, r: usize) {
-        self.registers.update_flags(r);
+/// `lc3-vm debug --tui prog.obj`: steps through a program in a full-screen debugger
+fn debug_file(load: cli::Load) {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        eprintln!("The debugger needs a terminal on stdin and stdout");
+        std::process::exit(1);
     }
 
-    /// Loads an LC-3 program file into memory
-    ///
-    /// # Arguments
-    /// * `file` - Path to the .obj file to load
-    ///
-    /// # Process
-    /// 1. Opens and reads the file into a buffer
-    /// 2. Extracts the origin address from the first two bytes
-    /// 3. Loads each subsequent 16-bit instruction into memory starting at origin
-    ///
-    /// # Errors
-    /// * `VMError::OpenFileFailed` - If file cannot be opened
-    /// * `VMError::LoadFailed` - If file format is invalid
-    /// * `VMError::InvalidMemoryAccess` - If program would load to invalid address
-    pub fn load_program(&mut self, file: &str) -> Result<(), VMError> {
-        let mut file = File::open(file).map_err(|_| VMError::OpenFileFailed(file.to_string()))?;
-
-        let mut buffer: Vec<u8> = Vec::new();
-
-        file.read_to_end(&mut buffer)
-            .map_err(|_| VMError::LoadFailed)?;
-
-        let origin = match (buffer.first(), buffer.get(1)) {
-            (Some(&first_byte), Some(&second_byte)) => {
-                u16::from_be_bytes([first_byte, second_byte])
-            }
-            _ => return Err(VMError::LoadFailed),
-        };
+    #[cfg(unix)]
+    {
+        use lc3_vm::console::Console;
+        use lc3_vm::tui::TuiDebugger;
+
+        // Saved before the VM puts the terminal in raw mode, so both restore the original settings
+        let mut console = UnixConsole::new(libc::STDIN_FILENO);
+        let symbols = find_symbols(load.symbols_file.clone(), &load.filenames);
+        let mut vm = build_vm(load.builder().symbols(symbols));
+        terminal::restore_on_panic(console);
+        if let Err(e) = console.enable_raw_mode() {
+            vm.release_console();
+            eprintln!("Failed to set up the terminal: {}", e);
+            std::process::exit(1);
+        }
 
-        let mut current_address = origin;
+        let mut debugger = TuiDebugger::new(&mut vm);
+        let mut stdout = std::io::stdout();
+        let _ = write!(stdout, "{}\x1b[2J", screen::ENTER);
+        loop {
+            let (width, height) = screen::size();
+            let _ = write!(stdout, "{}", screen::draw(&debugger, &vm, width, height));
+            let _ = stdout.flush();
 
-        for chunk in buffer.chunks_exact(2).skip(1) {
-            // check that the chunk is the correct size
-            if chunk.len() != 2 {
-                return Err(VMError::LoadFailed);
+            let Ok(key) = screen::read_key(&mut console) else {
+                break;
+            };
+            if !debugger.handle_key(&mut vm, key, screen::PANE_ROWS) {
+                break;
             }
+        }
 
-            let instruction = match (chunk.first(), chunk.get(1)) {
-                (Some(&first_byte), Some(&second_byte)) => {
-                    u16::from_be_bytes([first_byte, second_byte])
-                }
-                _ => return Err(VMError::LoadFailed),
-            };
+        let _ = write!(stdout, "{}", screen::LEAVE);
+        let _ = stdout.flush();
+        vm.release_console();
+        let _ = console.disable_raw_mode();
+    }
 
-            self.write_memory(current_address, instruction)?;
-            current_address = current_address.wrapping_add(1);
-        }
+    #[cfg(not(unix))]
+    {
+        let _ = load;
+        eprintln!("The debugger is only available on Unix terminals");
+        std::process::exit(1);
+    }
+}
 
-        Ok(())
+/// `lc3-vm debug prog.obj`: the line debugger, reading commands from the prompt or a script
+fn debug_lines(load: cli::Load, script: Option<String>, undo_depth: usize) {
+    let script = script.map(|path| match std::fs::read_to_string(&path) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("Failed to read debugger script {:?}: {}", path, e);
+            std::process::exit(1);
+        }
+    });
+
+    // Commands and the program's keys share stdin, read a line at a time
+    let symbols = find_symbols(load.symbols_file.clone(), &load.filenames);
+    let mut vm = build_vm(
+        load.builder()
+            .symbols(symbols)
+            .call_tracking(true)
+            .undo_depth(undo_depth)
+            .input(Box::new(std::io::stdin())),
+    );
+    let mut debugger = LineDebugger::new(&mut vm);
+    let mut stdout = std::io::stdout();
+
+    if let Some(script) = script {
+        if let Err(message) = debugger.run_script(&mut vm, &script, &mut stdout) {
+            eprintln!("Error on {}", message);
+            std::process::exit(1);
+        }
+        return;
     }
 
-    /// Runs the VM's main execution loop
-    ///
-    /// # Process
-    /// 1. Fetches instruction from memory at PC
-    /// 2. Increments PC
-    /// 3. Decodes instruction opcode
-    /// 4. Executes instruction
-    /// 5. Repeats until halted
-    ///
-    /// # Errors
-    /// Returns VMError if instruction execution fails
-    pub fn run(&mut self) -> Result<(), VMError> {
-        while self.state == VMState::Running {
-            // 1. Load one instruction from memory at the address of the PC
-            let instruction = self.read_memory(self.registers.pc)?;
-
-            // 2. Increment the PC
-            self.registers.pc = self.registers.pc.wrapping_add(1);
-
-            let instruction_read = (instruction >> 12) & 0xF;
-            let opcode: Opcode = Opcode::from(instruction_read);
-
-            self.execute(opcode, instruction)?;
-        }
-        Ok(())
+    loop {
+        print!("{}", debugger::PROMPT);
+        let _ = stdout.flush();
+        let mut line = String::new();
+        if !matches!(std::io::stdin().read_line(&mut line), Ok(read) if read > 0) {
+            break;
+        }
+        match debugger.execute_line(&mut vm, &line, &mut stdout) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(message) => eprintln!("{}", message),
+        }
     }
+}
+
+/// `lc3-vm inspect prog.obj`: prints what each program file contains without running it
+fn inspect_files(load: cli::Load) {
+    for filename in &load.filenames {
+        let bytes = match std::fs::read(filename) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Failed to read {:?}: {}", filename, e);
+                std::process::exit(1);
+            }
+        };
+        let format = load
+            .format
+            .unwrap_or_else(|| Format::detect(filename, &bytes));
+        let symbols = find_symbols(load.symbols_file.clone(), std::slice::from_ref(filename));
+        let vm = build_vm(
+            cli::Load {
+                filenames: vec![filename.clone()],
+                format: Some(format),
+                endianness: load.endianness,
+                symbols_file: None,
+            }
+            .builder()
+            .symbols(symbols)
+            // Nothing runs, so the VM gets no keyboard and leaves the terminal alone
+            .input(Box::new(std::io::empty())),
+        );
 
-    fn execute(&mut self, opcode: Opcode, instruction: u16) -> Result<(), VMError> {
-        match opcode {
-            Opcode::Br => conditional_branch(self, instruction),
-            Opcode::Add => add(self, instruction),
-            Opcode::Ld => load(self, instruction),
-            Opcode::St => store(self, instruction),
-            Opcode::Jsr => jump_subroutine(self, instruction),
-            Opcode::And => and(self, instruction),
-            Opcode::Ldr => load_register(self, instruction),
-            Opcode::Str => store_register(self, instruction),
-            Opcode::Rti => Err(VMError::UnimplemedOpcode(Opcode::Rti)),
-            Opcode::Not => not(self, instruction),
-            Opcode::Ldi => ldi(self, instruction),
-            Opcode::Sti => store_indirect(self, instruction),
-            Opcode::Jmp => jmp(self, instruction),
-            Opcode::Res => Err(VMError::UnimplemedOpcode(Opcode::Res)),
-            Opcode::Lea => load_effective_address(self, instruction),
-            Opcode::Trap => trap(self, instruction),
+        println!("{}", filename);
+        println!("  format    {}", format.name());
+        println!("  size      {} bytes", bytes.len());
+        for segment in vm.loaded_segments() {
+            println!(
+                "  segment   x{:04X}-x{:04X} ({} words)",
+                segment.origin,
+                segment.end.wrapping_sub(1),
+                segment.len
+            );
         }
+        println!("  symbols   {}", vm.symbols().len());
     }
 }
 
 fn main() {
-    // Configure termios
-    let mut termios = if let Ok(termios) = Termios::from_fd(0) {
-        termios
-    } else {
-        eprintln!("Failed to get termios settings");
-        std::process::exit(1);
+    // Warnings show by default, RUST_LOG=lc3_vm=debug or e.g. lc3_vm::memory=trace shows more
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", cli::USAGE);
+            eprintln!("Run with --help to list the commands and options");
+            std::process::exit(1);
+        }
     };
 
-    //turn on canonical mode and echo mode
-    termios.c_lflag &= !(ICANON | ECHO);
-
-    if let Err(e) = tcsetattr(0, TCSAFLUSH, &termios) {
-        eprintln!("Failed to set termios settings: {:?}", e);
-        std::process::exit(1);
+    match command {
+        cli::Command::Run(options) => run(*options),
+        cli::Command::Debug {
+            load, tui: true, ..
+        } => debug_file(load),
+        cli::Command::Debug {
+            load,
+            script,
+            undo_depth,
+            ..
+        } => debug_lines(load, script, undo_depth),
+        cli::Command::Asm { input, output } => assemble_file(input, output),
+        cli::Command::Disasm(load) => disassemble_files(load),
+        cli::Command::Inspect(load) => inspect_files(load),
+        cli::Command::Batch(options) => std::process::exit(batch::run(options)),
+        cli::Command::Help(help) => print!("{}", help),
     }
+}
 
-    // Read the program file given as the first command line argument
-    // This will be used ./lc3-vm path/to/program.obj
-    let args: Vec<String> = std::env::args().collect();
+/// Where and how `--json` reports the run
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
+struct JsonReport {
+    /// File to write to, stdout if `None`
+    path: Option<String>,
+    wall_time: Duration,
+    /// Captured output to embed
+    output: Option<Vec<u8>>,
+}
 
-    if args.len() < 2 {
-        eprintln!("Usage: ./lc3-vm path/to/program.obj");
-        std::process::exit(1);
+/// Writes the JSON report if one was asked for and exits with `status`
+fn finish(
+    vm: &mut VM,
+    result: &Result<RunOutcome, VMError>,
+    json: Option<&JsonReport>,
+    status: i32,
+) -> ! {
+    #[cfg(feature = "serde")]
+    if let Some(json) = json {
+        let mut report = lc3_vm::report::Report::new(vm, result, status, json.wall_time);
+        if let Some(output) = &json.output {
+            report = report.with_output(output.clone());
+        }
+        let written = report.to_json().and_then(|text| match &json.path {
+            Some(path) => {
+                std::fs::write(path, text + "\n").map_err(|e| VMError::SaveFailed(e.to_string()))
+            }
+            None => {
+                println!("{}", text);
+                Ok(())
+            }
+        });
+        if let Err(e) = written {
+            eprintln!("Failed to write the JSON report: {}", e);
+        }
     }
+    #[cfg(not(feature = "serde"))]
+    let _ = (vm, result, json);
 
-    let filename = if let Some(name) = args.get(1) {
-        name
+    std::process::exit(status)
+}
+
+/// `lc3-vm [run] prog.obj`: runs the programs with the options given on the command line
+fn run(options: cli::Options) {
+    let builder = options.builder();
+    let cli::Options {
+        load: cli::Load {
+            symbols_file,
+            filenames,
+            ..
+        },
+        exit_r0,
+        deterministic,
+        stdin_file,
+        input_timeout,
+        record,
+        replay,
+        quiet,
+        trace,
+        trace_traps,
+        trace_traps_file,
+        dump_regs,
+        stats,
+        profile,
+        coverage,
+        dump,
+        json,
+        json_out,
+        capture_output,
+        load_state,
+        save_state,
+        expect_output,
+        trailing_newlines,
+        ..
+    } = options;
+
+    let script = deterministic.map(|path| match std::fs::read(&path) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("Failed to read input script {:?}: {}", path, e);
+            std::process::exit(1);
+        }
+    });
+
+    let keyboard_file = stdin_file.map(|path| match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to open input file {:?}: {}", path, e);
+            std::process::exit(1);
+        }
+    });
+    let expected = expect_output.map(|path| match std::fs::read(&path) {
+        Ok(text) => (String::from_utf8_lossy(&text).into_owned(), path),
+        Err(e) => {
+            eprintln!("Failed to read expected output {:?}: {}", path, e);
+            std::process::exit(1);
+        }
+    });
+
+    // When stdin carries the program image, keyboard input comes from the controlling terminal
+    let tty = if script.is_some() || keyboard_file.is_some() {
+        None
+    } else if filenames.iter().any(|name| name == "-") {
+        match File::open("/dev/tty") {
+            Ok(tty) => Some(tty),
+            Err(e) => {
+                eprintln!(
+                    "Reading the program from stdin requires a terminal for keyboard input: {}",
+                    e
+                );
+                std::process::exit(1);
+            }
+        }
     } else {
-        eprintln!("No program file provided.");
-        std::process::exit(1);
+        None
+    };
+    #[cfg(unix)]
+    let input_fd = tty.as_ref().map_or(0, |tty| tty.as_raw_fd());
+    // Piped input is read as it comes, without a terminal to configure
+    let interactive = tty
+        .as_ref()
+        .map_or_else(|| std::io::stdin().is_terminal(), |tty| tty.is_terminal());
+
+    // The terminal's settings from before the VM switches it to raw mode, a run
+    // reading its input from a file never touches the terminal so it leaves it alone
+    #[cfg(unix)]
+    let restore = (script.is_none() && keyboard_file.is_none() && interactive)
+        .then(|| UnixConsole::new(input_fd));
+
+    let trap_trace: Option<Box<dyn std::io::Write>> = match &trace_traps_file {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(Box::new(file)),
+            Err(e) => {
+                eprintln!("Failed to create trap log {:?}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None if trace_traps => Some(Box::new(std::io::stderr())),
+        None => None,
     };
 
     // Main loop
-    let mut vm = VM::new();
+    let mut builder = builder.symbols(find_symbols(symbols_file, &filenames));
+    // A terminal on stdin becomes the console by itself
+    match tty {
+        // The terminal stays open until the process exits
+        #[cfg(unix)]
+        Some(tty) => builder = builder.console(Box::new(UnixConsole::new(tty.into_raw_fd()))),
+        #[cfg(not(unix))]
+        Some(tty) => builder = builder.input(Box::new(tty)),
+        // Waiting for piped input with a timeout needs a reader thread
+        None if input_timeout.is_some() && !interactive => {
+            builder = builder.input_source(Box::new(ChannelInput::spawn(std::io::stdin())))
+        }
+        None => {}
+    }
+    if let Some(path) = &replay {
+        let session = File::open(path)
+            .map_err(|_| VMError::OpenFileFailed(path.clone()))
+            .and_then(|mut file| Session::from_reader(&mut file));
+        match session {
+            Ok(session) => builder = builder.replay(session),
+            Err(e) => {
+                eprintln!("Failed to read session {:?}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(script) = script {
+        builder = builder.deterministic(script);
+    }
+    if let Some(file) = keyboard_file {
+        builder = builder.input(Box::new(file));
+    }
+    // Nobody is watching the banner of a run fed from a pipe, and it isn't
+    // part of the output being checked
+    if !interactive || expected.is_some() {
+        builder = builder.halt_banner(false);
+    }
+    // Keep stdout for the JSON report or the diff
+    if capture_output || expected.is_some() {
+        builder = builder.capture_output();
+    } else if json && json_out.is_none() {
+        builder = builder.output(Box::new(std::io::stderr()));
+    }
+    // Read before the VM takes over the terminal, a missing file exits with it untouched
+    #[cfg(feature = "serde")]
+    let state = load_state.as_ref().map(|path| {
+        match std::fs::read_to_string(path).map_err(|_| VMError::OpenFileFailed(path.clone())) {
+            Ok(json) => (path, json),
+            Err(e) => {
+                eprintln!("Failed to load state {:?}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    });
+    let mut vm = build_vm(builder);
+    #[cfg(feature = "serde")]
+    if let Some((path, json)) = &state {
+        if let Err(e) = vm.restore_state_json(json) {
+            vm.release_console();
+            eprintln!("Failed to load state {:?}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    vm.set_trap_trace(trap_trace);
+    if trace {
+        vm.set_instruction_trace(Some(Box::new(std::io::stderr())));
+    }
 
-    // TODO: Load the program into memory
-    if vm.load_program(filename).is_err() {
-        eprintln!("Error loading program: {:?}", filename);
-        std::process::exit(1);
+    #[cfg(unix)]
+    {
+        if let Some(console) = restore {
+            terminal::restore_on_panic(console);
+        }
+        match terminal::handle_interrupts(restore) {
+            Ok(flag) => vm.set_interrupt_flag(Some(flag)),
+            Err(e) => eprintln!("Ctrl-C will kill the VM without a report: {}", e),
+        }
+    }
+    let started = Instant::now();
+    let result = vm.run_program();
+    let wall_time = started.elapsed();
+    let captured = if capture_output || expected.is_some() {
+        vm.take_output()
+    } else {
+        Vec::new()
+    };
+    // The diff goes to stderr when stdout carries the JSON report or only the program's output
+    let diff_to_stdout = !quiet && (!json || json_out.is_some());
+    let json_report = json.then(|| JsonReport {
+        path: json_out,
+        wall_time,
+        output: capture_output.then(|| captured.clone()),
+    });
+    // The terminal is back to normal before any report is printed or the process exits
+    vm.release_console();
+
+    if let (Some(path), Some(session)) = (&record, vm.take_recording()) {
+        let written = File::create(path)
+            .map_err(|e| VMError::SaveFailed(e.to_string()))
+            .and_then(|mut file| session.to_writer(&mut file));
+        if let Err(e) = written {
+            eprintln!("Failed to write session {:?}: {}", path, e);
+        }
     }
 
-    match vm.run() {
-        Ok(_) => std::process::exit(0),
-        Err(e) => {
-            match e {
-                VMError::InvalidMemoryAccess(addr) => {
-                    eprintln!("Invalid memory access at address: 0x{:04X}", addr);
-                    std::process::exit(1);
-                }
-                VMError::UnimplemedOpcode(opcode) => {
-                    eprintln!("Unimplemented opcode: {:?}", opcode);
-                    std::process::exit(1);
-                }
-                VMError::TrapError(trap_error) => match trap_error {
-                    TrapError::IOError(msg) => {
-                        eprintln!("IO error: {:?}", msg);
-                        std::process::exit(1);
-                    }
-                    TrapError::InvalidTrapVector(vector) => {
-                        eprintln!("Invalid trap vector: 0x{:04X}", vector);
-                        std::process::exit(1);
-                    }
-                },
-                VMError::OpenFileFailed(path) => {
-                    eprintln!("Failed to open file: {:?}", path);
-                    std::process::exit(1);
-                }
+    #[cfg(feature = "serde")]
+    if let Some(path) = &save_state {
+        let written = vm.export_state_json().and_then(|json| {
+            std::fs::write(path, json + "\n").map_err(|e| VMError::SaveFailed(e.to_string()))
+        });
+        if let Err(e) = written {
+            eprintln!("Failed to write state {:?}: {}", path, e);
+        }
+    }
+    #[cfg(not(feature = "serde"))]
+    let _ = (load_state, save_state);
 
-                _ => {
-                    eprintln!("VM error: {:?}", e);
-                    std::process::exit(1);
-                }
-            }
-            // eprintln!("VM error: {:?}", e);
+    if let Some(path) = &coverage {
+        if let Err(e) = std::fs::write(path, vm.coverage().to_string()) {
+            eprintln!("Failed to write coverage report {:?}: {}", path, e);
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use registers::RegisterFlags;
+    // The error path prints the registers anyway
+    if dump_regs && result.is_ok() {
+        eprint!(
+            "{}",
+            vm.registers_snapshot().with_aliases(vm.register_aliases())
+        );
+    }
 
-    #[test]
-    #[allow(clippy::unwrap_used)]
-    fn test_load_program() {
-        let mut vm = VM::new();
+    if stats {
+        eprintln!("{}", vm.metrics());
+    }
 
-        const PATH: &str = "examples/hello-world.obj";
-        //print the current path to check if the file is being read
-        match vm.load_program(PATH) {
-            Ok(_) => (),
-            Err(e) => println!("Error: {:?}", e),
+    if profile {
+        eprint!("{}", vm.profile());
+        eprintln!("Hottest addresses:");
+        for hot in vm.hot_addresses(10) {
+            eprintln!("{}", hot);
         }
+    }
 
-        for i in 0..16 {
-            let value = vm.read_memory(0x3000 + i).unwrap();
-            println!("Memory[0x{:04X}] = 0x{:04X}", 0x3000 + i, value);
-        }
+    if vm.sanitized_count() > 0 {
+        eprintln!(
+            "Replaced {} unprintable characters in the output",
+            vm.sanitized_count()
+        );
     }
 
-    #[test]
-    #[allow(clippy::unwrap_used)]
-    #[allow(clippy::as_conversions)]
-    fn test_load_and_run_simple_add() -> Result<(), VMError> {
-        // Create VM and load program
-        let expected_values = [
-            0x5020, // AND R0, R0, #0
-            0x1025, // ADD R0, R0, #5
-            0x5260, // AND R1, R1, #0
-            0x1263, // ADD R1, R1, #3
-            0x1401, // ADD R2, R0, R1
-            0xF025, // TRAP x25 -> HALT
-        ];
-        const PATH: &str = "examples/simple_add.obj";
-        let mut vm = VM::new();
-        vm.load_program(PATH)?;
-
-        // Check that the loaded program is correct
-        for (i, &expected) in expected_values.iter().enumerate() {
-            let value = vm.read_memory(0x3000 + i as u16)?;
-            assert_eq!(
-                value,
-                expected,
-                "Memory[0x{:04X}] should be 0x{:04X}",
-                0x3000 + i as u16,
-                expected
+    let status = match &result {
+        Ok(RunOutcome::Paused(PauseReason::LimitReached { executed })) => {
+            eprintln!(
+                "Instruction limit reached after {} instructions, stopped at {}",
+                executed,
+                vm.describe_address(vm.pc())
+            );
+            EXIT_LIMIT_REACHED
+        }
+        Ok(RunOutcome::Paused(PauseReason::TimeLimitReached { executed })) => {
+            eprintln!(
+                "Time limit reached after {} instructions, stopped at {}",
+                executed,
+                vm.describe_address(vm.pc())
             );
+            EXIT_LIMIT_REACHED
+        }
+        Ok(RunOutcome::Paused(PauseReason::Breakpoint(pc))) => {
+            eprintln!("Breakpoint at {}", vm.describe_address(*pc));
+            eprint!(
+                "{}",
+                vm.registers_snapshot().with_aliases(vm.register_aliases())
+            );
+            EXIT_BREAKPOINT
+        }
+        Ok(RunOutcome::Halted(HaltReason::Interrupted)) => {
+            eprintln!("Interrupted at {}", vm.describe_address(vm.pc()));
+            eprint!(
+                "{}",
+                vm.registers_snapshot().with_aliases(vm.register_aliases())
+            );
+            EXIT_INTERRUPTED
+        }
+        Ok(RunOutcome::Halted(HaltReason::LikelyInfiniteLoop { pcs })) => {
+            eprintln!("Program is stuck in a loop that can never exit:");
+            for &pc in pcs {
+                let word = vm.memory().read_ram(pc);
+                eprintln!(
+                    "  {:<20} {}",
+                    vm.describe_address(pc),
+                    disasm::disassemble(word, pc)
+                );
+            }
+            1
         }
+        Ok(outcome) => {
+            match outcome {
+                RunOutcome::Halted(HaltReason::McrCleared) => eprintln!(
+                    "Machine halted by clearing MCR at {}",
+                    vm.describe_address(vm.pc().wrapping_sub(1))
+                ),
+                RunOutcome::Halted(HaltReason::InputExhausted) => eprintln!(
+                    "Machine halted at {} because the input ended",
+                    vm.describe_address(vm.pc().wrapping_sub(1))
+                ),
+                _ => (),
+            }
 
-        // Run the program
-        vm.run()?;
+            // Persist the memory the program built up
+            let dumped = match dump {
+                Some((range, path)) => match vm.save_program(&path, range) {
+                    Ok(()) => true,
+                    Err(VMError::SaveFailed(path)) => {
+                        eprintln!("Failed to write {:?}", path);
+                        false
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to dump memory to {:?}: {}", path, e);
+                        false
+                    }
+                },
+                None => true,
+            };
+            let matched = match &expected {
+                Some((text, path)) => match expect::check(
+                    text,
+                    &String::from_utf8_lossy(&captured),
+                    trailing_newlines,
+                    path,
+                    "output",
+                ) {
+                    Some(diff) if diff_to_stdout => {
+                        print!("{}", diff);
+                        false
+                    }
+                    Some(diff) => {
+                        eprint!("{}", diff);
+                        false
+                    }
+                    None => true,
+                },
+                None => true,
+            };
+            match vm.exit_code() {
+                _ if !dumped || !matched => 1,
+                Some(code) if exit_r0 => i32::from(code),
+                _ => 0,
+            }
+        }
+        Err(e) => {
+            match e {
+                VMError::AtInstruction { .. } | VMError::StackViolation { .. } => {
+                    eprintln!("Error {}", vm.describe_error(e))
+                }
+                _ => eprintln!("Error: {}", vm.describe_error(e)),
+            }
 
-        // Verify final register values
-        assert_eq!(vm.read_register(0)?, 5, "R0 should contain 5");
+            eprintln!("Registers:");
+            eprint!(
+                "{}",
+                vm.registers_snapshot().with_aliases(vm.register_aliases())
+            );
 
-        assert_eq!(vm.read_register(1)?, 3, "R1 should contain 3");
+            let backtrace = vm.backtrace();
+            if !backtrace.is_empty() {
+                if vm.backtrace_is_reliable() {
+                    eprintln!("Backtrace:");
+                } else {
+                    eprintln!("Backtrace (unreliable, R7 was changed by the program):");
+                }
+                for frame in backtrace {
+                    eprintln!("  {}", frame);
+                }
+            }
 
-        assert_eq!(
-            vm.read_register(2)?,
-            8,
-            "R2 should contain 8 (sum of R0 and R1)"
-        );
+            let trace = vm.recent_trace();
+            if !trace.is_empty() {
+                eprintln!("Last instructions:");
+                for entry in trace {
+                    eprintln!("  {}", entry);
+                }
+            }
 
-        // Verify condition flags
-        // Result was positive (8), so positive flag should be set
-        assert_eq!(
-            vm.registers.condition,
-            RegisterFlags::Pos,
-            "Condition flags should be set to positive after addition"
-        );
+            let faulting = match e {
+                VMError::AtInstruction { pc, .. } | VMError::StackViolation { pc, .. } => *pc,
+                _ => vm.pc().wrapping_sub(1),
+            };
+            if !vm.is_loaded(faulting) {
+                eprintln!(
+                    "Note: the faulting instruction at {} is outside every loaded program",
+                    vm.describe_address(faulting)
+                );
+            }
+            match e.root() {
+                VMError::TrapError(TrapError::InvalidTrapVector(_)) => EXIT_INVALID_TRAP,
+                _ => 1,
+            }
+        }
+    };
 
-        Ok(())
-    }
+    finish(&mut vm, &result, json_report.as_ref(), status)
 }
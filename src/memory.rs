@@ -1,77 +1,185 @@
-use crate::errors::VMError;
-use std::io::Read;
+use alloc::boxed::Box;
+use alloc::vec;
 
-const MEMORY_MAX: usize = 1 << 16;
+/// Number of addressable 16-bit words
+pub const MEMORY_MAX: usize = 1 << 16;
 
+/// Keyboard status register
+pub const MR_KBSR: u16 = 0xFE00;
+/// Keyboard data register
+pub const MR_KBDR: u16 = 0xFE02;
+/// Display status register, always ready
+pub const MR_DSR: u16 = 0xFE04;
+/// Display data register, writing it prints the low byte
+pub const MR_DDR: u16 = 0xFE06;
+/// Machine control register, clearing bit 15 stops the clock
+pub const MR_MCR: u16 = 0xFFFE;
+
+#[derive(Clone, PartialEq)]
 pub struct Memory {
-    mem: [u16; MEMORY_MAX],
+    mem: Box<[u16; MEMORY_MAX]>,
 }
 
-/// Keyboard status register
-const MR_KBSR: u16 = 0xFE00;
-/// Keyboard data register
-const MR_KBDR: u16 = 0xFE02;
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Memory {
     /// Creates a new Memory instance with all memory locations initialized to 0
     pub fn new() -> Self {
-        Self {
-            mem: [0; MEMORY_MAX],
-        }
+        Self::from_buffer(Self::zeroed_buffer())
     }
 
-    /// Reads a 16-bit value from the given memory address
+    /// Returns a buffer of zeroed words for [`Memory::from_buffer`]
     ///
-    /// Special handling for memory-mapped registers:
-    /// - KBSR (0xFE00): Returns keyboard status (MSB set if key available)
-    /// - KBDR (0xFE02): Returns ASCII code of last key pressed
+    /// The allocator hands out zeroed pages, nothing is written word by word
+    /// or built on the stack first.
+    pub fn zeroed_buffer() -> Box<[u16; MEMORY_MAX]> {
+        let words = vec![0u16; MEMORY_MAX].into_boxed_slice();
+        // The slice has the right length, so the fallback never runs
+        words
+            .try_into()
+            .unwrap_or_else(|_| Box::new([0; MEMORY_MAX]))
+    }
+
+    /// Uses `buffer` as the memory, keeping the words already in it
+    pub fn from_buffer(buffer: Box<[u16; MEMORY_MAX]>) -> Self {
+        Self { mem: buffer }
+    }
+
+    /// Gives the buffer back, to be reused with [`Memory::from_buffer`]
+    pub fn into_buffer(self) -> Box<[u16; MEMORY_MAX]> {
+        self.mem
+    }
+
+    /// Reads the word at `address`
     ///
-    /// Returns:
-    /// - Ok(value) if address is valid
-    /// - Err(InvalidMemoryAccess) if address is out of bounds
-    pub fn read(&mut self, address: u16) -> Result<u16, VMError> {
-        if address == MR_KBSR {
-            self.handle_keyboard()?;
+    /// Memory-mapped device registers are handled by the VM, this is plain RAM.
+    /// Every u16 is an address of the 64K words, so a read can't fail.
+    #[inline]
+    pub fn read_ram(&self, address: u16) -> u16 {
+        // In bounds by construction, the compiler drops the check
+        self.mem.get(usize::from(address)).copied().unwrap_or(0)
+    }
+
+    /// Writes `value` to the word at `address`
+    #[inline]
+    pub fn write_ram(&mut self, address: u16, value: u16) {
+        if let Some(cell) = self.mem.get_mut(usize::from(address)) {
+            *cell = value;
         }
+    }
 
-        let addr: usize = address.into();
+    /// Sets every memory location back to 0
+    pub fn clear(&mut self) {
+        self.mem.fill(0);
+    }
+}
 
-        self.mem
-            .get(addr)
-            .copied()
-            .ok_or(VMError::InvalidMemoryAccess(address))
+/// Memory is serialized as its big-endian bytes, base64 encoded for text formats like JSON
+#[cfg(feature = "serde")]
+impl serde::Serialize for Memory {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use base64::Engine;
+
+        let bytes: Vec<u8> = self
+            .mem
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(bytes))
+        } else {
+            serializer.serialize_bytes(&bytes)
+        }
     }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Memory {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use base64::Engine;
+        use serde::de::Error;
+
+        struct BytesVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{} bytes of memory", MEMORY_MAX.saturating_mul(2))
+            }
 
-    fn handle_keyboard(&mut self) -> Result<(), VMError> {
-        let mut buffer = [0; 1];
-        std::io::stdin()
-            .read_exact(&mut buffer)
-            .map_err(|_| VMError::InvalidCharacter)?;
-
-        if buffer[0] != 0 {
-            self.write(MR_KBSR, 1 << 15)?;
-            // self.mem[MR_KBDR] = u16::from(*buffer.first().unwrap_or(&0));
-            let char = u16::from(buffer[0]);
-            self.write(MR_KBDR, char)?;
+            fn visit_str<E: Error>(self, text: &str) -> Result<Vec<u8>, E> {
+                base64::engine::general_purpose::STANDARD
+                    .decode(text)
+                    .map_err(E::custom)
+            }
+
+            fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+                Ok(bytes.to_vec())
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Vec<u8>, A::Error> {
+                let mut bytes = Vec::new();
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(bytes)
+            }
+        }
+
+        let bytes = if deserializer.is_human_readable() {
+            deserializer.deserialize_str(BytesVisitor)?
         } else {
-            self.write(MR_KBDR, 0)?;
+            deserializer.deserialize_bytes(BytesVisitor)?
+        };
+
+        if bytes.len() != MEMORY_MAX.saturating_mul(2) {
+            return Err(D::Error::invalid_length(bytes.len(), &BytesVisitor));
         }
 
-        Ok(())
+        let mut memory = Memory::new();
+        for (cell, pair) in memory.mem.iter_mut().zip(bytes.chunks_exact(2)) {
+            if let [high, low] = *pair {
+                *cell = u16::from_be_bytes([high, low]);
+            }
+        }
+
+        Ok(memory)
     }
+}
 
-    /// Writes a 16-bit value to the given memory address
-    ///
-    /// Returns:
-    /// - Ok(()) if address is valid
-    /// - Err(InvalidMemoryAccess) if address is out of bounds
-    pub fn write(&mut self, address: u16, value: u16) -> Result<(), VMError> {
-        let addr: usize = address.into();
-        self.mem
-            .get_mut(addr)
-            .map(|cell| {
-                *cell = value;
-            })
-            .ok_or(VMError::InvalidMemoryAccess(address))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_read_write() {
+        let mut memory = Memory::new();
+
+        assert_eq!(memory.read_ram(0x3000), 0);
+
+        memory.write_ram(0x3000, 0x1234);
+        memory.write_ram(0xFFFF, 0xBEEF);
+
+        assert_eq!(memory.read_ram(0x3000), 0x1234);
+        assert_eq!(memory.read_ram(0xFFFF), 0xBEEF);
+    }
+
+    #[test]
+    fn test_buffer_keeps_its_words() {
+        let mut memory = Memory::new();
+        memory.write_ram(0x3000, 0x1234);
+
+        let memory = Memory::from_buffer(memory.into_buffer());
+        assert_eq!(memory.read_ram(0x3000), 0x1234);
+        assert!(Memory::zeroed_buffer().iter().all(|&word| word == 0));
     }
 }
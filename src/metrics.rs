@@ -0,0 +1,108 @@
+//! Counters of what a run did, see [`VM::metrics`](crate::VM::metrics)
+
+use std::fmt;
+use std::time::Duration;
+
+/// What the VM did since it was created or reset
+///
+/// Memory reads and writes are the accesses of instructions and traps through
+/// [`VM::read_memory`](crate::VM::read_memory) and
+/// [`VM::write_memory`](crate::VM::write_memory), instruction fetches aren't
+/// counted. Device accesses are the reads and writes at xFE00 and above.
+///
+/// Printed as one line:
+///
+/// ```text
+/// 6 instructions in 0.021ms (285714 IPS), 0 reads, 0 writes, 0 device accesses, 1 traps
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metrics {
+    /// Instructions executed
+    pub instructions: u64,
+    /// Time spent in `run`, `run_program`, `run_with_limit` and `run_until`
+    pub wall_time: Duration,
+    pub memory_reads: u64,
+    pub memory_writes: u64,
+    pub device_accesses: u64,
+    /// TRAP instructions executed, whether a built-in routine or a host handler ran them
+    pub traps: u64,
+    /// Most words R6 was below the top of the stack, `None` without stack bounds
+    pub max_stack_depth: Option<u16>,
+}
+
+impl Metrics {
+    /// Instructions executed per second of [`Metrics::wall_time`], 0 before anything ran
+    pub fn instructions_per_second(&self) -> f64 {
+        let seconds = self.wall_time.as_secs_f64();
+        if seconds > 0.0 {
+            // Precision only goes after 2^53 instructions
+            #[allow(clippy::as_conversions, clippy::cast_precision_loss)]
+            let instructions = self.instructions as f64;
+            instructions / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Records R6 after an instruction, for a stack whose top is `top`
+    pub(crate) fn observe_stack(&mut self, top: u16, sp: u16) {
+        // An empty stack has R6 at top + 1
+        let depth = top.wrapping_add(1).wrapping_sub(sp);
+        if self.max_stack_depth.is_none_or(|max| depth > max) {
+            self.max_stack_depth = Some(depth);
+        }
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} instructions in {:.3}ms ({:.0} IPS), {} reads, {} writes, {} device accesses, {} traps",
+            self.instructions,
+            self.wall_time.as_secs_f64() * 1000.0,
+            self.instructions_per_second(),
+            self.memory_reads,
+            self.memory_writes,
+            self.device_accesses,
+            self.traps
+        )?;
+        if let Some(depth) = self.max_stack_depth {
+            write!(f, ", stack depth up to {}", depth)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stack_depth_from_top() {
+        let mut metrics = Metrics::default();
+        metrics.observe_stack(0xFDFF, 0xFE00);
+        assert_eq!(metrics.max_stack_depth, Some(0));
+
+        metrics.observe_stack(0xFDFF, 0xFDFD);
+        metrics.observe_stack(0xFDFF, 0xFDFF);
+        assert_eq!(metrics.max_stack_depth, Some(3));
+    }
+
+    #[test]
+    fn test_instructions_per_second() {
+        let mut metrics = Metrics {
+            instructions: 500,
+            ..Metrics::default()
+        };
+        assert_eq!(metrics.instructions_per_second(), 0.0);
+
+        metrics.wall_time = Duration::from_millis(250);
+        assert_eq!(metrics.instructions_per_second(), 2000.0);
+        assert_eq!(
+            metrics.to_string(),
+            "500 instructions in 250.000ms (2000 IPS), 0 reads, 0 writes, 0 device accesses, 0 traps"
+        );
+    }
+}
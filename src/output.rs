@@ -0,0 +1,277 @@
+//! Destination of everything the program prints
+
+use std::borrow::Cow;
+use std::io::{self, BufWriter, IsTerminal, Write};
+
+/// How characters that could mess with a terminal are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sanitize {
+    /// Prints every character as it is
+    #[default]
+    Off,
+    /// Prints `.` instead
+    Dot,
+    /// Prints a hex escape such as `\x1B` instead
+    Hex,
+}
+
+impl Sanitize {
+    /// Replaces every character outside printable ASCII, `\n`, `\r` and `\t`,
+    /// returning the text to print and how many characters were replaced
+    pub(crate) fn apply(self, text: &str) -> (Cow<'_, str>, usize) {
+        let printable = |c: char| matches!(c, ' '..='~' | '\n' | '\r' | '\t');
+        if self == Sanitize::Off || text.chars().all(printable) {
+            return (Cow::Borrowed(text), 0);
+        }
+
+        let mut replaced: usize = 0;
+        let mut sanitized = String::with_capacity(text.len());
+        for c in text.chars() {
+            if printable(c) {
+                sanitized.push(c);
+                continue;
+            }
+
+            replaced = replaced.saturating_add(1);
+            match self {
+                Sanitize::Hex => sanitized.push_str(&format!("\\x{:02X}", u32::from(c))),
+                _ => sanitized.push('.'),
+            }
+        }
+
+        (Cow::Owned(sanitized), replaced)
+    }
+}
+
+/// Where the OUT, PUTS, PUTSP, IN and HALT traps write their characters
+pub(crate) enum Output {
+    /// Writes to a stream such as stdout through a buffer, flushed at every newline
+    Stream(BufWriter<Box<dyn Write>>),
+    /// Collects the output in memory until [`VM::take_output`](crate::VM::take_output)
+    Captured(Vec<u8>),
+}
+
+impl Output {
+    /// Buffers writes to `stream`
+    pub(crate) fn stream(stream: Box<dyn Write>) -> Self {
+        Output::Stream(BufWriter::new(stream))
+    }
+
+    /// Returns the captured bytes, leaving the buffer empty
+    ///
+    /// Streams have nothing to return.
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        match self {
+            Output::Stream(_) => Vec::new(),
+            Output::Captured(buffer) => std::mem::take(buffer),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stream(stream) => {
+                let written = stream.write(buf)?;
+                if buf.get(..written).is_some_and(|line| line.contains(&b'\n')) {
+                    stream.flush()?;
+                }
+                Ok(written)
+            }
+            Output::Captured(buffer) => buffer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stream(stream) => stream.flush(),
+            Output::Captured(_) => Ok(()),
+        }
+    }
+}
+
+/// The console every trap and device prints through, owned by the VM
+///
+/// Wraps the sink with the sanitizer, so OUT, PUTS, PUTSP, IN, the extension
+/// traps and the HALT banner all share one buffer and reach the sink in the
+/// order they were printed.
+pub(crate) struct ConsoleOut {
+    sink: Output,
+    /// Whether the sink is stdout on a terminal
+    is_terminal: bool,
+    /// `None` sanitizes with dots while the sink is stdout on a terminal
+    sanitize: Option<Sanitize>,
+    /// Characters the sanitizer replaced so far
+    sanitized: u64,
+}
+
+impl ConsoleOut {
+    /// Prints to stdout
+    pub(crate) fn stdout() -> Self {
+        Self {
+            sink: Output::stream(Box::new(io::stdout())),
+            is_terminal: io::stdout().is_terminal(),
+            sanitize: None,
+            sanitized: 0,
+        }
+    }
+
+    /// Prints to `sink` instead, keeping the sanitizer settings
+    pub(crate) fn set_sink(&mut self, sink: Output) {
+        self.sink = sink;
+        self.is_terminal = false;
+    }
+
+    pub(crate) fn is_terminal(&self) -> bool {
+        self.is_terminal
+    }
+
+    pub(crate) fn set_sanitize(&mut self, sanitize: Sanitize) {
+        self.sanitize = Some(sanitize);
+    }
+
+    /// How many characters the sanitizer replaced
+    pub(crate) fn sanitized(&self) -> u64 {
+        self.sanitized
+    }
+
+    /// Prints `text`, sanitized if it goes to a terminal or sanitizing was asked for
+    pub(crate) fn put_str(&mut self, text: &str) -> io::Result<()> {
+        let sanitize = self.sanitize.unwrap_or(if self.is_terminal {
+            Sanitize::Dot
+        } else {
+            Sanitize::Off
+        });
+        let (printed, replaced) = sanitize.apply(text);
+        self.sanitized = self
+            .sanitized
+            .saturating_add(u64::try_from(replaced).unwrap_or(u64::MAX));
+
+        self.sink.write_all(printed.as_bytes())
+    }
+
+    /// Prints the character with code `c`
+    pub(crate) fn put_char(&mut self, c: u8) -> io::Result<()> {
+        self.put_str(char::from(c).encode_utf8(&mut [0; 4]))
+    }
+
+    /// Writes what's buffered to the sink
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    /// Returns the captured bytes, see [`Output::take`]
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        self.sink.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Stream whose bytes stay readable after it was handed to the output
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stream_flushes_at_newline() -> io::Result<()> {
+        let shared = Shared::default();
+        let mut output = Output::stream(Box::new(shared.clone()));
+
+        write!(output, "Hel")?;
+        assert!(shared.0.borrow().is_empty());
+        write!(output, "lo\nWor")?;
+        assert_eq!(*shared.0.borrow(), b"Hello\nWor");
+
+        write!(output, "ld")?;
+        output.flush()?;
+        assert_eq!(*shared.0.borrow(), b"Hello\nWorld");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let text = "a\u{1B}[2J\u{0}\u{1}b\n\tc\u{FF}";
+
+        assert_eq!(Sanitize::Off.apply(text), (Cow::Borrowed(text), 0));
+        assert_eq!(Sanitize::Dot.apply(text).0, "a.[2J..b\n\tc.");
+        assert_eq!(
+            Sanitize::Hex.apply(text),
+            (Cow::Owned("a\\x1B[2J\\x00\\x01b\n\tc\\xFF".to_string()), 4)
+        );
+        assert_eq!(
+            Sanitize::Dot.apply("fine\r\n"),
+            (Cow::Borrowed("fine\r\n"), 0)
+        );
+    }
+
+    #[test]
+    fn test_take_captured() -> io::Result<()> {
+        let mut output = Output::Captured(Vec::new());
+        write!(output, "Hello")?;
+
+        assert_eq!(output.take(), b"Hello");
+        assert!(output.take().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_console_keeps_put_order() -> io::Result<()> {
+        let shared = Shared::default();
+        let mut console = ConsoleOut::stdout();
+        console.set_sink(Output::stream(Box::new(shared.clone())));
+
+        console.put_char(b'>')?;
+        console.put_str("ab")?;
+        console.put_char(b'c')?;
+        assert!(shared.0.borrow().is_empty());
+
+        console.flush()?;
+        assert_eq!(*shared.0.borrow(), b">abc");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_console_counts_sanitized_characters() -> io::Result<()> {
+        let mut console = ConsoleOut::stdout();
+        console.set_sink(Output::Captured(Vec::new()));
+        console.put_char(0x1B)?;
+        assert_eq!(console.sanitized(), 0);
+
+        console.set_sanitize(Sanitize::Dot);
+        console.put_char(0x1B)?;
+        console.put_str("ok\u{7}")?;
+
+        assert_eq!(console.take(), b"\x1B.ok.");
+        assert_eq!(console.sanitized(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_has_nothing_to_take() -> io::Result<()> {
+        let mut output = Output::stream(Box::new(io::sink()));
+        write!(output, "Hello")?;
+
+        assert!(output.take().is_empty());
+
+        Ok(())
+    }
+}
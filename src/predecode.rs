@@ -0,0 +1,323 @@
+//! Instructions of the loaded programs decoded once, behind [`VM::set_predecode`](crate::VM::set_predecode)
+//!
+//! The run loop executes a cached [`Instruction`] instead of extracting the
+//! opcode and operand fields from the word again. Every write to a cached
+//! address drops its entry, so self-modifying code runs what it wrote.
+
+use crate::instruction::Instruction;
+use crate::keyboard::MR_KBSR;
+use crate::loader::LoadedProgram;
+use crate::memory::{Memory, MEMORY_MAX};
+
+/// The decoded instruction at each address, `None` where the word has to be decoded when it runs
+#[derive(Clone)]
+pub(crate) struct DecodeCache {
+    entries: Box<[Option<Instruction>]>,
+}
+
+impl DecodeCache {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: vec![None; MEMORY_MAX].into_boxed_slice(),
+        }
+    }
+
+    /// Decodes the words written by `program`
+    ///
+    /// Words that aren't well-formed instructions, data most likely, are left
+    /// out and decoded like any other word if they ever run. So are the device
+    /// registers, fetching from them has to go through the devices.
+    pub(crate) fn decode(&mut self, memory: &Memory, program: &LoadedProgram) {
+        let mut address = program.origin;
+        for _ in 0..program.len {
+            let decoded = Some(memory.read_ram(address))
+                .filter(|_| address < MR_KBSR)
+                .and_then(|word| Instruction::decode(word).ok());
+            if let Some(entry) = self.entries.get_mut(usize::from(address)) {
+                *entry = decoded;
+            }
+            address = address.wrapping_add(1);
+        }
+    }
+
+    pub(crate) fn get(&self, address: u16) -> Option<Instruction> {
+        self.entries.get(usize::from(address)).copied().flatten()
+    }
+
+    /// Forgets the instruction at `address` after the word there changed
+    pub(crate) fn invalidate(&mut self, address: u16) {
+        if let Some(entry) = self.entries.get_mut(usize::from(address)) {
+            *entry = None;
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::disassemble;
+    use crate::instruction::Operand;
+    use crate::keyboard::{EofPolicy, ScriptedInput};
+    use crate::loader::Endianness;
+    use crate::opdcodes::Opcode;
+    use crate::{StepOutcome, VMError, VM};
+
+    /// Multiplies 7 by 6 in a subroutine, stores the product through a pointer
+    /// and counts down with a never-taken `BR` word in the loop
+    const COMPUTE: &str = "        .ORIG x3000
+        LEA R6, STACK
+        AND R0, R0, #0
+        ADD R1, R0, #7
+        ADD R2, R0, #6
+        JSR MULT
+        STI R0, RESULT
+        LDI R3, RESULT
+        LD R4, RESULT
+        STR R3, R4, #1
+        LDR R5, R4, #1
+        NOT R5, R5
+        LEA R7, DONE
+        JMP R7
+        ADD R0, R0, #15
+DONE    HALT
+MULT    ADD R0, R0, R1
+        .FILL x0000
+        ADD R2, R2, #-1
+        BRp MULT
+        RET
+RESULT  .FILL x4000
+STACK   .BLKW 4
+        .END
+";
+
+    fn run(source: &str, predecode: bool) -> Result<VM, VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_halt_banner(false);
+        vm.set_predecode(predecode);
+        vm.load_asm(source)?;
+        vm.run()?;
+        Ok(vm)
+    }
+
+    #[test]
+    fn test_predecoded_run_matches_decoding_each_word() -> Result<(), VMError> {
+        let decoded = run(COMPUTE, false)?;
+        let predecoded = run(COMPUTE, true)?;
+
+        assert_eq!(predecoded.read_register(3)?, 42);
+        assert_eq!(predecoded.read_register(5)?, !42);
+        assert_eq!(
+            predecoded.registers_snapshot(),
+            decoded.registers_snapshot()
+        );
+        assert!(predecoded.memory() == decoded.memory());
+        assert_eq!(
+            predecoded.instructions_executed(),
+            decoded.instructions_executed()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_modifying_code_with_predecode() -> Result<(), VMError> {
+        // The first pass through TARGET adds 1, then the loop patches it to add 5
+        let source = "        .ORIG x3000
+        AND R0, R0, #0
+        ADD R2, R0, #2
+AGAIN   ADD R0, R0, #0
+TARGET  ADD R0, R0, #1
+        LD R1, PATCH
+        ST R1, TARGET
+        ADD R2, R2, #-1
+        BRp AGAIN
+        HALT
+PATCH   ADD R0, R0, #5
+        .END
+";
+        let vm = run(source, true)?;
+
+        assert_eq!(vm.read_register(0)?, 6);
+        assert_eq!(
+            vm.registers_snapshot(),
+            run(source, false)?.registers_snapshot()
+        );
+
+        Ok(())
+    }
+
+    fn cached(vm: &VM, address: u16) -> Option<Instruction> {
+        vm.decode_cache
+            .as_ref()
+            .and_then(|cache| cache.get(address))
+    }
+
+    #[test]
+    fn test_cache_follows_loads_and_restores() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_predecode(true);
+        vm.load_asm("        .ORIG x3000\n        ADD R0, R0, #1\n        .END\n")?;
+        let add = Some(Instruction::Add {
+            dr: 0,
+            sr1: 0,
+            operand: Operand::Immediate(1),
+        });
+        assert_eq!(cached(&vm, 0x3000), add);
+
+        let snapshot = vm.snapshot();
+        // ADD R1, R1, #2
+        vm.write_memory(0x3000, 0x1262)?;
+        assert_eq!(cached(&vm, 0x3000), None);
+
+        vm.restore(&snapshot);
+        assert_eq!(cached(&vm, 0x3000), add);
+        vm.step()?;
+        assert_eq!(vm.read_register(0)?, 1);
+        assert_eq!(vm.read_register(1)?, 0);
+
+        vm.reset();
+        assert_eq!(cached(&vm, 0x3000), None);
+
+        Ok(())
+    }
+
+    /// Opcodes whose words may run differently from the cache, each with the reason
+    ///
+    /// Empty: both ways of running agree on every generated case.
+    const KNOWN_DIFFERENCES: &[(Opcode, &str)] = &[];
+
+    /// splitmix64, so a failing case reproduces from its seed
+    fn next_word(state: &mut u64) -> u16 {
+        *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let z = (*state ^ (*state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        let [.., high, low] = (z ^ (z >> 31)).to_be_bytes();
+        u16::from_be_bytes([high, low])
+    }
+
+    /// A VM with scrambled registers, random data from x2F00 to x31FF and
+    /// `words` loaded over it at x3000
+    fn engine(seed: u64, words: &[u16], predecode: bool) -> Result<VM, VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_halt_banner(false);
+        vm.set_input_source(Box::new(ScriptedInput::new(b"lc3\n".to_vec())));
+        vm.set_eof_policy(EofPolicy::Zero);
+        vm.scramble_registers(seed);
+        let mut state = seed;
+        for address in 0x2F00..0x3200 {
+            vm.write_memory(address, next_word(&mut state))?;
+        }
+        vm.set_predecode(predecode);
+        let image: Vec<u8> = std::iter::once(0x3000)
+            .chain(words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        vm.load_bytes(&image, Endianness::Big)?;
+
+        Ok(vm)
+    }
+
+    /// Runs `words` both ways in lockstep for up to `max_steps` instructions
+    ///
+    /// # Returns
+    /// A description of the first step after which the registers, the flags,
+    /// the output or the outcome differ, with the instruction decoded, or of
+    /// the first memory word that differs at the end
+    fn first_divergence(
+        seed: u64,
+        words: &[u16],
+        max_steps: usize,
+    ) -> Result<Option<String>, VMError> {
+        let mut decoded = engine(seed, words, false)?;
+        let mut predecoded = engine(seed, words, true)?;
+
+        for step in 0..max_steps {
+            let pc = decoded.pc();
+            let word = decoded.memory().read_ram(pc);
+            let outcomes = (decoded.step(), predecoded.step());
+            let states = (
+                (decoded.registers_snapshot(), decoded.take_output()),
+                (predecoded.registers_snapshot(), predecoded.take_output()),
+            );
+            let allowed = KNOWN_DIFFERENCES
+                .iter()
+                .any(|(opcode, _)| *opcode == Opcode::from(word >> 12));
+            if (format!("{:?}", outcomes.0) != format!("{:?}", outcomes.1) || states.0 != states.1)
+                && !allowed
+            {
+                return Ok(Some(format!(
+                    "step {step}, x{pc:04X}: {:04X} {}\n  each word: {:?} {:?}\n  predecoded: {:?} {:?}",
+                    word,
+                    disassemble(word, pc),
+                    outcomes.0,
+                    states.0,
+                    outcomes.1,
+                    states.1
+                )));
+            }
+            if !matches!(outcomes.0, Ok(StepOutcome::Executed { halted: None, .. })) {
+                break;
+            }
+        }
+
+        Ok((decoded.memory() != predecoded.memory())
+            .then(|| {
+                (0..=u16::MAX).find(|&address| {
+                    decoded.memory().read_ram(address) != predecoded.memory().read_ram(address)
+                })
+            })
+            .flatten()
+            .map(|address| {
+                format!(
+                    "memory at x{address:04X}: x{:04X} each word, x{:04X} predecoded",
+                    decoded.memory().read_ram(address),
+                    predecoded.memory().read_ram(address)
+                )
+            }))
+    }
+
+    #[test]
+    fn test_single_instructions_run_the_same_predecoded() -> Result<(), VMError> {
+        let mut state = 0x630;
+        for seed in 0..2000 {
+            let word = next_word(&mut state);
+            let divergence = first_divergence(seed, &[word, 0xF025], 2)?;
+            assert!(
+                divergence.is_none(),
+                "seed {seed}: {}",
+                divergence.unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_small_programs_run_the_same_predecoded() -> Result<(), VMError> {
+        let mut state = 0x6300;
+        for seed in 0..300 {
+            // One word in eight is a trap among GETC, OUT, PUTS, IN, PUTSP and HALT
+            let words: Vec<u16> = (0..16)
+                .map(|_| match next_word(&mut state) {
+                    word if word.is_multiple_of(8) => 0xF020 | ((word >> 3) % 6),
+                    word => word,
+                })
+                .chain([0xF025])
+                .collect();
+            let divergence = first_divergence(seed, &words, 200)?;
+            assert!(
+                divergence.is_none(),
+                "seed {seed}: {}",
+                divergence.unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+}
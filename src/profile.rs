@@ -0,0 +1,218 @@
+//! Execution counters collected while profiling is enabled
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::disasm;
+use crate::memory::MEMORY_MAX;
+use crate::Opcode;
+
+/// Counters the VM keeps while profiling is on
+#[derive(Debug, Clone)]
+pub(crate) struct Profiler {
+    pub(crate) report: ProfileReport,
+    fetches: Box<[u32; MEMORY_MAX]>,
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Self {
+            report: ProfileReport::default(),
+            fetches: Box::new([0; MEMORY_MAX]),
+        }
+    }
+
+    /// Counts the fetch at `pc` and the executed instruction
+    pub(crate) fn record(&mut self, pc: u16, instruction: u16, next_pc: u16) {
+        if let Some(count) = self.fetches.get_mut(usize::from(pc)) {
+            *count = count.saturating_add(1);
+        }
+
+        self.report.record(pc, instruction, next_pc);
+    }
+
+    /// Returns up to `top_n` fetched addresses with their counts, most fetched first
+    ///
+    /// Addresses fetched equally often are ordered by address.
+    pub(crate) fn hottest(&self, top_n: usize) -> Vec<(u16, u32)> {
+        let mut hot: Vec<(u16, u32)> = self
+            .fetches
+            .iter()
+            .zip(0..=u16::MAX)
+            .filter(|&(&count, _)| count > 0)
+            .map(|(&count, address)| (address, count))
+            .collect();
+        hot.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        hot.truncate(top_n);
+
+        hot
+    }
+}
+
+/// An address from [`VM::hot_addresses`](crate::VM::hot_addresses)
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotAddress {
+    /// Fetched address
+    pub address: u16,
+    /// Number of fetches, saturating at `u32::MAX`
+    pub count: u32,
+    /// Address with its enclosing label, e.g. `LOOP+1 (x3003)`
+    pub location: String,
+}
+
+impl fmt::Display for HotAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>10}  {}", self.count, self.location)
+    }
+}
+
+/// Instruction counts per opcode, branch outcomes and trap vectors
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProfileReport {
+    opcodes: [u64; 16],
+    branches_taken: u64,
+    branches_not_taken: u64,
+    traps: BTreeMap<u8, u64>,
+}
+
+impl ProfileReport {
+    /// Counts an executed instruction
+    ///
+    /// `next_pc` is the PC after execution, used to tell taken branches apart.
+    pub fn record(&mut self, pc: u16, instruction: u16, next_pc: u16) {
+        if let Some(count) = self.opcodes.get_mut(usize::from(instruction >> 12)) {
+            *count = count.saturating_add(1);
+        }
+
+        match Opcode::from(instruction >> 12) {
+            Opcode::Br if next_pc != pc.wrapping_add(1) => {
+                self.branches_taken = self.branches_taken.saturating_add(1);
+            }
+            Opcode::Br => {
+                self.branches_not_taken = self.branches_not_taken.saturating_add(1);
+            }
+            Opcode::Trap => {
+                let vector = instruction.to_be_bytes()[1];
+                let count = self.traps.entry(vector).or_default();
+                *count = count.saturating_add(1);
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns how many instructions with `opcode` were executed
+    pub fn count(&self, opcode: Opcode) -> u64 {
+        self.opcodes
+            .iter()
+            .zip(0u16..)
+            .find(|&(_, bits)| Opcode::from(bits) == opcode)
+            .map_or(0, |(&count, _)| count)
+    }
+
+    /// Returns the total number of executed instructions
+    pub fn total(&self) -> u64 {
+        self.opcodes
+            .iter()
+            .fold(0, |total, &count| total.saturating_add(count))
+    }
+
+    /// Returns how many branches were taken and how many fell through
+    pub fn branches(&self) -> (u64, u64) {
+        (self.branches_taken, self.branches_not_taken)
+    }
+
+    /// Returns how many times the trap at `vector` was executed
+    pub fn trap_count(&self, vector: u8) -> u64 {
+        self.traps.get(&vector).copied().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    /// Prints the opcodes by descending count, then branches and traps
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut opcodes: Vec<(u64, Opcode)> = self
+            .opcodes
+            .iter()
+            .zip(0u16..)
+            .filter(|&(&count, _)| count > 0)
+            .map(|(&count, bits)| (count, Opcode::from(bits)))
+            .collect();
+        opcodes.sort_by_key(|&(count, _)| std::cmp::Reverse(count));
+
+        writeln!(f, "{:<8}{:>12}", "Opcode", "Count")?;
+        for (count, opcode) in opcodes {
+            let name = format!("{:?}", opcode).to_uppercase();
+            writeln!(f, "{:<8}{:>12}", name, count)?;
+        }
+        writeln!(f, "{:<8}{:>12}", "Total", self.total())?;
+
+        writeln!(
+            f,
+            "Branches: {} taken, {} not taken",
+            self.branches_taken, self.branches_not_taken
+        )?;
+
+        if !self.traps.is_empty() {
+            writeln!(f, "Traps:")?;
+            for (&vector, count) in &self.traps {
+                let name = disasm::disassemble(u16::from_be_bytes([0xF0, vector]), 0);
+                writeln!(f, "  x{:02X} {:<8}{:>10}", vector, name, count)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fetch_counts_saturate() {
+        let mut profiler = Profiler::new();
+        if let Some(count) = profiler.fetches.get_mut(0x3000) {
+            *count = u32::MAX;
+        }
+        profiler.record(0x3000, 0x1021, 0x3001);
+        profiler.record(0x3001, 0x1021, 0x3002);
+
+        assert_eq!(profiler.hottest(10), vec![(0x3000, u32::MAX), (0x3001, 1)]);
+        assert_eq!(profiler.hottest(1), vec![(0x3000, u32::MAX)]);
+    }
+
+    #[test]
+    fn test_record_branches_and_traps() {
+        let mut profile = ProfileReport::default();
+        // BRz x3003 taken, then not taken
+        profile.record(0x3000, 0x0402, 0x3003);
+        profile.record(0x3000, 0x0402, 0x3001);
+        profile.record(0x3001, 0xF025, 0x3002);
+
+        assert_eq!(profile.count(Opcode::Br), 2);
+        assert_eq!(profile.branches(), (1, 1));
+        assert_eq!(profile.trap_count(0x25), 1);
+        assert_eq!(profile.trap_count(0x20), 0);
+        assert_eq!(profile.total(), 3);
+    }
+
+    #[test]
+    fn test_report_display() {
+        let mut profile = ProfileReport::default();
+        profile.record(0x3000, 0x1021, 0x3001);
+        profile.record(0x3001, 0x1021, 0x3002);
+        profile.record(0x3002, 0xF025, 0x3003);
+
+        assert_eq!(
+            profile.to_string(),
+            "Opcode         Count
+ADD                2
+TRAP               1
+Total              3
+Branches: 0 taken, 0 not taken
+Traps:
+  x25 HALT             1
+"
+        );
+    }
+}
@@ -1,8 +1,13 @@
+use alloc::string::String;
+use core::fmt;
+
 use crate::errors::VMError;
 
-const PC_START: u16 = 0x3000;
+/// Where execution starts unless a program or the VM says otherwise, lower addresses are reserved for the OS
+pub const PC_START: u16 = 0x3000;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RegisterFlags {
     Pos = 1 << 0,
     Zro = 1 << 1,
@@ -11,19 +16,28 @@ pub enum RegisterFlags {
 
 const NUM_REGISTERS: usize = 8; // R0-R7
 
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Registers {
     regs: [u16; NUM_REGISTERS],
     pub pc: u16,
     pub condition: RegisterFlags,
 }
 
+impl Default for Registers {
+    /// Registers with the PC at [`PC_START`]
+    fn default() -> Self {
+        Self::new(PC_START)
+    }
+}
+
 impl Registers {
     /// Creates a new instance of Registers with all registers initialized to 0,
-    /// program counter set to PC_START, and condition flags set to zero
-    pub fn new() -> Self {
+    /// program counter set to `pc`, and condition flags set to zero
+    pub fn new(pc: u16) -> Self {
         Self {
             regs: [0; NUM_REGISTERS],
-            pc: PC_START, // Program counter starts at 0x3000, lower addresses are reserved for OS
+            pc,
             condition: RegisterFlags::Zro,
         }
     }
@@ -43,6 +57,14 @@ impl Registers {
             .ok_or(VMError::InvalidRegister)
     }
 
+    /// Reads a register index taken from a 3-bit instruction field
+    ///
+    /// The index is masked to 0-7, so unlike [`Registers::get`] there is nothing to fail.
+    #[inline]
+    pub(crate) fn read(&self, register: usize) -> u16 {
+        self.regs.get(register & 0x7).copied().unwrap_or_default()
+    }
+
     /// Sets the value of the specified register
     ///
     /// # Arguments
@@ -75,13 +97,153 @@ impl Registers {
     }
 }
 
+/// Copy of every register at one moment, printed as an aligned table
+///
+/// ```text
+/// R0: x0005      5
+/// ...
+/// R7: xFFFF     -1
+/// PC: x3004
+/// CC: P (positive)
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegisterSnapshot {
+    /// R0 to R7
+    pub r: [u16; NUM_REGISTERS],
+    pub pc: u16,
+    pub flags: RegisterFlags,
+}
+
+impl Registers {
+    /// Copies the general purpose registers, the PC and the condition flags
+    pub fn snapshot(&self) -> RegisterSnapshot {
+        RegisterSnapshot {
+            r: self.regs,
+            pc: self.pc,
+            flags: self.condition,
+        }
+    }
+}
+
+/// Names of the registers by the role course conventions give them, such as SP for R6
+///
+/// Shown next to the numbered names and accepted wherever a register is named,
+/// case-insensitively. A name that already means something else, such as `pc`
+/// or `r3`, keeps that meaning.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterAliases {
+    names: [Option<String>; NUM_REGISTERS],
+}
+
+impl Default for RegisterAliases {
+    /// R6 is SP, the stack pointer, and R7 is RA, the return address
+    fn default() -> Self {
+        let mut aliases = Self::none();
+        aliases.names[6] = Some(String::from("SP"));
+        aliases.names[7] = Some(String::from("RA"));
+        aliases
+    }
+}
+
+impl RegisterAliases {
+    /// No aliases, registers are only R0-R7
+    pub fn none() -> Self {
+        Self {
+            names: Default::default(),
+        }
+    }
+
+    /// Calls `register` by `name` as well, replacing its previous alias
+    ///
+    /// # Errors
+    /// Returns `VMError::InvalidRegister` if `register` isn't 0-7
+    pub fn set(&mut self, register: usize, name: &str) -> Result<(), VMError> {
+        let slot = self
+            .names
+            .get_mut(register)
+            .ok_or(VMError::InvalidRegister)?;
+        *slot = Some(name.to_ascii_uppercase());
+        Ok(())
+    }
+
+    /// Removes the alias of `register`
+    pub fn remove(&mut self, register: usize) {
+        if let Some(slot) = self.names.get_mut(register) {
+            *slot = None;
+        }
+    }
+
+    /// Returns the alias of `register`, uppercase
+    pub fn get(&self, register: usize) -> Option<&str> {
+        self.names.get(register)?.as_deref()
+    }
+
+    /// Returns the register called `name`, ignoring case
+    pub fn register(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|alias| {
+            alias
+                .as_deref()
+                .is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+impl RegisterSnapshot {
+    /// Prints like the snapshot itself, with the alias after each register that has one
+    ///
+    /// ```text
+    /// R6: xFDFF   -513 (SP)
+    /// ```
+    pub fn with_aliases<'a>(&'a self, aliases: &'a RegisterAliases) -> AliasedRegisters<'a> {
+        AliasedRegisters {
+            snapshot: self,
+            aliases,
+        }
+    }
+}
+
+/// A [`RegisterSnapshot`] printed with aliases, see [`RegisterSnapshot::with_aliases`]
+pub struct AliasedRegisters<'a> {
+    snapshot: &'a RegisterSnapshot,
+    aliases: &'a RegisterAliases,
+}
+
+impl fmt::Display for AliasedRegisters<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (number, value) in self.snapshot.r.iter().enumerate() {
+            let signed = i16::from_be_bytes(value.to_be_bytes());
+            write!(f, "R{}: x{:04X} {:>6}", number, value, signed)?;
+            match self.aliases.get(number) {
+                Some(alias) => writeln!(f, " ({})", alias)?,
+                None => writeln!(f)?,
+            }
+        }
+        writeln!(f, "PC: x{:04X}", self.snapshot.pc)?;
+
+        let flags = match self.snapshot.flags {
+            RegisterFlags::Neg => "N (negative)",
+            RegisterFlags::Zro => "Z (zero)",
+            RegisterFlags::Pos => "P (positive)",
+        };
+        writeln!(f, "CC: {}", flags)
+    }
+}
+
+impl fmt::Display for RegisterSnapshot {
+    /// Prints R0-R7 without aliases, the PC and the condition flags, one per line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.with_aliases(&RegisterAliases::none()).fmt(f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_registers_init() {
-        let regs = Registers::new();
+        let regs = Registers::default();
 
         for reg in regs.regs.iter() {
             assert_eq!(*reg, 0);
@@ -92,11 +254,70 @@ mod tests {
 
         // assert program counter is set to 0x3000
         assert_eq!(regs.pc, PC_START);
+        assert_eq!(Registers::new(0x0200).pc, 0x0200);
+    }
+
+    #[test]
+    fn test_snapshot_display() {
+        let mut regs = Registers::default();
+        regs.set(0, 5);
+        regs.set(3, 0x8000);
+        regs.set(7, 0xFFFF);
+        regs.update_flags(7);
+        regs.pc = 0x3004;
+
+        assert_eq!(
+            regs.snapshot().to_string(),
+            "R0: x0005      5
+R1: x0000      0
+R2: x0000      0
+R3: x8000 -32768
+R4: x0000      0
+R5: x0000      0
+R6: x0000      0
+R7: xFFFF     -1
+PC: x3004
+CC: N (negative)
+"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_display_with_aliases() -> Result<(), VMError> {
+        let mut regs = Registers::default();
+        regs.set(6, 0xFDFF);
+
+        let mut aliases = RegisterAliases::default();
+        aliases.set(5, "fp")?;
+        assert_eq!(aliases.register("sp"), Some(6));
+        assert_eq!(aliases.register("FP"), Some(5));
+        assert_eq!(aliases.register("r6"), None);
+        assert!(aliases.set(8, "X").is_err());
+
+        let snapshot = regs.snapshot();
+        let printed = snapshot.with_aliases(&aliases).to_string();
+        assert!(printed.contains("\nR4: x0000      0\n"), "{}", printed);
+        assert!(printed.contains("\nR5: x0000      0 (FP)\n"), "{}", printed);
+        assert!(printed.contains("\nR6: xFDFF   -513 (SP)\n"), "{}", printed);
+        assert!(
+            printed.contains("\nR7: x0000      0 (RA)\nPC: x3000\n"),
+            "{}",
+            printed
+        );
+
+        aliases.remove(6);
+        assert_eq!(aliases.get(6), None);
+        assert_eq!(
+            snapshot.with_aliases(&RegisterAliases::none()).to_string(),
+            snapshot.to_string()
+        );
+
+        Ok(())
     }
 
     #[test]
     fn test_update_flags() {
-        let mut regs = Registers::new();
+        let mut regs = Registers::default();
         regs.set(0, 0);
         regs.update_flags(0);
         assert_eq!(regs.condition, RegisterFlags::Zro);
@@ -0,0 +1,168 @@
+//! Machine-readable summary of a run, for graders and CI pipelines
+
+use std::time::Duration;
+
+use crate::loader::LoadedProgram;
+use crate::registers::RegisterSnapshot;
+use crate::{HaltReason, PauseReason, RunOutcome, VMError, VM};
+
+/// Final state of a run, written as one JSON object with the `serde` feature
+///
+/// ```json
+/// {
+///   "outcome": "halted",
+///   "reason": "trap_halt",
+///   "exit_status": 0,
+///   "instructions": 3,
+///   "wall_time_ms": 1,
+///   "registers": {"r": [12291, 0, 0, 0, 0, 0, 0, 12291], "pc": 12291, "flags": "Pos"},
+///   "segments": [{"origin": 12288, "len": 16, "end": 12304, "source": "examples/hello-world.obj"}],
+///   "output": "SGVsbG8gV29ybGQh"
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Report {
+    /// `halted`, `paused` or `error`
+    pub outcome: &'static str,
+    /// Why the run ended, such as `trap_halt` or `limit_reached`, or the error
+    pub reason: String,
+    /// Status the process exits with
+    pub exit_status: i32,
+    /// Instructions executed since the VM was created
+    pub instructions: u64,
+    /// Milliseconds the run took
+    pub wall_time_ms: u64,
+    /// R0-R7, the PC and the condition flags at the end of the run
+    pub registers: RegisterSnapshot,
+    /// The program images that were loaded
+    pub segments: Vec<LoadedProgram>,
+    /// What the program printed, base64 encoded, if it was captured
+    #[cfg_attr(feature = "serde", serde(serialize_with = "base64_output"))]
+    pub output: Option<Vec<u8>>,
+}
+
+impl Report {
+    /// Summarizes the run of `vm` that ended with `result` after `wall_time`
+    pub fn new(
+        vm: &VM,
+        result: &Result<RunOutcome, VMError>,
+        exit_status: i32,
+        wall_time: Duration,
+    ) -> Self {
+        let (outcome, reason) = match result {
+            Ok(RunOutcome::Halted(reason)) => ("halted", halt_reason(reason).to_string()),
+            Ok(RunOutcome::Paused(reason)) => ("paused", pause_reason(reason).to_string()),
+            Err(e) => ("error", vm.describe_error(e)),
+        };
+
+        Self {
+            outcome,
+            reason,
+            exit_status,
+            instructions: vm.instructions_executed(),
+            wall_time_ms: u64::try_from(wall_time.as_millis()).unwrap_or(u64::MAX),
+            registers: vm.registers_snapshot(),
+            segments: vm.loaded_segments().to_vec(),
+            output: None,
+        }
+    }
+
+    /// Adds what the program printed
+    pub fn with_output(mut self, output: Vec<u8>) -> Self {
+        self.output = Some(output);
+        self
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Report {
+    /// The report as a single line of JSON
+    ///
+    /// # Errors
+    /// Returns `VMError::SaveFailed` if the report can't be serialized
+    pub fn to_json(&self) -> Result<String, VMError> {
+        serde_json::to_string(self).map_err(|e| VMError::SaveFailed(e.to_string()))
+    }
+}
+
+#[cfg(feature = "serde")]
+fn base64_output<S: serde::Serializer>(
+    output: &Option<Vec<u8>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use base64::Engine;
+
+    match output {
+        Some(bytes) => {
+            serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(bytes))
+        }
+        None => serializer.serialize_none(),
+    }
+}
+
+fn halt_reason(reason: &HaltReason) -> &'static str {
+    match reason {
+        HaltReason::TrapHalt => "trap_halt",
+        HaltReason::McrCleared => "mcr_cleared",
+        HaltReason::LikelyInfiniteLoop { .. } => "infinite_loop",
+        HaltReason::InputExhausted => "input_exhausted",
+        HaltReason::Interrupted => "interrupted",
+    }
+}
+
+fn pause_reason(reason: &PauseReason) -> &'static str {
+    match reason {
+        PauseReason::Breakpoint(_) => "breakpoint",
+        PauseReason::LimitReached { .. } => "limit_reached",
+        PauseReason::TimeLimitReached { .. } => "time_limit_reached",
+        PauseReason::AddressReached(_) => "address_reached",
+        PauseReason::Requested => "requested",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_of_halted_run() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.load_program("examples/hello-world.obj", crate::loader::Endianness::Big)?;
+        let result = vm.run();
+        let report =
+            Report::new(&vm, &result, 0, Duration::from_millis(3)).with_output(vm.take_output());
+
+        assert_eq!(report.outcome, "halted");
+        assert_eq!(report.reason, "trap_halt");
+        assert_eq!(report.instructions, 3);
+        assert_eq!(report.wall_time_ms, 3);
+        assert_eq!(report.segments.first().map(|s| s.origin), Some(0x3000));
+        assert_eq!(report.output.as_deref(), Some(&b"Hello World!"[..]));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_report_json() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.load_program("examples/simple_add.obj", crate::loader::Endianness::Big)?;
+        let result = vm.run_with_limit(2);
+        let report = Report::new(&vm, &result, 124, Duration::ZERO);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()?)
+            .map_err(|e| VMError::SaveFailed(e.to_string()))?;
+        let field = |path: &str| json.pointer(path).cloned().unwrap_or_default();
+        assert_eq!(field("/outcome"), "paused");
+        assert_eq!(field("/reason"), "limit_reached");
+        assert_eq!(field("/exit_status"), 124);
+        assert_eq!(field("/registers/r/0"), 5);
+        assert_eq!(field("/registers/pc"), 0x3002);
+        assert!(json.get("output").is_some_and(serde_json::Value::is_null));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! Drawing the full-screen debugger with ANSI escape sequences
+
+use lc3_vm::console::Console;
+use lc3_vm::tui::{Key, Line, TuiDebugger};
+use lc3_vm::VM;
+
+/// Rows of the memory and output panes
+pub const PANE_ROWS: u16 = 4;
+
+/// Width of the registers pane
+const REGISTERS_WIDTH: u16 = 22;
+
+/// Switches to the alternate screen and hides the cursor
+pub const ENTER: &str = "\x1b[?1049h\x1b[?25l";
+
+/// Shows the cursor and returns to the normal screen
+pub const LEAVE: &str = "\x1b[?25h\x1b[?1049l";
+
+/// Columns and rows of the terminal on stdout, 80x24 if it can't be told
+pub fn size() -> (u16, u16) {
+    #[cfg(unix)]
+    {
+        let mut size = libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: TIOCGWINSZ only writes to `size`
+        let found = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if found == 0 && size.ws_col > 0 && size.ws_row > 0 {
+            return (size.ws_col, size.ws_row);
+        }
+    }
+
+    (80, 24)
+}
+
+/// `line` cut or padded to exactly `width` columns, highlighted as the view model asks
+fn styled(line: Option<&Line>, width: u16) -> String {
+    let width = usize::from(width);
+    let Some(line) = line else {
+        return " ".repeat(width);
+    };
+    let text: String = line.text.chars().take(width).collect();
+    let padded = format!("{:<width$}", text, width = width);
+
+    match (line.selected, line.highlight) {
+        (true, _) => format!("\x1b[7m{}\x1b[0m", padded),
+        (false, true) => format!("\x1b[33m{}\x1b[0m", padded),
+        (false, false) => padded,
+    }
+}
+
+/// A separator line titled `title`
+fn rule(title: &str, width: u16) -> String {
+    let line: String = format!("── {} ", title)
+        .chars()
+        .chain(std::iter::repeat('─'))
+        .take(usize::from(width))
+        .collect();
+
+    format!("\x1b[2m{}\x1b[0m", line)
+}
+
+/// Renders every pane of `debugger` for a `width` x `height` terminal
+pub fn draw(debugger: &TuiDebugger, vm: &VM, width: u16, height: u16) -> String {
+    let code_width = width.saturating_sub(REGISTERS_WIDTH).max(20);
+    let code_rows = height
+        .saturating_sub(PANE_ROWS.saturating_mul(2).saturating_add(4))
+        .max(3);
+
+    let code = debugger.disassembly(vm, code_rows);
+    let registers = debugger.registers(vm);
+    let mut rows = vec![rule("Code", width)];
+    for row in 0..usize::from(code_rows) {
+        rows.push(format!(
+            "{} {}",
+            styled(code.get(row), code_width.saturating_sub(1)),
+            styled(registers.get(row), REGISTERS_WIDTH)
+        ));
+    }
+    rows.push(rule("Memory", width));
+    for line in debugger.memory(vm, PANE_ROWS) {
+        rows.push(styled(Some(&line), width));
+    }
+    rows.push(rule("Output", width));
+    let output = debugger.output(PANE_ROWS);
+    for row in 0..usize::from(PANE_ROWS) {
+        rows.push(styled(output.get(row), width));
+    }
+    rows.push(styled(
+        Some(&Line {
+            text: debugger.status().to_string(),
+            highlight: false,
+            selected: true,
+        }),
+        width,
+    ));
+
+    format!("\x1b[H{}", rows.join("\n"))
+}
+
+/// Waits for the next key the debugger knows, decoding arrow and page keys
+pub fn read_key(console: &mut impl Console) -> std::io::Result<Key> {
+    let escape_timeout = std::time::Duration::from_millis(50);
+    loop {
+        let byte = console.read_key()?;
+        if byte != 0x1b {
+            return Ok(Key::Char(char::from(byte)));
+        }
+        if console.read_key_timeout(escape_timeout)? != Some(b'[') {
+            continue;
+        }
+        let key = match console.read_key_timeout(escape_timeout)? {
+            Some(b'A') => Key::Up,
+            Some(b'B') => Key::Down,
+            Some(b'5') => Key::PageUp,
+            Some(b'6') => Key::PageDown,
+            _ => continue,
+        };
+        if matches!(key, Key::PageUp | Key::PageDown) {
+            // The `~` ending the sequence
+            console.read_key_timeout(escape_timeout)?;
+        }
+
+        return Ok(key);
+    }
+}
@@ -0,0 +1,217 @@
+//! Recording and replaying console I/O
+//!
+//! A [`Session`] lists every character the program received and printed,
+//! each with the number of instructions executed at that moment. Replaying
+//! a session with [`VM::replay`](crate::VM::replay) hands the recorded
+//! characters to the program at the same instruction counts, so the run is
+//! reproduced exactly no matter how fast the user typed.
+//!
+//! Sessions are stored in a small binary format: the magic `LC3S` and a
+//! version byte, followed by one 10-byte record per character, a kind byte
+//! (`I` for input, `O` for output), the big-endian instruction count and
+//! the character.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+use crate::errors::VMError;
+
+const MAGIC: &[u8; 4] = b"LC3S";
+const VERSION: u8 = 1;
+
+/// One character crossing the console
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionByte {
+    /// Number of instructions executed before the character was received or printed
+    pub at: u64,
+    /// The character
+    pub byte: u8,
+}
+
+/// Console I/O of one run, see [`VM::start_recording`](crate::VM::start_recording)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Session {
+    /// Characters received from the keyboard, in order
+    pub input: Vec<SessionByte>,
+    /// Characters printed, in order
+    pub output: Vec<SessionByte>,
+}
+
+impl Session {
+    /// Creates an empty session
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the printed characters without their instruction counts
+    pub fn output_bytes(&self) -> Vec<u8> {
+        self.output.iter().map(|event| event.byte).collect()
+    }
+
+    /// Writes the session in the binary session format
+    ///
+    /// # Errors
+    /// Returns `VMError::SaveFailed` if the writer fails
+    pub fn to_writer<W: Write>(&self, writer: &mut W) -> Result<(), VMError> {
+        let mut buffer = MAGIC.to_vec();
+        buffer.push(VERSION);
+
+        let records = self
+            .input
+            .iter()
+            .map(|event| (b'I', event))
+            .chain(self.output.iter().map(|event| (b'O', event)));
+        for (kind, event) in records {
+            buffer.push(kind);
+            buffer.extend_from_slice(&event.at.to_be_bytes());
+            buffer.push(event.byte);
+        }
+
+        writer
+            .write_all(&buffer)
+            .map_err(|e| VMError::SaveFailed(e.to_string()))
+    }
+
+    /// Reads a session written by [`Session::to_writer`]
+    ///
+    /// # Errors
+    /// * `VMError::LoadFailed` - If the reader fails
+    /// * `VMError::InvalidSession` - If the data isn't a session of a supported version
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self, VMError> {
+        let mut buffer = Vec::new();
+        reader
+            .read_to_end(&mut buffer)
+            .map_err(|_| VMError::LoadFailed)?;
+
+        let Some(records) = buffer.strip_prefix(MAGIC) else {
+            return Err(VMError::InvalidSession("not a session file"));
+        };
+        let Some((&version, records)) = records.split_first() else {
+            return Err(VMError::InvalidSession("missing version"));
+        };
+        if version != VERSION {
+            return Err(VMError::InvalidSession("unsupported version"));
+        }
+
+        let mut session = Session::new();
+        let mut chunks = records.chunks_exact(10);
+        for record in &mut chunks {
+            let (kind, rest) = record.split_at(1);
+            let (at, byte) = rest.split_at(8);
+            let at = u64::from_be_bytes(
+                at.try_into()
+                    .map_err(|_| VMError::InvalidSession("truncated record"))?,
+            );
+            let event = SessionByte {
+                at,
+                byte: byte.first().copied().unwrap_or_default(),
+            };
+
+            match kind {
+                b"I" => session.input.push(event),
+                b"O" => session.output.push(event),
+                _ => return Err(VMError::InvalidSession("unknown record kind")),
+            }
+        }
+        if !chunks.remainder().is_empty() {
+            return Err(VMError::InvalidSession("truncated record"));
+        }
+
+        Ok(session)
+    }
+}
+
+/// Recorded input being handed back to the program
+#[derive(Debug, Clone)]
+pub(crate) struct Replay {
+    input: VecDeque<SessionByte>,
+}
+
+impl Replay {
+    pub(crate) fn new(session: Session) -> Self {
+        Self {
+            input: session.input.into(),
+        }
+    }
+
+    /// Returns the next character if it was received at or before instruction `now`
+    pub(crate) fn poll(&mut self, now: u64) -> Option<u8> {
+        if self.input.front()?.at > now {
+            return None;
+        }
+
+        self.next()
+    }
+
+    /// Returns the next character whenever it was received
+    pub(crate) fn next(&mut self) -> Option<u8> {
+        self.input.pop_front().map(|event| event.byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session {
+            input: vec![SessionByte { at: 3, byte: b'y' }],
+            output: vec![
+                SessionByte { at: 0, byte: b'?' },
+                SessionByte {
+                    at: 1 << 40,
+                    byte: b'\n',
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_session_round_trip() -> Result<(), VMError> {
+        let mut bytes = Vec::new();
+        session().to_writer(&mut bytes)?;
+
+        assert_eq!(bytes.len(), 5 + 3 * 10);
+        assert_eq!(Session::from_reader(&mut bytes.as_slice())?, session());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_rejects_garbage() -> Result<(), VMError> {
+        let mut bytes = Vec::new();
+        session().to_writer(&mut bytes)?;
+
+        let mut truncated = bytes.clone();
+        truncated.pop();
+        assert!(matches!(
+            Session::from_reader(&mut truncated.as_slice()),
+            Err(VMError::InvalidSession("truncated record"))
+        ));
+
+        let mut newer = bytes;
+        if let Some(version) = newer.get_mut(4) {
+            *version = 2;
+        }
+        assert!(matches!(
+            Session::from_reader(&mut newer.as_slice()),
+            Err(VMError::InvalidSession("unsupported version"))
+        ));
+
+        assert!(matches!(
+            Session::from_reader(&mut &b"LC3-VM"[..]),
+            Err(VMError::InvalidSession("not a session file"))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_waits_for_recorded_instruction() {
+        let mut replay = Replay::new(session());
+
+        assert_eq!(replay.poll(2), None);
+        assert_eq!(replay.poll(3), Some(b'y'));
+        assert_eq!(replay.next(), None);
+    }
+}
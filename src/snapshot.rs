@@ -0,0 +1,216 @@
+//! Checkpoints of the complete machine state
+
+#[cfg(feature = "serde")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "serde")]
+use crate::errors::VMError;
+use crate::memory::Memory;
+use crate::metrics::Metrics;
+use crate::registers::Registers;
+use crate::VMState;
+
+/// Version of the serialized snapshot format, bumped on incompatible changes
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// Copy of everything a running program can observe, taken with [`crate::VM::snapshot`]
+///
+/// Memory is copied in full, 128 KiB per snapshot. The keyboard's input
+/// source can't be rewound, only a character already waiting in KBDR is kept.
+/// The instruction count and the metrics but the wall time go back with the
+/// machine, so a restored run reaches its limits and replayed keys at the same
+/// instructions.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "SnapshotFile", try_from = "SnapshotFile")
+)]
+pub struct Snapshot {
+    pub(crate) memory: Box<Memory>,
+    pub(crate) registers: Registers,
+    pub(crate) state: VMState,
+    pub(crate) keyboard_data: Option<u8>,
+    pub(crate) resume_from: Option<u16>,
+    pub(crate) instructions: u64,
+    pub(crate) metrics: Metrics,
+}
+
+impl Snapshot {
+    /// Returns the program counter at the time of the snapshot
+    pub fn pc(&self) -> u16 {
+        self.registers.pc
+    }
+
+    /// Returns whether the VM was running or halted at the time of the snapshot
+    pub fn state(&self) -> VMState {
+        self.state.clone()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Snapshot {
+    /// Writes the snapshot as JSON, with memory base64 encoded
+    ///
+    /// # Errors
+    /// Returns `VMError::InvalidSnapshot` if the writer fails
+    pub fn to_writer<W: Write>(&self, writer: W) -> Result<(), VMError> {
+        serde_json::to_writer(writer, self).map_err(|e| VMError::InvalidSnapshot(e.to_string()))
+    }
+
+    /// Reads a snapshot written by [`Snapshot::to_writer`]
+    ///
+    /// # Errors
+    /// Returns `VMError::InvalidSnapshot` if the data can't be read, isn't a
+    /// snapshot, or was written by an incompatible version
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, VMError> {
+        let mut text = Vec::new();
+        reader
+            .read_to_end(&mut text)
+            .map_err(|e| VMError::InvalidSnapshot(e.to_string()))?;
+
+        // Check the version before the rest, whose layout may have changed
+        let header: SnapshotHeader =
+            serde_json::from_slice(&text).map_err(|e| VMError::InvalidSnapshot(e.to_string()))?;
+        check_version(header.version).map_err(VMError::InvalidSnapshot)?;
+
+        serde_json::from_slice(&text).map_err(|e| VMError::InvalidSnapshot(e.to_string()))
+    }
+}
+
+/// On-disk layout of a snapshot
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SnapshotFile {
+    version: u32,
+    registers: Registers,
+    state: VMState,
+    keyboard_data: Option<u8>,
+    resume_from: Option<u16>,
+    instructions: u64,
+    metrics: Metrics,
+    memory: Box<Memory>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+#[cfg(feature = "serde")]
+fn check_version(version: u32) -> Result<(), String> {
+    if version == SNAPSHOT_VERSION {
+        Ok(())
+    } else {
+        Err(format!(
+            "snapshot format version {} is not supported, expected {}",
+            version, SNAPSHOT_VERSION
+        ))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Snapshot> for SnapshotFile {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            registers: snapshot.registers,
+            state: snapshot.state,
+            keyboard_data: snapshot.keyboard_data,
+            resume_from: snapshot.resume_from,
+            instructions: snapshot.instructions,
+            metrics: snapshot.metrics,
+            memory: snapshot.memory,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SnapshotFile> for Snapshot {
+    type Error = String;
+
+    fn try_from(file: SnapshotFile) -> Result<Self, String> {
+        check_version(file.version)?;
+
+        Ok(Self {
+            memory: file.memory,
+            registers: file.registers,
+            state: file.state,
+            keyboard_data: file.keyboard_data,
+            resume_from: file.resume_from,
+            instructions: file.instructions,
+            metrics: file.metrics,
+        })
+    }
+}
+
+impl std::fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Leave out the 64K words of memory
+        f.debug_struct("Snapshot")
+            .field("registers", &self.registers)
+            .field("state", &self.state)
+            .field("keyboard_data", &self.keyboard_data)
+            .field("instructions", &self.instructions)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::VM;
+
+    #[allow(clippy::unwrap_used)]
+    fn halted_snapshot() -> Snapshot {
+        let mut vm = VM::new();
+        vm.load_asm(".ORIG x3000\nADD R0, R0, #-3\nST R0, VALUE\nHALT\nVALUE .FILL x1234\n.END\n")
+            .unwrap();
+        vm.run().unwrap();
+        vm.snapshot()
+    }
+
+    #[test]
+    fn test_snapshot_json_round_trip() -> Result<(), VMError> {
+        let snapshot = halted_snapshot();
+
+        let mut json = Vec::new();
+        snapshot.to_writer(&mut json)?;
+        // Memory is a single base64 string rather than 65536 numbers
+        assert!(json.len() < 200_000);
+
+        assert_eq!(Snapshot::from_reader(json.as_slice())?, snapshot);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_bincode_round_trip() {
+        let snapshot = halted_snapshot();
+
+        let bytes = bincode::serialize(&snapshot).ok();
+        assert!(bytes.as_ref().is_some_and(|b| b.len() < 140_000));
+
+        let restored: Option<Snapshot> = bytes.and_then(|b| bincode::deserialize(&b).ok());
+        assert_eq!(restored, Some(snapshot));
+    }
+
+    #[test]
+    fn test_snapshot_rejects_other_versions() -> Result<(), VMError> {
+        let mut json = Vec::new();
+        halted_snapshot().to_writer(&mut json)?;
+        let json = String::from_utf8_lossy(&json).replacen("\"version\":2", "\"version\":1", 1);
+
+        assert!(matches!(
+            Snapshot::from_reader(json.as_bytes()),
+            Err(VMError::InvalidSnapshot(message)) if message.contains("version 1")
+        ));
+        assert!(matches!(
+            Snapshot::from_reader(&b"{\"version\":2}"[..]),
+            Err(VMError::InvalidSnapshot(_))
+        ));
+
+        Ok(())
+    }
+}
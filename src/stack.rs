@@ -0,0 +1,93 @@
+//! Bounds for the R6 stack
+
+use core::fmt;
+use core::str::FromStr;
+
+/// The region the R6 stack may use, growing down from `top` to `bottom`
+///
+/// R6 may also hold `top + 1`, the stack pointer of an empty stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackBounds {
+    /// Highest address of the stack, e.g. xFDFF
+    pub top: u16,
+    /// Lowest address the stack may grow to, e.g. xFA00
+    pub bottom: u16,
+}
+
+impl StackBounds {
+    /// Creates the bounds between two addresses given in either order
+    pub fn new(top: u16, bottom: u16) -> Self {
+        Self {
+            top: top.max(bottom),
+            bottom: top.min(bottom),
+        }
+    }
+
+    /// Returns whether `address` is inside the stack
+    pub fn contains(&self, address: u16) -> bool {
+        (self.bottom..=self.top).contains(&address)
+    }
+
+    /// Returns whether R6 may hold `sp`, either inside the stack or just above it
+    #[cfg(feature = "std")]
+    pub(crate) fn allows_pointer(&self, sp: u16) -> bool {
+        self.contains(sp) || Some(sp) == self.top.checked_add(1)
+    }
+}
+
+impl fmt::Display for StackBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "x{:04X}:x{:04X}", self.top, self.bottom)
+    }
+}
+
+impl FromStr for StackBounds {
+    type Err = ();
+
+    /// Parses `top:bottom` with hex addresses such as `0xFDFF:0xFA00`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let parse = |address: &str| {
+            let digits = address
+                .strip_prefix("0x")
+                .or_else(|| address.strip_prefix('x'))
+                .unwrap_or(address);
+            u16::from_str_radix(digits, 16).map_err(|_| ())
+        };
+
+        let (top, bottom) = text.split_once(':').ok_or(())?;
+        Ok(Self::new(parse(top)?, parse(bottom)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bounds() {
+        assert_eq!(
+            "0xFDFF:0xFA00".parse::<StackBounds>(),
+            Ok(StackBounds {
+                top: 0xFDFF,
+                bottom: 0xFA00
+            })
+        );
+        assert_eq!(
+            "xFA00:xFDFF".parse::<StackBounds>(),
+            Ok(StackBounds::new(0xFDFF, 0xFA00))
+        );
+        assert!("0xFDFF".parse::<StackBounds>().is_err());
+        assert!("0xFDFF:zz".parse::<StackBounds>().is_err());
+    }
+
+    #[test]
+    fn test_empty_stack_pointer_is_allowed() {
+        let bounds = StackBounds::new(0xFDFF, 0xFA00);
+
+        assert!(bounds.allows_pointer(0xFE00));
+        assert!(bounds.allows_pointer(0xFA00));
+        assert!(!bounds.allows_pointer(0xF9FF));
+        assert!(!bounds.contains(0xFE00));
+        assert_eq!(bounds.to_string(), "xFDFF:xFA00");
+    }
+}
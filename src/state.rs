@@ -0,0 +1,276 @@
+//! Human-readable machine state files, for bug reports
+//!
+//! Unlike a [`Snapshot`], whose memory is one base64 blob of all 64K words,
+//! a state file lists the registers as numbers and keeps only the parts of
+//! memory that aren't zero:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "registers": {"r": [5, 3, 8, 0, 0, 0, 0, 12294], "pc": 12294, "flags": "Pos"},
+//!   "state": {"Halted": "TrapHalt"},
+//!   "memory": [{"origin": 12288, "words_base64": "UCAQJVJgEmMUAfAl"}]
+//! }
+//! ```
+
+use base64::Engine;
+
+use crate::errors::VMError;
+use crate::memory::{Memory, MEMORY_MAX};
+use crate::registers::{RegisterSnapshot, Registers};
+use crate::snapshot::Snapshot;
+use crate::{VMState, VM};
+
+/// Version of the state file format, bumped on incompatible changes
+pub const STATE_VERSION: u32 = 1;
+
+/// Zero words in a row that end a run of memory, shorter gaps stay inside the run
+const ZERO_GAP: usize = 8;
+
+/// On-disk layout of a state file
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StateFile {
+    version: u32,
+    registers: RegisterSnapshot,
+    state: VMState,
+    memory: Vec<MemoryRun>,
+}
+
+/// Words stored from `origin` on, as their big-endian bytes in base64
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MemoryRun {
+    origin: u16,
+    words_base64: String,
+}
+
+#[derive(serde::Deserialize)]
+struct StateHeader {
+    version: u32,
+}
+
+impl VM {
+    /// Writes the registers, flags, halt state and non-zero memory as a JSON state file
+    ///
+    /// # Errors
+    /// Returns `VMError::InvalidSnapshot` if the state can't be serialized
+    pub fn export_state_json(&self) -> Result<String, VMError> {
+        let memory = runs(self.memory())
+            .into_iter()
+            .map(|(origin, words)| MemoryRun {
+                origin,
+                words_base64: base64::engine::general_purpose::STANDARD.encode(
+                    words
+                        .iter()
+                        .flat_map(|word| word.to_be_bytes())
+                        .collect::<Vec<u8>>(),
+                ),
+            })
+            .collect();
+
+        let file = StateFile {
+            version: STATE_VERSION,
+            registers: self.registers_snapshot(),
+            state: self.state().clone(),
+            memory,
+        };
+        serde_json::to_string_pretty(&file).map_err(|e| VMError::InvalidSnapshot(e.to_string()))
+    }
+
+    /// Creates a VM from a state file written by [`VM::export_state_json`]
+    ///
+    /// # Errors
+    /// See [`VM::restore_state_json`]
+    pub fn import_state_json(json: &str) -> Result<VM, VMError> {
+        let mut vm = VM::new();
+        vm.restore_state_json(json)?;
+        Ok(vm)
+    }
+
+    /// Replaces memory, registers and the halt state with those of a state file
+    ///
+    /// Memory outside the runs in the file is zeroed.
+    ///
+    /// # Errors
+    /// Returns `VMError::InvalidSnapshot` if the file isn't valid JSON, has another
+    /// version, or a memory run is not whole words, runs past the end of memory or
+    /// overlaps another run
+    pub fn restore_state_json(&mut self, json: &str) -> Result<(), VMError> {
+        // Check the version before the rest, whose layout may have changed
+        let header: StateHeader =
+            serde_json::from_str(json).map_err(|e| VMError::InvalidSnapshot(e.to_string()))?;
+        if header.version != STATE_VERSION {
+            return Err(VMError::InvalidSnapshot(format!(
+                "state file version {} is not supported, expected {}",
+                header.version, STATE_VERSION
+            )));
+        }
+        let file: StateFile =
+            serde_json::from_str(json).map_err(|e| VMError::InvalidSnapshot(e.to_string()))?;
+
+        let mut memory = Memory::new();
+        let mut written = vec![false; MEMORY_MAX];
+        for run in &file.memory {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(&run.words_base64)
+                .map_err(|e| {
+                    VMError::InvalidSnapshot(format!("run at x{:04X}: {}", run.origin, e))
+                })?;
+            if !bytes.len().is_multiple_of(2) {
+                return Err(VMError::InvalidSnapshot(format!(
+                    "run at x{:04X} has an odd number of bytes",
+                    run.origin
+                )));
+            }
+
+            let start = usize::from(run.origin);
+            let end = start.saturating_add(bytes.len() / 2);
+            let cells = written.get_mut(start..end).ok_or_else(|| {
+                VMError::InvalidSnapshot(format!(
+                    "run at x{:04X} runs past the end of memory",
+                    run.origin
+                ))
+            })?;
+            if cells.iter().any(|&cell| cell) {
+                return Err(VMError::InvalidSnapshot(format!(
+                    "run at x{:04X} overlaps another run",
+                    run.origin
+                )));
+            }
+            cells.fill(true);
+
+            let mut address = run.origin;
+            for pair in bytes.chunks_exact(2) {
+                if let [high, low] = *pair {
+                    memory.write_ram(address, u16::from_be_bytes([high, low]));
+                }
+                address = address.wrapping_add(1);
+            }
+        }
+
+        let mut registers = Registers::default();
+        for (number, &value) in file.registers.r.iter().enumerate() {
+            registers.set(number, value);
+        }
+        registers.pc = file.registers.pc;
+        registers.condition = file.registers.flags;
+
+        self.restore(&Snapshot {
+            memory: Box::new(memory),
+            registers,
+            state: file.state,
+            keyboard_data: None,
+            resume_from: None,
+            // A state file doesn't record how the machine got there
+            instructions: self.instructions_executed(),
+            metrics: self.metrics.clone(),
+        });
+        Ok(())
+    }
+}
+
+/// The parts of `memory` holding anything but zeros, as `(origin, words)`
+///
+/// Gaps of fewer than [`ZERO_GAP`] zero words are kept inside a run.
+fn runs(memory: &Memory) -> Vec<(u16, Vec<u16>)> {
+    let mut runs: Vec<(u16, Vec<u16>)> = Vec::new();
+    let mut zeros: usize = 0;
+
+    for address in 0..=u16::MAX {
+        let word = memory.read_ram(address);
+        if word == 0 {
+            zeros = zeros.saturating_add(1);
+            continue;
+        }
+
+        match runs.last_mut() {
+            Some((_, words)) if zeros < ZERO_GAP => {
+                words.extend(std::iter::repeat_n(0, zeros));
+                words.push(word);
+            }
+            _ => runs.push((address, vec![word])),
+        }
+        zeros = 0;
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HaltReason;
+
+    #[test]
+    fn test_runs_skip_long_zero_gaps() {
+        let mut memory = Memory::new();
+        memory.write_ram(0x3000, 1);
+        memory.write_ram(0x3002, 2);
+        memory.write_ram(0x4000, 3);
+        memory.write_ram(0xFFFF, 4);
+
+        assert_eq!(
+            runs(&memory),
+            vec![
+                (0x3000, vec![1, 0, 2]),
+                (0x4000, vec![3]),
+                (0xFFFF, vec![4])
+            ]
+        );
+        assert!(runs(&Memory::new()).is_empty());
+    }
+
+    #[test]
+    fn test_state_json_round_trip() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(".ORIG x3000\nADD R0, R0, #-3\nST R0, VALUE\nHALT\nVALUE .FILL x1234\n.END\n")?;
+        vm.write_memory(0xFDFF, 0x8000)?;
+        vm.set_halt_banner(false);
+        vm.run()?;
+
+        let json = vm.export_state_json()?;
+        let imported = VM::import_state_json(&json)?;
+
+        assert!(imported.memory() == vm.memory());
+        assert_eq!(imported.registers_snapshot(), vm.registers_snapshot());
+        assert_eq!(imported.state(), &VMState::Halted(HaltReason::TrapHalt));
+        assert_eq!(imported.export_state_json()?, json);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_json_rejects_bad_files() {
+        let state = |version: u32, memory: &str| {
+            format!(
+                "{{\"version\":{},\"registers\":{{\"r\":[0,0,0,0,0,0,0,0],\"pc\":12288,\"flags\":\"Zro\"}},\
+                 \"state\":\"Running\",\"memory\":[{}]}}",
+                version, memory
+            )
+        };
+        let rejected = |json: String, expected: &str| {
+            matches!(
+                VM::import_state_json(&json),
+                Err(VMError::InvalidSnapshot(message)) if message.contains(expected)
+            )
+        };
+
+        assert!(VM::import_state_json(&state(1, "")).is_ok());
+        assert!(rejected(state(2, ""), "version 2"));
+        assert!(rejected(
+            state(1, "{\"origin\":65535,\"words_base64\":\"AAEAAg==\"}"),
+            "past the end"
+        ));
+        assert!(rejected(
+            state(1, "{\"origin\":12288,\"words_base64\":\"AAE=\"},{\"origin\":12288,\"words_base64\":\"AAI=\"}"),
+            "overlaps"
+        ));
+        assert!(rejected(
+            state(1, "{\"origin\":12288,\"words_base64\":\"AA==\"}"),
+            "odd number"
+        ));
+        assert!(rejected(
+            state(1, "{\"origin\":12288,\"words_base64\":\"!\"}"),
+            "x3000"
+        ));
+    }
+}
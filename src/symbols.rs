@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+
+use crate::errors::VMError;
+
+/// Label to address mapping read from an lc3as `.sym` file
+///
+/// lc3as writes one symbol per line below a small header, every line being a
+/// `//` comment:
+///
+/// ```text
+/// // Symbol table
+/// // Scope level 0:
+/// //    Symbol Name       Page Address
+/// //    ----------------  ------------
+/// //    LOOP              3002
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SymbolTable {
+    by_address: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    /// Creates an empty symbol table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the contents of an lc3as `.sym` file
+    ///
+    /// Header and separator lines are skipped, as is anything that isn't a
+    /// label followed by a hex address.
+    pub fn parse(text: &str) -> Self {
+        let mut table = Self::new();
+
+        for line in text.lines() {
+            let line = line.trim_start().trim_start_matches('/');
+            let mut tokens = line.split_whitespace();
+
+            if let (Some(name), Some(address), None) = (tokens.next(), tokens.next(), tokens.next())
+            {
+                if let Ok(address) = u16::from_str_radix(address, 16) {
+                    table.insert(name, address);
+                }
+            }
+        }
+
+        table
+    }
+
+    /// Reads and parses a `.sym` file
+    ///
+    /// # Errors
+    /// Returns `VMError::OpenFileFailed` if the file can't be read
+    pub fn load(path: &str) -> Result<Self, VMError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|_| VMError::OpenFileFailed(path.to_string()))?;
+
+        Ok(Self::parse(&text))
+    }
+
+    /// Adds a label, replacing any other label at the same address
+    pub fn insert(&mut self, name: &str, address: u16) {
+        self.by_address.insert(address, name.to_string());
+    }
+
+    /// Adds every label of `other` to this table
+    pub fn extend(&mut self, other: SymbolTable) {
+        self.by_address.extend(other.by_address);
+    }
+
+    /// Number of labels in the table
+    pub fn len(&self) -> usize {
+        self.by_address.len()
+    }
+
+    /// Whether the table has no labels
+    pub fn is_empty(&self) -> bool {
+        self.by_address.is_empty()
+    }
+
+    /// Returns the label defined exactly at `address`
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.by_address.get(&address).map(String::as_str)
+    }
+
+    /// Finds the closest label at or below `address`
+    ///
+    /// # Returns
+    /// The label together with the label's address
+    pub fn lookup(&self, address: u16) -> Option<(&str, u16)> {
+        self.by_address
+            .range(..=address)
+            .next_back()
+            .map(|(&label_address, label)| (label.as_str(), label_address))
+    }
+}
+
+/// Formats an address as `LABEL+offset (xADDR)`, or `xADDR` without a label
+pub fn format_address(label: Option<(&str, u16)>, address: u16) -> String {
+    match label {
+        Some((name, label_address)) if label_address == address => {
+            format!("{} (x{:04X})", name, address)
+        }
+        Some((name, label_address)) => format!(
+            "{}+{} (x{:04X})",
+            name,
+            address.wrapping_sub(label_address),
+            address
+        ),
+        None => format!("x{:04X}", address),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+//\tSTART             3000
+//\tLOOP              3002
+//\tDONE              3008
+
+";
+
+    #[test]
+    fn test_parse_sym_file() {
+        let table = SymbolTable::parse(SAMPLE);
+
+        assert_eq!(table.lookup(0x3000), Some(("START", 0x3000)));
+        assert_eq!(table.lookup(0x3002), Some(("LOOP", 0x3002)));
+        assert_eq!(table.lookup(0x3008), Some(("DONE", 0x3008)));
+        assert_eq!(table.lookup(0x2FFF), None);
+        // Header lines don't produce symbols
+        assert_eq!(table.by_address.len(), 3);
+    }
+
+    #[test]
+    fn test_symbolize_addresses() {
+        let table = SymbolTable::parse(SAMPLE);
+
+        assert_eq!(format_address(table.lookup(0x3002), 0x3002), "LOOP (x3002)");
+        assert_eq!(
+            format_address(table.lookup(0x3005), 0x3005),
+            "LOOP+3 (x3005)"
+        );
+        assert_eq!(format_address(table.lookup(0x2FFF), 0x2FFF), "x2FFF");
+    }
+}
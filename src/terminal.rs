@@ -0,0 +1,107 @@
+//! Getting the terminal back to normal when the run ends abruptly, and Ctrl-C
+
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+#[cfg(unix)]
+use std::sync::{Arc, OnceLock};
+
+#[cfg(unix)]
+use lc3_vm::console::{Console, UnixConsole};
+
+/// Restores `console` before a panic message is printed
+///
+/// A panic that aborts never drops the VM, which restores the console otherwise.
+#[cfg(unix)]
+pub fn restore_on_panic<C: Console + Clone + Send + Sync + 'static>(console: C) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = console.clone().disable_raw_mode();
+        default_hook(info);
+    }));
+}
+
+/// Exit status after Ctrl-C, as shells report a process killed by SIGINT
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// A second Ctrl-C this soon after the first exits straight away
+#[cfg(unix)]
+const GRACE_PERIOD_MS: i64 = 2000;
+
+/// Raised by Ctrl-C for the VM, see [`lc3_vm::VM::set_interrupt_flag`]
+#[cfg(unix)]
+static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+/// When the last Ctrl-C was pressed in milliseconds on the monotonic clock, 0 before the first
+#[cfg(unix)]
+static LAST_INTERRUPT: AtomicI64 = AtomicI64::new(0);
+/// Terminal a hard exit restores
+#[cfg(unix)]
+static CONSOLE: OnceLock<UnixConsole> = OnceLock::new();
+
+/// Makes Ctrl-C raise the returned flag instead of killing the process
+///
+/// A second Ctrl-C within the grace period restores `console` and exits
+/// with [`EXIT_INTERRUPTED`], for a program stuck waiting for a key.
+///
+/// # Errors
+/// Fails if the signal handler can't be installed
+#[cfg(unix)]
+pub fn handle_interrupts(console: Option<UnixConsole>) -> io::Result<Arc<AtomicBool>> {
+    if let Some(console) = console {
+        let _ = CONSOLE.set(console);
+    }
+    let flag = Arc::clone(INTERRUPT.get_or_init(Arc::default));
+
+    // SAFETY: the handler only touches atomics and calls async-signal-safe functions
+    let installed = unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        // A handler is passed to sigaction as an address
+        #[allow(clippy::as_conversions)]
+        {
+            action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        }
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut())
+    };
+    if installed != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(flag)
+}
+
+/// Milliseconds on the monotonic clock, never 0
+#[cfg(unix)]
+fn now_ms() -> i64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_gettime only writes to `now`
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+
+    now.tv_sec
+        .saturating_mul(1000)
+        .saturating_add(now.tv_nsec / 1_000_000)
+        .max(1)
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    let now = now_ms();
+    let last = LAST_INTERRUPT.swap(now, Ordering::SeqCst);
+    if last != 0 && now.saturating_sub(last) < GRACE_PERIOD_MS {
+        // tcsetattr is async-signal-safe
+        if let Some(console) = CONSOLE.get() {
+            let _ = console.clone().disable_raw_mode();
+        }
+        // SAFETY: _exit is async-signal-safe, unlike running destructors
+        unsafe { libc::_exit(EXIT_INTERRUPTED) };
+    }
+
+    if let Some(flag) = INTERRUPT.get() {
+        flag.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,146 @@
+//! Ring buffer of the most recently executed instructions, and the register trace of a run
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::disasm;
+use crate::vm::{StepOutcome, VM};
+use crate::VMError;
+
+/// Number of instructions kept unless configured otherwise
+pub const DEFAULT_TRACE_CAPACITY: usize = 64;
+
+/// An executed instruction, disassembled only when displayed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEntry {
+    /// Address the instruction was fetched from
+    pub pc: u16,
+    /// The raw instruction word
+    pub instruction: u16,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "x{:04X}: {:04X}  {}",
+            self.pc,
+            self.instruction,
+            disasm::disassemble(self.instruction, self.pc)
+        )
+    }
+}
+
+/// Fixed-size buffer dropping the oldest entry once full
+#[derive(Debug, Clone)]
+pub struct TraceBuffer {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+}
+
+impl TraceBuffer {
+    /// Creates a buffer keeping the last `capacity` instructions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records an executed instruction
+    pub fn push(&mut self, pc: u16, instruction: u16) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry { pc, instruction });
+    }
+
+    /// Returns the recorded instructions, oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    /// Forgets every recorded instruction
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Steps `vm` until it halts, one line per instruction: its address and word, then R0-R7 and the flags after it
+///
+/// This is the format of the trace snapshots in `tests/golden/`, which a
+/// Python model outside the crate writes, a trap counts as one instruction.
+///
+/// # Errors
+/// The first error of [`VM::step`]
+pub fn step_trace(vm: &mut VM) -> Result<String, VMError> {
+    let mut trace = String::new();
+    while let StepOutcome::Executed {
+        pc,
+        instruction,
+        halted,
+        ..
+    } = vm.step()?
+    {
+        let registers = vm.registers_snapshot();
+        let values: Vec<String> = registers
+            .r
+            .iter()
+            .map(|value| format!("x{:04X}", value))
+            .collect();
+        trace.push_str(&format!(
+            "x{:04X} x{:04X} {} {:?}\n",
+            pc,
+            instruction,
+            values.join(" "),
+            registers.flags
+        ));
+        if halted.is_some() {
+            break;
+        }
+    }
+
+    Ok(trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_buffer_keeps_last_entries() {
+        let mut trace = TraceBuffer::new(2);
+        trace.push(0x3000, 0x5020);
+        trace.push(0x3001, 0x1025);
+        trace.push(0x3002, 0xF025);
+
+        let pcs: Vec<u16> = trace.entries().map(|entry| entry.pc).collect();
+        assert_eq!(pcs, vec![0x3001, 0x3002]);
+    }
+
+    #[test]
+    fn test_trace_entry_display() {
+        let entry = TraceEntry {
+            pc: 0x3001,
+            instruction: 0x1025,
+        };
+
+        assert_eq!(entry.to_string(), "x3001: 1025  ADD R0, R0, #5");
+    }
+
+    #[test]
+    fn test_step_trace() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_halt_banner(false);
+        vm.load_asm(".ORIG x3000\nADD R1, R1, #-2\nHALT\n.END")?;
+
+        assert_eq!(
+            step_trace(&mut vm)?,
+            "x3000 x127E x0000 xFFFE x0000 x0000 x0000 x0000 x0000 x0000 Neg\n\
+             x3001 xF025 x0000 xFFFE x0000 x0000 x0000 x0000 x0000 x3002 Neg\n"
+        );
+
+        Ok(())
+    }
+}
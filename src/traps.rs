@@ -0,0 +1,63 @@
+//! Trap vectors handled by the host instead of the built-in service routines
+
+use crate::errors::TrapError;
+use crate::{HaltReason, VMState, VM};
+
+/// Host function run for a trap vector, see [`VM::register_trap`]
+pub type TrapHandler = Box<dyn FnMut(&mut VmContext<'_>) -> Result<(), TrapError>>;
+
+/// Vectors of the built-in GETC, OUT, PUTS, IN, PUTSP and HALT routines
+pub const BUILTIN_TRAPS: std::ops::RangeInclusive<u8> = 0x20..=0x25;
+
+/// Extension trap printing R0 as a signed decimal, see [`VM::set_extension_traps`]
+pub const TRAP_PRINT_DECIMAL: u8 = 0x27;
+
+/// Extension trap reading a line into memory, see [`VM::set_extension_traps`]
+pub const TRAP_READ_LINE: u8 = 0x2A;
+
+/// The machine as seen by a [`TrapHandler`]
+///
+/// Memory is accessed directly, without the keyboard and MCR devices behind
+/// their registers.
+pub struct VmContext<'a> {
+    vm: &'a mut VM,
+}
+
+impl<'a> VmContext<'a> {
+    pub(crate) fn new(vm: &'a mut VM) -> Self {
+        Self { vm }
+    }
+
+    /// Returns the value of R`register`, 0 for a register that doesn't exist
+    pub fn register(&self, register: usize) -> u16 {
+        self.vm.registers.get(register).unwrap_or_default()
+    }
+
+    /// Sets R`register` and the condition flags, like an instruction writing it
+    pub fn set_register(&mut self, register: usize, value: u16) {
+        self.vm.registers.set(register, value);
+        self.vm.registers.update_flags(register);
+    }
+
+    /// Returns the word at `address`
+    pub fn read_memory(&self, address: u16) -> u16 {
+        self.vm.memory.read_ram(address)
+    }
+
+    /// Stores `value` at `address`
+    pub fn write_memory(&mut self, address: u16, value: u16) {
+        self.vm.forget_decoded(address);
+        // Every u16 is a valid address
+        self.vm.memory.write_ram(address, value);
+    }
+
+    /// Returns the address following the TRAP instruction, where the program continues
+    pub fn pc(&self) -> u16 {
+        self.vm.registers.pc
+    }
+
+    /// Halts the VM once the handler returns, with `HaltReason::TrapHalt`
+    pub fn halt(&mut self) {
+        self.vm.state = VMState::Halted(HaltReason::TrapHalt);
+    }
+}
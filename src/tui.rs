@@ -0,0 +1,392 @@
+//! What the full-screen debugger shows, independent of any terminal
+//!
+//! [`TuiDebugger`] keeps the debugger's state and turns the VM into the lines
+//! of each pane. A front end draws the lines and feeds keys back through
+//! [`TuiDebugger::handle_key`]; everything runs on [`VM::step`], the
+//! breakpoints and the disassembler.
+
+use crate::disasm;
+use crate::registers::{RegisterFlags, RegisterSnapshot};
+use crate::{RunOutcome, VMError, VMState, VM};
+
+/// Instructions run per press of `c`, so a program that never stops can't freeze the debugger
+pub const CONTINUE_CHUNK: u64 = 1_000_000;
+
+/// Words per row of the memory pane
+pub const MEMORY_ROW_WORDS: u16 = 8;
+
+/// A key the debugger reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+}
+
+/// One line of a pane
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub text: String,
+    /// The line of the PC, or a register that changed with the last command
+    pub highlight: bool,
+    /// The line the cursor is on
+    pub selected: bool,
+}
+
+impl Line {
+    fn plain(text: String) -> Self {
+        Self {
+            text,
+            highlight: false,
+            selected: false,
+        }
+    }
+}
+
+/// State of the full-screen debugger: the selected line, the memory window and the program's output
+pub struct TuiDebugger {
+    selected: u16,
+    memory_base: u16,
+    previous: RegisterSnapshot,
+    output: String,
+    status: String,
+}
+
+impl TuiDebugger {
+    /// Starts debugging `vm` at its PC, collecting the program's output for the output pane
+    pub fn new(vm: &mut VM) -> Self {
+        vm.capture_output();
+        let pc = vm.pc();
+
+        Self {
+            selected: pc,
+            memory_base: pc & !(MEMORY_ROW_WORDS - 1),
+            previous: vm.registers_snapshot(),
+            output: String::new(),
+            status: "s step  c continue  b breakpoint  arrows select  PgUp/PgDn memory  q quit"
+                .to_string(),
+        }
+    }
+
+    /// Address of the line the cursor is on
+    pub fn selected(&self) -> u16 {
+        self.selected
+    }
+
+    /// First address of the memory pane
+    pub fn memory_base(&self) -> u16 {
+        self.memory_base
+    }
+
+    /// Moves the memory pane to start at the row of `address`
+    pub fn set_memory_base(&mut self, address: u16) {
+        self.memory_base = address & !(MEMORY_ROW_WORDS - 1);
+    }
+
+    /// What happened with the last command
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    /// Reacts to `key`, returning false once the user quit
+    pub fn handle_key(&mut self, vm: &mut VM, key: Key, memory_rows: u16) -> bool {
+        let page = memory_rows.wrapping_mul(MEMORY_ROW_WORDS);
+        match key {
+            Key::Char('q') => return false,
+            Key::Char('s') => self.step(vm),
+            Key::Char('c') => self.continue_run(vm),
+            Key::Char('b') => self.toggle_breakpoint(vm),
+            Key::Up => self.selected = self.selected.wrapping_sub(1),
+            Key::Down => self.selected = self.selected.wrapping_add(1),
+            Key::PageUp => self.memory_base = self.memory_base.wrapping_sub(page),
+            Key::PageDown => self.memory_base = self.memory_base.wrapping_add(page),
+            Key::Char(_) => (),
+        }
+
+        true
+    }
+
+    fn step(&mut self, vm: &mut VM) {
+        self.previous = vm.registers_snapshot();
+        self.status = match vm.step() {
+            Ok(_) => match vm.state() {
+                VMState::Halted(reason) => format!("Halted: {:?}", reason),
+                _ => format!("Stepped to {}", vm.describe_address(vm.pc())),
+            },
+            Err(e) => format!("Error: {}", vm.describe_error(&e)),
+        };
+        self.follow_pc(vm);
+    }
+
+    fn continue_run(&mut self, vm: &mut VM) {
+        self.previous = vm.registers_snapshot();
+        self.status = match vm.run_with_limit(CONTINUE_CHUNK) {
+            Ok(RunOutcome::Halted(reason)) => format!("Halted: {:?}", reason),
+            Ok(RunOutcome::Paused(reason)) => {
+                format!("Paused at {}: {:?}", vm.describe_address(vm.pc()), reason)
+            }
+            Err(VMError::Halted(reason)) => format!("Already halted: {:?}", reason),
+            Err(e) => format!("Error: {}", vm.describe_error(&e)),
+        };
+        self.follow_pc(vm);
+    }
+
+    fn toggle_breakpoint(&mut self, vm: &mut VM) {
+        let existing = vm
+            .breakpoints()
+            .iter()
+            .find(|breakpoint| breakpoint.address == self.selected)
+            .map(|breakpoint| breakpoint.id);
+
+        match existing {
+            Some(id) => {
+                vm.remove_breakpoint(id);
+                self.status = format!("Removed the breakpoint at x{:04X}", self.selected);
+            }
+            None => {
+                vm.add_breakpoint(self.selected);
+                self.status = format!("Breakpoint at x{:04X}", self.selected);
+            }
+        }
+    }
+
+    /// Moves the cursor to the PC and collects what the program printed
+    fn follow_pc(&mut self, vm: &mut VM) {
+        self.selected = vm.pc();
+        self.output
+            .push_str(&String::from_utf8_lossy(&vm.take_output()));
+    }
+
+    /// Disassembles `height` lines around the selected line
+    ///
+    /// The PC is marked with `=>` and breakpoints with `*`.
+    pub fn disassembly(&self, vm: &VM, height: u16) -> Vec<Line> {
+        let start = self.selected.wrapping_sub(height / 3);
+
+        (0..height)
+            .map(|offset| {
+                let address = start.wrapping_add(offset);
+                let word = vm.memory().read_ram(address);
+                let breakpoint = vm
+                    .breakpoints()
+                    .iter()
+                    .any(|breakpoint| breakpoint.address == address && breakpoint.enabled);
+                let marker = match (address == vm.pc(), breakpoint) {
+                    (true, true) => "*=>",
+                    (true, false) => " =>",
+                    (false, true) => "*  ",
+                    (false, false) => "   ",
+                };
+                let label = vm.symbols().label_at(address).unwrap_or_default();
+
+                Line {
+                    text: format!(
+                        "{} x{:04X} {:<8} {}",
+                        marker,
+                        address,
+                        label,
+                        disasm::disassemble(word, address)
+                    ),
+                    highlight: address == vm.pc(),
+                    selected: address == self.selected,
+                }
+            })
+            .collect()
+    }
+
+    /// Lists R0-R7, the PC and the condition flags, highlighting what the last command changed
+    pub fn registers(&self, vm: &VM) -> Vec<Line> {
+        let now = vm.registers_snapshot();
+        let mut lines: Vec<Line> = now
+            .r
+            .iter()
+            .zip(self.previous.r.iter())
+            .enumerate()
+            .map(|(number, (value, before))| Line {
+                text: format!(
+                    "R{}  x{:04X} {:>6}",
+                    number,
+                    value,
+                    i16::from_be_bytes(value.to_be_bytes())
+                ),
+                highlight: value != before,
+                selected: false,
+            })
+            .collect();
+
+        lines.push(Line {
+            text: format!("PC  x{:04X}", now.pc),
+            highlight: now.pc != self.previous.pc,
+            selected: false,
+        });
+        let flag = |flags: RegisterFlags| match flags {
+            RegisterFlags::Neg => 'N',
+            RegisterFlags::Zro => 'Z',
+            RegisterFlags::Pos => 'P',
+        };
+        lines.push(Line {
+            text: format!("CC  {}", flag(now.flags)),
+            highlight: now.flags != self.previous.flags,
+            selected: false,
+        });
+
+        lines
+    }
+
+    /// Dumps `rows` rows of memory from the memory base, e.g. `x3000  1220 1240 ...`
+    pub fn memory(&self, vm: &VM, rows: u16) -> Vec<Line> {
+        (0..rows)
+            .map(|row| {
+                let start = self
+                    .memory_base
+                    .wrapping_add(row.wrapping_mul(MEMORY_ROW_WORDS));
+                let words: Vec<String> = (0..MEMORY_ROW_WORDS)
+                    .map(|offset| {
+                        let word = vm.memory().read_ram(start.wrapping_add(offset));
+                        format!("{:04X}", word)
+                    })
+                    .collect();
+
+                Line::plain(format!("x{:04X}  {}", start, words.join(" ")))
+            })
+            .collect()
+    }
+
+    /// Returns the last `rows` lines the program printed
+    pub fn output(&self, rows: u16) -> Vec<Line> {
+        let lines: Vec<&str> = self.output.split('\n').collect();
+        let skip = lines.len().saturating_sub(usize::from(rows));
+
+        lines
+            .into_iter()
+            .skip(skip)
+            .map(|line| Line::plain(line.replace('\r', "")))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HaltReason;
+
+    const COUNT_DOWN: &str = "        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #2
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        LEA R0, DONE
+        PUTS
+        HALT
+DONE    .STRINGZ \"done\\n\"
+        .END
+";
+
+    fn debugger() -> Result<(VM, TuiDebugger), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_DOWN)?;
+        let mut symbols = crate::symbols::SymbolTable::new();
+        symbols.insert("LOOP", 0x3002);
+        vm.add_symbols(symbols);
+        let debugger = TuiDebugger::new(&mut vm);
+
+        Ok((vm, debugger))
+    }
+
+    #[test]
+    fn test_disassembly_pane() -> Result<(), VMError> {
+        let (mut vm, mut debugger) = debugger()?;
+        debugger.handle_key(&mut vm, Key::Down, 4);
+        debugger.handle_key(&mut vm, Key::Down, 4);
+        debugger.handle_key(&mut vm, Key::Char('b'), 4);
+
+        let lines = debugger.disassembly(&vm, 6);
+        let text: Vec<&str> = lines
+            .iter()
+            .take(4)
+            .map(|line| line.text.as_str())
+            .collect();
+        assert_eq!(
+            text,
+            [
+                " => x3000          AND R1, R1, #0",
+                "    x3001          ADD R1, R1, #2",
+                "*   x3002 LOOP     ADD R1, R1, #-1",
+                "    x3003          BRp x3002",
+            ]
+        );
+        assert!(lines.first().is_some_and(|line| line.highlight));
+        assert!(lines.get(2).is_some_and(|line| line.selected));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_highlights_changed_registers() -> Result<(), VMError> {
+        let (mut vm, mut debugger) = debugger()?;
+        debugger.handle_key(&mut vm, Key::Char('s'), 4);
+        debugger.handle_key(&mut vm, Key::Char('s'), 4);
+
+        let registers = debugger.registers(&vm);
+        let changed: Vec<&str> = registers
+            .iter()
+            .filter(|line| line.highlight)
+            .map(|line| line.text.as_str())
+            .collect();
+        assert_eq!(changed, ["R1  x0002      2", "PC  x3002", "CC  P"]);
+        assert_eq!(debugger.selected(), 0x3002);
+        assert_eq!(debugger.status(), "Stepped to LOOP (x3002)");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_continue_stops_at_breakpoint_then_collects_output() -> Result<(), VMError> {
+        let (mut vm, mut debugger) = debugger()?;
+        vm.add_breakpoint(0x3003);
+
+        debugger.handle_key(&mut vm, Key::Char('c'), 4);
+        assert_eq!(vm.pc(), 0x3003);
+        assert!(debugger.status().starts_with("Paused at LOOP+1 (x3003)"));
+
+        debugger.handle_key(&mut vm, Key::Char('b'), 4);
+        assert!(vm.breakpoints().is_empty());
+        debugger.handle_key(&mut vm, Key::Char('c'), 4);
+        assert_eq!(vm.state(), &VMState::Halted(HaltReason::TrapHalt));
+
+        let output: Vec<Line> = debugger.output(2);
+        assert_eq!(
+            output,
+            [Line::plain("done".to_string()), Line::plain(String::new())]
+        );
+        assert!(debugger.handle_key(&mut vm, Key::Char('s'), 4));
+        assert!(debugger
+            .status()
+            .starts_with("Error: the machine has halted"));
+        assert!(!debugger.handle_key(&mut vm, Key::Char('q'), 4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_pane_pages() -> Result<(), VMError> {
+        let (mut vm, mut debugger) = debugger()?;
+
+        let memory = debugger.memory(&vm, 1);
+        assert_eq!(
+            memory.first().map(|line| line.text.as_str()),
+            Some("x3000  5260 1262 127F 03FE E002 F022 F025 0064")
+        );
+
+        debugger.handle_key(&mut vm, Key::PageDown, 2);
+        assert_eq!(debugger.memory_base(), 0x3010);
+        debugger.handle_key(&mut vm, Key::PageUp, 4);
+        assert_eq!(debugger.memory_base(), 0x2FF0);
+        debugger.set_memory_base(0x4005);
+        assert_eq!(debugger.memory_base(), 0x4000);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,4917 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, log_enabled, trace, warn, Level};
+
+use crate::asm;
+use crate::breakpoints::{Breakpoint, BreakpointId, Breakpoints, Condition, Value};
+use crate::callstack::{CallStack, Frame};
+use crate::console::{Console, ConsoleInput};
+use crate::coverage::{Coverage, CoverageReport};
+use crate::disasm;
+use crate::errors::{HaltReason, TrapError, VMError};
+use crate::instruction::Instruction;
+use crate::journal::{Journal, Undo};
+use crate::keyboard::{EofPolicy, InputSource, Keyboard, ScriptedInput, MR_KBDR, MR_KBSR};
+use crate::loader::{self, hex, ihex, Endianness, Format, LoadedProgram};
+use crate::loops::LoopDetector;
+use crate::machine::{self, HostIo, Machine};
+use crate::memory::{Memory, MEMORY_MAX, MR_DDR, MR_DSR};
+use crate::metrics::Metrics;
+use crate::opdcodes::*;
+use crate::output::{ConsoleOut, Output, Sanitize};
+use crate::predecode::DecodeCache;
+use crate::profile::{HotAddress, ProfileReport, Profiler};
+use crate::registers::{RegisterAliases, RegisterFlags, RegisterSnapshot, Registers, PC_START};
+use crate::session::{Replay, Session, SessionByte};
+use crate::snapshot::Snapshot;
+use crate::stack::StackBounds;
+use crate::symbols::{self, SymbolTable};
+use crate::trace::{TraceBuffer, TraceEntry, DEFAULT_TRACE_CAPACITY};
+use crate::traps::{TrapHandler, BUILTIN_TRAPS};
+
+pub use crate::machine::DEFAULT_IN_PROMPT;
+pub use crate::memory::MR_MCR;
+
+/// An LC-3 machine: memory, registers, the keyboard device and the loaded programs
+pub struct VM {
+    pub(crate) memory: Memory,
+    pub(crate) registers: Registers,
+    pub(crate) keyboard: Keyboard,
+    /// Set while the keyboard reads a console in raw mode
+    console: bool,
+    /// Every character the program prints goes through here
+    console_out: ConsoleOut,
+    /// Prints `HALT` to stderr when the program halts, by default on a terminal only
+    halt_banner: Option<bool>,
+    /// Keeps the output for the program: no banner, the IN prompt on stderr
+    pub(crate) quiet: bool,
+    loaded: Vec<LoadedProgram>,
+    strict_load: bool,
+    allow_wrap: bool,
+    symbols: SymbolTable,
+    /// Where execution starts and [`VM::reset`] returns to, the first program's origin if `None`
+    reset_vector: Option<u16>,
+    breakpoints: Breakpoints,
+    /// Breakpoint the VM last stopped at, skipped once so resuming executes its instruction
+    resume_from: Option<u16>,
+    trace: Option<TraceBuffer>,
+    profile: Option<Profiler>,
+    coverage: Option<Coverage>,
+    pub(crate) decode_cache: Option<DecodeCache>,
+    call_stack: Option<CallStack>,
+    stack_bounds: Option<StackBounds>,
+    /// Role names of the registers, printed in dumps and accepted by the debugger
+    register_aliases: RegisterAliases,
+    journal: Option<Journal>,
+    trap_trace: Option<Box<dyn Write>>,
+    instruction_trace: Option<Box<dyn Write>>,
+    /// Host handlers consulted before the built-in trap routines
+    pub(crate) traps: BTreeMap<u8, TrapHandler>,
+    /// Whether the extension traps such as [`TRAP_PRINT_DECIMAL`](crate::traps::TRAP_PRINT_DECIMAL) are available
+    pub(crate) extension_traps: bool,
+    /// Printed by the IN trap before it reads a character
+    pub(crate) in_prompt: Option<String>,
+    /// Whether GETC prints the character it read
+    pub(crate) echo_getc: bool,
+    /// Whether reading a character from KBDR prints it
+    echo_kbdr: bool,
+    loop_detector: Option<LoopDetector>,
+    /// Set from another thread or a signal handler to stop the run between instructions
+    interrupt: Option<Arc<AtomicBool>>,
+    limit: Option<u64>,
+    time_limit: Option<Duration>,
+    /// Instructions executed since creation or the last reset
+    instructions: u64,
+    /// Everything [`VM::metrics`] reports but the instruction count
+    pub(crate) metrics: Metrics,
+    recording: Option<Session>,
+    replay: Option<Replay>,
+    /// Only devices with scripted or fixed behaviour may be read
+    deterministic: bool,
+    eof_policy: EofPolicy,
+    /// What GETC and IN read when no key arrives within the input timeout, `None` applying the EOF policy
+    timeout_sentinel: Option<u8>,
+    /// Longest string PUTS and PUTSP print before giving up on finding its terminator
+    pub(crate) max_string_length: usize,
+    /// Set when the current instruction touched a device register or wrote memory
+    side_effect: bool,
+    /// Set when the current instruction touched a device register
+    device_io: bool,
+    pub(crate) state: VMState,
+}
+
+/// Default for [`VM::set_max_string_length`]
+pub const DEFAULT_MAX_STRING_LENGTH: usize = 65535;
+
+// Opcode bits `VM::step` tests on every instruction, without building an `Opcode`
+const OP_JSR: u16 = Opcode::Jsr.bits();
+const OP_LDR: u16 = Opcode::Ldr.bits();
+const OP_STR: u16 = Opcode::Str.bits();
+const OP_JMP: u16 = Opcode::Jmp.bits();
+const OP_TRAP: u16 = Opcode::Trap.bits();
+
+/// Whether the VM keeps executing instructions
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VMState {
+    Running,
+    /// Stopped before the instruction at the PC, `step` or `run` continue from there
+    Paused(PauseReason),
+    /// Stopped for good, only [`VM::reset`] or [`VM::restore`] make it run again
+    Halted(HaltReason),
+}
+
+/// Why the VM paused
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PauseReason {
+    /// The PC reached a breakpoint
+    Breakpoint(u16),
+    /// The instruction budget of [`VM::run_with_limit`] ran out
+    LimitReached { executed: u64 },
+    /// The time budget of [`VM::set_time_limit`] ran out
+    TimeLimitReached { executed: u64 },
+    /// The PC reached the address given to [`VM::run_until`]
+    AddressReached(u16),
+    /// [`VM::pause`] was called
+    Requested,
+}
+
+/// What happened during a call to [`VM::step`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepOutcome {
+    /// One instruction was executed
+    Executed {
+        /// Address the instruction was fetched from
+        pc: u16,
+        /// The raw instruction word
+        instruction: u16,
+        /// The decoded opcode
+        opcode: Opcode,
+        /// Set if the instruction halted the VM
+        halted: Option<HaltReason>,
+    },
+    /// The instruction at this address has a breakpoint and was not executed,
+    /// the VM is paused
+    Breakpoint(u16),
+}
+
+/// Why [`VM::run`] and its variants returned
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunOutcome {
+    /// The program halted
+    Halted(HaltReason),
+    /// The VM paused and can be resumed, the instruction at the PC is next
+    Paused(PauseReason),
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VM {
+    /// Creates a new VM instance with initialized memory and registers
+    pub fn new() -> Self {
+        Self::new_with_memory(Memory::zeroed_buffer())
+    }
+
+    /// Creates a VM whose memory is `buffer`, such as one given back by [`VM::into_memory`]
+    ///
+    /// Lets a test suite or a fuzzer reuse buffers instead of allocating one per VM.
+    /// The words already in `buffer` are kept, [`VM::reset`] clears them.
+    pub fn new_with_memory(buffer: Box<[u16; MEMORY_MAX]>) -> Self {
+        Self {
+            memory: Memory::from_buffer(buffer),
+            registers: Registers::default(),
+            keyboard: Keyboard::new(Box::new(std::io::stdin())),
+            console: false,
+            console_out: ConsoleOut::stdout(),
+            halt_banner: None,
+            quiet: false,
+            loaded: Vec::new(),
+            strict_load: false,
+            allow_wrap: false,
+            symbols: SymbolTable::new(),
+            reset_vector: None,
+            breakpoints: Breakpoints::default(),
+            resume_from: None,
+            trace: Some(TraceBuffer::new(DEFAULT_TRACE_CAPACITY)),
+            profile: None,
+            coverage: None,
+            decode_cache: None,
+            call_stack: None,
+            stack_bounds: None,
+            register_aliases: RegisterAliases::default(),
+            journal: None,
+            trap_trace: None,
+            instruction_trace: None,
+            traps: BTreeMap::new(),
+            extension_traps: false,
+            in_prompt: Some(DEFAULT_IN_PROMPT.to_string()),
+            echo_getc: false,
+            echo_kbdr: false,
+            loop_detector: None,
+            interrupt: None,
+            limit: None,
+            time_limit: None,
+            instructions: 0,
+            metrics: Metrics::default(),
+            recording: None,
+            replay: None,
+            deterministic: false,
+            eof_policy: EofPolicy::Error,
+            timeout_sentinel: None,
+            max_string_length: DEFAULT_MAX_STRING_LENGTH,
+            side_effect: false,
+            device_io: false,
+            state: VMState::Running,
+        }
+    }
+
+    /// Reads a 16-bit value from the specified memory address
+    ///
+    /// Addresses below the device registers are plain RAM and always read,
+    /// the rest go through [`VM::read_mmio`].
+    ///
+    /// # Errors
+    /// Returns the error of the device register read, see [`VM::read_mmio`]
+    #[inline]
+    pub fn read_memory(&mut self, address: u16) -> Result<u16, VMError> {
+        self.metrics.memory_reads = self.metrics.memory_reads.saturating_add(1);
+        let value = if address < MR_KBSR {
+            self.memory.read_ram(address)
+        } else {
+            self.read_mmio(address)?
+        };
+        trace!(target: "lc3_vm::memory", "read x{:04X} = x{:04X}", address, value);
+
+        Ok(value)
+    }
+
+    /// Reads the pointer cell of LDI or STI, always from plain RAM
+    ///
+    /// See [`Machine::read_pointer`]: the address stored in the cell is what
+    /// goes through the devices, never the cell itself.
+    #[inline]
+    pub(crate) fn read_pointer(&mut self, address: u16) -> u16 {
+        self.metrics.memory_reads = self.metrics.memory_reads.saturating_add(1);
+        let value = self.memory.read_ram(address);
+        trace!(target: "lc3_vm::memory", "read pointer x{:04X} = x{:04X}", address, value);
+
+        value
+    }
+
+    /// Reads a memory-mapped device register, at xFE00 and above
+    ///
+    /// Memory-mapped keyboard registers are delegated to the keyboard device:
+    /// - KBSR (0xFE00): Polls the keyboard and returns its status (MSB set if key available)
+    /// - KBDR (0xFE02): Returns ASCII code of last key pressed
+    ///
+    /// The display is always ready, DSR (0xFE04) reads with bit 15 set.
+    ///
+    /// MCR (0xFFFE) always reads with bit 15 set since the clock runs while instructions execute.
+    ///
+    /// # Errors
+    /// * `VMError::NondeterministicDevice` - If the VM is deterministic and the address is
+    ///   neither a keyboard register nor MCR
+    /// * Whatever polling the keyboard or echoing KBDR fails with
+    fn read_mmio(&mut self, address: u16) -> Result<u16, VMError> {
+        self.side_effect = true;
+        self.device_io = true;
+        self.metrics.device_accesses = self.metrics.device_accesses.saturating_add(1);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(address, "device read");
+
+        match address {
+            MR_KBSR => {
+                self.poll_keyboard()?;
+                Ok(self.keyboard.status())
+            }
+            MR_KBDR => {
+                let key = self.keyboard.pending();
+                if let (Some(c), true) = (key, self.echo_kbdr) {
+                    self.put_char(c)?;
+                }
+                Ok(self.keyboard.take_data())
+            }
+            MR_DSR => Ok(0x8000),
+            MR_MCR => Ok(self.memory.read_ram(address) | 0x8000),
+            _ if self.deterministic => Err(VMError::NondeterministicDevice(address)),
+            _ => Ok(self.memory.read_ram(address)),
+        }
+    }
+
+    /// Writes a 16-bit value to the specified memory address
+    ///
+    /// Writing DDR (0xFE06) prints its low byte, writing MCR (0xFFFE) with bit 15
+    /// clear halts the VM.
+    ///
+    /// # Errors
+    /// Returns the error of printing a character written to DDR
+    pub fn write_memory(&mut self, address: u16, value: u16) -> Result<(), VMError> {
+        self.side_effect = true;
+        self.metrics.memory_writes = self.metrics.memory_writes.saturating_add(1);
+        if address >= MR_KBSR {
+            self.device_io = true;
+            self.metrics.device_accesses = self.metrics.device_accesses.saturating_add(1);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(address, value, "device write");
+        }
+        if address == MR_MCR && value & 0x8000 == 0 {
+            self.state = VMState::Halted(HaltReason::McrCleared);
+        }
+        if address == MR_DDR {
+            let [_, c] = value.to_be_bytes();
+            self.put_char(c)?;
+        }
+        self.forget_decoded(address);
+        trace!(target: "lc3_vm::memory", "write x{:04X} = x{:04X}", address, value);
+
+        self.memory.write_ram(address, value);
+        Ok(())
+    }
+
+    /// Lets the keyboard receive the next character, from the replayed session if there is one
+    fn poll_keyboard(&mut self) -> Result<(), VMError> {
+        // A replayed key arrives at an exact instruction count, anything else can wait
+        if self.replay.is_none() && self.keyboard.throttled(self.instructions) {
+            return Ok(());
+        }
+        self.flush_output()?;
+
+        let received = match &mut self.replay {
+            Some(replay) if self.keyboard.pending().is_none() => {
+                let received = replay.poll(self.instructions);
+                self.keyboard.set_pending(received);
+                received
+            }
+            Some(_) => None,
+            None => match self.keyboard.poll_at(self.instructions) {
+                Err(VMError::InputExhausted) => match self.eof_policy {
+                    EofPolicy::Error => return Err(VMError::InvalidCharacter),
+                    EofPolicy::Zero => {
+                        self.keyboard.set_pending(Some(0));
+                        Some(0)
+                    }
+                    EofPolicy::Halt => return Err(VMError::InputExhausted),
+                    EofPolicy::Hang => Self::hang(),
+                },
+                other => other?,
+            },
+        };
+
+        if let Some(byte) = received {
+            self.record_input(byte);
+        }
+
+        Ok(())
+    }
+
+    /// Waits for a keyboard character, used by the GETC and IN traps
+    ///
+    /// A character already received through KBSR is returned first. Once the
+    /// input ended, the [`EofPolicy`] decides what happens.
+    ///
+    /// # Errors
+    /// Returns `TrapError::IOError` if the input source fails or the replayed session ran out
+    pub(crate) fn read_key(&mut self) -> Result<u8, VMError> {
+        match self.read_key_until_eof()? {
+            Some(byte) => Ok(byte),
+            None => match self.eof_policy {
+                EofPolicy::Error => Err(VMError::TrapError(TrapError::IOError(
+                    "the keyboard input ended".to_string(),
+                ))),
+                EofPolicy::Zero => Ok(0),
+                EofPolicy::Halt => Err(VMError::InputExhausted),
+                EofPolicy::Hang => Self::hang(),
+            },
+        }
+    }
+
+    /// Waits for a keyboard character like [`VM::read_key`], but returns `None`
+    /// once the input ended instead of applying the [`EofPolicy`]
+    pub(crate) fn read_key_until_eof(&mut self) -> Result<Option<u8>, VMError> {
+        // Show the prompt before waiting
+        self.flush_output()?;
+
+        if let Some(byte) = self.keyboard.pending() {
+            self.keyboard.set_pending(None);
+            return Ok(Some(byte));
+        }
+
+        let byte = match &mut self.replay {
+            Some(replay) => replay.next().ok_or_else(|| {
+                VMError::TrapError(TrapError::IOError(
+                    "the replayed session has no more input".to_string(),
+                ))
+            })?,
+            None => match self.keyboard.read_byte() {
+                Ok(byte) => byte,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => match self.timeout_sentinel {
+                    Some(sentinel) => sentinel,
+                    None => return Ok(None),
+                },
+                Err(e) => return Err(VMError::TrapError(TrapError::IOError(e.to_string()))),
+            },
+        };
+        self.record_input(byte);
+
+        Ok(Some(byte))
+    }
+
+    /// Blocks the thread for good, for [`EofPolicy::Hang`]
+    fn hang() -> ! {
+        loop {
+            std::thread::park();
+        }
+    }
+
+    fn record_input(&mut self, byte: u8) {
+        if let Some(recording) = &mut self.recording {
+            recording.input.push(SessionByte {
+                at: self.instructions,
+                byte,
+            });
+        }
+    }
+
+    /// Prints `text` to the console
+    ///
+    /// Streams are buffered and flushed at each newline, before the program reads
+    /// the keyboard, at HALT and whenever a run stops.
+    ///
+    /// # Errors
+    /// Returns `TrapError::IOError` if the sink fails
+    pub(crate) fn put_str(&mut self, text: &str) -> Result<(), VMError> {
+        self.console_out
+            .put_str(text)
+            .map_err(|e| VMError::TrapError(TrapError::IOError(e.to_string())))?;
+        self.record_output(text.bytes());
+        Ok(())
+    }
+
+    /// Adds what the program printed to the session being recorded
+    fn record_output(&mut self, bytes: impl Iterator<Item = u8>) {
+        if let Some(recording) = &mut self.recording {
+            let at = self.instructions;
+            recording
+                .output
+                .extend(bytes.map(|byte| SessionByte { at, byte }));
+        }
+    }
+
+    /// Prints the character with code `c` to the console
+    ///
+    /// # Errors
+    /// Returns `TrapError::IOError` if the sink fails
+    pub(crate) fn put_char(&mut self, c: u8) -> Result<(), VMError> {
+        self.console_out
+            .put_char(c)
+            .map_err(|e| VMError::TrapError(TrapError::IOError(e.to_string())))?;
+        self.record_output(char::from(c).encode_utf8(&mut [0; 4]).bytes());
+        Ok(())
+    }
+
+    /// Flushes the console
+    ///
+    /// Runs flush when they stop and traps flush before waiting for a key,
+    /// a host stepping the VM itself flushes when it wants the output shown.
+    ///
+    /// # Errors
+    /// Returns `TrapError::IOError` if the sink fails
+    pub fn flush_output(&mut self) -> Result<(), VMError> {
+        self.console_out
+            .flush()
+            .map_err(|e| VMError::TrapError(TrapError::IOError(e.to_string())))
+    }
+
+    /// Reads the value of the specified register
+    ///
+    /// # Errors
+    /// Returns `VMError::InvalidRegister` if register number is invalid
+    pub fn read_register(&self, r: usize) -> Result<u16, VMError> {
+        self.registers.get(r)
+    }
+
+    /// Writes a 16-bit value to the specified register
+    pub fn write_register(&mut self, r: usize, value: u16) {
+        self.registers.set(r, value);
+    }
+
+    /// Fills R0-R7 with pseudo-random values derived from `seed`
+    ///
+    /// Registers hold garbage when real hardware powers on, so this shows
+    /// programs that rely on them starting at zero. The same seed always gives
+    /// the same values.
+    pub fn scramble_registers(&mut self, seed: u64) {
+        // splitmix64
+        let mut state = seed;
+        for r in 0..8 {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^= z >> 31;
+            let [.., high, low] = z.to_be_bytes();
+            self.registers.set(r, u16::from_be_bytes([high, low]));
+        }
+    }
+
+    /// Updates the condition flags based on the value in the specified register
+    pub fn update_flags(&mut self, r: usize) {
+        self.registers.update_flags(r);
+    }
+
+    /// Loads an LC-3 program file into memory
+    ///
+    /// The file format is detected from its contents, see [`Format::detect`].
+    ///
+    /// # Arguments
+    /// * `file` - Path to the file to load, `-` reads the image from stdin
+    /// * `endianness` - Byte order of the words in object files
+    ///
+    /// # Returns
+    /// One entry per contiguous block written to memory
+    ///
+    /// # Errors
+    /// * `VMError::OpenFileFailed` - If file cannot be opened
+    /// * `VMError::LoadFailed` - If file cannot be read
+    /// * See [`VM::load_image`] for errors in the image itself
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self, endianness), fields(origin, len))
+    )]
+    pub fn load_program(
+        &mut self,
+        file: &str,
+        endianness: Endianness,
+    ) -> Result<Vec<LoadedProgram>, VMError> {
+        let buffer = loader::read_source(file)?;
+        let format = Format::detect(file, &buffer);
+        let programs = self.load_image(&buffer, format, endianness)?;
+        log_loaded(file, format, &programs);
+        #[cfg(feature = "tracing")]
+        if let Some(first) = programs.first() {
+            let span = tracing::Span::current();
+            span.record("origin", first.origin);
+            let len: usize = programs
+                .iter()
+                .map(|program| usize::from(program.len))
+                .sum();
+            span.record("len", len);
+        }
+
+        Ok(self.set_source(file, programs))
+    }
+
+    /// Loads an LC-3 program file in the given format, skipping format detection
+    ///
+    /// # Errors
+    /// Same as [`VM::load_program`]
+    pub fn load_program_as(
+        &mut self,
+        file: &str,
+        format: Format,
+        endianness: Endianness,
+    ) -> Result<Vec<LoadedProgram>, VMError> {
+        let buffer = loader::read_source(file)?;
+        let programs = self.load_image(&buffer, format, endianness)?;
+        log_loaded(file, format, &programs);
+
+        Ok(self.set_source(file, programs))
+    }
+
+    /// Records the file the most recently loaded `programs` came from
+    fn set_source(&mut self, file: &str, mut programs: Vec<LoadedProgram>) -> Vec<LoadedProgram> {
+        let source = (file != "-").then(|| PathBuf::from(file));
+        let first = self.loaded.len().saturating_sub(programs.len());
+
+        for program in programs
+            .iter_mut()
+            .chain(self.loaded.iter_mut().skip(first))
+        {
+            program.source.clone_from(&source);
+        }
+
+        programs
+    }
+
+    /// Loads a program image in the given format from an in-memory buffer
+    ///
+    /// # Errors
+    /// * `VMError::InvalidIntelHex` - If an Intel HEX image is not valid text
+    /// * `VMError::InvalidHexListing` - If a hex listing is not valid text
+    /// * `VMError::AssemblyError` - If assembly source is not valid text
+    /// * See [`VM::load_bytes`], [`VM::load_ihex`] and [`VM::load_hex_text`] for
+    ///   format specific errors
+    pub fn load_image(
+        &mut self,
+        buffer: &[u8],
+        format: Format,
+        endianness: Endianness,
+    ) -> Result<Vec<LoadedProgram>, VMError> {
+        match format {
+            Format::Obj => Ok(vec![self.load_bytes(buffer, endianness)?]),
+            Format::Ihex => {
+                let text = std::str::from_utf8(buffer).map_err(|_| VMError::InvalidIntelHex {
+                    line: 0,
+                    reason: "file is not valid text",
+                })?;
+                self.load_ihex(text)
+            }
+            Format::Hex => {
+                let text = std::str::from_utf8(buffer).map_err(|_| VMError::InvalidHexListing {
+                    line: 0,
+                    token: String::new(),
+                })?;
+                Ok(vec![self.load_hex_text(text)?])
+            }
+            Format::Asm => {
+                let source = std::str::from_utf8(buffer).map_err(|_| VMError::AssemblyError {
+                    line: 0,
+                    message: "source is not valid UTF-8".to_string(),
+                })?;
+                Ok(vec![self.load_asm(source)?])
+            }
+        }
+    }
+
+    /// Assembles LC-3 assembly source and loads the result into memory
+    ///
+    /// The labels of the program are added to the VM's symbol table.
+    ///
+    /// # Errors
+    /// * `VMError::AssemblyError` - With the line number of the first assembly error
+    /// * See [`VM::load_bytes`] for errors when writing the image
+    pub fn load_asm(&mut self, source: &str) -> Result<LoadedProgram, VMError> {
+        let assembled = asm::assemble(source)?;
+
+        let program = self.load_words(assembled.origin, &assembled.words)?;
+        self.add_symbols(assembled.symbols);
+
+        Ok(program)
+    }
+
+    /// Loads a plain-text hex listing into memory
+    ///
+    /// The first word of the listing is the origin, blank lines and `;` comments are ignored.
+    ///
+    /// # Errors
+    /// * `VMError::InvalidHexListing` - With the line number of the first unparsable token
+    /// * See [`VM::load_bytes`] for errors when writing the image
+    pub fn load_hex_text(&mut self, text: &str) -> Result<LoadedProgram, VMError> {
+        let (origin, words) = hex::parse(text)?;
+
+        self.load_words(origin, &words)
+    }
+
+    /// Loads an Intel HEX image into memory
+    ///
+    /// # Returns
+    /// One entry per contiguous block of words in the file
+    ///
+    /// # Errors
+    /// * `VMError::InvalidIntelHex` - If a record is malformed or fails its checksum
+    /// * See [`VM::load_bytes`] for errors when writing the image
+    pub fn load_ihex(&mut self, text: &str) -> Result<Vec<LoadedProgram>, VMError> {
+        ihex::parse(text)?
+            .iter()
+            .map(|(origin, words)| self.load_words(*origin, words))
+            .collect()
+    }
+
+    /// Loads an LC-3 program image from an in-memory buffer
+    ///
+    /// # Process
+    /// 1. Extracts the origin address from the first two bytes
+    /// 2. Loads each subsequent 16-bit instruction into memory starting at origin
+    ///
+    /// Words are decoded with the given `endianness`, lc3as writes big-endian images.
+    /// An origin that only makes sense byte-swapped is reported as a warning.
+    ///
+    /// Can be called repeatedly to load several images, memory is not reset in between.
+    ///
+    /// # Errors
+    /// * `VMError::MalformedObject` - If the buffer is too short or has an odd length
+    /// * `VMError::ProgramTooLarge` - If the image would wrap past the end of memory
+    /// * `VMError::OverlappingLoad` - If strict loading is on and the image overlaps a previous one
+    pub fn load_bytes(
+        &mut self,
+        buffer: &[u8],
+        endianness: Endianness,
+    ) -> Result<LoadedProgram, VMError> {
+        let origin = match (buffer.first(), buffer.get(1)) {
+            (Some(&first_byte), Some(&second_byte)) => endianness.word([first_byte, second_byte]),
+            _ => {
+                return Err(VMError::MalformedObject {
+                    len: buffer.len(),
+                    reason: "file is shorter than the 2-byte origin",
+                })
+            }
+        };
+
+        // A trailing odd byte means the last instruction was truncated
+        if !buffer.len().is_multiple_of(2) {
+            return Err(VMError::MalformedObject {
+                len: buffer.len(),
+                reason: "odd number of bytes, the last word is incomplete",
+            });
+        }
+
+        if Endianness::looks_swapped(origin) {
+            let hint = match endianness {
+                Endianness::Big => "the image may be little-endian, try --le",
+                Endianness::Little => "the image may be big-endian, try without --le",
+            };
+            warn!(
+                "origin x{:04X} looks byte-swapped (x{:04X}), {}",
+                origin,
+                origin.swap_bytes(),
+                hint
+            );
+        }
+
+        let words: Vec<u16> = buffer
+            .chunks_exact(2)
+            .skip(1)
+            .map(|chunk| match *chunk {
+                [first_byte, second_byte] => endianness.word([first_byte, second_byte]),
+                // chunks_exact only yields pairs
+                _ => 0,
+            })
+            .collect();
+
+        self.load_words(origin, &words)
+    }
+
+    /// Writes a decoded program image into memory starting at `origin`
+    ///
+    /// Overlaps with previously loaded images are reported as a warning,
+    /// or as an error when strict loading is enabled.
+    fn load_words(&mut self, origin: u16, words: &[u16]) -> Result<LoadedProgram, VMError> {
+        // Refuse images that would wrap past 0xFFFF into the vector table
+        if !self.allow_wrap && usize::from(origin).saturating_add(words.len()) > MEMORY_MAX {
+            return Err(VMError::ProgramTooLarge {
+                origin,
+                words: words.len(),
+            });
+        }
+
+        // Even a wrapping image can't write more than all of memory once
+        let len = u16::try_from(words.len()).map_err(|_| VMError::ProgramTooLarge {
+            origin,
+            words: words.len(),
+        })?;
+        let program = LoadedProgram::new(origin, len);
+
+        if let Some(existing) = self.loaded.iter().find(|loaded| loaded.overlaps(&program)) {
+            if self.strict_load {
+                return Err(VMError::OverlappingLoad {
+                    origin,
+                    existing: existing.origin,
+                });
+            }
+            warn!(
+                "program at 0x{:04X} overlaps program loaded at 0x{:04X}",
+                origin, existing.origin
+            );
+        }
+
+        let mut current_address = origin;
+
+        for &word in words {
+            self.memory.write_ram(current_address, word);
+            current_address = current_address.wrapping_add(1);
+        }
+
+        if let Some(cache) = &mut self.decode_cache {
+            cache.decode(&self.memory, &program);
+        }
+        self.loaded.push(program.clone());
+
+        // Execution starts at the origin of the first image unless a reset vector is set
+        if self.reset_vector.is_none() && self.loaded.len() == 1 {
+            self.registers.pc = origin;
+        }
+
+        Ok(program)
+    }
+
+    /// Writes the words in `range`, its end included, to an lc3as object file
+    ///
+    /// The file holds the big-endian start of the range followed by the
+    /// big-endian words, so it can be read back with [`VM::load_program`].
+    ///
+    /// # Errors
+    /// Returns `VMError::SaveFailed` if the file can't be written
+    pub fn save_program(&self, path: &str, range: RangeInclusive<u16>) -> Result<(), VMError> {
+        let mut buffer = range.start().to_be_bytes().to_vec();
+
+        for address in range {
+            buffer.extend(self.memory.read_ram(address).to_be_bytes());
+        }
+
+        std::fs::write(path, buffer).map_err(|_| VMError::SaveFailed(path.to_string()))
+    }
+
+    /// Replaces the source of keyboard input used by GETC, IN and the KBSR/KBDR registers
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.keyboard.set_input(input);
+        self.console = false;
+    }
+
+    /// Replaces the source of keyboard input with one that can be polled without blocking
+    pub fn set_input_source(&mut self, input: Box<dyn InputSource>) {
+        self.keyboard.set_source(input);
+        self.console = false;
+    }
+
+    /// Reads keys from `console`, switched to raw mode so each key reaches the program as it's pressed
+    ///
+    /// The console is restored once the VM reads from another source, is dropped,
+    /// or [`VM::release_console`] is called.
+    ///
+    /// # Errors
+    /// Returns `VMError::ConsoleFailed` if raw mode can't be enabled
+    pub fn set_console(&mut self, console: Box<dyn Console>) -> Result<(), VMError> {
+        let input =
+            ConsoleInput::raw(console).map_err(|e| VMError::ConsoleFailed(e.to_string()))?;
+        self.keyboard.set_source(Box::new(input));
+        self.console = true;
+
+        Ok(())
+    }
+
+    /// Restores the console given to [`VM::set_console`], the program gets no more keys afterwards
+    pub fn release_console(&mut self) {
+        if std::mem::take(&mut self.console) {
+            self.keyboard
+                .set_source(Box::new(ScriptedInput::new(Vec::new())));
+        }
+    }
+
+    /// Sends the program's output to `output` instead of stdout
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.set_output_sink(Output::stream(output));
+    }
+
+    /// Collects the program's output in memory, see [`VM::take_output`]
+    pub fn capture_output(&mut self) {
+        self.set_output_sink(Output::Captured(Vec::new()));
+    }
+
+    pub(crate) fn set_output_sink(&mut self, output: Output) {
+        self.console_out.set_sink(output);
+    }
+
+    /// Prints `HALT` to stderr when the program executes TRAP x25, or never with `false`
+    ///
+    /// By default the banner is printed only while the output goes to stdout on a terminal,
+    /// so it never ends up in the program's output.
+    pub fn set_halt_banner(&mut self, enabled: bool) {
+        self.halt_banner = Some(enabled);
+    }
+
+    /// Leaves the program's output with only what the program printed
+    ///
+    /// A quiet VM never prints the HALT banner and prints the IN trap's prompt
+    /// to stderr, so stdout carries exactly the bytes the program wrote.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Sets how characters outside printable ASCII, `\n`, `\r` and `\t` are printed
+    ///
+    /// By default they are replaced with `.` while the output goes to stdout on a
+    /// terminal, where escape sequences could clear the screen or change its modes,
+    /// and printed as they are to any other sink. Recorded sessions keep the
+    /// original characters.
+    pub fn set_sanitize(&mut self, sanitize: Sanitize) {
+        self.console_out.set_sanitize(sanitize);
+    }
+
+    /// Returns how many characters the output sanitizer replaced, see [`VM::set_sanitize`]
+    pub fn sanitized_count(&self) -> u64 {
+        self.console_out.sanitized()
+    }
+
+    /// Flushes the program's output, then prints the HALT banner if it's enabled
+    pub(crate) fn print_halt_banner(&mut self) -> Result<(), VMError> {
+        if !self.quiet && self.halt_banner.unwrap_or(self.console_out.is_terminal()) {
+            self.flush_output()?;
+            eprintln!("HALT");
+        }
+        Ok(())
+    }
+
+    /// Returns the output captured since the last call, empty unless [`VM::capture_output`] was called
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.console_out.take()
+    }
+
+    /// Adds labels to the symbol table used to label addresses in diagnostics
+    pub fn add_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols.extend(symbols);
+    }
+
+    /// Formats an address for diagnostics, e.g. `LOOP+2 (x3005)`
+    ///
+    /// A label is only used when it lies in the same loaded program as the address,
+    /// anything else is printed as a bare `x3005`.
+    pub fn describe_address(&self, address: u16) -> String {
+        let label = self.symbols.lookup(address).filter(|(_, label_address)| {
+            self.loaded
+                .iter()
+                .any(|program| program.contains(address) && program.contains(*label_address))
+        });
+
+        symbols::format_address(label, address)
+    }
+
+    /// Describes an error for the user like its `Display`, with addresses labelled from
+    /// the symbol table and the faulting instruction disassembled
+    ///
+    /// ```text
+    /// at LOOP+1 (x3001) (.FILL xD000): unimplemented opcode Res
+    /// ```
+    pub fn describe_error(&self, error: &VMError) -> String {
+        match error {
+            VMError::InvalidMemoryAccess(address) => {
+                format!("invalid memory access {}", self.describe_address(*address))
+            }
+            VMError::TrapError(TrapError::UnterminatedString { start }) => format!(
+                "string at {} has no terminating zero",
+                self.describe_address(*start)
+            ),
+            VMError::StackViolation { sp, bounds, pc } => format!(
+                "at {}: stack pointer x{:04X} outside the stack {}",
+                self.describe_address(*pc),
+                sp,
+                bounds
+            ),
+            VMError::AtInstruction { pc, instr, source } => format!(
+                "at {} ({}): {}",
+                self.describe_address(*pc),
+                disasm::disassemble(*instr, *pc),
+                self.describe_error(source)
+            ),
+            other => other.to_string(),
+        }
+    }
+
+    /// Makes overlapping program loads fail instead of only printing a warning
+    pub fn set_strict_load(&mut self, strict: bool) {
+        self.strict_load = strict;
+    }
+
+    /// Lets program images wrap past 0xFFFF back to address 0x0000
+    pub fn set_allow_wrap(&mut self, allow: bool) {
+        self.allow_wrap = allow;
+    }
+
+    /// Returns the program counter
+    pub fn pc(&self) -> u16 {
+        self.registers.pc
+    }
+
+    /// Whether the next instruction is a built-in GETC or IN trap with no key waiting in KBDR
+    ///
+    /// A host that can't block, such as one feeding a [`KeyQueue`](crate::keyboard::KeyQueue),
+    /// stops stepping while this holds and its queue is empty.
+    pub fn waits_for_key(&self) -> bool {
+        let word = self.memory.read_ram(self.registers.pc);
+        let [_, vector] = word.to_be_bytes();
+
+        word >> 12 == 0xF
+            && matches!(vector, 0x20 | 0x23)
+            && !self.traps.contains_key(&vector)
+            && self.keyboard.pending().is_none()
+    }
+
+    /// Copies every register, e.g. to print them with `{}`
+    pub fn registers_snapshot(&self) -> RegisterSnapshot {
+        self.registers.snapshot()
+    }
+
+    /// Returns the role names of the registers, SP for R6 and RA for R7 unless set
+    ///
+    /// Print a snapshot with them through [`RegisterSnapshot::with_aliases`].
+    pub fn register_aliases(&self) -> &RegisterAliases {
+        &self.register_aliases
+    }
+
+    /// Replaces the role names of the registers, [`RegisterAliases::none`] removes them all
+    pub fn set_register_aliases(&mut self, aliases: RegisterAliases) {
+        self.register_aliases = aliases;
+    }
+
+    /// Returns the condition flags set by the last instruction that wrote a register
+    pub fn condition(&self) -> RegisterFlags {
+        self.registers.condition
+    }
+
+    /// Returns the machine's RAM, without going through the memory-mapped devices
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// Drops the VM, giving back its memory for [`VM::new_with_memory`]
+    pub fn into_memory(self) -> Box<[u16; MEMORY_MAX]> {
+        self.memory.into_buffer()
+    }
+
+    /// Returns the labels known for the loaded programs
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Returns whether the VM is running or has halted
+    pub fn state(&self) -> &VMState {
+        &self.state
+    }
+
+    /// Returns the program images loaded so far, in load order
+    pub fn loaded_segments(&self) -> &[LoadedProgram] {
+        &self.loaded
+    }
+
+    /// Returns true if `address` was written by one of the loaded images
+    pub fn is_loaded(&self, address: u16) -> bool {
+        self.loaded.iter().any(|program| program.contains(address))
+    }
+
+    /// Returns the VM to its power-on state so another program can be loaded
+    ///
+    /// Memory is zeroed in place, the loaded segments and their symbols are
+    /// forgotten, registers go back to the reset vector (x3000 unless set with
+    /// [`VM::set_reset_vector`]) with the Z flag set, the VM is no longer halted
+    /// and the keyboard drops any unread character.
+    ///
+    /// Configuration is preserved: the keyboard's input source, strict
+    /// loading, wrapping, breakpoints, and the reset vector.
+    pub fn reset(&mut self) {
+        self.memory.clear();
+        self.registers = Registers::new(self.reset_vector());
+        self.keyboard.reset();
+        self.loaded.clear();
+        self.symbols = SymbolTable::new();
+        self.resume_from = None;
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
+        if let Some(detector) = &mut self.loop_detector {
+            detector.reset();
+        }
+        if let Some(coverage) = &mut self.coverage {
+            *coverage = Coverage::new();
+        }
+        if let Some(call_stack) = &mut self.call_stack {
+            *call_stack = CallStack::default();
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+        if let Some(cache) = &mut self.decode_cache {
+            cache.clear();
+        }
+        self.instructions = 0;
+        self.metrics = Metrics::default();
+        self.state = VMState::Running;
+    }
+
+    /// Captures memory, registers, the VM state, the keyboard's pending character and the counters
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: Box::new(self.memory.clone()),
+            registers: self.registers.clone(),
+            state: self.state.clone(),
+            keyboard_data: self.keyboard.pending(),
+            resume_from: self.resume_from,
+            instructions: self.instructions,
+            // Time spent running is the host's, it keeps counting across a restore
+            metrics: Metrics {
+                wall_time: Duration::ZERO,
+                ..self.metrics.clone()
+            },
+        }
+    }
+
+    /// Puts the machine back into the state captured by `snapshot`
+    ///
+    /// Loaded segments, symbols, breakpoints and the keyboard's input source
+    /// are left as they are. The backtrace starts over with no open calls.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.memory.clone_from(&snapshot.memory);
+        self.registers.clone_from(&snapshot.registers);
+        self.state = snapshot.state.clone();
+        self.keyboard.set_pending(snapshot.keyboard_data);
+        self.resume_from = snapshot.resume_from;
+        self.instructions = snapshot.instructions;
+        if let Some(call_stack) = &mut self.call_stack {
+            *call_stack = CallStack::default();
+        }
+        self.metrics = Metrics {
+            wall_time: self.metrics.wall_time,
+            ..snapshot.metrics.clone()
+        };
+        if let Some(detector) = &mut self.loop_detector {
+            detector.reset();
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+        if self.decode_cache.is_some() {
+            self.set_predecode(true);
+        }
+    }
+
+    /// Sets how many executed instructions are kept for [`VM::recent_trace`], 0 turns tracing off
+    pub fn set_trace_capacity(&mut self, capacity: usize) {
+        self.trace = (capacity > 0).then(|| TraceBuffer::new(capacity));
+    }
+
+    /// Makes runs reproducible: the keyboard receives the bytes of `script` in order
+    /// instead of live keys, or `None` goes back to allowing any device
+    ///
+    /// KBSR reports a key as soon as it is polled while the script lasts, GETC and IN fail
+    /// once it is used up, and MCR always reads as running. Any other device register
+    /// fails with `VMError::NondeterministicDevice`. Replacing the input source afterwards
+    /// gives up the guarantee.
+    pub fn set_deterministic(&mut self, script: Option<Vec<u8>>) {
+        self.deterministic = script.is_some();
+        if let Some(script) = script {
+            self.keyboard
+                .set_source(Box::new(ScriptedInput::new(script)));
+            self.keyboard.reset();
+            self.console = false;
+        }
+    }
+
+    /// Sets what GETC, IN and the KBSR/KBDR registers do once the keyboard input ended
+    pub fn set_eof_policy(&mut self, policy: EofPolicy) {
+        self.eof_policy = policy;
+    }
+
+    /// Sets how many instructions pass before KBSR asks the input source again after it had no key
+    ///
+    /// Meanwhile KBSR reads as no key pressed without polling, so a program spinning on
+    /// it runs at full speed instead of making a system call per read. A key typed
+    /// in between is seen at most `interval` instructions late. 0 polls on every read,
+    /// the default is [`DEFAULT_POLL_INTERVAL`](crate::keyboard::DEFAULT_POLL_INTERVAL).
+    /// Replayed sessions are never throttled.
+    pub fn set_poll_interval(&mut self, interval: u64) {
+        self.keyboard.set_poll_interval(interval);
+    }
+
+    /// Limits how long GETC and IN wait for a key, `None` waiting as long as it takes
+    ///
+    /// When no key arrives in time GETC and IN read the timeout sentinel, or
+    /// follow the EOF policy without one, and KBSR reports no key pressed.
+    /// Only input sources that can wait for a limited time, like
+    /// [`ChannelInput`](crate::keyboard::ChannelInput), ever time out.
+    pub fn set_input_timeout(&mut self, timeout: Option<Duration>) {
+        self.keyboard.set_timeout(timeout);
+    }
+
+    /// Sets the character GETC and IN read when the input timeout passes, `None` following the EOF policy
+    pub fn set_timeout_sentinel(&mut self, sentinel: Option<u8>) {
+        self.timeout_sentinel = sentinel;
+    }
+
+    /// Sets the prompt the IN trap prints before reading a character, `None` printing none
+    pub fn set_in_prompt(&mut self, prompt: Option<String>) {
+        self.in_prompt = prompt;
+    }
+
+    /// Makes GETC print the character it read, like IN without the prompt
+    pub fn set_getc_echo(&mut self, enabled: bool) {
+        self.echo_getc = enabled;
+    }
+
+    /// Makes the keyboard device print each character the program reads from KBDR
+    pub fn set_kbdr_echo(&mut self, enabled: bool) {
+        self.echo_kbdr = enabled;
+    }
+
+    /// Sets how many characters PUTS and PUTSP print at most, see [`DEFAULT_MAX_STRING_LENGTH`]
+    ///
+    /// A longer string, or one that wraps around the whole address space without a
+    /// terminating zero, fails with `TrapError::UnterminatedString` without printing anything.
+    pub fn set_max_string_length(&mut self, max: usize) {
+        self.max_string_length = max;
+    }
+
+    /// Runs `handler` whenever the program executes TRAP `vector`, e.g. to give graded
+    /// programs host assertions or scoring
+    ///
+    /// # Errors
+    /// Returns `VMError::ReservedTrap` for the built-in vectors x20-x25, which only
+    /// [`VM::override_trap`] replaces
+    pub fn register_trap(&mut self, vector: u8, handler: TrapHandler) -> Result<(), VMError> {
+        if BUILTIN_TRAPS.contains(&vector) {
+            return Err(VMError::ReservedTrap(vector));
+        }
+        self.traps.insert(vector, handler);
+        Ok(())
+    }
+
+    /// Runs `handler` for TRAP `vector`, replacing a built-in routine as well
+    pub fn override_trap(&mut self, vector: u8, handler: TrapHandler) {
+        self.traps.insert(vector, handler);
+    }
+
+    /// Turns the extension traps on or off, which are unknown vectors otherwise
+    ///
+    /// - `TRAP x27` ([`TRAP_PRINT_DECIMAL`](crate::traps::TRAP_PRINT_DECIMAL)) writes R0 as a
+    ///   signed decimal without padding or newline
+    /// - `TRAP x2A` ([`TRAP_READ_LINE`](crate::traps::TRAP_READ_LINE)) reads a line into the
+    ///   buffer at R0 of R1 words, one character per word followed by a NUL, and returns the
+    ///   number of characters in R0. The newline isn't stored and the rest of a line that
+    ///   doesn't fit is dropped. End of input ends a line, and applies the [`EofPolicy`] when
+    ///   it comes before any character.
+    ///
+    /// Apart from R0 for `x2A`, they only change R7, to the return address.
+    pub fn set_extension_traps(&mut self, enabled: bool) {
+        self.extension_traps = enabled;
+    }
+
+    /// Removes the handler of `vector`, returning whether there was one
+    pub fn unregister_trap(&mut self, vector: u8) -> bool {
+        self.traps.remove(&vector).is_some()
+    }
+
+    /// Logs one line per executed trap to `trace`, or stops logging with `None`
+    ///
+    /// Each line holds the instruction count, the PC, the trap and R0 before and after it:
+    ///
+    /// ```text
+    /// n=3 pc=x3002 trap=PUTS r0=x3004->x3004
+    /// ```
+    pub fn set_trap_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.trap_trace = trace;
+    }
+
+    /// Logs one line per executed instruction to `trace`, or stops logging with `None`
+    ///
+    /// Each line holds the instruction count, the PC and the instruction:
+    ///
+    /// ```text
+    /// n=1 pc=x3000 AND R1, R1, #0
+    /// ```
+    pub fn set_instruction_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.instruction_trace = trace;
+    }
+
+    /// Sets how many instructions [`VM::step_back`] can undo, 0 turns the journal off
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.journal = (depth > 0).then(|| Journal::new(depth));
+    }
+
+    /// Undoes the last executed instruction, restoring the PC, the condition flags and
+    /// the register or memory word it overwrote
+    ///
+    /// Returns false when there is nothing to undo: the journal is off or empty, or the
+    /// previous instruction was a trap or touched a device, whose I/O can't be taken back.
+    /// The instruction count is rewound too, but the profile, coverage and backtrace are not.
+    pub fn step_back(&mut self) -> bool {
+        let Some(undo) = self.journal.as_mut().and_then(Journal::pop) else {
+            return false;
+        };
+
+        if let Some((register, value)) = undo.register {
+            self.registers.set(register, value);
+        }
+        if let Some((address, value)) = undo.memory {
+            self.forget_decoded(address);
+            self.memory.write_ram(address, value);
+        }
+        self.registers.pc = undo.pc;
+        self.registers.condition = undo.condition;
+        self.instructions = self.instructions.saturating_sub(1);
+
+        // Stepping forward again runs the instruction even if it has a breakpoint
+        self.resume_from = Some(undo.pc);
+        if matches!(self.state, VMState::Halted(_)) {
+            self.state = VMState::Running;
+        }
+        if let Some(detector) = &mut self.loop_detector {
+            detector.reset();
+        }
+
+        true
+    }
+
+    /// Collects what the instruction at `pc` is about to overwrite
+    fn undo_for(&self, pc: u16, opcode: Opcode, instruction: u16) -> Result<Undo, VMError> {
+        let destination = usize::from((instruction >> 9) & 0x7);
+        let base = usize::from((instruction >> 6) & 0x7);
+        let next = pc.wrapping_add(1);
+
+        let register = match opcode {
+            Opcode::Add
+            | Opcode::And
+            | Opcode::Not
+            | Opcode::Ld
+            | Opcode::Ldi
+            | Opcode::Ldr
+            | Opcode::Lea => Some(destination),
+            Opcode::Jsr => Some(7),
+            _ => None,
+        };
+        let address = match opcode {
+            Opcode::St => Some(next.wrapping_add(sign_extend(instruction & 0x1FF, 9))),
+            Opcode::Sti => Some(
+                self.memory
+                    .read_ram(next.wrapping_add(sign_extend(instruction & 0x1FF, 9))),
+            ),
+            Opcode::Str => Some(
+                self.registers
+                    .get(base)?
+                    .wrapping_add(sign_extend(instruction & 0x3F, 6)),
+            ),
+            _ => None,
+        };
+
+        Ok(Undo {
+            pc,
+            condition: self.registers.condition,
+            register: register
+                .map(|register| Ok((register, self.registers.get(register)?)))
+                .transpose()?,
+            memory: address.map(|address| (address, self.memory.read_ram(address))),
+        })
+    }
+
+    /// Returns the most recently executed instructions, oldest first
+    ///
+    /// After `run` or `step` fails, the last entry is the faulting instruction.
+    pub fn recent_trace(&self) -> Vec<TraceEntry> {
+        self.trace
+            .iter()
+            .flat_map(|trace| trace.entries().copied())
+            .collect()
+    }
+
+    /// Turns counting of executed opcodes, branches and traps on or off
+    ///
+    /// Turning profiling on starts from zero. While it's on the VM also counts
+    /// the fetches at every address, see [`VM::hot_addresses`].
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(Profiler::new);
+    }
+
+    /// Returns the counters collected since profiling was turned on, all zero when it's off
+    pub fn profile(&self) -> ProfileReport {
+        self.profile
+            .as_ref()
+            .map(|profiler| profiler.report.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the `top_n` most fetched addresses, most fetched first
+    ///
+    /// Each address is described with its enclosing label when symbols are loaded.
+    /// Empty when profiling is off.
+    pub fn hot_addresses(&self, top_n: usize) -> Vec<HotAddress> {
+        let Some(profiler) = &self.profile else {
+            return Vec::new();
+        };
+
+        profiler
+            .hottest(top_n)
+            .into_iter()
+            .map(|(address, count)| HotAddress {
+                address,
+                count,
+                location: self.describe_address(address),
+            })
+            .collect()
+    }
+
+    /// Turns tracking of executed addresses on or off, see [`VM::coverage`]
+    ///
+    /// Turning it on starts with nothing executed.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = enabled.then(Coverage::new);
+    }
+
+    /// Lists every loaded address with whether it was executed since coverage was turned on
+    ///
+    /// With coverage off every address is reported as not executed.
+    pub fn coverage(&self) -> CoverageReport {
+        match &self.coverage {
+            Some(coverage) => coverage.report(&self.loaded, &self.symbols),
+            None => Coverage::new().report(&self.loaded, &self.symbols),
+        }
+    }
+
+    /// Turns decoding the loaded programs ahead of execution on or off
+    ///
+    /// Turning it on decodes every instruction of the loaded segments into a
+    /// cache, and programs loaded afterwards as they load; the run loop then
+    /// executes cached instructions without decoding their words again. A
+    /// write to a cached address drops its entry, so self-modifying code
+    /// behaves the same either way.
+    pub fn set_predecode(&mut self, enabled: bool) {
+        self.decode_cache = enabled.then(|| {
+            let mut cache = DecodeCache::new();
+            for program in &self.loaded {
+                cache.decode(&self.memory, program);
+            }
+            cache
+        });
+    }
+
+    /// Drops the decoded instruction at `address` before the word there is overwritten
+    pub(crate) fn forget_decoded(&mut self, address: u16) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.invalidate(address);
+        }
+    }
+
+    /// Turns the shadow call stack behind [`VM::backtrace`] on or off
+    ///
+    /// Turning it on starts with no open calls.
+    pub fn set_call_tracking(&mut self, enabled: bool) {
+        self.call_stack = enabled.then(CallStack::default);
+    }
+
+    /// Returns the subroutine calls that haven't returned yet, innermost first
+    ///
+    /// Empty unless call tracking is on. Addresses are labelled from the symbol table.
+    pub fn backtrace(&self) -> Vec<Frame> {
+        self.call_stack
+            .iter()
+            .flat_map(CallStack::frames)
+            .map(|(target, return_address)| Frame {
+                target,
+                return_address,
+                function: self.describe_address(target),
+                caller: self.describe_address(return_address.wrapping_sub(1)),
+            })
+            .collect()
+    }
+
+    /// Returns false once the program returned somewhere other than the innermost call,
+    /// e.g. after changing R7 by hand, so [`VM::backtrace`] may be wrong
+    pub fn backtrace_is_reliable(&self) -> bool {
+        self.call_stack.as_ref().is_none_or(CallStack::is_reliable)
+    }
+
+    /// Checks every use of R6 as a stack pointer against `bounds`, or stops checking with `None`
+    ///
+    /// An LDR or STR based on R6 must address the stack and any new value of R6 must stay
+    /// inside it, or one past its top for an empty stack. Otherwise [`VM::step`] returns
+    /// `VMError::StackViolation`. A violating LDR or STR is not executed and the PC stays
+    /// on it. An instruction moving R6 out has run by then, [`VM::step_back`] undoes it
+    /// when the journal is on.
+    pub fn set_stack_bounds(&mut self, bounds: Option<StackBounds>) {
+        self.stack_bounds = bounds;
+    }
+
+    /// Halts with `HaltReason::LikelyInfiniteLoop` when the program spins in a tight loop
+    ///
+    /// The loop is reported once the registers repeat after `threshold` consecutive
+    /// instructions that neither trapped, wrote memory nor accessed a device register,
+    /// so a loop polling KBSR never triggers it. `None` turns detection off.
+    /// [`loops::DEFAULT_LOOP_THRESHOLD`](crate::loops::DEFAULT_LOOP_THRESHOLD) is a
+    /// reasonable threshold.
+    pub fn set_loop_detection(&mut self, threshold: Option<u64>) {
+        self.loop_detector = threshold.map(LoopDetector::new);
+    }
+
+    /// Halts the run with [`HaltReason::Interrupted`] once `flag` is set, e.g. by a Ctrl-C handler
+    ///
+    /// The flag is checked between instructions and cleared when the VM halts,
+    /// an instruction waiting for a key finishes first.
+    pub fn set_interrupt_flag(&mut self, flag: Option<Arc<AtomicBool>>) {
+        self.interrupt = flag;
+    }
+
+    /// Adds a breakpoint stopping the VM before it executes the instruction at `address`
+    pub fn add_breakpoint(&mut self, address: u16) -> BreakpointId {
+        self.breakpoints.add(address, None)
+    }
+
+    /// Adds a breakpoint at `address` that only stops the VM while `condition` holds
+    ///
+    /// The number of times the condition was false is kept in [`Breakpoint::skipped`].
+    pub fn add_conditional_breakpoint(
+        &mut self,
+        address: u16,
+        condition: Condition,
+    ) -> BreakpointId {
+        self.breakpoints.add(address, Some(condition))
+    }
+
+    /// Reads `value` as a condition or a watch sees it, `None` for an invalid register
+    ///
+    /// Memory is read without triggering memory-mapped devices.
+    pub fn value(&self, value: Value) -> Option<u16> {
+        value.read(&self.registers, &self.memory)
+    }
+
+    /// Removes a breakpoint, returning false if `id` is unknown
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        self.breakpoints.remove(id)
+    }
+
+    /// Disables a breakpoint without removing it, or enables it again
+    ///
+    /// Returns false if `id` is unknown
+    pub fn set_breakpoint_enabled(&mut self, id: BreakpointId, enabled: bool) -> bool {
+        self.breakpoints.set_enabled(id, enabled)
+    }
+
+    /// Returns the breakpoints in the order they were added
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        self.breakpoints.list()
+    }
+
+    /// Sets the address execution starts from and [`VM::reset`] returns to
+    ///
+    /// Takes precedence over the origin of the first loaded program, whether
+    /// it's called before or after loading. OS images typically start at x0200.
+    pub fn set_reset_vector(&mut self, pc: u16) {
+        self.reset_vector = Some(pc);
+        self.resume_from = None;
+        self.registers.pc = pc;
+    }
+
+    /// Returns the address [`VM::reset`] puts the PC at, [`PC_START`] unless set
+    pub fn reset_vector(&self) -> u16 {
+        self.reset_vector.unwrap_or(PC_START)
+    }
+
+    /// Moves the PC to `pc`, which also becomes the reset vector, see [`VM::set_reset_vector`]
+    pub fn set_pc(&mut self, pc: u16) {
+        self.set_reset_vector(pc);
+    }
+
+    /// Moves the PC to `pc`, leaving the reset vector where it was
+    ///
+    /// A breakpoint at `pc` stops the next step, as it would for a jump there.
+    pub fn set_pc_only(&mut self, pc: u16) {
+        self.resume_from = None;
+        self.registers.pc = pc;
+    }
+
+    /// Executes a single instruction
+    ///
+    /// # Process
+    /// 1. Fetches instruction from memory at PC
+    /// 2. Increments PC
+    /// 3. Decodes instruction opcode
+    /// 4. Executes instruction
+    ///
+    /// Stepping a paused VM resumes it. At an enabled breakpoint nothing is
+    /// executed, the VM pauses and `StepOutcome::Breakpoint` is returned; the
+    /// next step executes the instruction there.
+    ///
+    /// # Errors
+    /// * `VMError::Halted` - If the VM already halted, nothing is executed
+    /// * `VMError::AtInstruction` - With the address and word of the instruction if it fails to execute
+    /// * Other errors if the instruction can't be fetched
+    pub fn step(&mut self) -> Result<StepOutcome, VMError> {
+        match &self.state {
+            VMState::Halted(reason) => return Err(VMError::Halted(reason.clone())),
+            VMState::Paused(_) => self.state = VMState::Running,
+            VMState::Running => (),
+        }
+
+        let pc = self.registers.pc;
+
+        // Stop before a breakpointed instruction, unless resuming from it
+        if self.resume_from.take() != Some(pc)
+            && self.breakpoints.hit(pc, &self.registers, &self.memory)
+        {
+            self.resume_from = Some(pc);
+            self.state = VMState::Paused(PauseReason::Breakpoint(pc));
+            return Ok(StepOutcome::Breakpoint(pc));
+        }
+
+        // 1. Load one instruction from memory at the address of the PC, cached
+        // instructions are never in the device registers. Fetches don't count
+        // as memory reads in the metrics.
+        let decoded = self.decode_cache.as_ref().and_then(|cache| cache.get(pc));
+        let instruction = match decoded {
+            Some(_) => self.memory.read_ram(pc),
+            None if pc < MR_KBSR => self.memory.read_ram(pc),
+            None => self.read_mmio(pc)?,
+        };
+        trace!(target: "lc3_vm::memory", "fetch x{:04X} = x{:04X}", pc, instruction);
+
+        if let Some(trace) = &mut self.trace {
+            trace.push(pc, instruction);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark(pc);
+        }
+
+        // The opcode is only built where it's reported, the hot path tests its bits
+        let opcode_bits = instruction >> 12;
+        let is_trap = opcode_bits == OP_TRAP;
+
+        // A violating LDR or STR isn't executed, so the PC stays on it
+        let stack = self
+            .stack_bounds
+            .map(|bounds| (bounds, self.registers.read(6)));
+        if let Some((bounds, sp)) = stack {
+            let ldr_or_str = matches!(opcode_bits, OP_LDR | OP_STR);
+            if ldr_or_str && (instruction >> 6) & 0x7 == 6 {
+                let address = sp.wrapping_add(sign_extend(instruction & 0x3F, 6));
+                if !bounds.contains(address) {
+                    return Err(VMError::StackViolation {
+                        sp: address,
+                        bounds,
+                        pc,
+                    });
+                }
+            }
+        }
+
+        // 2. Increment the PC
+        self.registers.pc = pc.wrapping_add(1);
+
+        debug!(
+            "fetch x{:04X}: x{:04X}, dispatch {:?}",
+            pc,
+            instruction,
+            Opcode::from(opcode_bits)
+        );
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("step", pc, opcode = ?Opcode::from(opcode_bits)).entered();
+
+        let undo = match self.journal {
+            Some(_) => Some(self.undo_for(pc, Opcode::from(opcode_bits), instruction)?),
+            None => None,
+        };
+
+        let r0 = match (&self.trap_trace, is_trap) {
+            (Some(_), true) => self.registers.read(0),
+            _ => 0,
+        };
+
+        self.side_effect = false;
+        self.device_io = false;
+        let result = match decoded {
+            Some(decoded) => machine::execute(self, decoded),
+            None => self.execute(instruction),
+        };
+        match result {
+            Err(VMError::InputExhausted) if self.eof_policy == EofPolicy::Halt => {
+                self.state = VMState::Halted(HaltReason::InputExhausted);
+            }
+            result => result.map_err(|source| VMError::AtInstruction {
+                pc,
+                instr: instruction,
+                source: Box::new(source),
+            })?,
+        }
+        self.instructions = self.instructions.saturating_add(1);
+        if log_enabled!(Level::Trace) {
+            let registers: Vec<String> = (self.registers_snapshot().r.iter().enumerate())
+                .map(|(number, value)| format!("R{}=x{:04X}", number, value))
+                .collect();
+            trace!(
+                "x{:04X} {}: {}",
+                pc,
+                disasm::disassemble(instruction, pc),
+                registers.join(" ")
+            );
+        }
+
+        if let Some(trace) = &mut self.instruction_trace {
+            writeln!(
+                trace,
+                "n={} pc=x{:04X} {}",
+                self.instructions,
+                pc,
+                disasm::disassemble(instruction, pc)
+            )
+            .map_err(|e| VMError::TrapError(TrapError::IOError(e.to_string())))?;
+        }
+        if let (Some(trace), true) = (&mut self.trap_trace, is_trap) {
+            writeln!(
+                trace,
+                "n={} pc=x{:04X} trap={} r0=x{:04X}->x{:04X}",
+                self.instructions,
+                pc,
+                disasm::disassemble(instruction, pc),
+                r0,
+                self.registers.read(0)
+            )
+            .map_err(|e| VMError::TrapError(TrapError::IOError(e.to_string())))?;
+        }
+
+        // Recorded before a stack violation returns, so stepping back undoes the write to R6
+        if let (Some(journal), Some(undo)) = (&mut self.journal, undo) {
+            if self.device_io || is_trap {
+                journal.clear();
+            } else {
+                journal.push(undo);
+            }
+        }
+
+        if let Some((bounds, sp)) = stack {
+            let new_sp = self.registers.read(6);
+            if new_sp != sp && !bounds.allows_pointer(new_sp) {
+                return Err(VMError::StackViolation {
+                    sp: new_sp,
+                    bounds,
+                    pc,
+                });
+            }
+            if bounds.allows_pointer(new_sp) {
+                self.metrics.observe_stack(bounds.top, new_sp);
+            }
+        }
+
+        if let Some(profile) = &mut self.profile {
+            profile.record(pc, instruction, self.registers.pc);
+        }
+
+        if let Some(call_stack) = &mut self.call_stack {
+            match opcode_bits {
+                // JSR or JSRR
+                OP_JSR => call_stack.call(self.registers.pc, pc.wrapping_add(1)),
+                // RET is JMP R7
+                OP_JMP if (instruction >> 6) & 0x7 == 7 => call_stack.ret(self.registers.pc),
+                _ => (),
+            }
+        }
+
+        if let Some(detector) = &mut self.loop_detector {
+            if self.side_effect || is_trap {
+                detector.reset();
+            } else if let Some(pcs) = detector.observe(&self.registers) {
+                self.state = VMState::Halted(HaltReason::LikelyInfiniteLoop { pcs });
+            }
+        }
+
+        Ok(StepOutcome::Executed {
+            pc,
+            instruction,
+            opcode: Opcode::from(opcode_bits),
+            halted: match &self.state {
+                VMState::Halted(reason) => Some(reason.clone()),
+                VMState::Running | VMState::Paused(_) => None,
+            },
+        })
+    }
+
+    /// Returns the low byte of R0 once the program halted through TRAP x25
+    ///
+    /// Programs following the convention of other simulators leave their exit status there.
+    /// `None` while running and after any other kind of halt.
+    pub fn exit_code(&self) -> Option<u8> {
+        match self.state {
+            VMState::Halted(HaltReason::TrapHalt) => {
+                self.registers.get(0).ok().map(|r0| r0.to_be_bytes()[1])
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the number of instructions executed since the VM was created or reset
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Returns the counters of everything the VM did since it was created or reset
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            instructions: self.instructions,
+            ..self.metrics.clone()
+        }
+    }
+
+    /// Starts logging every character the program receives and prints
+    ///
+    /// Any earlier recording is discarded.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Session::new());
+    }
+
+    /// Stops recording and returns what was recorded
+    pub fn take_recording(&mut self) -> Option<Session> {
+        self.recording.take()
+    }
+
+    /// Feeds the program the input of a recorded session instead of the input source
+    ///
+    /// Every character becomes available at the instruction count it was
+    /// received at during the recording, so the run repeats it exactly.
+    pub fn replay(&mut self, session: Session) {
+        self.replay = Some(Replay::new(session));
+    }
+
+    /// Sets the instruction budget of [`VM::run_program`], `None` for no limit
+    pub fn set_instruction_limit(&mut self, max_instructions: Option<u64>) {
+        self.limit = max_instructions;
+    }
+
+    /// Sets how long [`VM::run_program`] may take, `None` for no limit
+    ///
+    /// The clock starts with each call to `run_program`.
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.time_limit = limit;
+    }
+
+    /// Runs until the program halts or the budget from [`VM::set_instruction_limit`]
+    /// or [`VM::set_time_limit`] runs out
+    ///
+    /// # Errors
+    /// Returns VMError if instruction execution fails
+    pub fn run_program(&mut self) -> Result<RunOutcome, VMError> {
+        let deadline = self
+            .time_limit
+            .and_then(|limit| Instant::now().checked_add(limit));
+
+        self.run_bounded(None, self.limit, deadline)
+    }
+
+    /// Pauses a running VM, the next `run` or `step` continues where it stopped
+    pub fn pause(&mut self) {
+        if self.state == VMState::Running {
+            self.state = VMState::Paused(PauseReason::Requested);
+        }
+    }
+
+    /// Lets a paused VM run again without executing anything
+    ///
+    /// Does nothing if the VM is running or halted.
+    pub fn resume(&mut self) {
+        if let VMState::Paused(_) = self.state {
+            self.state = VMState::Running;
+        }
+    }
+
+    /// Runs the VM's main execution loop, resuming it if paused, until it halts or pauses again
+    ///
+    /// Running a halted VM executes nothing and returns why it halted.
+    ///
+    /// # Errors
+    /// Returns VMError if instruction execution fails
+    pub fn run(&mut self) -> Result<RunOutcome, VMError> {
+        self.run_bounded(None, None, None)
+    }
+
+    /// Runs until the VM halts or pauses, or `max_instructions` have been executed
+    ///
+    /// The VM pauses after reaching the limit: calling `run` or
+    /// `run_with_limit` again continues from the next instruction.
+    ///
+    /// # Errors
+    /// Returns VMError if instruction execution fails
+    pub fn run_with_limit(&mut self, max_instructions: u64) -> Result<RunOutcome, VMError> {
+        self.run_bounded(None, Some(max_instructions), None)
+    }
+
+    /// Runs until the PC equals `address`, the VM halts, or the optional instruction limit runs out
+    ///
+    /// The PC is checked before each fetch, so nothing is executed when it
+    /// already equals `address`; `step()` past it to run to the next visit.
+    /// The instruction at `address` has not been executed when the VM pauses
+    /// with `PauseReason::AddressReached`, and `step()`, `run()` or another
+    /// `run_until` continue from there.
+    ///
+    /// # Errors
+    /// Returns VMError if instruction execution fails
+    pub fn run_until(
+        &mut self,
+        address: u16,
+        max_instructions: Option<u64>,
+    ) -> Result<RunOutcome, VMError> {
+        self.run_bounded(Some(address), max_instructions, None)
+    }
+
+    fn run_bounded(
+        &mut self,
+        until: Option<u16>,
+        max_instructions: Option<u64>,
+        deadline: Option<Instant>,
+    ) -> Result<RunOutcome, VMError> {
+        let started = Instant::now();
+        let result = self.run_steps(until, max_instructions, deadline);
+        self.metrics.wall_time = self.metrics.wall_time.saturating_add(started.elapsed());
+
+        // However the run stopped, everything printed so far becomes visible
+        let flushed = self.flush_output();
+        let outcome = result?;
+        flushed?;
+
+        Ok(outcome)
+    }
+
+    fn run_steps(
+        &mut self,
+        until: Option<u16>,
+        max_instructions: Option<u64>,
+        deadline: Option<Instant>,
+    ) -> Result<RunOutcome, VMError> {
+        let mut executed: u64 = 0;
+        self.resume();
+
+        loop {
+            match &self.state {
+                VMState::Halted(reason) => return Ok(RunOutcome::Halted(reason.clone())),
+                VMState::Paused(reason) => return Ok(RunOutcome::Paused(reason.clone())),
+                VMState::Running => (),
+            }
+
+            if self
+                .interrupt
+                .as_ref()
+                .is_some_and(|flag| flag.swap(false, Ordering::SeqCst))
+            {
+                self.state = VMState::Halted(HaltReason::Interrupted);
+            } else if until == Some(self.registers.pc) {
+                self.state = VMState::Paused(PauseReason::AddressReached(self.registers.pc));
+            } else if max_instructions.is_some_and(|max| executed >= max) {
+                self.state = VMState::Paused(PauseReason::LimitReached { executed });
+            } else if deadline.is_some_and(|deadline| {
+                // Reading the clock costs more than an instruction
+                executed & 0x3FF == 0 && Instant::now() >= deadline
+            }) {
+                self.state = VMState::Paused(PauseReason::TimeLimitReached { executed });
+            } else if let StepOutcome::Executed { .. } = self.step()? {
+                executed = executed.saturating_add(1);
+            }
+        }
+    }
+
+    /// Decodes `instruction` the way the hardware runs it and executes it on this VM
+    ///
+    /// # Errors
+    /// Returns `VMError::UnimplemedOpcode` for the reserved opcode and RTI, otherwise
+    /// see [`machine::execute`]
+    #[inline]
+    pub(crate) fn execute(&mut self, instruction: u16) -> Result<(), VMError> {
+        let decoded = Instruction::decode_lenient(instruction)
+            .map_err(|_| VMError::UnimplemedOpcode(Opcode::Res))?;
+        machine::execute(self, decoded)
+    }
+}
+
+impl Machine for VM {
+    #[inline]
+    fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    #[inline]
+    fn read_memory(&mut self, address: u16) -> Result<u16, VMError> {
+        VM::read_memory(self, address)
+    }
+
+    #[inline]
+    fn read_pointer(&mut self, address: u16) -> u16 {
+        VM::read_pointer(self, address)
+    }
+
+    #[inline]
+    fn write_memory(&mut self, address: u16, value: u16) -> Result<(), VMError> {
+        VM::write_memory(self, address, value)
+    }
+
+    fn trap(&mut self, vector: u8) -> Result<(), VMError> {
+        trap(self, vector)
+    }
+}
+
+/// The built-in trap routines read and print through the VM's keyboard and console
+impl HostIo for VM {
+    fn read_key(&mut self) -> Result<u8, VMError> {
+        VM::read_key(self)
+    }
+
+    fn poll_key(&mut self) -> Result<Option<u8>, VMError> {
+        self.poll_keyboard()?;
+        let key = self.keyboard.pending();
+        self.keyboard.set_pending(None);
+        Ok(key)
+    }
+
+    fn put_char(&mut self, c: u8) -> Result<(), VMError> {
+        VM::put_char(self, c)
+    }
+
+    /// Prints the prompt set with [`VM::set_in_prompt`], to stderr when quiet
+    fn in_prompt(&mut self) -> Result<(), VMError> {
+        match self.in_prompt.clone() {
+            // Keep the prompt in order with what the program printed before it
+            Some(prompt) if self.quiet => {
+                self.flush_output()?;
+                eprint!("{}", prompt);
+            }
+            Some(prompt) => self.put_str(&prompt)?,
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn echo_getc(&self) -> bool {
+        self.echo_getc
+    }
+}
+
+/// Logs a one-line summary of a program file once it's in memory
+fn log_loaded(file: &str, format: Format, programs: &[LoadedProgram]) {
+    let words: usize = programs
+        .iter()
+        .map(|program| usize::from(program.len))
+        .sum();
+    let origin = programs.first().map_or(0, |program| program.origin);
+
+    info!(
+        "loaded {} ({:?}): {} words in {} segment(s), origin x{:04X}",
+        file,
+        format,
+        words,
+        programs.len(),
+        origin
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::TrapError;
+    use crate::keyboard::KeyQueue;
+
+    #[test]
+    fn test_step_halted_vm_errors() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_bytes(&[0x30, 0x00, 0xF0, 0x25, 0x10, 0x21], Endianness::Big)?;
+
+        assert_eq!(
+            vm.step()?,
+            StepOutcome::Executed {
+                pc: 0x3000,
+                instruction: 0xF025,
+                opcode: Opcode::Trap,
+                halted: Some(HaltReason::TrapHalt)
+            }
+        );
+
+        assert!(matches!(
+            vm.step(),
+            Err(VMError::Halted(HaltReason::TrapHalt))
+        ));
+        assert_eq!(vm.pc(), 0x3001);
+        assert_eq!(vm.read_register(0)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_limit_stops_infinite_loop() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        // ADD R0, R0, #1 ; BRnzp back to the ADD
+        vm.load_bytes(&[0x30, 0x00, 0x10, 0x21, 0x0F, 0xFE], Endianness::Big)?;
+
+        assert_eq!(
+            vm.run_with_limit(101)?,
+            RunOutcome::Paused(PauseReason::LimitReached { executed: 101 })
+        );
+        assert_eq!(vm.read_register(0)?, 51);
+        assert_eq!(vm.pc(), 0x3001);
+
+        // The VM resumes where it stopped
+        assert_eq!(
+            vm.run_with_limit(1)?,
+            RunOutcome::Paused(PauseReason::LimitReached { executed: 1 })
+        );
+        assert_eq!(vm.pc(), 0x3000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_until_address() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(
+            "        .ORIG x3000
+        AND R0, R0, #0
+        ADD R1, R0, #3
+LOOP    ADD R0, R0, #2
+        ADD R1, R1, #-1
+        BRp LOOP
+DONE    ADD R2, R0, #1
+        HALT
+        .END
+",
+        )?;
+
+        assert_eq!(
+            vm.run_until(0x3005, None)?,
+            RunOutcome::Paused(PauseReason::AddressReached(0x3005))
+        );
+        assert_eq!(vm.read_register(0)?, 6);
+        assert_eq!(vm.read_register(1)?, 0);
+        assert_eq!(vm.read_register(2)?, 0);
+
+        // Already at the target, nothing runs
+        assert_eq!(
+            vm.run_until(0x3005, None)?,
+            RunOutcome::Paused(PauseReason::AddressReached(0x3005))
+        );
+
+        vm.run()?;
+        assert_eq!(vm.read_register(2)?, 7);
+        assert_eq!(
+            vm.run_until(0x3005, None)?,
+            RunOutcome::Halted(HaltReason::TrapHalt)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_until_respects_limit() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        // ADD R0, R0, #1 ; BRnzp back to the ADD
+        vm.load_bytes(&[0x30, 0x00, 0x10, 0x21, 0x0F, 0xFE], Endianness::Big)?;
+
+        assert_eq!(
+            vm.run_until(0x4000, Some(10))?,
+            RunOutcome::Paused(PauseReason::LimitReached { executed: 10 })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_runs_program_again() -> Result<(), VMError> {
+        let source = "        .ORIG x3000
+        LD R0, VALUE
+        ADD R0, R0, #1
+        ST R0, VALUE
+        HALT
+VALUE   .FILL #41
+        .END
+";
+        let mut vm = VM::new();
+        vm.load_asm(source)?;
+        vm.run()?;
+        let first = (vm.read_register(0)?, vm.memory.read_ram(0x3004), vm.pc());
+
+        vm.reset();
+        assert_eq!(vm.state(), &VMState::Running);
+        assert_eq!(vm.pc(), 0x3000);
+        assert_eq!(vm.condition(), RegisterFlags::Zro);
+        assert_eq!(vm.read_register(0)?, 0);
+        assert_eq!(vm.memory.read_ram(0x3004), 0);
+        assert!(vm.loaded_segments().is_empty());
+        assert_eq!(vm.symbols().lookup(0x3004), None);
+
+        // Loading doesn't warn about overlapping the previous run
+        vm.set_strict_load(true);
+        vm.load_asm(source)?;
+        vm.run()?;
+        let second = (vm.read_register(0)?, vm.memory.read_ram(0x3004), vm.pc());
+
+        assert_eq!(first, (42, 42, 0x3004));
+        assert_eq!(first, second);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_keeps_entry_point() {
+        let mut vm = VM::new();
+        vm.set_pc(0x4000);
+        vm.reset();
+
+        assert_eq!(vm.pc(), 0x4000);
+    }
+
+    #[test]
+    fn test_reused_memory_is_cleared_by_reset() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm("        .ORIG x3000\n        HALT\n        .END\n")?;
+        vm.write_memory(0x0000, 0xFFFF)?;
+        vm.write_memory(0xFDFF, 0xBEEF)?;
+
+        let mut vm = VM::new_with_memory(vm.into_memory());
+        assert_eq!(vm.memory.read_ram(0x3000), 0xF025);
+
+        vm.reset();
+        let cleared = Memory::new();
+        assert!(vm.memory() == &cleared);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_reports_halt_reason() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_bytes(&[0x30, 0x00, 0xF0, 0x25], Endianness::Big)?;
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+
+        let mut vm = VM::new();
+        vm.load_asm(
+            "        .ORIG x3000
+        AND R0, R0, #0
+        STI R0, MCR
+        ADD R1, R1, #1
+MCR     .FILL xFFFE
+        .END
+",
+        )?;
+        assert_eq!(vm.read_memory(MR_MCR)?, 0x8000);
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::McrCleared));
+        assert_eq!(vm.state(), &VMState::Halted(HaltReason::McrCleared));
+        // The instruction after the store never ran
+        assert_eq!(vm.read_register(1)?, 0);
+
+        Ok(())
+    }
+
+    const COUNT_TO_THREE: &str = "        .ORIG x3000
+        AND R0, R0, #0
+        ADD R1, R0, #3
+LOOP    ADD R0, R0, #1
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+        .END
+";
+
+    #[test]
+    fn test_hot_addresses_loop() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_profiling(true);
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R1, COUNT
+LOOP    ADD R0, R0, #1
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+COUNT   .FILL #1000
+        .END
+",
+        )?;
+        vm.run()?;
+
+        let hot = vm.hot_addresses(4);
+        let counts: Vec<(u16, u32)> = hot.iter().map(|hot| (hot.address, hot.count)).collect();
+        assert_eq!(
+            counts,
+            vec![(0x3001, 1000), (0x3002, 1000), (0x3003, 1000), (0x3000, 1)]
+        );
+        assert_eq!(
+            hot.iter()
+                .map(|hot| hot.location.as_str())
+                .collect::<Vec<_>>(),
+            vec!["LOOP (x3001)", "LOOP+1 (x3002)", "LOOP+2 (x3003)", "x3000"]
+        );
+        assert_eq!(vm.profile().branches(), (999, 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hot_addresses_without_profiling() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_THREE)?;
+        vm.run()?;
+
+        assert!(vm.hot_addresses(10).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_getc_answer_from_input() -> Result<(), VMError> {
+        const ASK: &str = "        .ORIG x3000
+        GETC
+        LD R1, NOT_Y
+        ADD R1, R0, R1
+        BRz YES
+        AND R2, R2, #0
+        HALT
+YES     AND R2, R2, #0
+        ADD R2, R2, #1
+        HALT
+NOT_Y   .FILL #-121
+        .END
+";
+
+        for (input, answer) in [(&b"y\n"[..], 1), (&b"n\n"[..], 0)] {
+            let mut vm = VM::new();
+            vm.set_input(Box::new(std::io::Cursor::new(input)));
+            vm.capture_output();
+            vm.load_asm(ASK)?;
+            vm.run()?;
+
+            assert_eq!(vm.read_register(2)?, answer);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_getc_after_kbsr_poll_sees_same_character() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_input(Box::new(std::io::Cursor::new(b"ab")));
+        vm.capture_output();
+        vm.load_asm(
+            "        .ORIG x3000
+        LDI R1, KBSR
+        GETC
+        HALT
+KBSR    .FILL xFE00
+        .END
+",
+        )?;
+        vm.run()?;
+
+        assert_eq!(vm.read_register(0)?, u16::from(b'a'));
+
+        Ok(())
+    }
+
+    /// Spins on KBSR until a key arrives, then reads it from KBDR into R0
+    const WAIT_FOR_KEY: &str = "        .ORIG x3000
+POLL    LDI R1, KBSR
+        BRzp POLL
+        LDI R0, KBDR
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END
+";
+
+    /// Never has a key, counting how often it was polled
+    struct CountingInput(std::rc::Rc<std::cell::Cell<u64>>);
+
+    impl InputSource for CountingInput {
+        fn poll_byte(&mut self) -> std::io::Result<Option<u8>> {
+            self.0.set(self.0.get().saturating_add(1));
+            Ok(None)
+        }
+
+        fn read_byte(&mut self) -> std::io::Result<u8> {
+            Err(std::io::ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    #[test]
+    fn test_kbsr_polls_are_throttled() -> Result<(), VMError> {
+        for (interval, expected) in [(100, 100), (0, 5000)] {
+            let polls = std::rc::Rc::new(std::cell::Cell::new(0));
+            let mut vm = VM::new();
+            vm.set_input_source(Box::new(CountingInput(polls.clone())));
+            vm.set_poll_interval(interval);
+            vm.load_asm(WAIT_FOR_KEY)?;
+            vm.run_with_limit(10_000)?;
+
+            // Each pass of the loop reads KBSR once in two instructions
+            assert_eq!(polls.get(), expected, "interval {}", interval);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_sent_mid_run_is_seen_within_the_poll_interval() -> Result<(), VMError> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut vm = VM::new();
+        vm.set_input_source(Box::new(crate::keyboard::ChannelInput::new(receiver)));
+        vm.set_poll_interval(100);
+        vm.load_asm(WAIT_FOR_KEY)?;
+        assert_eq!(
+            vm.run_with_limit(1001)?,
+            RunOutcome::Paused(PauseReason::LimitReached { executed: 1001 })
+        );
+
+        assert!(sender.send(b'k').is_ok());
+        let sent_at = vm.instructions_executed();
+        vm.run_with_limit(1000)?;
+
+        assert_eq!(vm.read_register(0)?, u16::from(b'k'));
+        // Up to 100 instructions until the next poll, then BRzp, LDI and HALT
+        let waited = vm.instructions_executed().saturating_sub(sent_at);
+        assert!(
+            waited <= 103,
+            "the key was seen {} instructions late",
+            waited
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_and_replay_session() -> Result<(), VMError> {
+        // Waits for a key by polling KBSR, then reads a second one with GETC and echoes both
+        const ECHO_TWO: &str = "        .ORIG x3000
+POLL    LDI R1, KBSR
+        BRzp POLL
+        LDI R0, KBDR
+        OUT
+        GETC
+        OUT
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END
+";
+        let mut recorded = VM::new();
+        // NULs stand for "no key pressed" while polling
+        recorded.set_input(Box::new(std::io::Cursor::new(b"\0\0\0ab")));
+        // Every poll reaches the input, so the keys arrive at the counts below
+        recorded.set_poll_interval(0);
+        recorded.capture_output();
+        recorded.start_recording();
+        recorded.load_asm(ECHO_TWO)?;
+        recorded.run()?;
+
+        let session = recorded.take_recording().unwrap_or_default();
+        assert_eq!(
+            session.input,
+            vec![
+                SessionByte { at: 6, byte: b'a' },
+                SessionByte { at: 10, byte: b'b' }
+            ]
+        );
+        assert_eq!(session.output_bytes(), b"ab");
+
+        let mut replayed = VM::new();
+        replayed.set_input(Box::new(std::io::empty()));
+        replayed.capture_output();
+        replayed.start_recording();
+        replayed.replay(session.clone());
+        replayed.load_asm(ECHO_TWO)?;
+        replayed.run()?;
+
+        assert_eq!(replayed.take_recording(), Some(session));
+        assert_eq!(replayed.take_output(), recorded.take_output());
+        assert_eq!(
+            replayed.snapshot(),
+            recorded.snapshot(),
+            "replay ended in a different state"
+        );
+        assert_eq!(
+            replayed.instructions_executed(),
+            recorded.instructions_executed()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_out_of_input() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.replay(Session::new());
+        vm.load_asm(
+            "        .ORIG x3000
+        GETC
+        HALT
+        .END
+",
+        )?;
+
+        let error = vm.run().err();
+        assert!(matches!(
+            error.as_ref().map(VMError::root),
+            Some(VMError::TrapError(TrapError::IOError(_)))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_registers_snapshot_matches_reads() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.load_bytes(
+            include_bytes!("../examples/simple_add.obj"),
+            Endianness::Big,
+        )?;
+        vm.run()?;
+
+        let snapshot = vm.registers_snapshot();
+        for (number, value) in snapshot.r.iter().enumerate() {
+            assert_eq!(vm.read_register(number)?, *value);
+        }
+        assert_eq!(snapshot.pc, vm.pc());
+        assert_eq!(snapshot.flags, vm.condition());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coverage_reports_unreachable_arm() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_coverage(true);
+        vm.load_asm(
+            "        .ORIG x3000
+        AND R0, R0, #0
+        BRnp NEVER
+        ADD R0, R0, #1
+        HALT
+NEVER   ADD R0, R0, #2
+        HALT
+        .END
+",
+        )?;
+        vm.run()?;
+
+        let report = vm.coverage();
+        assert_eq!(report.uncovered().collect::<Vec<_>>(), vec![0x3004, 0x3005]);
+        assert_eq!(
+            report
+                .lines
+                .iter()
+                .find(|line| line.address == 0x3004)
+                .and_then(|line| line.label.clone()),
+            Some("NEVER".to_string())
+        );
+        assert_eq!(
+            report
+                .segments
+                .first()
+                .map(|segment| (segment.executed, segment.len)),
+            Some((4, 6))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_code_from_r0() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R0, STATUS
+        HALT
+STATUS  .FILL x0103
+        .END
+",
+        )?;
+
+        assert_eq!(vm.exit_code(), None);
+        vm.run()?;
+        assert_eq!(vm.exit_code(), Some(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_code_only_after_trap_halt() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_THREE)?;
+        vm.add_breakpoint(0x3005);
+
+        assert_eq!(
+            vm.run()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3005))
+        );
+        assert_eq!(vm.exit_code(), None);
+
+        Ok(())
+    }
+
+    /// MAIN calls OUTER, which calls INNER, which stops at a breakpoint on STOP.
+    /// OUTER then tail-calls TAIL with JMP, whose RET goes back to MAIN.
+    const NESTED_CALLS: &str = "        .ORIG x3000
+MAIN    JSR OUTER
+        HALT
+OUTER   ADD R5, R7, #0
+        JSR INNER
+        ADD R7, R5, #0
+        LEA R2, TAIL
+        JMP R2
+INNER   LEA R3, OUTER
+STOP    RET
+TAIL    ADD R0, R0, #1
+        RET
+        .END
+";
+
+    #[test]
+    fn test_backtrace_nested_calls() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_call_tracking(true);
+        vm.load_asm(NESTED_CALLS)?;
+        vm.add_breakpoint(0x3008);
+
+        assert_eq!(
+            vm.run()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3008))
+        );
+        let frames: Vec<String> = vm.backtrace().iter().map(Frame::to_string).collect();
+        assert_eq!(
+            frames,
+            vec![
+                "INNER (x3007) called from OUTER+1 (x3003)",
+                "OUTER (x3002) called from MAIN (x3000)",
+            ]
+        );
+
+        // RET from INNER, then the tail call to TAIL keeps OUTER's frame
+        vm.add_breakpoint(0x3009);
+        assert_eq!(
+            vm.run()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3009))
+        );
+        assert_eq!(
+            vm.backtrace()
+                .iter()
+                .map(|frame| frame.target)
+                .collect::<Vec<_>>(),
+            vec![0x3002]
+        );
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert!(vm.backtrace().is_empty());
+        assert!(vm.backtrace_is_reliable());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backtrace_survives_manual_r7() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_call_tracking(true);
+        vm.load_asm(
+            "        .ORIG x3000
+        JSR SUB
+        HALT
+SUB     LEA R7, DONE
+        RET
+DONE    HALT
+        .END
+",
+        )?;
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert!(!vm.backtrace_is_reliable());
+        assert_eq!(vm.backtrace().len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stack_overflow_on_r6_update() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_stack_bounds(Some(StackBounds::new(0xFA01, 0xFA00)));
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R6, BASE
+PUSH    ADD R6, R6, #-1
+        STR R0, R6, #0
+        BRnzp PUSH
+BASE    .FILL xFA02
+        .END
+",
+        )?;
+
+        let error = vm.run().err();
+        assert!(matches!(
+            error,
+            Some(VMError::StackViolation {
+                sp: 0xF9FF,
+                bounds: StackBounds {
+                    top: 0xFA01,
+                    bottom: 0xFA00
+                },
+                pc: 0x3001,
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stack_violation_leaves_the_vm_resumable() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_undo_depth(8);
+        vm.set_stack_bounds(Some(StackBounds::new(0xFA01, 0xFA00)));
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R6, BASE
+        LDR R0, R6, #-3
+        ADD R6, R6, #-3
+BASE    .FILL xFA02
+        .END
+",
+        )?;
+
+        vm.step()?;
+        assert!(matches!(
+            vm.step(),
+            Err(VMError::StackViolation { pc: 0x3001, .. })
+        ));
+        // The LDR didn't run, the PC still points at it
+        assert_eq!(vm.pc(), 0x3001);
+
+        vm.set_pc(0x3002);
+        assert!(matches!(
+            vm.step(),
+            Err(VMError::StackViolation {
+                sp: 0xF9FF,
+                pc: 0x3002,
+                ..
+            })
+        ));
+        assert_eq!(vm.read_register(6)?, 0xF9FF);
+        assert!(vm.step_back());
+        assert_eq!(vm.read_register(6)?, 0xFA02);
+        assert_eq!(vm.pc(), 0x3002);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stack_overflow_on_store() -> Result<(), VMError> {
+        let source = "        .ORIG x3000
+        LD R6, BASE
+        ADD R0, R0, #7
+PUSH    STR R0, R6, #-1
+        ADD R6, R6, #-1
+        BRnzp PUSH
+BASE    .FILL xFA02
+        .END
+";
+        let mut vm = VM::new();
+        vm.set_stack_bounds(Some(StackBounds::new(0xFDFF, 0xFA00)));
+        vm.load_asm(source)?;
+
+        let error = vm.run().err();
+        assert!(matches!(
+            error,
+            Some(VMError::StackViolation {
+                sp: 0xF9FF,
+                pc: 0x3002,
+                ..
+            })
+        ));
+        // The store was stopped before it wrote below the stack
+        assert_eq!(vm.memory().read_ram(0xF9FF), 0);
+        assert_eq!(vm.memory().read_ram(0xFA00), 7);
+
+        // Without bounds the same program keeps pushing
+        let mut vm = VM::new();
+        vm.load_asm(source)?;
+        assert_eq!(
+            vm.run_with_limit(100)?,
+            RunOutcome::Paused(PauseReason::LimitReached { executed: 100 })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics_count_accesses_and_stack_depth() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_halt_banner(false);
+        vm.set_deterministic(Some(Vec::new()));
+        vm.set_stack_bounds(Some(StackBounds::new(0xFDFF, 0xFA00)));
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R6, BASE
+        ADD R6, R6, #-1
+        STR R0, R6, #0
+        ADD R6, R6, #-1
+        STR R0, R6, #0
+        ADD R6, R6, #2
+        LDI R1, KBSR
+        HALT
+BASE    .FILL xFE00
+KBSR    .FILL xFE00
+        .END
+",
+        )?;
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        let metrics = vm.metrics();
+        assert_eq!(metrics.instructions, 8);
+        // LD and both halves of LDI read, the two STRs write
+        assert_eq!(metrics.memory_reads, 3);
+        assert_eq!(metrics.memory_writes, 2);
+        assert_eq!(metrics.device_accesses, 1);
+        assert_eq!(metrics.traps, 1);
+        assert_eq!(metrics.max_stack_depth, Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_back_restores_memory() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_undo_depth(2);
+        vm.load_asm(
+            "        .ORIG x3000
+        ADD R0, R0, #3
+        ST R0, VALUE
+        ADD R0, R0, #1
+        STI R0, POINTER
+        HALT
+VALUE   .FILL #9
+POINTER .FILL x3005
+        .END
+",
+        )?;
+        for _ in 0..4 {
+            vm.step()?;
+        }
+        assert_eq!(vm.memory().read_ram(0x3005), 4);
+
+        // Only the last two instructions fit in the journal
+        assert!(vm.step_back());
+        assert_eq!(vm.memory().read_ram(0x3005), 3);
+        assert!(vm.step_back());
+        assert_eq!(vm.read_register(0)?, 3);
+        assert_eq!(vm.condition(), RegisterFlags::Pos);
+        assert!(!vm.step_back());
+        assert_eq!(vm.pc(), 0x3002);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_back_stops_at_traps() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_undo_depth(8);
+        vm.load_asm(
+            "        .ORIG x3000
+        ADD R0, R0, #10
+        OUT
+        ADD R1, R1, #1
+        .END
+",
+        )?;
+        for _ in 0..3 {
+            vm.step()?;
+        }
+
+        assert!(vm.step_back());
+        assert_eq!(vm.pc(), 0x3002);
+        // The character printed by OUT can't be taken back
+        assert!(!vm.step_back());
+        assert_eq!(vm.pc(), 0x3002);
+
+        Ok(())
+    }
+
+    /// Echoes two keys polled through KBSR/KBDR, then one read with GETC
+    const ECHO_KEYS: &str = "        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #2
+POLL    LDI R2, KBSR
+        BRzp POLL
+        LDI R0, KBDR
+        OUT
+        ADD R1, R1, #-1
+        BRp POLL
+        GETC
+        OUT
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END
+";
+
+    #[test]
+    fn test_deterministic_runs_match() -> Result<(), VMError> {
+        let run = || -> Result<(Vec<u8>, RegisterSnapshot, u64), VMError> {
+            let mut vm = VM::new();
+            vm.capture_output();
+            vm.set_deterministic(Some(b"abc".to_vec()));
+            vm.load_asm(ECHO_KEYS)?;
+            vm.run()?;
+            Ok((
+                vm.take_output(),
+                vm.registers_snapshot(),
+                vm.instructions_executed(),
+            ))
+        };
+
+        let first = run()?;
+        assert_eq!(first.0, b"abc");
+        assert_eq!(run()?, first);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deterministic_rejects_unbacked_device() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_deterministic(Some(Vec::new()));
+        vm.load_asm(
+            "        .ORIG x3000
+        LDI R0, UNBACKED
+        HALT
+UNBACKED .FILL xFE08
+        .END
+",
+        )?;
+
+        let error = vm.run().err();
+        assert!(matches!(
+            error.as_ref().map(VMError::root),
+            Some(VMError::NondeterministicDevice(0xFE08))
+        ));
+
+        // GETC past the end of the script fails instead of waiting
+        let mut vm = VM::new();
+        vm.set_deterministic(Some(Vec::new()));
+        vm.load_asm(include_str!("../examples/getc.asm"))?;
+        assert!(vm.run().is_err());
+
+        Ok(())
+    }
+
+    /// Writer whose bytes stay readable after it was handed to the VM
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Keyboard that only hands out a key once the prompt is visible
+    struct AfterPrompt(SharedBuffer);
+
+    impl InputSource for AfterPrompt {
+        fn poll_byte(&mut self) -> std::io::Result<Option<u8>> {
+            Ok(None)
+        }
+
+        fn read_byte(&mut self) -> std::io::Result<u8> {
+            if *self.0 .0.borrow() == b">" {
+                Ok(b'y')
+            } else {
+                Err(std::io::Error::other("the prompt isn't visible"))
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_is_flushed_before_getc() -> Result<(), VMError> {
+        let screen = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(screen.clone()));
+        vm.set_input_source(Box::new(AfterPrompt(screen.clone())));
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R0, PROMPT
+        OUT
+        GETC
+        OUT
+        HALT
+PROMPT  .FILL x3E
+        .END
+",
+        )?;
+
+        // OUT alone leaves the prompt in the buffer
+        vm.step()?;
+        vm.step()?;
+        assert!(screen.0.borrow().is_empty());
+
+        // GETC shows it before asking for the key
+        vm.run()?;
+        assert_eq!(*screen.0.borrow(), b">y");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_out_and_puts_interleave_in_order() -> Result<(), VMError> {
+        let screen = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(screen.clone()));
+        vm.set_halt_banner(false);
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R0, OPEN
+        OUT
+        LEA R0, WORDS
+        PUTS
+        LD R0, CLOSE
+        OUT
+        LEA R0, PACKED
+        PUTSP
+        LEA R0, WORDS
+        PUTS
+        HALT
+OPEN    .FILL x5B
+CLOSE   .FILL x5D
+WORDS   .STRINGZ \"ab\"
+PACKED  .FILL x6463
+        .FILL x0065
+        .END
+",
+        )?;
+        vm.run()?;
+
+        assert_eq!(*screen.0.borrow(), b"[ab]cdeab");
+
+        Ok(())
+    }
+
+    const IN_TWICE: &str = "        .ORIG x3000
+        IN
+        IN
+        HALT
+        .END
+";
+
+    #[test]
+    fn test_in_prompt() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_input(Box::new(std::io::Cursor::new(b"ab".to_vec())));
+        vm.load_asm(IN_TWICE)?;
+        vm.run()?;
+        assert_eq!(
+            vm.take_output(),
+            b"Enter a character: a\nEnter a character: b\n"
+        );
+
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_in_prompt(Some("? ".to_string()));
+        vm.set_input(Box::new(std::io::Cursor::new(b"ab".to_vec())));
+        vm.load_asm(IN_TWICE)?;
+        vm.run()?;
+        assert_eq!(vm.take_output(), b"? a\n? b\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quiet_in_prompt_leaves_the_output() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_quiet(true);
+        vm.set_halt_banner(true);
+        vm.set_input(Box::new(std::io::Cursor::new(b"ab".to_vec())));
+        vm.load_asm(IN_TWICE)?;
+        vm.run()?;
+
+        assert_eq!(vm.take_output(), b"a\nb\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_without_prompt_only_echoes() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_in_prompt(None);
+        vm.set_input(Box::new(std::io::Cursor::new(b"ab".to_vec())));
+        vm.load_asm(IN_TWICE)?;
+        vm.run()?;
+
+        assert_eq!(vm.take_output(), b"a\nb\n");
+        assert_eq!(vm.read_register(0)?, u16::from(b'b'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_getc_echo() -> Result<(), VMError> {
+        let run = |echo: bool| -> Result<Vec<u8>, VMError> {
+            let mut vm = VM::new();
+            vm.capture_output();
+            vm.set_getc_echo(echo);
+            vm.set_input(Box::new(std::io::Cursor::new(b"ok".to_vec())));
+            vm.load_asm(
+                "        .ORIG x3000
+        GETC
+        GETC
+        HALT
+        .END
+",
+            )?;
+            vm.run()?;
+            assert_eq!(vm.read_register(0)?, u16::from(b'k'));
+            Ok(vm.take_output())
+        };
+
+        assert!(run(false)?.is_empty());
+        assert_eq!(run(true)?, b"ok");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_kbdr_echo() -> Result<(), VMError> {
+        let run = |echo: bool| -> Result<Vec<u8>, VMError> {
+            let mut vm = VM::new();
+            vm.capture_output();
+            vm.set_kbdr_echo(echo);
+            vm.set_input(Box::new(std::io::Cursor::new(b"okx".to_vec())));
+            // Two keys read through KBDR, then one through GETC, without printing them
+            vm.load_asm(&ECHO_KEYS.replace("        OUT\n", ""))?;
+            vm.run()?;
+            Ok(vm.take_output())
+        };
+
+        assert!(run(false)?.is_empty());
+        // GETC still doesn't echo
+        assert_eq!(run(true)?, b"ok");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trap_trace_lines() -> Result<(), VMError> {
+        let log = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_trap_trace(Some(Box::new(log.clone())));
+        vm.load_asm(
+            "        .ORIG x3000
+        LEA R0, TEXT
+        PUTS
+        AND R0, R0, #0
+        HALT
+TEXT    .STRINGZ \"hi\"
+        .END
+",
+        )?;
+        vm.run()?;
+
+        assert_eq!(vm.take_output(), b"hi");
+        assert_eq!(
+            String::from_utf8_lossy(&log.0.borrow()),
+            "n=2 pc=x3001 trap=PUTS r0=x3004->x3004\n\
+             n=4 pc=x3003 trap=HALT r0=x0000->x0000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_instruction_trace_lines() -> Result<(), VMError> {
+        let log = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_instruction_trace(Some(Box::new(log.clone())));
+        vm.load_asm(
+            "        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #3
+        HALT
+        .END
+",
+        )?;
+        vm.run()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&log.0.borrow()),
+            "n=1 pc=x3000 AND R1, R1, #0\n\
+             n=2 pc=x3001 ADD R1, R1, #3\n\
+             n=3 pc=x3002 HALT\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_limit_pauses_endless_loop() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm("        .ORIG x3000\nLOOP    BRnzp LOOP\n        .END\n")?;
+        vm.set_time_limit(Some(Duration::from_millis(20)));
+
+        let outcome = vm.run_program()?;
+        assert!(matches!(
+            outcome,
+            RunOutcome::Paused(PauseReason::TimeLimitReached { executed }) if executed > 0
+        ));
+        assert_eq!(vm.pc(), 0x3000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scramble_registers_is_repeatable() -> Result<(), VMError> {
+        let registers = |seed: u64| -> Result<Vec<u16>, VMError> {
+            let mut vm = VM::new();
+            vm.scramble_registers(seed);
+            (0..8).map(|r| vm.read_register(r)).collect()
+        };
+
+        assert_eq!(registers(7)?, registers(7)?);
+        assert_ne!(registers(7)?, registers(8)?);
+        assert!(registers(7)?.iter().any(|&value| value != 0));
+
+        Ok(())
+    }
+
+    const GETC_THREE_TIMES: &str = "        .ORIG x3000
+        GETC
+        ADD R1, R0, #0
+        GETC
+        ADD R2, R0, #0
+        GETC
+        ADD R3, R0, #0
+        HALT
+        .END
+";
+
+    fn run_with_eof(source: &str, policy: EofPolicy) -> Result<(VM, RunOutcome), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_input(Box::new(std::io::Cursor::new(b"ab".to_vec())));
+        vm.set_eof_policy(policy);
+        vm.load_asm(source)?;
+        let outcome = vm.run()?;
+        Ok((vm, outcome))
+    }
+
+    #[test]
+    fn test_eof_policy_error() -> Result<(), VMError> {
+        let error = run_with_eof(GETC_THREE_TIMES, EofPolicy::Error).err();
+        assert!(
+            matches!(
+                &error,
+                Some(VMError::AtInstruction { pc: 0x3004, source, .. })
+                    if matches!(**source, VMError::TrapError(TrapError::IOError(_)))
+            ),
+            "unexpected {:?}",
+            error
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eof_policy_zero() -> Result<(), VMError> {
+        let (vm, outcome) = run_with_eof(GETC_THREE_TIMES, EofPolicy::Zero)?;
+
+        assert_eq!(outcome, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(1)?, u16::from(b'a'));
+        assert_eq!(vm.read_register(2)?, u16::from(b'b'));
+        assert_eq!(vm.read_register(3)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eof_policy_halt() -> Result<(), VMError> {
+        let (vm, outcome) = run_with_eof(GETC_THREE_TIMES, EofPolicy::Halt)?;
+
+        assert_eq!(outcome, RunOutcome::Halted(HaltReason::InputExhausted));
+        assert_eq!(vm.read_register(2)?, u16::from(b'b'));
+        assert_eq!(vm.pc(), 0x3005);
+        assert_eq!(vm.exit_code(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupt_flag_stops_run() -> Result<(), VMError> {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut vm = VM::new();
+        vm.set_interrupt_flag(Some(Arc::clone(&flag)));
+        vm.load_asm(
+            "        .ORIG x3000
+LOOP    ADD R1, R1, #1
+        BRnzp LOOP
+        .END
+",
+        )?;
+
+        let setter = Arc::clone(&flag);
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            setter.store(true, Ordering::SeqCst);
+        });
+        let outcome = vm.run()?;
+        assert!(interrupter.join().is_ok());
+
+        assert_eq!(outcome, RunOutcome::Halted(HaltReason::Interrupted));
+        assert!(!flag.load(Ordering::SeqCst));
+        assert!(matches!(vm.pc(), 0x3000 | 0x3001));
+        assert!(vm.instructions_executed() > 0);
+
+        Ok(())
+    }
+
+    /// Console with keys typed in advance, raw while the flag is set
+    struct FakeConsole {
+        keys: std::collections::VecDeque<u8>,
+        raw: Arc<AtomicBool>,
+    }
+
+    impl Console for FakeConsole {
+        fn enable_raw_mode(&mut self) -> std::io::Result<()> {
+            self.raw.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn disable_raw_mode(&mut self) -> std::io::Result<()> {
+            self.raw.store(false, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn poll_key(&mut self) -> std::io::Result<Option<u8>> {
+            Ok(self.keys.pop_front())
+        }
+
+        fn read_key(&mut self) -> std::io::Result<u8> {
+            self.keys
+                .pop_front()
+                .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+        }
+
+        fn write(&mut self, _: &[u8]) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_getc_and_kbdr_read_the_console() -> Result<(), VMError> {
+        let raw = Arc::new(AtomicBool::new(false));
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_console(Box::new(FakeConsole {
+            keys: b"ab".to_vec().into(),
+            raw: Arc::clone(&raw),
+        }))?;
+        assert!(raw.load(Ordering::SeqCst));
+
+        vm.load_asm(
+            "        .ORIG x3000
+        GETC
+        ADD R1, R0, #0
+        LDI R0, KBSR_ADDR
+        LDI R2, KBDR_ADDR
+        HALT
+KBSR_ADDR .FILL xFE00
+KBDR_ADDR .FILL xFE02
+        .END
+",
+        )?;
+        vm.run()?;
+
+        assert_eq!(vm.read_register(1)?, u16::from(b'a'));
+        assert_eq!(vm.read_register(0)?, 1 << 15);
+        assert_eq!(vm.read_register(2)?, u16::from(b'b'));
+
+        vm.release_console();
+        assert!(!raw.load(Ordering::SeqCst));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eof_policy_hang() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = run_with_eof(GETC_THREE_TIMES, EofPolicy::Hang);
+            let _ = sender.send(());
+        });
+
+        assert!(receiver
+            .recv_timeout(std::time::Duration::from_millis(200))
+            .is_err());
+    }
+
+    /// Runs `source` with a keyboard nobody types into, waiting at most 20ms for a key
+    fn run_with_timeout(
+        source: &str,
+        sentinel: Option<u8>,
+    ) -> Result<(VM, RunOutcome, std::time::Duration), VMError> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_input_source(Box::new(crate::keyboard::ChannelInput::new(receiver)));
+        vm.set_input_timeout(Some(std::time::Duration::from_millis(20)));
+        vm.set_timeout_sentinel(sentinel);
+        vm.set_eof_policy(EofPolicy::Zero);
+        vm.load_asm(source)?;
+
+        let start = std::time::Instant::now();
+        let outcome = vm.run()?;
+        let elapsed = start.elapsed();
+        drop(sender);
+
+        Ok((vm, outcome, elapsed))
+    }
+
+    #[test]
+    fn test_input_timeout_follows_eof_policy() -> Result<(), VMError> {
+        let (vm, outcome, elapsed) = run_with_timeout(GETC_THREE_TIMES, None)?;
+
+        assert_eq!(outcome, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(1)?, 0);
+        assert_eq!(vm.read_register(3)?, 0);
+        assert!(elapsed < std::time::Duration::from_secs(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_timeout_sentinel() -> Result<(), VMError> {
+        let (vm, outcome, _) = run_with_timeout(GETC_THREE_TIMES, Some(0xFF))?;
+
+        assert_eq!(outcome, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(1)?, 0xFF);
+        assert_eq!(vm.read_register(2)?, 0xFF);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_timeout_kbsr_not_ready() -> Result<(), VMError> {
+        let source = "        .ORIG x3000
+        LDI R0, KBSR_ADDR
+        HALT
+KBSR_ADDR .FILL xFE00
+        .END
+";
+        let (vm, outcome, _) = run_with_timeout(source, Some(0xFF))?;
+
+        assert_eq!(outcome, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(0)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eof_policy_applies_to_kbsr() -> Result<(), VMError> {
+        let source = "        .ORIG x3000
+        LDI R1, KBDR_ADDR
+        LDI R0, KBSR_ADDR
+        LDI R1, KBDR_ADDR
+        LDI R0, KBSR_ADDR
+        LDI R2, KBDR_ADDR
+        LDI R0, KBSR_ADDR
+        LDI R3, KBDR_ADDR
+        HALT
+KBSR_ADDR .FILL xFE00
+KBDR_ADDR .FILL xFE02
+        .END
+";
+        let (vm, outcome) = run_with_eof(source, EofPolicy::Zero)?;
+        assert_eq!(outcome, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(2)?, u16::from(b'b'));
+        assert_eq!(vm.read_register(0)?, 1 << 15);
+        assert_eq!(vm.read_register(3)?, 0);
+
+        let (vm, outcome) = run_with_eof(source, EofPolicy::Halt)?;
+        assert_eq!(outcome, RunOutcome::Halted(HaltReason::InputExhausted));
+        assert_eq!(vm.pc(), 0x3006);
+
+        let error = run_with_eof(source, EofPolicy::Error).err();
+        assert!(matches!(
+            error.as_ref().map(VMError::root),
+            Some(VMError::InvalidCharacter)
+        ));
+
+        Ok(())
+    }
+
+    /// Fills every address with a nonzero word and runs PUTS on x4000
+    fn run_puts_without_terminator(max_string_length: usize) -> Option<VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_input(Box::new(std::io::repeat(b'x')));
+        vm.set_max_string_length(max_string_length);
+        for address in 0..=u16::MAX {
+            vm.memory.write_ram(address, 0x0041);
+        }
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R0, TEXT
+        PUTS
+        HALT
+TEXT    .FILL x4000
+        .END
+",
+        )
+        .ok()?;
+
+        let error = vm.run().err();
+        assert!(vm.take_output().is_empty());
+        error
+    }
+
+    #[test]
+    fn test_puts_unterminated_string() {
+        for max_string_length in [DEFAULT_MAX_STRING_LENGTH, usize::MAX] {
+            let error = run_puts_without_terminator(max_string_length);
+            assert!(
+                matches!(
+                    error.as_ref().map(VMError::root),
+                    Some(VMError::TrapError(TrapError::UnterminatedString {
+                        start: 0x4000
+                    }))
+                ),
+                "unexpected {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_string_length_limit() -> Result<(), VMError> {
+        let source = "        .ORIG x3000
+        LEA R0, TEXT
+        PUTSP
+        HALT
+TEXT    .FILL x6548
+        .FILL x6C6C
+        .FILL x006F
+        .FILL x0000
+        .END
+";
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_max_string_length(4);
+        vm.load_asm(source)?;
+        let error = vm.run().err();
+        assert!(matches!(
+            error.as_ref().map(VMError::root),
+            Some(VMError::TrapError(TrapError::UnterminatedString {
+                start: 0x3003
+            }))
+        ));
+        assert!(vm.take_output().is_empty());
+
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_max_string_length(5);
+        vm.load_asm(source)?;
+        vm.run()?;
+        assert_eq!(vm.take_output(), b"Hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_trap_vector_leaves_vm_usable() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.load_asm(
+            "        .ORIG x3000
+        TRAP x7F
+        ADD R0, R0, #4
+        HALT
+        .END
+",
+        )?;
+
+        let error = vm.run().err();
+        assert!(matches!(
+            error,
+            Some(VMError::AtInstruction { pc: 0x3000, ref source, .. })
+                if matches!(**source, VMError::TrapError(TrapError::InvalidTrapVector(0x7F)))
+        ));
+
+        // Execution continues after the faulting trap
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(0)?, 4);
+        assert!(vm.take_output().is_empty());
+
+        Ok(())
+    }
+
+    /// Calls TRAP x30 on x4000 and halts
+    const CALLS_X30: &str = "        .ORIG x3000
+        LD R0, VALUE
+        TRAP x30
+        HALT
+VALUE   .FILL x4000
+        .END
+";
+
+    #[test]
+    fn test_custom_trap_doubles_r0() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.register_trap(
+            0x30,
+            Box::new(|context| {
+                let value = context.register(0);
+                context.set_register(0, value.wrapping_mul(2));
+                Ok(())
+            }),
+        )?;
+        vm.load_asm(CALLS_X30)?;
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(0)?, 0x8000);
+        assert_eq!(vm.condition(), RegisterFlags::Neg);
+        assert_eq!(vm.read_register(7)?, 0x3003);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_trap_can_halt() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.register_trap(
+            0x30,
+            Box::new(|context| {
+                context.write_memory(0x5000, context.pc());
+                context.halt();
+                Ok(())
+            }),
+        )?;
+        vm.load_asm(CALLS_X30)?;
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.memory().read_ram(0x5000), 0x3002);
+        // Halted before the program's own HALT printed anything
+        assert!(vm.take_output().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builtin_traps_need_override() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+
+        assert!(matches!(
+            vm.register_trap(0x25, Box::new(|_| Ok(()))),
+            Err(VMError::ReservedTrap(0x25))
+        ));
+
+        vm.override_trap(
+            0x25,
+            Box::new(|_| Err(TrapError::IOError("no halting".to_string()))),
+        );
+        vm.load_asm(CALLS_X30)?;
+        vm.set_pc(0x3002);
+        assert!(matches!(
+            vm.run().err().as_ref().map(VMError::root),
+            Some(VMError::TrapError(TrapError::IOError(_)))
+        ));
+
+        assert!(vm.unregister_trap(0x25));
+        vm.set_pc(0x3002);
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_decimal_trap() -> Result<(), VMError> {
+        for (value, expected) in [
+            (0x0000, "0"),
+            (0x7FFF, "32767"),
+            (0xFFFF, "-1"),
+            (0x8000, "-32768"),
+        ] {
+            let mut vm = VM::new();
+            vm.capture_output();
+            vm.set_extension_traps(true);
+            vm.load_asm(&format!(
+                "        .ORIG x3000
+        LD R0, VALUE
+        ADD R1, R1, #1
+        TRAP x27
+        HALT
+VALUE   .FILL x{:04X}
+        .END
+",
+                value
+            ))?;
+            vm.run()?;
+
+            assert_eq!(vm.take_output(), expected.as_bytes());
+            assert_eq!(vm.read_register(0)?, value);
+            // The flags are still those of the ADD before the trap
+            assert_eq!(vm.condition(), RegisterFlags::Pos);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extension_traps_are_off_by_default() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(
+            "        .ORIG x3000
+        TRAP x27
+        .END
+",
+        )?;
+
+        assert!(matches!(
+            vm.run().err().as_ref().map(VMError::root),
+            Some(VMError::TrapError(TrapError::InvalidTrapVector(0x27)))
+        ));
+
+        Ok(())
+    }
+
+    /// Reads two lines into buffers of `size` words at x4000 and x4010
+    fn read_lines(size: u16, input: &'static [u8], policy: EofPolicy) -> Result<VM, VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_extension_traps(true);
+        vm.set_eof_policy(policy);
+        vm.set_input(Box::new(std::io::Cursor::new(input)));
+        vm.load_asm(&format!(
+            "        .ORIG x3000
+        LD R1, SIZE
+        LD R0, FIRST
+        TRAP x2A
+        ADD R2, R0, #0
+        LD R0, SECOND
+        TRAP x2A
+        ADD R3, R0, #0
+        HALT
+SIZE    .FILL #{}
+FIRST   .FILL x4000
+SECOND  .FILL x4010
+        .END
+",
+            size
+        ))?;
+        vm.run()?;
+
+        Ok(vm)
+    }
+
+    fn buffer(vm: &VM, address: u16, len: u16) -> Vec<u16> {
+        (address..address.wrapping_add(len))
+            .map(|a| vm.memory().read_ram(a))
+            .collect()
+    }
+
+    #[test]
+    fn test_read_line_trap() -> Result<(), VMError> {
+        let vm = read_lines(16, b"hello\nworld\n", EofPolicy::Error)?;
+
+        assert_eq!(vm.read_register(2)?, 5);
+        assert_eq!(vm.read_register(3)?, 5);
+        let expected: Vec<u16> = b"hello\0".iter().copied().map(u16::from).collect();
+        assert_eq!(buffer(&vm, 0x4000, 6), expected);
+        let expected: Vec<u16> = b"world\0".iter().copied().map(u16::from).collect();
+        assert_eq!(buffer(&vm, 0x4010, 6), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_line_truncates() -> Result<(), VMError> {
+        let vm = read_lines(3, b"hello\nworld\n", EofPolicy::Error)?;
+
+        assert_eq!(vm.read_register(2)?, 2);
+        assert_eq!(buffer(&vm, 0x4000, 4), vec![0x68, 0x65, 0, 0]);
+        // The rest of the first line was dropped, the second starts fresh
+        assert_eq!(buffer(&vm, 0x4010, 3), vec![0x77, 0x6F, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_line_eof() -> Result<(), VMError> {
+        // End of input ends the last line, then applies the policy
+        let vm = read_lines(16, b"hi", EofPolicy::Zero)?;
+        assert_eq!(vm.read_register(2)?, 2);
+        assert_eq!(vm.read_register(3)?, 0);
+        assert_eq!(buffer(&vm, 0x4010, 1), vec![0]);
+
+        let vm = read_lines(16, b"hi", EofPolicy::Halt)?;
+        assert_eq!(vm.state(), &VMState::Halted(HaltReason::InputExhausted));
+        assert_eq!(vm.read_register(2)?, 2);
+
+        assert!(read_lines(16, b"hi", EofPolicy::Error).is_err());
+
+        Ok(())
+    }
+
+    /// Runs PUTSP on the words after the program and returns what it printed
+    fn putsp_output(words: &str) -> Result<Vec<u8>, VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.load_asm(&format!(
+            "        .ORIG x3000
+        LEA R0, TEXT
+        PUTSP
+        HALT
+TEXT    {}
+        .FILL x5858
+        .FILL x0000
+        .END
+",
+            words
+        ))?;
+        vm.run()?;
+
+        Ok(vm.take_output())
+    }
+
+    #[test]
+    fn test_putsp_byte_order() -> Result<(), VMError> {
+        // "Hi!" is odd, its last word ends the string with a zero high byte
+        assert_eq!(putsp_output(".FILL x6948\n .FILL x0021")?, b"Hi!");
+        assert_eq!(
+            putsp_output(".FILL x6548\n .FILL x6C6C\n .FILL x006F")?,
+            b"Hello"
+        );
+        // An even-length string ends with a word of x0000
+        assert_eq!(putsp_output(".FILL x6948\n .FILL x0000")?, b"Hi");
+        assert!(putsp_output(".FILL x0000")?.is_empty());
+        // A single character: the very first word already has a zero high byte
+        assert_eq!(putsp_output(".FILL x0041")?, b"A");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_traps_are_captured() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R0, BANG
+        OUT
+        LEA R0, TEXT
+        PUTS
+        LEA R0, PACKED
+        PUTSP
+        HALT
+BANG    .FILL x21
+TEXT    .STRINGZ \"ok\"
+PACKED  .FILL x6968
+        .FILL x0021
+        .FILL x0000
+        .END
+",
+        )?;
+        vm.run()?;
+
+        assert_eq!(vm.take_output(), b"!okhi!");
+        assert!(vm.take_output().is_empty());
+
+        Ok(())
+    }
+
+    const PUTS_ESCAPES: &str = "        .ORIG x3000
+        LEA R0, TEXT
+        PUTS
+        LD R0, CONTROL
+        OUT
+        HALT
+TEXT    .FILL x1B
+        .STRINGZ \"[2Jok\\n\"
+CONTROL .FILL x01
+        .END
+";
+
+    #[test]
+    fn test_captured_output_is_not_sanitized() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.load_asm(PUTS_ESCAPES)?;
+        vm.run()?;
+
+        assert_eq!(vm.take_output(), b"\x1B[2Jok\n\x01");
+        assert_eq!(vm.sanitized_count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sanitized_output() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_sanitize(Sanitize::Hex);
+        vm.load_asm(PUTS_ESCAPES)?;
+        vm.run()?;
+
+        assert_eq!(vm.take_output(), b"\\x1B[2Jok\n\\x01");
+        assert_eq!(vm.sanitized_count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_registers() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_halt_banner(false);
+        vm.set_deterministic(Some(Vec::new()));
+        vm.load_asm(
+            "        .ORIG x3000
+WAIT    LDI R1, DSR
+        BRzp WAIT
+        LD R0, CHAR
+        STI R0, DDR
+        ST R0, SAVED
+        LD R2, SAVED
+        LEA R3, DDR
+        LDR R3, R3, #0
+        STR R0, R3, #0
+        HALT
+CHAR    .FILL x0141
+SAVED   .BLKW 1
+DSR     .FILL xFE04
+DDR     .FILL xFE06
+        .END
+",
+        )?;
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        // The high byte is dropped, STI and STR through a register both print
+        assert_eq!(vm.take_output(), b"AA");
+        assert_eq!(vm.read_register(1)?, 0x8000);
+        assert_eq!(vm.read_register(2)?, 0x0141);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_carries_faulting_instruction() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_input(Box::new(std::io::empty()));
+        // LDI reads KBSR through its pointer, polling a keyboard without input fails
+        vm.load_asm(
+            "        .ORIG x3000
+        AND R1, R1, #0
+        LDI R1, KBSR
+        HALT
+KBSR    .FILL xFE00
+        .END
+",
+        )?;
+
+        let error = vm.run().err();
+        assert!(
+            matches!(
+                &error,
+                Some(VMError::AtInstruction { pc: 0x3001, instr: 0xA201, source })
+                    if matches!(**source, VMError::InvalidCharacter)
+            ),
+            "unexpected {:?}",
+            error
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_root() {
+        let error = VMError::AtInstruction {
+            pc: 0x3000,
+            instr: 0x8000,
+            source: Box::new(VMError::UnimplemedOpcode(Opcode::Rti)),
+        };
+
+        assert!(matches!(
+            error.root(),
+            VMError::UnimplemedOpcode(Opcode::Rti)
+        ));
+    }
+
+    #[test]
+    fn test_loop_detection_self_branch() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_loop_detection(Some(100));
+        vm.load_asm(
+            "        .ORIG x3000
+        ADD R0, R0, #1
+SPIN    BRnzp SPIN
+        .END
+",
+        )?;
+
+        assert_eq!(
+            vm.run_with_limit(1000)?,
+            RunOutcome::Halted(HaltReason::LikelyInfiniteLoop { pcs: vec![0x3001] })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_detection_two_instruction_spin() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_loop_detection(Some(100));
+        vm.load_asm(
+            "        .ORIG x3000
+LOOP    AND R1, R1, #0
+        BRz LOOP
+        .END
+",
+        )?;
+
+        assert_eq!(
+            vm.run_with_limit(1000)?,
+            RunOutcome::Halted(HaltReason::LikelyInfiniteLoop {
+                pcs: vec![0x3000, 0x3001]
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_detection_ignores_polling() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_loop_detection(Some(100));
+        vm.set_input(Box::new(std::io::repeat(0)));
+        vm.load_asm(
+            "        .ORIG x3000
+POLL    LDI R0, KBSR
+        BRzp POLL
+        HALT
+KBSR    .FILL xFE00
+        .END
+",
+        )?;
+
+        assert_eq!(
+            vm.run_with_limit(1000)?,
+            RunOutcome::Paused(PauseReason::LimitReached { executed: 1000 })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_detection_ignores_counting_loop() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_loop_detection(Some(2));
+        vm.load_asm(COUNT_TO_THREE)?;
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_across_breakpoint_pause_resume_halt() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.load_asm(COUNT_TO_THREE)?;
+        let id = vm.add_breakpoint(0x3003);
+        assert_eq!(vm.state(), &VMState::Running);
+
+        assert_eq!(
+            vm.run()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3003))
+        );
+        assert_eq!(
+            vm.state(),
+            &VMState::Paused(PauseReason::Breakpoint(0x3003))
+        );
+
+        // Stepping a paused VM resumes it
+        assert!(matches!(
+            vm.step()?,
+            StepOutcome::Executed { pc: 0x3003, .. }
+        ));
+        assert_eq!(vm.state(), &VMState::Running);
+
+        vm.pause();
+        assert_eq!(vm.state(), &VMState::Paused(PauseReason::Requested));
+        vm.resume();
+        assert_eq!(vm.state(), &VMState::Running);
+
+        vm.pause();
+        assert_eq!(
+            vm.run()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3003))
+        );
+        vm.remove_breakpoint(id);
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.state(), &VMState::Halted(HaltReason::TrapHalt));
+
+        // Halting is final, unlike pausing
+        vm.pause();
+        vm.resume();
+        assert_eq!(vm.state(), &VMState::Halted(HaltReason::TrapHalt));
+        assert!(matches!(
+            vm.step(),
+            Err(VMError::Halted(HaltReason::TrapHalt))
+        ));
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_breakpoint_hit_resume_rehit() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_THREE)?;
+        vm.add_breakpoint(0x3002);
+
+        for iteration in 0..3 {
+            assert_eq!(
+                vm.run()?,
+                RunOutcome::Paused(PauseReason::Breakpoint(0x3002))
+            );
+            assert_eq!(vm.pc(), 0x3002);
+            // The instruction at the breakpoint has not run yet
+            assert_eq!(vm.read_register(0)?, iteration);
+        }
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(0)?, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_over_breakpoint() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_THREE)?;
+        vm.add_breakpoint(0x3000);
+
+        assert_eq!(vm.step()?, StepOutcome::Breakpoint(0x3000));
+        assert!(matches!(
+            vm.step()?,
+            StepOutcome::Executed { pc: 0x3000, .. }
+        ));
+        assert!(matches!(
+            vm.step()?,
+            StepOutcome::Executed { pc: 0x3001, .. }
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_and_disabled_breakpoints() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_THREE)?;
+        let first = vm.add_breakpoint(0x3001);
+        let loop_body = vm.add_breakpoint(0x3003);
+        vm.add_breakpoint(0x3005);
+        assert_eq!(vm.breakpoints().len(), 3);
+
+        assert!(vm.set_breakpoint_enabled(loop_body, false));
+        assert_eq!(
+            vm.run()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3001))
+        );
+        assert_eq!(
+            vm.run()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3005))
+        );
+        assert_eq!(vm.read_register(0)?, 3);
+
+        assert!(vm.remove_breakpoint(first));
+        assert!(!vm.remove_breakpoint(first));
+        assert_eq!(vm.breakpoints().len(), 2);
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditional_breakpoint_fires_on_requested_iteration() -> Result<(), VMError> {
+        use crate::breakpoints::{Comparison, Value};
+
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_THREE)?;
+        // Stop at the loop body only once R1 is down to 1
+        let id = vm.add_conditional_breakpoint(
+            0x3002,
+            Condition::Compare {
+                left: Value::Register(1),
+                comparison: Comparison::Eq,
+                right: Value::Constant(1),
+            },
+        );
+
+        assert_eq!(
+            vm.run()?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3002))
+        );
+        assert_eq!(vm.read_register(0)?, 2);
+        assert_eq!(
+            vm.breakpoints()
+                .iter()
+                .find(|b| b.id == id)
+                .map(|b| b.skipped),
+            Some(2)
+        );
+
+        assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+        assert_eq!(vm.read_register(0)?, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_limit_stops_at_breakpoint() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(COUNT_TO_THREE)?;
+        vm.add_breakpoint(0x3004);
+
+        assert_eq!(
+            vm.run_with_limit(100)?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3004))
+        );
+        assert_eq!(
+            vm.run_with_limit(100)?,
+            RunOutcome::Paused(PauseReason::Breakpoint(0x3004))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_restore_replays_identically() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R0, VALUE
+        ADD R0, R0, R0
+        ST R0, VALUE
+        ADD R1, R0, #-1
+        HALT
+VALUE   .FILL #21
+        .END
+",
+        )?;
+        vm.step()?;
+        vm.step()?;
+
+        let snapshot = vm.snapshot();
+        assert_eq!(snapshot.pc(), 0x3002);
+
+        vm.run()?;
+        let first = (
+            vm.read_register(0)?,
+            vm.read_register(1)?,
+            vm.memory.read_ram(0x3005),
+            vm.pc(),
+            vm.condition(),
+        );
+
+        vm.restore(&snapshot);
+        assert_eq!(vm.state(), &VMState::Running);
+        assert_eq!(vm.pc(), 0x3002);
+        assert_eq!(vm.memory.read_ram(0x3005), 21);
+
+        vm.run()?;
+        let second = (
+            vm.read_register(0)?,
+            vm.read_register(1)?,
+            vm.memory.read_ram(0x3005),
+            vm.pc(),
+            vm.condition(),
+        );
+
+        assert_eq!(first, (42, 41, 42, 0x3005, RegisterFlags::Pos));
+        assert_eq!(first, second);
+        assert!(vm.snapshot() != snapshot);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_rolls_back_the_counters() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(
+            "        .ORIG x3000
+        LD R1, COUNT
+LOOP    ST R1, LAST
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+COUNT   .FILL #50
+LAST    .FILL #0
+        .END
+",
+        )?;
+        vm.set_instruction_limit(Some(40));
+        vm.run_with_limit(10)?;
+        let snapshot = vm.snapshot();
+
+        // Wall time is the only counter that differs between the two runs
+        let run = |vm: &mut VM| -> Result<_, VMError> {
+            let outcome = vm.run_program()?;
+            let metrics = Metrics {
+                wall_time: Duration::ZERO,
+                ..vm.metrics()
+            };
+            Ok((outcome, vm.instructions_executed(), metrics))
+        };
+        let first = run(&mut vm)?;
+        vm.restore(&snapshot);
+        assert_eq!(vm.instructions_executed(), 10);
+        let second = run(&mut vm)?;
+
+        assert_eq!(
+            first.0,
+            RunOutcome::Paused(PauseReason::LimitReached { executed: 40 })
+        );
+        assert_eq!(first.1, 50);
+        assert_eq!(first, second);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recent_trace_ends_with_faulting_instruction() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(
+            "        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #1
+        STI R0, BAD
+        TRAP x99
+BAD     .FILL x4000
+        .END
+",
+        )?;
+        vm.set_trace_capacity(3);
+
+        let error = vm.run().err();
+        assert!(matches!(
+            error.as_ref().map(VMError::root),
+            Some(VMError::TrapError(TrapError::InvalidTrapVector(0x99)))
+        ));
+
+        let dump: Vec<String> = vm.recent_trace().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            dump,
+            vec![
+                "x3001: 1021  ADD R0, R0, #1",
+                "x3002: B001  STI R0, x3004",
+                "x3003: F099  TRAP x99",
+            ]
+        );
+
+        vm.set_trace_capacity(0);
+        assert!(vm.recent_trace().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyboard_registers_delegate_to_device() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.keyboard = Keyboard::new(Box::new(std::io::Cursor::new(b"z".to_vec())));
+
+        assert_eq!(vm.read_memory(MR_KBSR)?, 1 << 15);
+        assert_eq!(vm.read_memory(MR_KBDR)?, u16::from(b'z'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_bytes() -> Result<(), VMError> {
+        let mut vm = VM::new();
+
+        let program = vm.load_bytes(
+            include_bytes!("../examples/simple_add.obj"),
+            Endianness::Big,
+        )?;
+
+        assert_eq!(program, LoadedProgram::new(0x3000, 6));
+        assert_eq!(vm.read_memory(0x3000)?, 0x5020);
+        assert_eq!(vm.read_memory(0x3005)?, 0xF025);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_bytes_little_endian() -> Result<(), VMError> {
+        let big = include_bytes!("../examples/simple_add.obj");
+        let little: Vec<u8> = big
+            .chunks_exact(2)
+            .flat_map(|word| word.iter().rev().copied())
+            .collect();
+
+        let mut from_big = VM::new();
+        let mut from_little = VM::new();
+        let program = from_big.load_bytes(big, Endianness::Big)?;
+        assert_eq!(
+            from_little.load_bytes(&little, Endianness::Little)?,
+            program
+        );
+
+        for address in 0x3000..0x3006 {
+            assert_eq!(
+                from_little.memory.read_ram(address),
+                from_big.memory.read_ram(address)
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_runs_from_load_origin() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_asm(".ORIG x4000\nAND R0, R0, #0\nADD R0, R0, #7\nHALT\n.END\n")?;
+
+        assert_eq!(vm.registers.pc, 0x4000);
+        vm.run()?;
+        assert_eq!(vm.read_register(0)?, 7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_pc_overrides_load_origin() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_pc(0x4001);
+        vm.load_asm(".ORIG x4000\nADD R0, R0, #1\nADD R0, R0, #2\nHALT\n.END\n")?;
+        vm.load_bytes(&[0x30, 0x00, 0xF0, 0x25], Endianness::Big)?;
+
+        assert_eq!(vm.registers.pc, 0x4001);
+        vm.run()?;
+        assert_eq!(vm.read_register(0)?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_program_sets_pc() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_bytes(&[0x40, 0x00, 0xF0, 0x25], Endianness::Big)?;
+        vm.load_bytes(&[0x30, 0x00, 0xF0, 0x25], Endianness::Big)?;
+
+        assert_eq!(vm.registers.pc, 0x4000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loaded_segments() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_bytes(&[0x30, 0x00, 0x12, 0x34], Endianness::Big)?;
+        vm.load_bytes(&[0xFF, 0xFF, 0x56, 0x78], Endianness::Big)?;
+
+        let segments = vm.loaded_segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments.first().map(|p| p.end), Some(0x3001));
+        // An image ending at 0xFFFF has its end wrap around
+        assert_eq!(segments.get(1).map(|p| p.end), Some(0x0000));
+        assert!(segments.iter().all(|p| p.source.is_none()));
+
+        assert!(vm.is_loaded(0x3000));
+        assert!(vm.is_loaded(0xFFFF));
+        assert!(!vm.is_loaded(0x3001));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_bytes_missing_origin() {
+        let mut vm = VM::new();
+
+        assert!(matches!(
+            vm.load_bytes(&[], Endianness::Big),
+            Err(VMError::MalformedObject { len: 0, .. })
+        ));
+        assert!(matches!(
+            vm.load_bytes(&[0x30], Endianness::Big),
+            Err(VMError::MalformedObject { len: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_bytes_truncated() -> Result<(), VMError> {
+        let mut vm = VM::new();
+
+        assert!(matches!(
+            vm.load_bytes(&[0x30, 0x00, 0x50, 0x20, 0xF0], Endianness::Big),
+            Err(VMError::MalformedObject { len: 5, .. })
+        ));
+        assert_eq!(vm.read_memory(0x3000)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_bytes_origin_only() -> Result<(), VMError> {
+        let mut vm = VM::new();
+
+        let program = vm.load_bytes(&[0x30, 0x00], Endianness::Big)?;
+
+        assert_eq!(program, LoadedProgram::new(0x3000, 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_multiple_programs() -> Result<(), VMError> {
+        let mut vm = VM::new();
+
+        let program = vm.load_bytes(&[0x30, 0x00, 0x12, 0x34, 0x56, 0x78], Endianness::Big)?;
+        let data = vm.load_bytes(&[0x40, 0x00, 0xAB, 0xCD], Endianness::Big)?;
+
+        assert_eq!(program, LoadedProgram::new(0x3000, 2));
+        assert_eq!(data, LoadedProgram::new(0x4000, 1));
+
+        // Loading the second file must not reset memory written by the first
+        assert_eq!(vm.read_memory(0x3000)?, 0x1234);
+        assert_eq!(vm.read_memory(0x3001)?, 0x5678);
+        assert_eq!(vm.read_memory(0x4000)?, 0xABCD);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_overlapping_programs() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_bytes(&[0x30, 0x00, 0x12, 0x34, 0x56, 0x78], Endianness::Big)?;
+
+        // Without strict loading the overlap is only a warning and the later file wins
+        vm.load_bytes(&[0x30, 0x01, 0xAB, 0xCD], Endianness::Big)?;
+        assert_eq!(vm.read_memory(0x3001)?, 0xABCD);
+
+        vm.set_strict_load(true);
+        let result = vm.load_bytes(&[0x30, 0x00, 0x00, 0x00], Endianness::Big);
+        assert!(matches!(
+            result,
+            Err(VMError::OverlappingLoad {
+                origin: 0x3000,
+                existing: 0x3000
+            })
+        ));
+
+        // A rejected file leaves memory untouched
+        assert_eq!(vm.read_memory(0x3000)?, 0x1234);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_program_ending_at_last_address() -> Result<(), VMError> {
+        let mut vm = VM::new();
+
+        let program = vm.load_bytes(&[0xFF, 0xFE, 0x12, 0x34, 0x56, 0x78], Endianness::Big)?;
+
+        assert_eq!(program, LoadedProgram::new(0xFFFE, 2));
+        assert_eq!(vm.read_memory(0xFFFF)?, 0x5678);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_program_wrapping_past_end() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let image = [0xFF, 0xFE, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+
+        assert!(matches!(
+            vm.load_bytes(&image, Endianness::Big),
+            Err(VMError::ProgramTooLarge {
+                origin: 0xFFFE,
+                words: 3
+            })
+        ));
+        assert_eq!(vm.read_memory(0x0000)?, 0);
+
+        // The escape hatch restores the old wrapping behavior
+        vm.set_allow_wrap(true);
+        vm.load_bytes(&image, Endianness::Big)?;
+        assert_eq!(vm.read_memory(0x0000)?, 0x9ABC);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrapped_load_overlaps_low_memory() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_allow_wrap(true);
+        vm.set_strict_load(true);
+        vm.load_bytes(&[0x00, 0x01, 0x12, 0x34], Endianness::Big)?;
+
+        let result = vm.load_bytes(&[0xFF, 0xFF, 0, 0, 0, 0, 0, 0], Endianness::Big);
+        assert!(matches!(
+            result,
+            Err(VMError::OverlappingLoad {
+                origin: 0xFFFF,
+                existing: 0x0001
+            })
+        ));
+        assert_eq!(vm.read_memory(0x0001)?, 0x1234);
+
+        // A whole memory's worth of words plus one can't be loaded even wrapping
+        let image = vec![0; MEMORY_MAX.saturating_add(1).saturating_mul(2)];
+        assert!(matches!(
+            VM::new().load_bytes(&image, Endianness::Big),
+            Err(VMError::ProgramTooLarge { words: 65536, .. })
+        ));
+
+        Ok(())
+    }
+
+    /// Loads `image` like the fuzz target in `fuzz/` and checks what it loaded
+    fn check_load(image: &[u8], endianness: Endianness, allow_wrap: bool) {
+        let mut vm = VM::new();
+        vm.set_allow_wrap(allow_wrap);
+        let Ok(program) = vm.load_bytes(image, endianness) else {
+            return;
+        };
+
+        let mut words = image.chunks_exact(2).map(|pair| match *pair {
+            [first, second] => endianness.word([first, second]),
+            _ => 0,
+        });
+        assert!(image.len().is_multiple_of(2));
+        assert_eq!(words.next(), Some(program.origin));
+        let words: Vec<u16> = words.collect();
+        assert_eq!(usize::from(program.len), words.len());
+        assert_eq!(program.end, program.origin.wrapping_add(program.len));
+        assert!(
+            allow_wrap || usize::from(program.origin).saturating_add(words.len()) <= MEMORY_MAX
+        );
+        let addresses = (0..program.len).map(|i| program.origin.wrapping_add(i));
+        for (address, word) in addresses.zip(words) {
+            assert_eq!(vm.memory.read_ram(address), word);
+        }
+    }
+
+    #[test]
+    fn test_load_bytes_survives_mutated_images() {
+        let seeds: [&[u8]; 4] = [
+            include_bytes!("../examples/hello-world.obj"),
+            include_bytes!("../examples/simple_add.obj"),
+            include_bytes!("../examples/rogue.obj"),
+            &[0xFF, 0xFE, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC],
+        ];
+        // splitmix64, so a failing case reproduces
+        let mut state = 0x10AD_u64;
+        let mut next = |below: usize| {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let z = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            let z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            let z = usize::try_from((z ^ (z >> 31)) & 0xFFFF).unwrap_or_default();
+            z.checked_rem(below).unwrap_or_default()
+        };
+        let byte = |value: usize| value.to_le_bytes().first().copied().unwrap_or_default();
+
+        for seed in seeds.iter().cycle().take(2000) {
+            let mut image = seed.to_vec();
+            match next(4) {
+                0 => image.truncate(next(image.len().saturating_add(1))),
+                1 => image.push(byte(next(256))),
+                2 => {
+                    let bit = next(8);
+                    if let Some(cell) = image.get_mut(next(seed.len())) {
+                        *cell ^= 1 << bit;
+                    }
+                }
+                // A random origin, often near the top of memory
+                _ => {
+                    if let Some(origin) = image.get_mut(..2) {
+                        origin.copy_from_slice(&[0xFF ^ byte(next(4)), byte(next(256))]);
+                    }
+                }
+            }
+
+            for endianness in [Endianness::Big, Endianness::Little] {
+                check_load(&image, endianness, false);
+                check_load(&image, endianness, true);
+            }
+        }
+    }
+
+    /// Runs `words` at x3000 like the `execute` fuzz target in `fuzz/`
+    ///
+    /// The program reads from `input`, getting NULs once it runs out, prints to a
+    /// buffer and stops after 10,000 instructions. Only a panic fails the test.
+    fn fuzz_execute(words: &[u16], input: Vec<u8>, predecode: bool) {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_halt_banner(false);
+        vm.set_input_source(Box::new(ScriptedInput::new(input)));
+        vm.set_eof_policy(EofPolicy::Zero);
+        vm.set_predecode(predecode);
+        if vm.load_words(0x3000, words).is_ok() {
+            let _ = vm.run_with_limit(10_000);
+            assert!(vm.instructions_executed() <= 10_000);
+        }
+    }
+
+    /// Steps up to `n` instructions like a host that can't block: stops before
+    /// a trap that would wait for a key while no key is queued
+    fn step_host(vm: &mut VM, keys: &KeyQueue, n: usize) -> Result<usize, VMError> {
+        let mut executed = 0;
+        while executed < n
+            && !matches!(vm.state(), VMState::Halted(_))
+            && !(vm.waits_for_key() && keys.is_empty())
+        {
+            vm.step()?;
+            executed = executed.saturating_add(1);
+        }
+        vm.flush_output()?;
+
+        Ok(executed)
+    }
+
+    #[test]
+    fn test_key_queue_host_steps_until_a_key_is_needed() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_halt_banner(false);
+        let keys = KeyQueue::new();
+        vm.set_input_source(Box::new(keys.clone()));
+        vm.load_asm(".ORIG x3000\nLOOP GETC\nOUT\nADD R1, R0, #-10\nBRnp LOOP\nHALT\n.END\n")?;
+
+        assert_eq!(step_host(&mut vm, &keys, 100)?, 0);
+        assert!(vm.waits_for_key());
+
+        keys.push(b'h');
+        keys.push(b'i');
+        assert_eq!(step_host(&mut vm, &keys, 100)?, 8);
+        assert_eq!(vm.take_output(), b"hi");
+        assert_eq!(vm.pc(), 0x3000);
+
+        keys.push(b'\n');
+        step_host(&mut vm, &keys, 100)?;
+        assert_eq!(vm.take_output(), b"\n");
+        assert!(matches!(vm.state(), VMState::Halted(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_instruction_streams_never_panic() {
+        // splitmix64, so a failing stream reproduces
+        let mut state = 0xE8EC_u64;
+        let mut next = || {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let z = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            let z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            let [.., high, low] = (z ^ (z >> 31)).to_be_bytes();
+            u16::from_be_bytes([high, low])
+        };
+
+        for case in 0..1000 {
+            let len = usize::from(next() % 257);
+            // One word in eight is a trap, most of them the built-in ones
+            let words: Vec<u16> = (0..len)
+                .map(|_| match next() {
+                    word if word % 8 == 0 => 0xF020 | (word >> 3) & 0x7,
+                    word => word,
+                })
+                .collect();
+            let input = next().to_be_bytes().to_vec();
+            fuzz_execute(&words, input, case % 2 == 1);
+        }
+    }
+
+    #[test]
+    fn test_load_reader() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        let mut image = std::io::Cursor::new(include_bytes!("../examples/simple_add.obj"));
+
+        let program = vm.load_bytes(&loader::read_all(&mut image)?, Endianness::Big)?;
+
+        assert_eq!(program, LoadedProgram::new(0x3000, 6));
+        assert_eq!(vm.read_memory(0x3005)?, 0xF025);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_getc_reads_from_keyboard_input() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.set_input(Box::new(std::io::Cursor::new(b"k".to_vec())));
+
+        // TRAP x20 (GETC)
+        trap(&mut vm, 0x20)?;
+
+        assert_eq!(vm.read_register(0)?, u16::from(b'k'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_ihex() -> Result<(), VMError> {
+        let mut vm = VM::new();
+
+        let programs =
+            vm.load_ihex(":0660000050201025526043\n:0660060012631401F025F5\n:00000001FF\n")?;
+
+        assert_eq!(programs, vec![LoadedProgram::new(0x3000, 6)]);
+
+        vm.run()?;
+        assert_eq!(vm.read_register(2)?, 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_hex_text_matches_obj() -> Result<(), VMError> {
+        const LISTING: &str = "\
+; simple_add as a hex listing
+3000        ; origin
+5020        ; AND R0, R0, #0
+1025        ; ADD R0, R0, #5
+5260        ; AND R1, R1, #0
+1263        ; ADD R1, R1, #3
+
+1401        ; ADD R2, R0, R1
+F025        ; HALT
+";
+        let mut from_listing = VM::new();
+        let mut from_obj = VM::new();
+
+        let listed = from_listing.load_hex_text(LISTING)?;
+        let loaded = from_obj.load_bytes(
+            include_bytes!("../examples/simple_add.obj"),
+            Endianness::Big,
+        )?;
+        assert_eq!(listed, loaded);
+
+        for address in 0x3000..0x3006 {
+            assert_eq!(
+                from_listing.read_memory(address)?,
+                from_obj.read_memory(address)?
+            );
+        }
+
+        from_listing.run()?;
+        from_obj.run()?;
+
+        for r in 0..8 {
+            assert_eq!(from_listing.read_register(r)?, from_obj.read_register(r)?);
+        }
+        assert_eq!(
+            from_listing.registers.condition,
+            from_obj.registers.condition
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe_address_with_symbols() -> Result<(), VMError> {
+        let mut vm = VM::new();
+        vm.load_bytes(
+            include_bytes!("../examples/simple_add.obj"),
+            Endianness::Big,
+        )?;
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("START", 0x3000);
+        symbols.insert("SUM", 0x3004);
+        vm.add_symbols(symbols);
+
+        assert_eq!(vm.describe_address(0x3000), "START (x3000)");
+        assert_eq!(vm.describe_address(0x3002), "START+2 (x3002)");
+        assert_eq!(vm.describe_address(0x3005), "SUM+1 (x3005)");
+        // Past the end of the loaded program no label covers the address
+        assert_eq!(vm.describe_address(0x3006), "x3006");
+        assert_eq!(vm.describe_address(0xFFFF), "xFFFF");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_asm() -> Result<(), VMError> {
+        let mut vm = VM::new();
+
+        let program = vm.load_asm(
+            "        .ORIG x3000
+        AND R0, R0, #0
+LOOP    ADD R0, R0, #2
+        ADD R1, R0, #-6
+        BRn LOOP
+        HALT
+        .END
+",
+        )?;
+
+        assert_eq!(program, LoadedProgram::new(0x3000, 5));
+        assert_eq!(vm.describe_address(0x3003), "LOOP+2 (x3003)");
+
+        vm.run()?;
+        assert_eq!(vm.read_register(0)?, 6);
+
+        Ok(())
+    }
+}
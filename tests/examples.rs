@@ -0,0 +1,885 @@
+mod support;
+
+use lc3_vm::builder::VMBuilder;
+use lc3_vm::encode::*;
+use lc3_vm::keyboard::ScriptedInput;
+use lc3_vm::loader::{self, Endianness, LoadedProgram};
+use lc3_vm::metrics::Metrics;
+use lc3_vm::registers::{RegisterFlags, RegisterSnapshot};
+use lc3_vm::symbols::SymbolTable;
+use lc3_vm::trace::step_trace;
+use lc3_vm::{disasm, HaltReason, Opcode, RunOutcome, StepOutcome, VMError, VM};
+use support::ObjFixture;
+
+#[test]
+fn test_load_program() -> Result<(), VMError> {
+    let fixture = ObjFixture::new(0x3000)
+        .words(&[encode_lea(0, 2)?, encode_trap(0x22), encode_trap(0x25)])
+        .string("Hello World!");
+    let path = fixture.build()?;
+    // The fixture is byte for byte the example it stands for
+    assert_eq!(
+        fixture.bytes(),
+        include_bytes!("../examples/hello-world.obj")
+    );
+
+    let mut vm = VM::new();
+    let programs = vm.load_program(path.as_str(), Endianness::Big)?;
+    let mut expected = LoadedProgram::new(0x3000, 16);
+    expected.source = Some(path.path().to_path_buf());
+    assert_eq!(programs, vec![expected]);
+    assert_eq!(programs.first().map(|p| p.end), Some(0x3010));
+    assert_eq!(vm.loaded_segments(), programs.as_slice());
+
+    assert_eq!(vm.read_memory(0x3000)?, 0xE002);
+    assert_eq!(vm.read_memory(0x3003)?, u16::from(b'H'));
+    assert_eq!(vm.read_memory(0x300E)?, u16::from(b'!'));
+    assert_eq!(vm.read_memory(0x300F)?, 0);
+
+    Ok(())
+}
+
+/// Runs `source` with its output captured, so nothing reaches the real stdout
+fn captured_output(vm: &mut VM) -> Result<Vec<u8>, VMError> {
+    vm.capture_output();
+    vm.set_halt_banner(false);
+    assert_eq!(
+        vm.run_with_limit(10_000)?,
+        RunOutcome::Halted(HaltReason::TrapHalt)
+    );
+
+    Ok(vm.take_output())
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_hello_world_output() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    vm.load_program("examples/hello-world.obj", Endianness::Big)?;
+
+    // The LEA at the origin points at the string, one character per word up to a zero
+    let lea = vm.read_memory(0x3000)?;
+    assert_eq!(Opcode::from(lea >> 12), Opcode::Lea);
+    let mut address = 0x3001_u16.wrapping_add(lea & 0x1FF);
+    let mut expected = Vec::new();
+    while let Ok(character @ 1..) = vm.read_memory(address) {
+        expected.push(u8::try_from(character).unwrap());
+        address = address.wrapping_add(1);
+    }
+
+    assert_eq!(captured_output(&mut vm)?, expected);
+    assert_eq!(expected, b"Hello World!");
+
+    Ok(())
+}
+
+#[test]
+fn test_puts_edge_cases() -> Result<(), VMError> {
+    let cases: [(&str, &[u8]); 2] = [
+        ("", b""),
+        (
+            "x = 42; y = -7, z = 3.14!? (ok) #1 @home",
+            b"x = 42; y = -7, z = 3.14!? (ok) #1 @home",
+        ),
+    ];
+    for (string, expected) in cases {
+        let mut vm = VM::new();
+        vm.load_asm(&format!(
+            ".ORIG x3000\nLEA R0, TEXT\nPUTS\nHALT\nTEXT .STRINGZ \"{string}\"\n.END\n"
+        ))?;
+
+        assert_eq!(captured_output(&mut vm)?, expected, "PUTS {string:?}");
+    }
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+#[allow(clippy::as_conversions)]
+fn test_load_and_run_simple_add() -> Result<(), VMError> {
+    // Create VM and load program
+    let expected_values = [
+        0x5020, // AND R0, R0, #0
+        0x1025, // ADD R0, R0, #5
+        0x5260, // AND R1, R1, #0
+        0x1263, // ADD R1, R1, #3
+        0x1401, // ADD R2, R0, R1
+        0xF025, // TRAP x25 -> HALT
+    ];
+    let fixture = ObjFixture::new(0x3000).words(&expected_values);
+    let path = fixture.build()?;
+    assert_eq!(
+        fixture.bytes(),
+        include_bytes!("../examples/simple_add.obj")
+    );
+    let mut vm = VM::new();
+    let programs = vm.load_program(path.as_str(), Endianness::Big)?;
+    assert_eq!(
+        programs.first().map(|p| (p.origin, p.len)),
+        Some((0x3000, 6))
+    );
+
+    // Check that the loaded program is correct
+    for (i, &expected) in expected_values.iter().enumerate() {
+        let value = vm.read_memory(0x3000 + i as u16)?;
+        assert_eq!(
+            value,
+            expected,
+            "Memory[0x{:04X}] should be 0x{:04X}",
+            0x3000 + i as u16,
+            expected
+        );
+    }
+
+    // Run the program
+    vm.run()?;
+
+    // Verify final register values
+    assert_eq!(vm.read_register(0)?, 5, "R0 should contain 5");
+
+    assert_eq!(vm.read_register(1)?, 3, "R1 should contain 3");
+
+    assert_eq!(
+        vm.read_register(2)?,
+        8,
+        "R2 should contain 8 (sum of R0 and R1)"
+    );
+
+    // Verify condition flags
+    // Result was positive (8), so positive flag should be set
+    assert_eq!(
+        vm.condition(),
+        RegisterFlags::Pos,
+        "Condition flags should be set to positive after addition"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_save_program_round_trip() -> Result<(), VMError> {
+    let image = include_bytes!("../examples/hello-world.obj");
+    let path = std::env::temp_dir().join(format!("lc3-save-{}.obj", std::process::id()));
+    let path = path.to_string_lossy();
+
+    let mut vm = VM::new();
+    let program = vm.load_bytes(image, Endianness::Big)?;
+    vm.save_program(&path, program.origin..=program.end.wrapping_sub(1))?;
+
+    let saved = loader::read_source(&path)?;
+    let _ = std::fs::remove_file(path.as_ref());
+    assert_eq!(saved.as_slice(), image.as_slice());
+
+    Ok(())
+}
+
+#[test]
+fn test_disassemble_simple_add_golden() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    vm.load_bytes(
+        include_bytes!("../examples/simple_add.obj"),
+        Endianness::Big,
+    )?;
+
+    let listing = disasm::listing(vm.memory(), vm.loaded_segments(), vm.symbols());
+    assert_eq!(
+        listing.join("\n") + "\n",
+        include_str!("../examples/simple_add.lst")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_simple_add_at_reset_vector() -> Result<(), VMError> {
+    let simple_add = [0x5020, 0x1025, 0x5260, 0x1263, 0x1401, 0xF025];
+    let fixture = ObjFixture::new(0x0800).words(&simple_add);
+    let path = fixture.build()?;
+
+    let mut vm = VMBuilder::new()
+        .program(path.as_str())
+        .reset_vector(0x0800)
+        .halt_banner(false)
+        .build()?;
+    assert_eq!(vm.pc(), 0x0800);
+    assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+    assert_eq!(vm.read_register(2)?, 8);
+
+    // Reset goes back to the configured vector, not x3000
+    vm.reset();
+    assert_eq!(vm.pc(), 0x0800);
+    assert_eq!(vm.reset_vector(), 0x0800);
+    vm.load_program(path.as_str(), Endianness::Big)?;
+    assert_eq!(vm.run()?, RunOutcome::Halted(HaltReason::TrapHalt));
+    assert_eq!(vm.read_register(2)?, 8);
+
+    // An explicit vector wins over the origin of the file, skipping the first AND here
+    let vm = VMBuilder::new()
+        .program(path.as_str())
+        .reset_vector(0x0801)
+        .halt_banner(false)
+        .build()?;
+    assert_eq!(vm.pc(), 0x0801);
+
+    // Without one the program starts at its origin and reset returns to x3000
+    let mut vm = VM::new();
+    vm.load_program(path.as_str(), Endianness::Big)?;
+    assert_eq!(vm.pc(), 0x0800);
+    vm.reset();
+    assert_eq!(vm.pc(), 0x3000);
+
+    Ok(())
+}
+
+#[test]
+fn test_step_simple_add() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    vm.load_program("examples/simple_add.obj", Endianness::Big)?;
+
+    // (opcode, R0, R1, R2) after each instruction
+    let expected = [
+        (Opcode::And, 0, 0, 0),
+        (Opcode::Add, 5, 0, 0),
+        (Opcode::And, 5, 0, 0),
+        (Opcode::Add, 5, 3, 0),
+        (Opcode::Add, 5, 3, 8),
+        (Opcode::Trap, 5, 3, 8),
+    ];
+
+    for (pc, (opcode, r0, r1, r2)) in (0x3000..).zip(expected) {
+        let outcome = vm.step()?;
+        assert!(
+            matches!(&outcome, StepOutcome::Executed { pc: p, opcode: o, halted, .. }
+                if *p == pc && *o == opcode && halted.is_some() == (opcode == Opcode::Trap)),
+            "unexpected {:?} at x{:04X}",
+            outcome,
+            pc
+        );
+        assert_eq!(vm.read_register(0)?, r0);
+        assert_eq!(vm.read_register(1)?, r1);
+        assert_eq!(vm.read_register(2)?, r2);
+        assert_eq!(vm.pc(), pc + 1);
+    }
+
+    assert!(matches!(
+        vm.step(),
+        Err(VMError::Halted(HaltReason::TrapHalt))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_hello_world_finishes_within_limit() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    vm.capture_output();
+    // The banner goes to stderr, never into the program's output
+    vm.set_halt_banner(true);
+    vm.load_program("examples/hello-world.obj", Endianness::Big)?;
+
+    assert_eq!(
+        vm.run_with_limit(1_000)?,
+        RunOutcome::Halted(HaltReason::TrapHalt)
+    );
+    let output = vm.take_output();
+    assert_eq!(output, b"Hello World!");
+    assert!(!output.windows(4).any(|w| w == b"HALT"));
+
+    Ok(())
+}
+
+#[test]
+fn test_profile_simple_add() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    vm.set_profiling(true);
+    vm.load_program("examples/simple_add.obj", Endianness::Big)?;
+    vm.run()?;
+
+    let profile = vm.profile();
+    assert_eq!(profile.count(Opcode::Add), 3);
+    assert_eq!(profile.count(Opcode::And), 2);
+    assert_eq!(profile.count(Opcode::Trap), 1);
+    assert_eq!(profile.count(Opcode::Br), 0);
+    assert_eq!(profile.trap_count(0x25), 1);
+    assert_eq!(profile.total(), 6);
+
+    Ok(())
+}
+
+#[test]
+fn test_metrics_simple_add() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    vm.set_halt_banner(false);
+    vm.load_program("examples/simple_add.obj", Endianness::Big)?;
+    vm.run()?;
+
+    let metrics = vm.metrics();
+    assert_eq!(metrics.instructions, 6);
+    assert_eq!(metrics.traps, 1);
+    assert_eq!(metrics.memory_reads, 0);
+    assert_eq!(metrics.memory_writes, 0);
+    assert_eq!(metrics.device_accesses, 0);
+    assert_eq!(metrics.max_stack_depth, None);
+    assert!(metrics.instructions_per_second() > 0.0);
+
+    vm.reset();
+    assert_eq!(vm.metrics(), Metrics::default());
+
+    Ok(())
+}
+
+#[test]
+fn test_step_back_simple_add() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    vm.set_undo_depth(16);
+    vm.load_program("examples/simple_add.obj", Endianness::Big)?;
+
+    let mut states = vec![vm.registers_snapshot()];
+    for _ in 0..5 {
+        vm.step()?;
+        states.push(vm.registers_snapshot());
+    }
+
+    for expected in states.iter().rev().skip(1).take(3) {
+        assert!(vm.step_back());
+        assert_eq!(vm.registers_snapshot(), *expected);
+    }
+    assert_eq!(vm.pc(), 0x3002);
+    assert_eq!(vm.instructions_executed(), 2);
+
+    for expected in states.iter().skip(3) {
+        vm.step()?;
+        assert_eq!(vm.registers_snapshot(), *expected);
+    }
+    assert_eq!(vm.read_register(2)?, 8);
+
+    Ok(())
+}
+
+#[test]
+fn test_trap_trace_goes_to_stderr_only_when_asked() -> Result<(), std::io::Error> {
+    let run = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_LC3-VM"))
+            .args(["--deterministic", "/dev/null"])
+            .args(extra)
+            .arg("examples/hello-world.obj")
+            .output()
+    };
+
+    let quiet = run(&[])?;
+    assert_eq!(quiet.stdout, b"Hello World!");
+    assert!(quiet.stderr.is_empty());
+
+    let traced = run(&["--trace-traps"])?;
+    assert_eq!(traced.stdout, b"Hello World!");
+    assert_eq!(
+        String::from_utf8_lossy(&traced.stderr),
+        "n=2 pc=x3001 trap=PUTS r0=x3003->x3003\nn=3 pc=x3002 trap=HALT r0=x3003->x3003\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_piped_stdin_needs_no_terminal() -> Result<(), std::io::Error> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_LC3-VM"))
+        .arg("examples/echo.asm")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(b"ab\n")?;
+    }
+    let output = child.wait_with_output()?;
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"ab");
+    assert!(output.stderr.is_empty());
+
+    Ok(())
+}
+
+/// Runs the binary with `args`, returning its exit status, stdout and stderr
+fn lc3_vm(args: &[&str]) -> Result<(Option<i32>, String, String), std::io::Error> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_LC3-VM"))
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .output()?;
+
+    Ok((
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    ))
+}
+
+#[test]
+fn test_run_subcommand_and_default() -> Result<(), std::io::Error> {
+    let (status, stdout, _) = lc3_vm(&["run", "--quiet", "examples/hello-world.obj"])?;
+    assert_eq!(status, Some(0));
+    assert_eq!(stdout, "Hello World!");
+
+    let (status, stdout, _) = lc3_vm(&["--quiet", "examples/hello-world.obj"])?;
+    assert_eq!(status, Some(0));
+    assert_eq!(stdout, "Hello World!");
+
+    let (status, _, stderr) = lc3_vm(&["run", "--break", "x3002", "examples/simple_add.obj"])?;
+    assert_eq!(status, Some(5));
+    assert!(stderr.starts_with("Breakpoint at x3002\n"), "{}", stderr);
+
+    Ok(())
+}
+
+#[test]
+fn test_asm_and_disasm_subcommands() -> Result<(), std::io::Error> {
+    let obj = std::env::temp_dir().join(format!("lc3-vm-echo-{}.obj", std::process::id()));
+    let obj_path = obj.to_string_lossy().into_owned();
+
+    let (status, _, stderr) = lc3_vm(&["asm", "examples/echo.asm", "-o", &obj_path])?;
+    assert_eq!(status, Some(0), "{}", stderr);
+
+    let (status, stdout, _) = lc3_vm(&["disasm", &obj_path])?;
+    std::fs::remove_file(&obj)?;
+    assert_eq!(status, Some(0));
+    assert!(
+        stdout.starts_with(".ORIG x3000\nx3000: F020  GETC\n"),
+        "{}",
+        stdout
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_inspect_subcommand() -> Result<(), std::io::Error> {
+    let (status, stdout, _) = lc3_vm(&["inspect", "examples/hello-world.obj"])?;
+
+    assert_eq!(status, Some(0));
+    assert_eq!(
+        stdout,
+        "examples/hello-world.obj\n  format    obj\n  size      34 bytes\n  segment   x3000-x300F (16 words)\n  symbols   0\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_debug_subcommand_needs_a_terminal() -> Result<(), std::io::Error> {
+    let (status, _, stderr) = lc3_vm(&["debug", "--tui", "examples/simple_add.obj"])?;
+
+    assert_eq!(status, Some(1));
+    assert_eq!(
+        stderr,
+        "The debugger needs a terminal on stdin and stdout\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_subcommand_help_and_errors() -> Result<(), std::io::Error> {
+    let (status, stdout, _) = lc3_vm(&["--help"])?;
+    assert_eq!(status, Some(0));
+    assert!(stdout.contains("inspect   Print what program files contain"));
+
+    let (status, stdout, _) = lc3_vm(&["disasm", "--help"])?;
+    assert_eq!(status, Some(0));
+    assert!(stdout.starts_with("Usage: ./lc3-vm disasm"));
+
+    let (status, _, stderr) = lc3_vm(&["disasm", "--limit", "5", "examples/simple_add.obj"])?;
+    assert_eq!(status, Some(1));
+    assert!(stderr.starts_with("Unknown option --limit\n"), "{}", stderr);
+
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json_report_for_hello_world() -> Result<(), Box<dyn std::error::Error>> {
+    let (status, stdout, stderr) = lc3_vm(&["--json", "examples/hello-world.obj"])?;
+    assert_eq!(status, Some(0));
+    // Without --capture-output the program prints to stderr, leaving stdout to the report
+    assert_eq!(stderr, "Hello World!");
+
+    let report: serde_json::Value = serde_json::from_str(&stdout)?;
+    let field = |path: &str| report.pointer(path).cloned().unwrap_or_default();
+    assert_eq!(field("/outcome"), "halted");
+    assert_eq!(field("/reason"), "trap_halt");
+    assert_eq!(field("/exit_status"), 0);
+    assert_eq!(field("/instructions"), 3);
+    assert!(field("/wall_time_ms").is_u64());
+    assert_eq!(field("/registers/pc"), 0x3003);
+    assert_eq!(field("/registers/flags"), "Pos");
+    assert_eq!(field("/segments/0/origin"), 0x3000);
+    assert_eq!(field("/segments/0/len"), 16);
+    assert!(field("/output").is_null());
+
+    let (status, stdout, stderr) = lc3_vm(&[
+        "--json",
+        "--capture-output",
+        "--limit",
+        "1",
+        "examples/hello-world.obj",
+    ])?;
+    assert_eq!(status, Some(124));
+    let report: serde_json::Value = serde_json::from_str(&stdout)?;
+    assert_eq!(
+        report.pointer("/reason").cloned().unwrap_or_default(),
+        "limit_reached"
+    );
+    assert_eq!(
+        report.pointer("/exit_status").cloned().unwrap_or_default(),
+        124
+    );
+    assert!(
+        stderr.starts_with("Instruction limit reached"),
+        "{}",
+        stderr
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_expected_output() -> Result<(), std::io::Error> {
+    let expect = |expected: &str, extra: &[&str]| {
+        let mut args = vec![
+            "run",
+            "tests/fixtures/greet.asm",
+            "--stdin-file",
+            "tests/fixtures/greet.input",
+            "--expect-output",
+            expected,
+        ];
+        args.extend_from_slice(extra);
+        lc3_vm(&args)
+    };
+
+    let (status, stdout, _) = expect("tests/fixtures/greet.expected", &[])?;
+    assert_eq!(status, Some(0));
+    assert_eq!(stdout, "");
+
+    let (status, stdout, _) = expect("tests/fixtures/greet-mismatch.expected", &[])?;
+    assert_eq!(status, Some(1));
+    assert_eq!(
+        stdout,
+        "--- tests/fixtures/greet-mismatch.expected\n+++ output\n\
+         @@ -1,1 +1,1 @@\n-Hello, Bob!\n+Hello, Ada!\n"
+    );
+
+    // The expectation lacks the final newline the program prints
+    let (status, _, _) = expect("tests/fixtures/greet-no-newline.expected", &[])?;
+    assert_eq!(status, Some(0));
+    let (status, stdout, _) = expect(
+        "tests/fixtures/greet-no-newline.expected",
+        &["--trailing-newlines", "strict"],
+    )?;
+    assert_eq!(status, Some(1));
+    assert!(
+        stdout.contains("\\ No newline at end of file"),
+        "{}",
+        stdout
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_quiet_stdout_carries_only_the_program_output() -> Result<(), std::io::Error> {
+    let ask = |extra: &[&str]| {
+        let mut args = vec![
+            "run",
+            "tests/fixtures/ask.asm",
+            "--stdin-file",
+            "tests/fixtures/ask.input",
+        ];
+        args.extend_from_slice(extra);
+        lc3_vm(&args)
+    };
+
+    let (status, stdout, _) = ask(&[])?;
+    assert_eq!(status, Some(0));
+    assert_eq!(stdout, "Enter a character: x\ndone\n");
+
+    let (status, stdout, stderr) = ask(&["--quiet", "--profile", "--dump-regs"])?;
+    assert_eq!(status, Some(0));
+    assert_eq!(stdout.as_bytes(), b"x\ndone\n");
+    assert!(stderr.starts_with("Enter a character: "), "{}", stderr);
+
+    // The diff of a mismatch stays out of stdout too
+    let (status, stdout, stderr) = ask(&[
+        "--quiet",
+        "--expect-output",
+        "tests/fixtures/greet.expected",
+    ])?;
+    assert_eq!(status, Some(1));
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("+done"), "{}", stderr);
+
+    Ok(())
+}
+
+#[test]
+fn test_debugger_script_transcript() -> Result<(), std::io::Error> {
+    let (status, stdout, stderr) = lc3_vm(&[
+        "debug",
+        "--script",
+        "tests/fixtures/simple_add.debug",
+        "examples/simple_add.obj",
+    ])?;
+
+    assert_eq!(status, Some(0), "{}", stderr);
+    assert_eq!(
+        stdout,
+        std::fs::read_to_string("tests/fixtures/simple_add.transcript")?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_debugger_script_stops_at_the_first_failing_command() -> Result<(), std::io::Error> {
+    let script = std::env::temp_dir().join("lc3_vm_failing.debug");
+    std::fs::write(&script, "step 2\nstep five\nregs\n")?;
+    let script = script.to_string_lossy().into_owned();
+
+    let (status, stdout, stderr) =
+        lc3_vm(&["debug", "--script", &script, "examples/simple_add.obj"])?;
+
+    assert_eq!(status, Some(1));
+    assert_eq!(
+        stdout,
+        "(lc3) step 2\nx3002: AND R1, R1, #0\n(lc3) step five\n"
+    );
+    assert_eq!(
+        stderr,
+        "Error on line 2: step expects a number of instructions, got \"five\"\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_batch_summary() -> Result<(), std::io::Error> {
+    let dir = std::env::temp_dir().join(format!("lc3_vm_batch_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("more"))?;
+    std::fs::copy("examples/hello-world.obj", dir.join("hello.obj"))?;
+    std::fs::write(dir.join("hello.expect"), "Hello World!\n")?;
+    // TRAP x99 has no handler
+    std::fs::write(dir.join("more").join("bad.obj"), [0x30, 0x00, 0xF0, 0x99])?;
+    let path = dir.to_string_lossy().into_owned();
+
+    let (status, stdout, _) = lc3_vm(&["batch", &path, "--limit", "1M", "--timeout", "2s"])?;
+    std::fs::remove_dir_all(&dir)?;
+
+    assert_eq!(status, Some(1));
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", stdout);
+    assert!(lines
+        .first()
+        .is_some_and(|line| line.starts_with("PROGRAM")));
+    assert!(
+        lines.get(1).is_some_and(|line| line.contains("hello.obj")
+            && line.contains(" halted ")
+            && line.ends_with(" 3")),
+        "{}",
+        stdout
+    );
+    assert!(
+        lines.get(2).is_some_and(|line| line.contains("bad.obj")
+            && line.contains(" error ")
+            && line.ends_with("invalid trap vector 0x0099")),
+        "{}",
+        stdout
+    );
+    assert_eq!(
+        lines.get(3),
+        Some(&"2 programs: 1 halted, 0 stopped by a limit, 1 failed")
+    );
+
+    Ok(())
+}
+
+/// Ends the uppercasing echo programs: the HALT, then -'q' and 'A' - 'a'
+fn upcase_tail(fixture: ObjFixture) -> ObjFixture {
+    fixture
+        .label("DONE")
+        .words(&[encode_trap(0x25)])
+        .label("NEG_Q")
+        .words(&[0xFF8F])
+        .label("TO_UPPER")
+        .words(&[0xFFE0])
+}
+
+/// Echoes each key read with GETC uppercased, until it reads a 'q'
+fn upcase_getc() -> Result<ObjFixture, VMError> {
+    Ok(upcase_tail(
+        ObjFixture::new(0x3000)
+            .words(&[encode_ld(1, 8)?, encode_ld(2, 8)?])
+            .label("LOOP")
+            .words(&[
+                encode_trap(0x20),
+                encode_add_reg(3, 0, 1)?,
+                encode_br(false, true, false, 3)?,
+                encode_add_reg(0, 0, 2)?,
+                encode_trap(0x21),
+                encode_br(true, true, true, -6)?,
+            ]),
+    ))
+}
+
+/// Same as [`upcase_getc`], but waits on KBSR and reads KBDR instead of calling GETC
+fn upcase_poll() -> Result<ObjFixture, VMError> {
+    Ok(upcase_tail(
+        ObjFixture::new(0x3000)
+            .words(&[encode_ld(1, 10)?, encode_ld(2, 10)?])
+            .label("LOOP")
+            .words(&[
+                encode_ldi(3, 10)?,
+                encode_br(false, true, true, -2)?,
+                encode_ldi(0, 9)?,
+                encode_add_reg(3, 0, 1)?,
+                encode_br(false, true, false, 3)?,
+                encode_add_reg(0, 0, 2)?,
+                encode_trap(0x21),
+                encode_br(true, true, true, -8)?,
+            ]),
+    )
+    .label("KBSR")
+    .words(&[0xFE00])
+    .label("KBDR")
+    .words(&[0xFE02]))
+}
+
+/// Runs one of the uppercasing echo programs on `abcq`, returning its output and final registers
+fn upcase(fixture: &ObjFixture, predecode: bool) -> Result<(Vec<u8>, RegisterSnapshot), VMError> {
+    let path = fixture.build()?;
+    let mut vm = VM::new();
+    vm.capture_output();
+    vm.set_halt_banner(false);
+    vm.set_predecode(predecode);
+    vm.set_input_source(Box::new(ScriptedInput::new(b"abcq".to_vec())));
+    vm.load_program(path.as_str(), Endianness::Big)?;
+    vm.add_symbols(SymbolTable::load(
+        path.sym_path().to_str().unwrap_or_default(),
+    )?);
+    assert_eq!(vm.symbols().label_at(0x3002), Some("LOOP"));
+
+    assert_eq!(
+        vm.run_with_limit(10_000)?,
+        RunOutcome::Halted(HaltReason::TrapHalt)
+    );
+
+    Ok((vm.take_output(), vm.registers_snapshot()))
+}
+
+#[test]
+fn test_getc_echo_transcript() -> Result<(), VMError> {
+    for predecode in [false, true] {
+        let (output, registers) = upcase(&upcase_getc()?, predecode)?;
+
+        assert_eq!(output, b"ABC");
+        // R0 holds the 'q' that ended the loop, R7 the return address of HALT
+        assert_eq!(
+            registers.r,
+            [0x0071, 0xFF8F, 0xFFE0, 0x0000, 0, 0, 0, 0x3009]
+        );
+        assert_eq!(registers.flags, RegisterFlags::Zro);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_kbsr_polling_transcript() -> Result<(), VMError> {
+    for predecode in [false, true] {
+        let (output, registers) = upcase(&upcase_poll()?, predecode)?;
+
+        assert_eq!(output, b"ABC");
+        // R3 ends at zero from the 'q' check, not with the last KBSR value
+        assert_eq!(
+            registers.r,
+            [0x0071, 0xFF8F, 0xFFE0, 0x0000, 0, 0, 0, 0x300B]
+        );
+        assert_eq!(registers.flags, RegisterFlags::Zro);
+    }
+
+    Ok(())
+}
+
+/// Steps through `tests/fixtures/opcodes.asm`, one line per instruction with the registers after it
+fn opcodes_trace(predecode: bool) -> Result<String, VMError> {
+    let mut vm = VM::new();
+    vm.capture_output();
+    vm.set_halt_banner(false);
+    vm.set_predecode(predecode);
+    vm.load_asm(include_str!("fixtures/opcodes.asm"))?;
+
+    let trace = step_trace(&mut vm)?;
+    assert_eq!(vm.take_output(), b"Aok");
+
+    Ok(trace)
+}
+
+#[test]
+fn test_opcodes_golden_trace() -> Result<(), VMError> {
+    let golden = include_str!("fixtures/opcodes.trace");
+
+    assert_eq!(opcodes_trace(false)?, golden);
+    assert_eq!(opcodes_trace(true)?, golden);
+
+    Ok(())
+}
+
+/// Runs `tests/golden/{name}.obj` and checks its trace against the recorded `{name}.trace`,
+/// failing on the first instruction where the two disagree
+fn check_golden(name: &str, predecode: bool) -> Result<Vec<u8>, VMError> {
+    let mut vm = VM::new();
+    vm.capture_output();
+    vm.set_halt_banner(false);
+    vm.set_predecode(predecode);
+    let input = std::fs::read(format!("tests/golden/{name}.input")).unwrap_or_default();
+    vm.set_input_source(Box::new(ScriptedInput::new(input)));
+    vm.load_program(&format!("tests/golden/{name}.obj"), Endianness::Big)?;
+
+    let actual = step_trace(&mut vm)?;
+    let golden = std::fs::read_to_string(format!("tests/golden/{name}.trace"))
+        .map_err(|e| VMError::OpenFileFailed(e.to_string()))?;
+    let mut expected_lines = golden.lines();
+    for (step, line) in actual.lines().enumerate() {
+        let expected = expected_lines.next();
+        assert!(
+            expected == Some(line),
+            "{name}: diverged at step {step}\n  snapshot: {}\n  vm:       {line}",
+            expected.unwrap_or("<end of trace>")
+        );
+    }
+    let rest = expected_lines.next();
+    assert!(
+        rest.is_none(),
+        "{name}: halted early, the snapshot continues with\n  {}",
+        rest.unwrap_or_default()
+    );
+
+    Ok(vm.take_output())
+}
+
+#[test]
+fn test_golden_traces() -> Result<(), VMError> {
+    for predecode in [false, true] {
+        for name in ["arithmetic", "memory", "branches", "subroutines"] {
+            assert_eq!(check_golden(name, predecode)?, b"");
+        }
+        assert_eq!(
+            check_golden("traps", predecode)?,
+            b"hi ab\nok\nblank line\n"
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! The C interface, called through its `extern "C"` functions as C code would
+#![cfg(feature = "ffi")]
+
+use std::ffi::{c_void, CStr};
+
+use lc3_vm::ffi::*;
+
+/// Appends the output to the `Vec<u8>` behind `userdata`
+extern "C" fn collect(bytes: *const u8, len: usize, userdata: *mut c_void) {
+    // SAFETY: the tests register a live Vec<u8> and the VM passes `len` bytes at `bytes`
+    unsafe {
+        let output = &mut *userdata.cast::<Vec<u8>>();
+        output.extend_from_slice(std::slice::from_raw_parts(bytes, len));
+    }
+}
+
+fn last_error() -> String {
+    let message = lc3_vm_last_error_message();
+    assert!(!message.is_null());
+    // SAFETY: a non-NULL message is a NUL-terminated string valid until the next failure
+    unsafe { CStr::from_ptr(message) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn test_ffi_runs_hello_world() {
+    let image = include_bytes!("../examples/hello-world.obj");
+    let mut output: Vec<u8> = Vec::new();
+
+    let vm = lc3_vm_new();
+    assert!(!vm.is_null());
+    // SAFETY: `vm` is live until lc3_vm_free, `output` outlives it
+    unsafe {
+        let userdata = std::ptr::from_mut(&mut output).cast::<c_void>();
+        assert_eq!(
+            lc3_vm_set_output_callback(vm, Some(collect), userdata),
+            LC3_OK
+        );
+        assert_eq!(lc3_vm_load_bytes(vm, image.as_ptr(), image.len()), LC3_OK);
+
+        let mut value = 0;
+        assert_eq!(lc3_vm_get_register(vm, LC3_REG_PC, &mut value), LC3_OK);
+        assert_eq!(value, 0x3000);
+        assert_eq!(lc3_vm_read_mem(vm, 0x3003, &mut value), LC3_OK);
+        assert_eq!(value, u16::from(b'H'));
+
+        // LEA R0, then PUTS
+        assert_eq!(lc3_vm_step(vm), LC3_OK);
+        assert_eq!(lc3_vm_get_register(vm, 0, &mut value), LC3_OK);
+        assert_eq!(value, 0x3003);
+        assert_eq!(lc3_vm_run(vm, 1), LC3_LIMIT_REACHED);
+        assert_eq!(lc3_vm_run(vm, 0), LC3_HALTED);
+
+        assert_eq!(lc3_vm_step(vm), LC3_ERR_HALTED);
+        lc3_vm_free(vm);
+    }
+
+    assert_eq!(output, b"Hello World!");
+}
+
+#[test]
+fn test_ffi_steps_to_halt() {
+    let image = include_bytes!("../examples/simple_add.obj");
+
+    let vm = lc3_vm_new();
+    // SAFETY: `vm` is live until lc3_vm_free
+    unsafe {
+        assert_eq!(lc3_vm_load_bytes(vm, image.as_ptr(), image.len()), LC3_OK);
+        let mut steps = 0;
+        while lc3_vm_step(vm) == LC3_OK {
+            steps += 1;
+        }
+        assert_eq!(steps, 5);
+
+        let mut r2 = 0;
+        assert_eq!(lc3_vm_get_register(vm, 2, &mut r2), LC3_OK);
+        assert_eq!(r2, 8);
+        lc3_vm_free(vm);
+    }
+}
+
+#[test]
+fn test_ffi_reports_errors() {
+    let vm = lc3_vm_new();
+    let mut value = 0;
+    // SAFETY: `vm` is live until lc3_vm_free, the NULL arguments are checked
+    unsafe {
+        assert_eq!(lc3_vm_get_register(vm, 9, &mut value), LC3_ERR_REGISTER);
+        assert_eq!(last_error(), "invalid register");
+
+        assert_eq!(lc3_vm_load_bytes(vm, [0x30].as_ptr(), 1), LC3_ERR_LOAD);
+        assert!(last_error().starts_with("malformed object file of 1 bytes"));
+
+        assert_eq!(lc3_vm_load_bytes(vm, std::ptr::null(), 0), LC3_ERR_NULL);
+        assert_eq!(last_error(), "the image is NULL");
+        assert_eq!(lc3_vm_step(std::ptr::null_mut()), LC3_ERR_NULL);
+        assert_eq!(
+            lc3_vm_read_mem(vm, 0x3000, std::ptr::null_mut()),
+            LC3_ERR_NULL
+        );
+
+        // A reserved opcode
+        let image = [0x30, 0x00, 0xD0, 0x00];
+        assert_eq!(lc3_vm_load_bytes(vm, image.as_ptr(), image.len()), LC3_OK);
+        assert_eq!(lc3_vm_step(vm), LC3_ERR_EXECUTION);
+        assert_eq!(
+            last_error(),
+            "at x3000 (.FILL xD000): unimplemented opcode Res"
+        );
+
+        lc3_vm_free(vm);
+        lc3_vm_free(std::ptr::null_mut());
+    }
+}
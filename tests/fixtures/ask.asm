@@ -0,0 +1,8 @@
+; Reads a character with IN, then says it's done
+        .ORIG x3000
+        IN
+        LEA R0, DONE
+        PUTS
+        HALT
+DONE    .STRINGZ "done\n"
+        .END
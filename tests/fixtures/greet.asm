@@ -0,0 +1,15 @@
+; Reads a name up to the end of the line and greets it
+        .ORIG x3000
+        LEA R0, HELLO
+        PUTS
+LOOP    GETC
+        ADD R1, R0, #-10        ; the newline ends the name
+        BRz DONE
+        OUT
+        BRnzp LOOP
+DONE    LEA R0, BANG
+        PUTS
+        HALT
+HELLO   .STRINGZ "Hello, "
+BANG    .STRINGZ "!\n"
+        .END
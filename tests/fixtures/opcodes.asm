@@ -0,0 +1,43 @@
+; Runs every implemented opcode, see test_opcodes_golden_trace
+        .ORIG x3000
+        LEA R6, STACK
+        AND R0, R0, #0
+        ADD R0, R0, #-3
+        ADD R1, R0, R0
+        AND R2, R1, R0
+        NOT R3, R2
+        LD R4, VALUE
+        LDI R5, POINTER
+        ST R4, SLOT
+        STI R0, POINTER
+        STR R1, R6, #1
+        LDR R2, R6, #1
+        JSR SUB
+        LEA R1, SUB2
+        JSRR R1
+        LEA R1, NEXT
+        JMP R1
+        ADD R0, R0, #15
+NEXT    ADD R0, R0, #0
+        BRn NEG
+        HALT
+NEG     BRzp NEXT
+        BRnp AFTER
+        HALT
+AFTER   LD R0, CHAR
+        OUT
+        LEA R0, TEXT
+        PUTS
+        HALT
+SUB     ADD R4, R4, #1
+        RET
+SUB2    ADD R3, R3, #1
+        RET
+VALUE   .FILL x7FFF
+POINTER .FILL DATA
+SLOT    .BLKW 1
+CHAR    .FILL x41
+TEXT    .STRINGZ "ok"
+DATA    .FILL x8000
+STACK   .BLKW 4
+        .END
@@ -0,0 +1,29 @@
+; ADD, AND and NOT on both operand forms, with each flag outcome
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R1, R0, #15
+        ADD R2, R0, #-16
+        ADD R3, R1, R2
+        ADD R4, R3, R3
+        NOT R5, R4
+        ADD R5, R5, #1
+        AND R6, R1, #10
+        AND R7, R4, R5
+        ADD R0, R1, R1
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        ADD R0, R0, R0
+        NOT R1, R0
+        AND R2, R2, #0
+        NOT R3, R2
+        ADD R3, R3, #1
+        HALT
+        .END
@@ -0,0 +1,28 @@
+; Every BR condition taken and not taken, backward and forward, and JMP
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R1, R0, #3
+        BRz SKIP1
+        BRnp NEXT1
+SKIP1   ADD R0, R0, #15
+NEXT1   ADD R1, R1, #-1
+        BRp NEXT1
+        BRn SKIP1
+        BRzp NEXT2
+        ADD R0, R0, #15
+NEXT2   ADD R2, R1, #-5
+        BRzp SKIP1
+        BRn NEXT3
+        ADD R0, R0, #15
+NEXT3   BRnzp NEXT4
+        ADD R0, R0, #15
+NEXT4   NOT R3, R2
+        BRp NEXT5
+        ADD R0, R0, #15
+NEXT5   LEA R4, DONE
+        JMP R4
+        ADD R0, R0, #15
+DONE    BR FINISH
+        ADD R0, R0, #15
+FINISH  HALT
+        .END
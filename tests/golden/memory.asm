@@ -0,0 +1,29 @@
+; LD, LDI, LDR, LEA, ST, STI and STR, including a copy loop through pointers
+        .ORIG x3000
+        LEA R1, SOURCE
+        LD R2, DEST
+        LD R3, COUNT
+COPY    LDR R4, R1, #0
+        STR R4, R2, #0
+        ADD R1, R1, #1
+        ADD R2, R2, #1
+        ADD R3, R3, #-1
+        BRp COPY
+        LDI R5, DEST
+        NOT R5, R5
+        STI R5, DEST
+        LDI R6, DEST
+        LD R0, SOURCE
+        ST R6, SOURCE
+        LD R0, SOURCE
+        LDR R7, R2, #-1
+        STR R7, R2, #5
+        LDR R7, R2, #5
+        HALT
+DEST    .FILL x4000
+COUNT   .FILL #4
+SOURCE  .FILL x0001
+        .FILL x8000
+        .FILL x0000
+        .FILL x7FFF
+        .END
@@ -0,0 +1,134 @@
+#!/usr/bin/env python3
+"""LC-3 model in Python writing the trace snapshots of tests/golden/
+
+    python3 tests/golden/reference.py tests/golden/NAME.obj > tests/golden/NAME.trace
+
+Runs NAME.obj, reading the keyboard from NAME.input next to it if there is
+one, and prints a line per instruction: its address and word, then R0-R7 and
+the condition flags after it, a trap counting as a single instruction.
+
+The traces are regression snapshots, not reference traces: this model was
+written next to the crate by the same hands, from the same reading of the ISA,
+and hasn't been checked against lc3sim or laser. It shares no code with the
+crate, so a slip in one of the two shows up, but a misreading of the ISA they
+share doesn't.
+
+It follows the VM where the LC-3 revisions differ: LEA sets the flags, and
+a trap writes R7 without touching them. Output of the traps is discarded.
+"""
+
+import sys
+from pathlib import Path
+
+FLAGS = {1: "Pos", 2: "Zro", 4: "Neg"}
+
+
+def sext(value, bits):
+    """Sign extends the low `bits` bits of `value` to 16 bits"""
+    value &= (1 << bits) - 1
+    if value >> (bits - 1):
+        value -= 1 << bits
+    return value & 0xFFFF
+
+
+class Machine:
+    def __init__(self, image, keys):
+        self.memory = [0] * 0x10000
+        origin = image[0] << 8 | image[1]
+        for i in range(2, len(image) - 1, 2):
+            self.memory[(origin + i // 2 - 1) & 0xFFFF] = image[i] << 8 | image[i + 1]
+        self.pc = origin
+        self.r = [0] * 8
+        self.cond = 2
+        self.keys = list(keys)
+        self.halted = False
+
+    def setcc(self, register):
+        value = self.r[register]
+        self.cond = 2 if value == 0 else 4 if value >> 15 else 1
+
+    def key(self):
+        if not self.keys:
+            sys.exit("the program read past the end of its input")
+        return self.keys.pop(0)
+
+    def trap(self, vector):
+        self.r[7] = self.pc
+        if vector in (0x20, 0x23):  # GETC, IN
+            self.r[0] = self.key()
+        elif vector == 0x25:  # HALT
+            self.halted = True
+        elif vector not in (0x21, 0x22, 0x24):  # OUT, PUTS, PUTSP
+            sys.exit(f"unknown trap x{vector:02X}")
+
+    def step(self):
+        pc = self.pc
+        word = self.memory[pc]
+        self.pc = (pc + 1) & 0xFFFF
+        op = word >> 12
+        dr = (word >> 9) & 7
+        sr1 = (word >> 6) & 7
+        pc_offset = (self.pc + sext(word, 9)) & 0xFFFF
+
+        def operand():
+            return sext(word, 5) if word & 0x20 else self.r[word & 7]
+
+        if op == 0x0:  # BR
+            if dr & self.cond:
+                self.pc = pc_offset
+        elif op == 0x1:  # ADD
+            self.r[dr] = (self.r[sr1] + operand()) & 0xFFFF
+            self.setcc(dr)
+        elif op == 0x5:  # AND
+            self.r[dr] = self.r[sr1] & operand()
+            self.setcc(dr)
+        elif op == 0x9:  # NOT
+            self.r[dr] = ~self.r[sr1] & 0xFFFF
+            self.setcc(dr)
+        elif op == 0x2:  # LD
+            self.r[dr] = self.memory[pc_offset]
+            self.setcc(dr)
+        elif op == 0xA:  # LDI
+            self.r[dr] = self.memory[self.memory[pc_offset]]
+            self.setcc(dr)
+        elif op == 0x6:  # LDR
+            self.r[dr] = self.memory[(self.r[sr1] + sext(word, 6)) & 0xFFFF]
+            self.setcc(dr)
+        elif op == 0xE:  # LEA
+            self.r[dr] = pc_offset
+            self.setcc(dr)
+        elif op == 0x3:  # ST
+            self.memory[pc_offset] = self.r[dr]
+        elif op == 0xB:  # STI
+            self.memory[self.memory[pc_offset]] = self.r[dr]
+        elif op == 0x7:  # STR
+            self.memory[(self.r[sr1] + sext(word, 6)) & 0xFFFF] = self.r[dr]
+        elif op == 0xC:  # JMP, RET
+            self.pc = self.r[sr1]
+        elif op == 0x4:  # JSR, JSRR
+            target = (self.pc + sext(word, 11)) & 0xFFFF if word & 0x800 else self.r[sr1]
+            self.r[7] = self.pc
+            self.pc = target
+        elif op == 0xF:  # TRAP
+            self.trap(word & 0xFF)
+        else:
+            sys.exit(f"x{pc:04X}: unsupported instruction x{word:04X}")
+
+        registers = " ".join(f"x{value:04X}" for value in self.r)
+        return f"x{pc:04X} x{word:04X} {registers} {FLAGS[self.cond]}"
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+    program = Path(sys.argv[1])
+    keys_file = program.with_suffix(".input")
+    keys = keys_file.read_bytes() if keys_file.exists() else b""
+
+    machine = Machine(program.read_bytes(), keys)
+    while not machine.halted:
+        print(machine.step())
+
+
+if __name__ == "__main__":
+    main()
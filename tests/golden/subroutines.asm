@@ -0,0 +1,41 @@
+; JSR, JSRR and RET with a stack in R6, computing 5 factorial by repeated addition
+        .ORIG x3000
+        LD R6, STACK
+        AND R0, R0, #0
+        ADD R0, R0, #5
+        JSR FACT
+        ST R0, RESULT
+        LEA R5, DOUBLE
+        JSRR R5
+        HALT
+; R0 = R0!, saving R1 and R7 on the stack
+FACT    ADD R6, R6, #-1
+        STR R7, R6, #0
+        ADD R6, R6, #-1
+        STR R1, R6, #0
+        ADD R1, R0, #-1
+        BRnz BASE
+        ADD R1, R0, #0
+        ADD R0, R0, #-1
+        JSR FACT
+        JSR MULT
+        BR UNWIND
+BASE    AND R0, R0, #0
+        ADD R0, R0, #1
+UNWIND  LDR R1, R6, #0
+        ADD R6, R6, #1
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+; R0 = R0 * R1 for R1 > 0, using R2
+MULT    AND R2, R2, #0
+MLOOP   ADD R2, R2, R0
+        ADD R1, R1, #-1
+        BRp MLOOP
+        ADD R0, R2, #0
+        RET
+DOUBLE  ADD R0, R0, R0
+        RET
+STACK   .FILL x4000
+RESULT  .BLKW 1
+        .END
@@ -0,0 +1,25 @@
+; GETC, OUT, PUTS and PUTSP, echoing the keys it reads
+        .ORIG x3000
+        LEA R0, HELLO
+        PUTS
+        GETC
+        ADD R1, R0, #0
+        OUT
+        ADD R0, R1, #1
+        OUT
+        LD R0, NEWLINE
+        OUT
+        LEA R0, PACKED
+        PUTSP
+        GETC
+        ADD R2, R0, #-10
+        BRnp SKIP
+        LEA R0, BLANK
+        PUTS
+SKIP    HALT
+HELLO   .STRINGZ "hi "
+BLANK   .STRINGZ "blank line\n"
+NEWLINE .FILL x000A
+PACKED  .FILL x6B6F
+        .FILL x000A
+        .END
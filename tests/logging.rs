@@ -0,0 +1,140 @@
+use std::sync::{Mutex, Once};
+use std::thread::{self, ThreadId};
+
+use lc3_vm::loader::Endianness;
+use lc3_vm::{HaltReason, RunOutcome, VMError, VM};
+use log::{Level, Log, Metadata, Record};
+
+/// A record kept by [`CapturingLogger`]
+struct Captured {
+    thread: ThreadId,
+    level: Level,
+    target: String,
+    message: String,
+}
+
+/// Keeps every record, tests only look at the ones logged on their own thread
+struct CapturingLogger {
+    records: Mutex<Vec<Captured>>,
+}
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if let Ok(mut records) = self.records.lock() {
+            records.push(Captured {
+                thread: thread::current().id(),
+                level: record.level(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    records: Mutex::new(Vec::new()),
+};
+
+/// Runs `f` and returns the records it logged as `(level, target, message)`
+fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, Vec<(Level, String, String)>) {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+    });
+
+    let result = f();
+
+    let current = thread::current().id();
+    let mut records = LOGGER.records.lock().unwrap_or_else(|e| e.into_inner());
+    let (mine, others) = records
+        .drain(..)
+        .partition(|record| record.thread == current);
+    *records = others;
+
+    let logged = mine
+        .into_iter()
+        .map(|record: Captured| (record.level, record.target, record.message))
+        .collect();
+    (result, logged)
+}
+
+#[test]
+fn test_load_logs_summary() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    let (loaded, logged) =
+        capture_logs(|| vm.load_program("examples/simple_add.obj", Endianness::Big));
+    loaded?;
+
+    let infos: Vec<_> = logged
+        .iter()
+        .filter(|(level, _, _)| *level == Level::Info)
+        .collect();
+    assert_eq!(
+        infos,
+        vec![&(
+            Level::Info,
+            "lc3_vm::vm".to_string(),
+            "loaded examples/simple_add.obj (Obj): 6 words in 1 segment(s), origin x3000"
+                .to_string()
+        )]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_clean_run_logs_no_warnings() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    vm.capture_output();
+    vm.set_halt_banner(false);
+
+    let (outcome, logged) = capture_logs(|| -> Result<RunOutcome, VMError> {
+        vm.load_program("examples/simple_add.obj", Endianness::Big)?;
+        vm.run_with_limit(100)
+    });
+    assert_eq!(outcome?, RunOutcome::Halted(HaltReason::TrapHalt));
+
+    assert!(logged.iter().all(|(level, _, _)| *level > Level::Warn));
+    // One fetch per instruction, the operand values and memory accesses at trace level
+    let fetches = logged
+        .iter()
+        .filter(|(level, _, message)| *level == Level::Debug && message.starts_with("fetch"))
+        .count();
+    assert_eq!(fetches, 6);
+    assert!(logged
+        .iter()
+        .any(|(level, target, _)| *level == Level::Trace && target == "lc3_vm::memory"));
+
+    Ok(())
+}
+
+#[test]
+fn test_overlapping_load_warns() -> Result<(), VMError> {
+    let mut vm = VM::new();
+    let (loaded, logged) = capture_logs(|| -> Result<(), VMError> {
+        vm.load_bytes(&[0x30, 0x00, 0x12, 0x34, 0x56, 0x78], Endianness::Big)?;
+        vm.load_bytes(&[0x30, 0x01, 0x00, 0x00], Endianness::Big)?;
+        Ok(())
+    });
+    loaded?;
+
+    let warnings: Vec<_> = logged
+        .iter()
+        .filter(|(level, _, _)| *level == Level::Warn)
+        .map(|(_, _, message)| message.as_str())
+        .collect();
+    assert_eq!(
+        warnings,
+        vec!["program at 0x3001 overlaps program loaded at 0x3000"]
+    );
+
+    Ok(())
+}
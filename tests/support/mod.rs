@@ -0,0 +1,126 @@
+//! Helpers shared by the integration tests
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lc3_vm::VMError;
+
+/// Numbers the fixtures of this test process, so parallel tests never share a file
+static NEXT_FIXTURE: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes a big-endian `.obj` image to a temporary file, with a matching `.sym` when it has labels
+///
+/// ```ignore
+/// let path = ObjFixture::new(0x3000)
+///     .words(&[0xE002, 0xF022, 0xF025])
+///     .label("TEXT")
+///     .string("HI")
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ObjFixture {
+    origin: u16,
+    words: Vec<u16>,
+    labels: Vec<(String, u16)>,
+}
+
+impl ObjFixture {
+    /// Starts an empty image loading at `origin`
+    pub fn new(origin: u16) -> Self {
+        Self {
+            origin,
+            words: Vec::new(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Address the next word will be loaded at
+    fn next_address(&self) -> u16 {
+        let len = u16::try_from(self.words.len()).unwrap_or(u16::MAX);
+        self.origin.wrapping_add(len)
+    }
+
+    /// Appends words, first to last
+    pub fn words(mut self, words: &[u16]) -> Self {
+        self.words.extend_from_slice(words);
+        self
+    }
+
+    /// Appends one word per character and a terminating zero, like `.STRINGZ`
+    pub fn string(mut self, text: &str) -> Self {
+        self.words.extend(text.bytes().map(u16::from));
+        self.words.push(0);
+        self
+    }
+
+    /// Names the address of the next word in the `.sym` file
+    pub fn label(mut self, name: &str) -> Self {
+        let address = self.next_address();
+        self.labels.push((name.to_string(), address));
+        self
+    }
+
+    /// The image as it is written: the origin, then every word, all big-endian
+    pub fn bytes(&self) -> Vec<u8> {
+        std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    }
+
+    /// Writes the image, and the `.sym` next to it if any label was added
+    ///
+    /// # Errors
+    /// Returns `VMError::SaveFailed` if a file can't be written
+    pub fn build(&self) -> Result<FixtureFile, VMError> {
+        let id = NEXT_FIXTURE.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("lc3-fixture-{}-{id}.obj", std::process::id()));
+        let failed = |path: &PathBuf| VMError::SaveFailed(path.display().to_string());
+
+        std::fs::write(&path, self.bytes()).map_err(|_| failed(&path))?;
+        let file = FixtureFile { path };
+        if !self.labels.is_empty() {
+            let mut sym = String::from(
+                "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n",
+            );
+            for (name, address) in &self.labels {
+                sym.push_str(&format!("//\t{name:<16}  {address:04X}\n"));
+            }
+            let sym_path = file.sym_path();
+            std::fs::write(&sym_path, sym).map_err(|_| failed(&sym_path))?;
+        }
+
+        Ok(file)
+    }
+}
+
+/// A `.obj` written by [`ObjFixture::build`], deleted together with its `.sym` when dropped
+#[derive(Debug)]
+pub struct FixtureFile {
+    path: PathBuf,
+}
+
+impl FixtureFile {
+    /// Path of the `.obj` file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of the `.obj` file as the string the VM loaders take
+    pub fn as_str(&self) -> &str {
+        self.path.to_str().unwrap_or_default()
+    }
+
+    /// Path of the `.sym` file next to it, which only exists if the fixture has labels
+    pub fn sym_path(&self) -> PathBuf {
+        self.path.with_extension("sym")
+    }
+}
+
+impl Drop for FixtureFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_file(self.sym_path());
+    }
+}
@@ -0,0 +1,79 @@
+//! Spans and events of the `tracing` feature, written by a `tracing_subscriber` fmt layer
+#![cfg(feature = "tracing")]
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use lc3_vm::loader::Endianness;
+use lc3_vm::{HaltReason, RunOutcome, VMError, VM};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Collects everything the subscriber writes
+#[derive(Clone, Default)]
+struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for TestWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Ok(mut bytes) = self.0.lock() {
+            bytes.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for TestWriter {
+    type Writer = TestWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+impl TestWriter {
+    fn lines(&self) -> Vec<String> {
+        let bytes = self.0.lock().map(|bytes| bytes.clone()).unwrap_or_default();
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+#[test]
+fn test_simple_add_spans_and_trap_events() -> Result<(), VMError> {
+    let writer = TestWriter::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(writer.clone())
+        .with_max_level(tracing::Level::TRACE)
+        .with_span_events(FmtSpan::NEW)
+        .with_ansi(false)
+        .without_time()
+        .with_target(false)
+        .finish();
+
+    let outcome = tracing::subscriber::with_default(subscriber, || {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.set_halt_banner(false);
+        vm.load_program("examples/simple_add.obj", Endianness::Big)?;
+        vm.run_with_limit(100)
+    })?;
+    assert_eq!(outcome, RunOutcome::Halted(HaltReason::TrapHalt));
+
+    let lines = writer.lines();
+    let count = |pattern: &str| lines.iter().filter(|line| line.contains(pattern)).count();
+
+    assert_eq!(count("load_program{file=\"examples/simple_add.obj\""), 1);
+    assert_eq!(count(": new"), 7, "{:#?}", lines);
+    assert_eq!(count("step{pc=12288 opcode=And}: new"), 1);
+    assert_eq!(count("step{pc=12293 opcode=Trap}: trap entry vector=37"), 1);
+    assert_eq!(count("trap exit vector=37 ok=true"), 1);
+    assert_eq!(count("device"), 0);
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! The VM in a browser page, built with `wasm-pack build --target web`
+//!
+//! The page hands key events to [`WasmVm::key_event`] and receives the output
+//! through the function given to [`WasmVm::new`]. [`WasmVm::step`] never
+//! blocks: it stops early when the program waits for a key nobody pressed yet,
+//! so the page calls it again, e.g. from `requestAnimationFrame`, and the
+//! program picks up the key once it is queued.
+
+use std::io::{self, Write};
+
+use js_sys::Function;
+use lc3_vm::keyboard::KeyQueue;
+use lc3_vm::prelude::*;
+use wasm_bindgen::prelude::*;
+
+/// Hands every write to a JavaScript function as a string
+struct JsOutput {
+    callback: Function,
+}
+
+impl Write for JsOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = JsValue::from_str(&String::from_utf8_lossy(buf));
+        self.callback
+            .call1(&JsValue::NULL, &text)
+            .map_err(|e| io::Error::other(format!("the output callback threw {e:?}")))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn js_error(error: VMError) -> JsError {
+    JsError::new(&format!("{error:?}"))
+}
+
+/// An LC-3 VM driven by a page: keys come from key events, output goes to a callback
+#[wasm_bindgen]
+pub struct WasmVm {
+    vm: VM,
+    keys: KeyQueue,
+}
+
+#[wasm_bindgen]
+impl WasmVm {
+    /// Creates a VM calling `output` with each piece of text the program prints
+    #[wasm_bindgen(constructor)]
+    pub fn new(output: Function) -> WasmVm {
+        let keys = KeyQueue::new();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(JsOutput { callback: output }));
+        vm.set_input_source(Box::new(keys.clone()));
+        vm.set_halt_banner(false);
+
+        WasmVm { vm, keys }
+    }
+
+    /// Loads a big-endian `.obj` image, such as the bytes of a fetched file
+    ///
+    /// Execution starts at the origin of the first image loaded, which is returned.
+    pub fn load_bytes(&mut self, image: &[u8]) -> Result<u16, JsError> {
+        let program = self
+            .vm
+            .load_bytes(image, Endianness::Big)
+            .map_err(js_error)?;
+
+        Ok(program.origin)
+    }
+
+    /// Runs at most `n` instructions and flushes the output
+    ///
+    /// Stops early once the program halts or waits for a key that hasn't been
+    /// pressed, see [`WasmVm::waiting_for_key`].
+    ///
+    /// # Returns
+    /// How many instructions ran
+    pub fn step(&mut self, n: u32) -> Result<u32, JsError> {
+        let mut executed = 0;
+        while executed < n && !self.halted() && !self.waiting_for_key() {
+            self.vm.step().map_err(js_error)?;
+            executed += 1;
+        }
+        self.vm.flush_output().map_err(js_error)?;
+
+        Ok(executed)
+    }
+
+    /// Queues a key pressed on the page, for GETC, IN or the KBSR/KBDR registers
+    pub fn key_event(&mut self, key: u8) {
+        self.keys.push(key);
+    }
+
+    /// Whether the program halted
+    pub fn halted(&self) -> bool {
+        matches!(self.vm.state(), VMState::Halted(_))
+    }
+
+    /// Whether the next instruction waits for a key and none is queued
+    pub fn waiting_for_key(&self) -> bool {
+        self.vm.waits_for_key() && self.keys.is_empty()
+    }
+
+    /// The program counter
+    pub fn pc(&self) -> u16 {
+        self.vm.pc()
+    }
+
+    /// The value of register `r`, R0 to R7
+    pub fn register(&self, r: usize) -> Result<u16, JsError> {
+        self.vm.read_register(r).map_err(js_error)
+    }
+}
@@ -0,0 +1,72 @@
+//! Runs in a headless browser with `wasm-pack test --headless --firefox` (or `--chrome`)
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use js_sys::Function;
+use lc3_vm_wasm::WasmVm;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+/// A JavaScript function appending the text it is called with to the returned string
+fn collector() -> (Function, Rc<RefCell<String>>) {
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = Rc::clone(&output);
+    let closure = Closure::<dyn FnMut(String)>::new(move |text: String| {
+        sink.borrow_mut().push_str(&text);
+    });
+
+    (closure.into_js_value().unchecked_into(), output)
+}
+
+#[wasm_bindgen_test]
+fn hello_world_reaches_the_callback() -> Result<(), JsValue> {
+    let (callback, output) = collector();
+    let mut vm = WasmVm::new(callback);
+
+    assert_eq!(
+        vm.load_bytes(include_bytes!("../../examples/hello-world.obj"))?,
+        0x3000
+    );
+    vm.step(1_000)?;
+
+    assert!(vm.halted());
+    assert_eq!(*output.borrow(), "Hello World!");
+
+    Ok(())
+}
+
+#[wasm_bindgen_test]
+fn key_events_feed_getc() -> Result<(), JsValue> {
+    // Echoes each key uppercased until a 'q':
+    // LD R1, NEG_Q / LD R2, TO_UPPER / GETC / ADD R3, R0, R1 / BRz DONE /
+    // ADD R0, R0, R2 / OUT / BRnzp back to GETC / HALT / x-'q' / 'A' - 'a'
+    let words: [u16; 12] = [
+        0x3000, 0x2208, 0x2408, 0xF020, 0x1601, 0x0403, 0x1002, 0xF021, 0x0FFA, 0xF025, 0xFF8F,
+        0xFFE0,
+    ];
+    let image: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+    let (callback, output) = collector();
+    let mut vm = WasmVm::new(callback);
+    vm.load_bytes(&image)?;
+
+    assert_eq!(vm.step(1_000)?, 2);
+    assert!(vm.waiting_for_key());
+
+    for key in *b"lc" {
+        vm.key_event(key);
+    }
+    vm.step(1_000)?;
+    assert_eq!(*output.borrow(), "LC");
+    assert!(vm.waiting_for_key());
+
+    vm.key_event(b'q');
+    vm.step(1_000)?;
+    assert!(vm.halted());
+    assert_eq!(vm.register(0)?, u16::from(b'q'));
+
+    Ok(())
+}